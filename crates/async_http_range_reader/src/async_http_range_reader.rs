@@ -736,6 +736,9 @@ mod test {
             0..size,
             "expected only two range requests"
         );
+
+        // Only the requested ranges were sent by the server.
+        assert_eq!(server.bytes_served(), 8192 + size);
     }
 
     #[rstest]
@@ -1,7 +1,16 @@
-use axum::routing::get_service;
+use axum::{
+    body::Body,
+    http::{header, Method, Request},
+    middleware::{self, Next},
+    routing::get_service,
+};
 use reqwest::Url;
 use std::net::SocketAddr;
 use std::path::Path;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
 use tokio::sync::oneshot;
 use tower_http::services::ServeDir;
 
@@ -11,6 +20,7 @@ use tower_http::services::ServeDir;
 pub struct StaticDirectoryServer {
     local_addr: SocketAddr,
     shutdown_sender: Option<oneshot::Sender<()>>,
+    bytes_served: Arc<AtomicU64>,
 }
 
 impl StaticDirectoryServer {
@@ -18,14 +28,41 @@ impl StaticDirectoryServer {
     pub fn url(&self) -> Url {
         Url::parse(&format!("http://localhost:{}", self.local_addr.port())).unwrap()
     }
+
+    /// Returns the total number of body bytes the server has sent so far.
+    pub fn bytes_served(&self) -> u64 {
+        self.bytes_served.load(Ordering::SeqCst)
+    }
 }
 
 impl StaticDirectoryServer {
     pub fn new(path: impl AsRef<Path>) -> Self {
         let service = get_service(ServeDir::new(path));
 
-        // Create a router that will serve the static files
-        let app = axum::Router::new().nest_service("/", service);
+        // Create a router that will serve the static files and keeps track of the number of bytes
+        // it sends. Range responses only contain the requested bytes.
+        let bytes_served = Arc::new(AtomicU64::new(0));
+        let app = axum::Router::new()
+            .nest_service("/", service)
+            .layer(middleware::from_fn({
+                let bytes_served = bytes_served.clone();
+                move |request: Request<Body>, next: Next<Body>| {
+                    let bytes_served = bytes_served.clone();
+                    async move {
+                        let is_head = request.method() == Method::HEAD;
+                        let response = next.run(request).await;
+                        let content_length = response
+                            .headers()
+                            .get(header::CONTENT_LENGTH)
+                            .and_then(|value| value.to_str().ok())
+                            .and_then(|value| value.parse::<u64>().ok());
+                        if let (false, Some(content_length)) = (is_head, content_length) {
+                            bytes_served.fetch_add(content_length, Ordering::SeqCst);
+                        }
+                        response
+                    }
+                }
+            }));
 
         // Construct the server that will listen on localhost but with a *random port*. The random
         // port is very important because it enables creating multiple instances at the same time.
@@ -49,6 +86,7 @@ impl StaticDirectoryServer {
         Self {
             local_addr: addr,
             shutdown_sender: Some(tx),
+            bytes_served,
         }
     }
 }
@@ -0,0 +1,120 @@
+use anyhow::Context;
+use rattler::{
+    default_cache_dir,
+    package_cache::{CacheKey, CleanPolicy, PackageCache},
+};
+use rattler_conda_types::PrefixRecord;
+use rattler_lock::LockFile;
+use std::path::PathBuf;
+
+#[derive(Debug, clap::Parser)]
+pub struct Opt {
+    /// Remove the least recently used packages until the cache is at most this size. Accepts a
+    /// number of bytes optionally followed by a `K`, `M`, `G` or `T` suffix.
+    #[clap(long, value_parser = parse_size, conflicts_with_all = ["keep_prefix", "keep_lock_file"])]
+    max_size: Option<u64>,
+
+    /// Keep only the packages that are installed in this prefix, can be specified multiple times.
+    #[clap(long)]
+    keep_prefix: Vec<PathBuf>,
+
+    /// Keep only the packages that are referenced by this lock file, can be specified multiple
+    /// times.
+    #[clap(long)]
+    keep_lock_file: Vec<PathBuf>,
+
+    /// Remove all the packages from the cache if the prefixes and lock files to keep don't
+    /// reference any package. Without this flag that is refused.
+    #[clap(long)]
+    allow_empty_keep: bool,
+}
+
+pub async fn clean(opt: Opt) -> anyhow::Result<()> {
+    let policy = if let Some(max_size) = opt.max_size {
+        CleanPolicy::MaxSize(max_size)
+    } else if !opt.keep_prefix.is_empty() || !opt.keep_lock_file.is_empty() {
+        let mut keep = Vec::new();
+        for prefix in &opt.keep_prefix {
+            // A typo in the path would otherwise remove all the packages of the prefix.
+            if !prefix.join("conda-meta").is_dir() {
+                anyhow::bail!(
+                    "{} is not a conda prefix, it does not contain a conda-meta directory",
+                    prefix.display()
+                );
+            }
+            let records = PrefixRecord::collect_from_prefix(prefix)
+                .with_context(|| format!("failed to read {}", prefix.display()))?;
+            if records.is_empty() {
+                anyhow::bail!("{} has no installed packages", prefix.display());
+            }
+            keep.extend(
+                records
+                    .iter()
+                    .map(|record| CacheKey::from(&record.repodata_record)),
+            );
+        }
+        for lock_file in &opt.keep_lock_file {
+            let lock_file = LockFile::from_path(lock_file)
+                .with_context(|| format!("failed to read {}", lock_file.display()))?;
+            for (_, environment) in lock_file.environments() {
+                for (_, packages) in environment.packages_by_platform() {
+                    keep.extend(packages.filter_map(|package| {
+                        package
+                            .as_conda()
+                            .map(|package| CacheKey::from(package.package_record()))
+                    }));
+                }
+            }
+        }
+        if keep.is_empty() && !opt.allow_empty_keep {
+            anyhow::bail!(
+                "no packages to keep, this would remove all packages from the cache. Pass \
+                --allow-empty-keep to do so anyway"
+            );
+        }
+        CleanPolicy::KeepOnly(keep)
+    } else {
+        anyhow::bail!("specify either --max-size or --keep-prefix/--keep-lock-file");
+    };
+
+    let report = PackageCache::new(default_cache_dir()?.join("pkgs")).clean(policy)?;
+
+    println!(
+        "{} Removed {} package(s), freeing {}",
+        console::style(console::Emoji("✔", "")).green(),
+        report.removed.len(),
+        indicatif::HumanBytes(report.removed.iter().map(|entry| entry.size).sum())
+    );
+    if !report.in_use.is_empty() {
+        println!(
+            "Kept {} package(s) that are currently in use",
+            report.in_use.len()
+        );
+    }
+    Ok(())
+}
+
+/// Parses a size like `500M` into a number of bytes.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (number, multiplier) = match s.char_indices().last() {
+        Some((idx, suffix)) if suffix.is_ascii_alphabetic() => {
+            let multiplier = match suffix.to_ascii_uppercase() {
+                'K' => 1 << 10,
+                'M' => 1 << 20,
+                'G' => 1 << 30,
+                'T' => 1 << 40,
+                _ => return Err(format!("unknown size suffix '{suffix}'")),
+            };
+            (&s[..idx], multiplier)
+        }
+        _ => (s, 1),
+    };
+    let number = number
+        .trim()
+        .parse::<u64>()
+        .map_err(|e| format!("invalid size '{s}': {e}"))?;
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("invalid size '{s}': the size is too large"))
+}
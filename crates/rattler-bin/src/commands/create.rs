@@ -1,22 +1,17 @@
 use crate::global_multi_progress;
 use anyhow::Context;
-use futures::{stream, stream::FuturesUnordered, FutureExt, StreamExt, TryFutureExt, TryStreamExt};
+use futures::StreamExt;
 use indicatif::{HumanBytes, ProgressBar, ProgressState, ProgressStyle};
 use rattler::{
     default_cache_dir,
-    install::{
-        link_package, unlink_package, InstallDriver, InstallOptions, Transaction,
-        TransactionOperation,
-    },
+    install::{Installer, Journal, Reporter, Transaction, TransactionOperation},
     package_cache::PackageCache,
 };
 use rattler_conda_types::{
     Channel, ChannelConfig, GenericVirtualPackage, MatchSpec, PackageRecord, Platform,
     PrefixRecord, RepoDataRecord, Version,
 };
-use rattler_networking::{
-    retry_policies::default_retry_policy, AuthenticationMiddleware, AuthenticationStorage,
-};
+use rattler_networking::{AuthenticationMiddleware, AuthenticationStorage};
 use rattler_repodata_gateway::fetch::{
     CacheResult, DownloadProgress, FetchRepoDataError, FetchRepoDataOptions,
 };
@@ -28,12 +23,10 @@ use std::{
     borrow::Cow,
     env,
     fmt::Write,
-    future::ready,
     path::{Path, PathBuf},
     str::FromStr,
     time::Duration,
 };
-use tokio::task::JoinHandle;
 
 #[derive(Debug, clap::Parser)]
 pub struct Opt {
@@ -54,6 +47,10 @@ pub struct Opt {
 
     #[clap(long)]
     use_experimental_libsolv_rs: bool,
+
+    /// Roll back the changes of an interrupted transaction before modifying the environment.
+    #[clap(long)]
+    recover: bool,
 }
 
 pub async fn create(opt: Opt) -> anyhow::Result<()> {
@@ -104,9 +101,24 @@ pub async fn create(opt: Opt) -> anyhow::Result<()> {
         })
         .collect::<Vec<_>>();
 
+    // A previous transaction that was interrupted leaves the environment in an inconsistent state,
+    // restore the environment before modifying it.
+    if Journal::is_interrupted(&target_prefix) {
+        if !opt.recover {
+            anyhow::bail!(
+                "the environment at {} was left behind by an interrupted transaction, rerun with --recover to restore it",
+                target_prefix.display()
+            );
+        }
+        Journal::recover(&target_prefix).context("failed to recover the environment")?;
+        println!(
+            "{} Recovered the environment from an interrupted transaction",
+            console::style(console::Emoji("✔", "")).green(),
+        );
+    }
+
     // Determine the packages that are currently installed in the environment.
-    let installed_packages = find_installed_packages(&target_prefix, 100)
-        .await
+    let installed_packages = PrefixRecord::collect_from_prefix(&target_prefix)
         .context("failed to determine currently installed packages")?;
 
     // For each channel/subdirectory combination, download and cache the `repodata.json` that should
@@ -215,12 +227,14 @@ pub async fn create(opt: Opt) -> anyhow::Result<()> {
         .map(|record| record.repodata_record.clone())
         .collect();
 
+    // The specs are recorded in the history of the environment.
+    let requested_specs = specs.clone();
+
     let solver_task = SolverTask {
-        available_packages: &repodatas,
         locked_packages,
         virtual_packages,
         specs,
-        pinned_packages: Vec::new(),
+        ..SolverTask::from_iter(&repodatas)
     };
 
     // Next, use a solver to solve this specific problem. This provides us with all the operations
@@ -287,7 +301,14 @@ pub async fn create(opt: Opt) -> anyhow::Result<()> {
         );
     } else {
         // Execute the operations that are returned by the solver.
-        execute_transaction(transaction, target_prefix, cache_dir, download_client).await?;
+        execute_transaction(
+            transaction,
+            requested_specs,
+            target_prefix,
+            cache_dir,
+            download_client,
+        )
+        .await?;
         println!(
             "{} Successfully updated the environment",
             console::style(console::Emoji("✔", "")).green(),
@@ -300,245 +321,78 @@ pub async fn create(opt: Opt) -> anyhow::Result<()> {
 /// Executes the transaction on the given environment.
 async fn execute_transaction(
     transaction: Transaction<PrefixRecord, RepoDataRecord>,
+    requested_specs: Vec<MatchSpec>,
     target_prefix: PathBuf,
     cache_dir: PathBuf,
     download_client: reqwest_middleware::ClientWithMiddleware,
 ) -> anyhow::Result<()> {
-    // Open the package cache
-    let package_cache = PackageCache::new(cache_dir.join("pkgs"));
-
-    // Create an install driver which helps limit the number of concurrent fileystem operations
-    let install_driver = InstallDriver::default();
-
-    // Define default installation options.
-    let install_options = InstallOptions {
-        python_info: transaction.python_info.clone(),
-        platform: Some(transaction.platform),
-        ..Default::default()
-    };
-
-    // Create a progress bars for downloads.
-    let multi_progress = global_multi_progress();
-    let total_packages_to_download = transaction
-        .operations
-        .iter()
-        .filter(|op| op.record_to_install().is_some())
-        .count();
-    let download_pb = if total_packages_to_download > 0 {
-        let pb = multi_progress.add(
-            indicatif::ProgressBar::new(total_packages_to_download as u64)
-                .with_style(default_progress_style())
-                .with_finish(indicatif::ProgressFinish::WithMessage("Done!".into()))
-                .with_prefix("downloading"),
-        );
-        pb.enable_steady_tick(Duration::from_millis(100));
-        Some(pb)
-    } else {
-        None
-    };
+    let reporter = IndicatifReporter::new(transaction.operations.len(), &global_multi_progress());
 
-    // Create a progress bar to track all operations.
-    let total_operations = transaction.operations.len();
-    let link_pb = multi_progress.add(
-        indicatif::ProgressBar::new(total_operations as u64)
-            .with_style(default_progress_style())
-            .with_finish(indicatif::ProgressFinish::WithMessage("Done!".into()))
-            .with_prefix("linking"),
-    );
-    link_pb.enable_steady_tick(Duration::from_millis(100));
-
-    // Perform all transactions operations in parallel.
-    stream::iter(transaction.operations)
-        .map(Ok)
-        .try_for_each_concurrent(50, |op| {
-            let target_prefix = target_prefix.clone();
-            let download_client = download_client.clone();
-            let package_cache = &package_cache;
-            let install_driver = &install_driver;
-            let download_pb = download_pb.as_ref();
-            let link_pb = &link_pb;
-            let install_options = &install_options;
-            async move {
-                execute_operation(
-                    &target_prefix,
-                    download_client,
-                    package_cache,
-                    install_driver,
-                    download_pb,
-                    link_pb,
-                    op,
-                    install_options,
-                )
-                .await
-            }
-        })
+    Installer::new(PackageCache::new(cache_dir.join("pkgs")))
+        .with_download_client(download_client)
+        .with_reporter(Arc::new(reporter))
+        .with_requested_specs(requested_specs)
+        .execute(&target_prefix, transaction)
         .await?;
 
-    // Perform any post processing that is required.
-    let prefix_records = find_installed_packages(&target_prefix, 100)
-        .await
-        .context("failed to determine currently installed packages")?;
-    install_driver
-        .post_process(&prefix_records, &target_prefix)
-        .expect("bla");
-
     Ok(())
 }
 
-/// Executes a single operation of a transaction on the environment.
-/// TODO: Move this into an object or something.
-#[allow(clippy::too_many_arguments)]
-async fn execute_operation(
-    target_prefix: &Path,
-    download_client: reqwest_middleware::ClientWithMiddleware,
-    package_cache: &PackageCache,
-    install_driver: &InstallDriver,
-    download_pb: Option<&ProgressBar>,
-    link_pb: &ProgressBar,
-    op: TransactionOperation<PrefixRecord, RepoDataRecord>,
-    install_options: &InstallOptions,
-) -> anyhow::Result<()> {
-    // Determine the package to install
-    let install_record = op.record_to_install();
-    let remove_record = op.record_to_remove();
-
-    // Create a future to remove the existing package
-    let remove_future = if let Some(remove_record) = remove_record {
-        remove_package_from_environment(target_prefix, remove_record).left_future()
-    } else {
-        ready(Ok(())).right_future()
-    };
+/// Displays the progress of the [`Installer`] using progress bars.
+struct IndicatifReporter {
+    download_pb: ProgressBar,
+    link_pb: ProgressBar,
+}
 
-    // Create a future to download the package
-    let cached_package_dir_fut = if let Some(install_record) = install_record {
-        async {
-            // Make sure the package is available in the package cache.
-            let result = package_cache
-                .get_or_fetch_from_url_with_retry(
-                    &install_record.package_record,
-                    install_record.url.clone(),
-                    download_client.clone(),
-                    default_retry_policy(),
-                )
-                .map_ok(|cache_dir| Some((install_record.clone(), cache_dir)))
-                .map_err(anyhow::Error::from)
-                .await;
+impl IndicatifReporter {
+    fn new(total_operations: usize, multi_progress: &indicatif::MultiProgress) -> Self {
+        // Create a progress bar for downloads, its length grows when packages are not found in
+        // the cache.
+        let download_pb = multi_progress.add(
+            indicatif::ProgressBar::new(0)
+                .with_style(default_progress_style())
+                .with_finish(indicatif::ProgressFinish::WithMessage("Done!".into()))
+                .with_prefix("downloading"),
+        );
+        download_pb.enable_steady_tick(Duration::from_millis(100));
 
-            // Increment the download progress bar.
-            if let Some(pb) = download_pb {
-                pb.inc(1);
-                if pb.length() == Some(pb.position()) {
-                    pb.set_style(finished_progress_style());
-                }
-            }
+        // Create a progress bar to track all operations.
+        let link_pb = multi_progress.add(
+            indicatif::ProgressBar::new(total_operations as u64)
+                .with_style(default_progress_style())
+                .with_finish(indicatif::ProgressFinish::WithMessage("Done!".into()))
+                .with_prefix("linking"),
+        );
+        link_pb.enable_steady_tick(Duration::from_millis(100));
 
-            result
+        Self {
+            download_pb,
+            link_pb,
         }
-        .left_future()
-    } else {
-        ready(Ok(None)).right_future()
-    };
-
-    // Await removal and downloading concurrently
-    let (_, install_package) = tokio::try_join!(remove_future, cached_package_dir_fut)?;
-
-    // If there is a package to install, do that now.
-    if let Some((record, package_dir)) = install_package {
-        install_package_to_environment(
-            target_prefix,
-            package_dir,
-            record.clone(),
-            install_driver,
-            install_options,
-        )
-        .await?;
     }
+}
 
-    // Increment the link progress bar since we finished a step!
-    link_pb.inc(1);
-    if link_pb.length() == Some(link_pb.position()) {
-        link_pb.set_style(finished_progress_style());
+impl Reporter for IndicatifReporter {
+    fn on_download_start(&self, _operation: usize, _record: &RepoDataRecord) {
+        self.download_pb.inc_length(1);
     }
 
-    Ok(())
-}
+    fn on_download_complete(&self, _operation: usize) {
+        self.download_pb.inc(1);
+    }
 
-/// Install a package into the environment and write a `conda-meta` file that contains information
-/// about how the file was linked.
-async fn install_package_to_environment(
-    target_prefix: &Path,
-    package_dir: PathBuf,
-    repodata_record: RepoDataRecord,
-    install_driver: &InstallDriver,
-    install_options: &InstallOptions,
-) -> anyhow::Result<()> {
-    // Link the contents of the package into our environment. This returns all the paths that were
-    // linked.
-    let paths = link_package(
-        &package_dir,
-        target_prefix,
-        install_driver,
-        install_options.clone(),
-    )
-    .await?;
-
-    // Construct a PrefixRecord for the package
-    let prefix_record = PrefixRecord {
-        repodata_record,
-        package_tarball_full_path: None,
-        extracted_package_dir: Some(package_dir),
-        files: paths
-            .iter()
-            .map(|entry| entry.relative_path.clone())
-            .collect(),
-        paths_data: paths.into(),
-        // TODO: Retrieve the requested spec for this package from the request
-        requested_spec: None,
-        // TODO: What to do with this?
-        link: None,
-    };
+    fn on_operation_complete(&self, _operation: usize) {
+        self.link_pb.inc(1);
+    }
 
-    // Create the conda-meta directory if it doesnt exist yet.
-    let target_prefix = target_prefix.to_path_buf();
-    match tokio::task::spawn_blocking(move || {
-        let conda_meta_path = target_prefix.join("conda-meta");
-        std::fs::create_dir_all(&conda_meta_path)?;
-
-        // Write the conda-meta information
-        let pkg_meta_path = conda_meta_path.join(format!(
-            "{}-{}-{}.json",
-            prefix_record
-                .repodata_record
-                .package_record
-                .name
-                .as_normalized(),
-            prefix_record.repodata_record.package_record.version,
-            prefix_record.repodata_record.package_record.build
-        ));
-        prefix_record.write_to_path(pkg_meta_path, true)
-    })
-    .await
-    {
-        Ok(result) => Ok(result?),
-        Err(err) => {
-            if let Ok(panic) = err.try_into_panic() {
-                std::panic::resume_unwind(panic);
-            }
-            // The operation has been cancelled, so we can also just ignore everything.
-            Ok(())
+    fn on_transaction_complete(&self) {
+        for pb in [&self.download_pb, &self.link_pb] {
+            pb.set_style(finished_progress_style());
+            pb.finish_using_style();
         }
     }
 }
 
-/// Completely remove the specified package from the environment.
-async fn remove_package_from_environment(
-    target_prefix: &Path,
-    package: &PrefixRecord,
-) -> anyhow::Result<()> {
-    unlink_package(target_prefix, package).await?;
-    Ok(())
-}
-
 /// Displays a spinner with the given message while running the specified function to completion.
 fn wrap_in_progress<T, F: FnOnce() -> T>(msg: impl Into<Cow<'static, str>>, func: F) -> T {
     let pb = ProgressBar::new_spinner();
@@ -712,61 +566,3 @@ fn errored_progress_style() -> indicatif::ProgressStyle {
 fn long_running_progress_style() -> indicatif::ProgressStyle {
     ProgressStyle::with_template("{spinner:.green} {msg}").unwrap()
 }
-
-/// Scans the conda-meta directory of an environment and returns all the [`PrefixRecord`]s found in
-/// there.
-async fn find_installed_packages(
-    target_prefix: &Path,
-    concurrency_limit: usize,
-) -> Result<Vec<PrefixRecord>, std::io::Error> {
-    let mut meta_futures =
-        FuturesUnordered::<JoinHandle<Result<PrefixRecord, std::io::Error>>>::new();
-    let mut result = Vec::new();
-    for entry in std::fs::read_dir(target_prefix.join("conda-meta"))
-        .into_iter()
-        .flatten()
-    {
-        let entry = entry?;
-        let path = entry.path();
-        if path.ends_with(".json") {
-            continue;
-        }
-
-        // If there are too many pending entries, wait for one to be finished
-        if meta_futures.len() >= concurrency_limit {
-            match meta_futures
-                .next()
-                .await
-                .expect("we know there are pending futures")
-            {
-                Ok(record) => result.push(record?),
-                Err(e) => {
-                    if let Ok(panic) = e.try_into_panic() {
-                        std::panic::resume_unwind(panic);
-                    }
-                    // The future was cancelled, we can simply return what we have.
-                    return Ok(result);
-                }
-            }
-        }
-
-        // Spawn loading on another thread
-        let future = tokio::task::spawn_blocking(move || PrefixRecord::from_path(path));
-        meta_futures.push(future);
-    }
-
-    while let Some(record) = meta_futures.next().await {
-        match record {
-            Ok(record) => result.push(record?),
-            Err(e) => {
-                if let Ok(panic) = e.try_into_panic() {
-                    std::panic::resume_unwind(panic);
-                }
-                // The future was cancelled, we can simply return what we have.
-                return Ok(result);
-            }
-        }
-    }
-
-    Ok(result)
-}
@@ -1 +1,3 @@
+pub mod clean;
 pub mod create;
+pub mod verify;
@@ -0,0 +1,92 @@
+use anyhow::Context;
+use rattler::{
+    default_cache_dir, install::Installer, package_cache::PackageCache, validation::validate_prefix,
+};
+use rattler_conda_types::Platform;
+use rattler_networking::{AuthenticationMiddleware, AuthenticationStorage};
+use reqwest::Client;
+use std::{env, path::PathBuf, str::FromStr, sync::Arc};
+
+#[derive(Debug, clap::Parser)]
+pub struct Opt {
+    /// The prefix to verify, defaults to the `.prefix` directory in the current directory.
+    #[clap(long)]
+    target_prefix: Option<PathBuf>,
+
+    /// The platform the prefix was installed for.
+    #[clap(long)]
+    platform: Option<String>,
+
+    /// Reinstall the broken packages from the package cache.
+    #[clap(long)]
+    repair: bool,
+}
+
+pub async fn verify(opt: Opt) -> anyhow::Result<()> {
+    let target_prefix = match opt.target_prefix {
+        Some(target_prefix) => target_prefix,
+        None => env::current_dir()?.join(".prefix"),
+    };
+    let platform = match opt.platform {
+        Some(platform) => Platform::from_str(&platform)?,
+        None => Platform::current(),
+    };
+
+    let report = validate_prefix(&target_prefix, platform)
+        .with_context(|| format!("failed to verify {}", target_prefix.display()))?;
+
+    for package in &report.broken_packages {
+        println!(
+            "{} {}",
+            console::style(console::Emoji("❌", "x")).red(),
+            package.prefix_record.repodata_record.file_name
+        );
+        for path in &package.missing_files {
+            println!("    missing: {}", path.display());
+        }
+        for (path, err) in &package.modified_files {
+            println!("    modified: {} ({err})", path.display());
+        }
+    }
+    for path in &report.unexpected_files {
+        println!("unexpected file: {}", path.display());
+    }
+
+    if report.is_valid() {
+        println!(
+            "{} All installed packages are intact",
+            console::style(console::Emoji("✔", "")).green(),
+        );
+        return Ok(());
+    }
+
+    if !opt.repair {
+        anyhow::bail!(
+            "{} package(s) are broken, rerun with --repair to reinstall them",
+            report.broken_packages.len()
+        );
+    }
+
+    let download_client = reqwest_middleware::ClientBuilder::new(
+        Client::builder()
+            .no_gzip()
+            .build()
+            .expect("failed to create client"),
+    )
+    .with_arc(Arc::new(AuthenticationMiddleware::new(
+        AuthenticationStorage::default(),
+    )))
+    .build();
+
+    Installer::new(PackageCache::new(default_cache_dir()?.join("pkgs")))
+        .with_download_client(download_client)
+        .repair(&target_prefix, &report, platform)
+        .await?;
+
+    println!(
+        "{} Reinstalled {} broken package(s)",
+        console::style(console::Emoji("✔", "")).green(),
+        report.broken_packages.len()
+    );
+    Ok(())
+}
@@ -38,6 +38,8 @@ struct Opt {
 #[derive(Debug, clap::Subcommand)]
 enum Command {
     Create(commands::create::Opt),
+    Clean(commands::clean::Opt),
+    Verify(commands::verify::Opt),
 }
 
 /// Entry point of the `rattler` cli.
@@ -70,5 +72,7 @@ async fn main() -> anyhow::Result<()> {
     // Dispatch the selected comment
     match opt.command {
         Command::Create(opts) => commands::create::create(opts).await,
+        Command::Clean(opts) => commands::clean::clean(opts).await,
+        Command::Verify(opts) => commands::verify::verify(opts).await,
     }
 }
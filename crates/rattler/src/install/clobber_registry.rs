@@ -9,6 +9,8 @@ use std::{
 use drop_bomb::DropBomb;
 use rattler_conda_types::{package::PathsJson, PackageName, PrefixRecord};
 
+use super::Journal;
+
 /// A registry for clobbering files
 /// The registry keeps track of all files that are installed by a package and
 /// can be used to rename files that are already installed by another package.
@@ -136,11 +138,13 @@ impl ClobberRegistry {
         clobber_paths
     }
 
-    /// Unclobber the paths after all installation steps have been completed.
+    /// Unclobber the paths after all installation steps have been completed. If a journal is
+    /// specified all renamed files and modified prefix records are recorded in it.
     pub fn unclobber(
         &mut self,
         sorted_prefix_records: &[&PrefixRecord],
         target_prefix: &Path,
+        journal: Option<&Journal>,
     ) -> Result<(), std::io::Error> {
         self.drop_bomb.defuse();
         let sorted_names = sorted_prefix_records
@@ -176,6 +180,9 @@ impl ClobberRegistry {
                     let loser_name = &clobbered_by_names[0];
                     let loser_path = Self::clobber_name(path, loser_name);
 
+                    if let Some(journal) = journal {
+                        journal.record_rename(path, &loser_path)?;
+                    }
                     fs::rename(target_prefix.join(path), target_prefix.join(&loser_path))?;
 
                     let loser_idx = sorted_clobbered_by
@@ -197,6 +204,11 @@ impl ClobberRegistry {
                         loser_name
                     );
 
+                    if let Some(journal) = journal {
+                        journal.record_modify(
+                            &Path::new("conda-meta").join(loser_prefix_record.file_name()),
+                        )?;
+                    }
                     loser_prefix_record
                         .write_to_path(conda_meta.join(loser_prefix_record.file_name()), true)?;
                 }
@@ -209,6 +221,9 @@ impl ClobberRegistry {
                     winner
                 );
 
+                if let Some(journal) = journal {
+                    journal.record_rename(&winner_path, path)?;
+                }
                 std::fs::rename(target_prefix.join(&winner_path), target_prefix.join(path))?;
 
                 let winner_prefix_record = rename_path_in_prefix_record(
@@ -217,6 +232,11 @@ impl ClobberRegistry {
                     path,
                     false,
                 );
+                if let Some(journal) = journal {
+                    journal.record_modify(
+                        &Path::new("conda-meta").join(winner_prefix_record.file_name()),
+                    )?;
+                }
                 winner_prefix_record
                     .write_to_path(conda_meta.join(winner_prefix_record.file_name()), true)?;
             }
@@ -403,7 +423,7 @@ mod tests {
         if let Some((record, package_dir)) = install_package {
             install_package_to_environment(
                 target_prefix,
-                package_dir,
+                package_dir.path().to_path_buf(),
                 record.clone(),
                 install_driver,
                 install_options,
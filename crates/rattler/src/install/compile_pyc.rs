@@ -0,0 +1,182 @@
+//! Compiles the Python source files of noarch python packages to bytecode.
+//!
+//! Noarch python packages do not ship bytecode because it depends on the version of python that is
+//! installed in the prefix. Instead of compiling the files of every package separately, all files
+//! are passed to a single invocation of `python -m compileall`.
+
+use super::{InstallError, PythonInfo};
+use std::{
+    ffi::OsString,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+/// Returns the location of the bytecode file that python writes for the source file at `py_path`.
+/// Since Python 3.2 bytecode is stored in a `__pycache__` directory next to the source file, older
+/// versions store it right next to the source file.
+pub fn pyc_path(py_path: &Path, python_info: &PythonInfo) -> PathBuf {
+    let (major, minor) = python_info.short_version;
+    if major < 3 {
+        return py_path.with_extension("pyc");
+    }
+
+    let mut file_name = py_path.file_stem().map(OsString::from).unwrap_or_default();
+    file_name.push(format!(".cpython-{major}{minor}.pyc"));
+    py_path
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join("__pycache__")
+        .join(file_name)
+}
+
+/// Compiles the given python source files, relative to the prefix, to bytecode using the python
+/// interpreter of the prefix. Returns the relative paths of the bytecode files that were written.
+///
+/// Files that fail to compile, for instance because they contain syntax that is not supported by
+/// the installed python version, are skipped. Only an interpreter that cannot be executed results
+/// in an error.
+pub fn compile_python_files(
+    target_prefix: &Path,
+    python_info: &PythonInfo,
+    files: &[PathBuf],
+) -> Result<Vec<PathBuf>, InstallError> {
+    if files.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // The files to compile are passed through stdin to avoid exceeding the maximum length of the
+    // command line.
+    let mut child = Command::new(target_prefix.join(python_info.path()))
+        .args(["-Wi", "-m", "compileall", "-q", "-l", "-i", "-"])
+        .current_dir(target_prefix)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(InstallError::FailedToCompilePython)?;
+
+    // Write the file list from another thread, python might fill up the stdout pipe before it has
+    // read all the files.
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let file_list = files
+        .iter()
+        .map(|path| format!("{}\n", path.display()))
+        .collect::<String>();
+    let writer = std::thread::spawn(move || stdin.write_all(file_list.as_bytes()));
+
+    let output = child
+        .wait_with_output()
+        .map_err(InstallError::FailedToCompilePython)?;
+    writer
+        .join()
+        .expect("writing to stdin does not panic")
+        .map_err(InstallError::FailedToCompilePython)?;
+
+    if !output.status.success() {
+        tracing::warn!(
+            "failed to compile some python files ({}):\n{}{}",
+            output.status,
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(files
+        .iter()
+        .map(|path| pyc_path(path, python_info))
+        .filter(|pyc_path| target_prefix.join(pyc_path).is_file())
+        .collect())
+}
+
+/// Links the `python3` of the system into the prefix as if it was installed there. Returns `None`
+/// if the system does not have a `python3`.
+#[cfg(all(test, unix))]
+pub(crate) fn link_system_python(target_prefix: &Path) -> Option<PythonInfo> {
+    let output = Command::new("python3")
+        .args([
+            "-c",
+            "import sys; print('%d.%d' % sys.version_info[:2]); print(sys.executable)",
+        ])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let output = String::from_utf8(output.stdout).ok()?;
+    let (version, executable) = output.trim().split_once('\n')?;
+
+    let version = version.parse::<rattler_conda_types::Version>().ok()?;
+    let python_info =
+        PythonInfo::from_version(&version, rattler_conda_types::Platform::current()).ok()?;
+    let python_path = target_prefix.join(python_info.path());
+    std::fs::create_dir_all(python_path.parent()?).ok()?;
+    std::os::unix::fs::symlink(executable, python_path).ok()?;
+    Some(python_info)
+}
+
+#[cfg(test)]
+mod test {
+    use super::pyc_path;
+    #[cfg(unix)]
+    use super::{compile_python_files, link_system_python};
+    use crate::install::PythonInfo;
+    use rattler_conda_types::{Platform, Version};
+    use std::path::Path;
+    #[cfg(unix)]
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_pyc_path() {
+        let python_info =
+            PythonInfo::from_version(&"3.10.4".parse::<Version>().unwrap(), Platform::Linux64)
+                .unwrap();
+        assert_eq!(
+            pyc_path(
+                Path::new("lib/python3.10/site-packages/foo/bar.py"),
+                &python_info
+            ),
+            Path::new("lib/python3.10/site-packages/foo/__pycache__/bar.cpython-310.pyc")
+        );
+
+        let python_info =
+            PythonInfo::from_version(&"2.7".parse::<Version>().unwrap(), Platform::Linux64)
+                .unwrap();
+        assert_eq!(
+            pyc_path(
+                Path::new("lib/python2.7/site-packages/foo.py"),
+                &python_info
+            ),
+            Path::new("lib/python2.7/site-packages/foo.pyc")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_compile_python_files() {
+        let target_prefix = tempfile::tempdir().unwrap();
+        let python_info =
+            link_system_python(target_prefix.path()).expect("python3 is required to run this test");
+
+        let site_packages = python_info.site_packages_path.clone();
+        let files: Vec<PathBuf> = vec![
+            site_packages.join("foo/__init__.py"),
+            site_packages.join("foo/bar.py"),
+            site_packages.join("broken.py"),
+        ];
+        std::fs::create_dir_all(target_prefix.path().join(site_packages.join("foo"))).unwrap();
+        std::fs::write(target_prefix.path().join(&files[0]), "").unwrap();
+        std::fs::write(target_prefix.path().join(&files[1]), "x = 1\n").unwrap();
+        std::fs::write(target_prefix.path().join(&files[2]), "def (:\n").unwrap();
+
+        let compiled = compile_python_files(target_prefix.path(), &python_info, &files).unwrap();
+        assert_eq!(
+            compiled,
+            vec![
+                pyc_path(&files[0], &python_info),
+                pyc_path(&files[1], &python_info)
+            ]
+        );
+        for path in &compiled {
+            assert!(target_prefix.path().join(path).is_file());
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use super::clobber_registry::ClobberRegistry;
-use super::InstallError;
+use super::{InstallError, Journal};
 use futures::stream::FuturesUnordered;
 use futures::{FutureExt, StreamExt};
 use rattler_conda_types::{PackageRecord, PrefixRecord};
@@ -23,6 +23,7 @@ pub struct InstallDriver {
     inner: Arc<Mutex<InstallDriverInner>>,
     concurrency_limit: usize,
     clobber_registry: Arc<Mutex<ClobberRegistry>>,
+    journal: Option<Arc<Journal>>,
 }
 
 struct InstallDriverInner {
@@ -89,9 +90,25 @@ impl InstallDriver {
             inner: Arc::new(Mutex::new(InstallDriverInner { tx, join_handle })),
             concurrency_limit,
             clobber_registry: Arc::new(Mutex::new(clobber_registry)),
+            journal: None,
         }
     }
 
+    /// Records all the changes made to the prefix through this driver in the given [`Journal`] so
+    /// they can be rolled back.
+    #[must_use]
+    pub fn with_journal(self, journal: Arc<Journal>) -> Self {
+        Self {
+            journal: Some(journal),
+            ..self
+        }
+    }
+
+    /// Returns the journal that records the changes made to the prefix, if any.
+    pub fn journal(&self) -> Option<&Arc<Journal>> {
+        self.journal.as_ref()
+    }
+
     /// Returns the number of tasks that can run in parallel.
     pub fn concurrency_limit(&self) -> usize {
         self.concurrency_limit
@@ -148,8 +165,8 @@ impl InstallDriver {
     /// Call this after all packages have been installed to perform any post processing that is
     /// required.
     ///
-    /// This function will select a winner among multiple packages that might write to a single package
-    /// and will also execute any `post-link.sh/bat` scripts
+    /// This function will select a winner among multiple packages that might write to a single
+    /// path. Link scripts are not executed, use [`super::run_link_script`] for that.
     pub fn post_process(
         &self,
         prefix_records: &[PrefixRecord],
@@ -159,7 +176,7 @@ impl InstallDriver {
             PackageRecord::sort_topologically(prefix_records.iter().collect::<Vec<_>>());
 
         self.clobber_registry()
-            .unclobber(&required_packages, target_prefix)
+            .unclobber(&required_packages, target_prefix, self.journal.as_deref())
             .map_err(InstallError::PostProcessFailed)?;
 
         Ok(())
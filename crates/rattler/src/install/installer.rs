@@ -0,0 +1,1439 @@
+//! Provides the [`Installer`] which executes a complete [`Transaction`] on a prefix.
+
+use super::unlink::UnlinkError;
+use super::{
+    compile_python_files, link_package, pyc_path, run_link_script, unlink_package, InstallDriver,
+    InstallError, InstallOptions, Journal, JournalError, LinkScriptMessage, LinkScriptType,
+    PythonInfo, Transaction, TransactionError,
+};
+use crate::{
+    package_cache::{CacheLock, DownloadReporter, PackageCache, PackageCacheError},
+    validation::PrefixValidationReport,
+};
+use futures::{stream, StreamExt, TryStreamExt};
+use rattler_conda_types::{
+    history::dist_str,
+    prefix_record::{PathType, PathsEntry},
+    History, HistoryChange, HistoryRevision, MatchSpec, PackageName, PackageRecord, Platform,
+    PrefixLock, PrefixLockError, PrefixLockMode, PrefixRecord, RepoDataRecord,
+};
+use rattler_networking::retry_policies::{default_retry_policy, RetryDecision, RetryPolicy};
+use reqwest_middleware::ClientWithMiddleware;
+use std::{
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Receives progress updates from an [`Installer`].
+///
+/// Operations are identified by their index in [`Transaction::operations`]. All methods have an
+/// implementation that does nothing, implementors only have to override the events they are
+/// interested in.
+pub trait Reporter: Send + Sync {
+    /// Called once before any of the operations of the transaction are executed.
+    fn on_transaction_start(&self, _transaction: &Transaction<PrefixRecord, RepoDataRecord>) {}
+
+    /// Called periodically while waiting for another process to release its lock on the prefix.
+    /// `waited` is the time spent waiting so far.
+    fn on_waiting_for_prefix_lock(&self, _waited: Duration) {}
+
+    /// Called when the package of an operation is not present in the package cache and is about
+    /// to be downloaded. If the download fails and is retried this is called again.
+    fn on_download_start(&self, _operation: usize, _record: &RepoDataRecord) {}
+
+    /// Called when bytes of a package have been downloaded. `bytes` is the total number of bytes
+    /// downloaded so far, `total` is the size of the package archive if it is known.
+    fn on_download_progress(&self, _operation: usize, _bytes: u64, _total: Option<u64>) {}
+
+    /// Called when a package has been downloaded completely.
+    fn on_download_complete(&self, _operation: usize) {}
+
+    /// Called when a package starts to be extracted into the package cache. Packages are
+    /// extracted after the downloaded archive has been verified, so this is called right after
+    /// [`Reporter::on_download_complete`].
+    fn on_extract_start(&self, _operation: usize) {}
+
+    /// Called when a package has been extracted into the package cache.
+    fn on_extract_complete(&self, _operation: usize) {}
+
+    /// Called when a package starts to be removed from the prefix.
+    fn on_unlink_start(&self, _operation: usize, _record: &PrefixRecord) {}
+
+    /// Called when a package has been removed from the prefix.
+    fn on_unlink_complete(&self, _operation: usize) {}
+
+    /// Called when a package starts to be linked into the prefix.
+    fn on_link_start(&self, _operation: usize, _record: &RepoDataRecord) {}
+
+    /// Called when a package has been linked into the prefix.
+    fn on_link_complete(&self, _operation: usize) {}
+
+    /// Called when all the steps of an operation have finished.
+    fn on_operation_complete(&self, _operation: usize) {}
+
+    /// Called once after all the operations of the transaction have been executed and the prefix
+    /// has been post-processed.
+    fn on_transaction_complete(&self) {}
+}
+
+/// An error that might occur while executing a transaction with an [`Installer`].
+#[derive(Debug, thiserror::Error)]
+pub enum InstallerError {
+    /// The prefix could not be locked, see [`Installer::with_lock_timeout`].
+    #[error("failed to lock the prefix")]
+    FailedToLockPrefix(#[source] PrefixLockError),
+
+    /// The records of the packages installed in the prefix could not be read.
+    #[error("failed to read the packages installed in the prefix")]
+    FailedToReadPrefix(#[source] io::Error),
+
+    /// A package could not be fetched into the package cache.
+    #[error("failed to fetch {0}")]
+    FailedToFetch(String, #[source] PackageCacheError),
+
+    /// A package could not be linked into the prefix.
+    #[error("failed to link {0}")]
+    LinkError(String, #[source] InstallError),
+
+    /// A package could not be removed from the prefix.
+    #[error("failed to unlink {0}")]
+    UnlinkError(String, #[source] UnlinkError),
+
+    /// The `conda-meta` file of a package could not be written.
+    #[error("failed to write the prefix record of {0}")]
+    FailedToWritePrefixRecord(String, #[source] io::Error),
+
+    /// The `conda-meta/history` file of the prefix could not be written.
+    #[error("failed to write the history of the prefix")]
+    FailedToWriteHistory(#[source] io::Error),
+
+    /// Post-processing of the prefix failed.
+    #[error("failed to post process the environment")]
+    PostProcessFailed(#[source] InstallError),
+
+    /// The python files of the noarch python packages could not be compiled to bytecode.
+    #[error("failed to compile the python files of the noarch packages")]
+    FailedToCompilePython(#[source] InstallError),
+
+    /// A link script of a package could not be executed or failed.
+    #[error(transparent)]
+    LinkScriptFailed(InstallError),
+
+    /// The journal that records the changes to the prefix could not be created or removed. This
+    /// is also returned when the prefix contains the journal of an interrupted transaction, see
+    /// [`Journal::recover`].
+    #[error(transparent)]
+    JournalError(JournalError),
+
+    /// The transaction failed and the changes to the prefix could not be rolled back. The journal
+    /// is left in the prefix, the rollback can be retried with [`Journal::recover`].
+    #[error("failed to roll back the changes to the prefix")]
+    RollbackFailed(#[source] JournalError),
+
+    /// The transaction to repair the prefix could not be constructed.
+    #[error("failed to determine the packages to repair")]
+    FailedToCreateTransaction(#[source] TransactionError),
+
+    /// The operation was cancelled.
+    #[error("the operation was cancelled")]
+    Cancelled,
+}
+
+/// The result of executing a transaction with an [`Installer`].
+#[derive(Debug, Default, Clone)]
+pub struct InstallationResult {
+    /// The messages that the link scripts of the packages left for the user. This is always empty
+    /// unless link scripts are executed, see [`Installer::with_execute_link_scripts`].
+    pub link_script_messages: Vec<LinkScriptMessage>,
+}
+
+/// Executes a [`Transaction`] on a prefix.
+///
+/// The installer downloads and extracts the packages that need to be installed into a
+/// [`PackageCache`], removes the packages that need to be removed, links the new packages into the
+/// prefix, writes their `conda-meta` files and finally post-processes the prefix. Multiple
+/// operations of the transaction are executed concurrently.
+///
+/// All changes to the prefix are recorded in a [`Journal`]. If the transaction fails the prefix is
+/// restored to the state it was in before the transaction. If the process is killed during the
+/// transaction the journal is left in the prefix and the next transaction on the prefix fails with
+/// [`JournalError::Interrupted`] until the prefix is restored with [`Journal::recover`].
+///
+/// Every transaction is recorded as a revision in the `conda-meta/history` file of the prefix, in
+/// the same format that conda uses, see [`History`].
+///
+/// An exclusive [`PrefixLock`] is held on the prefix while the transaction is executed, so multiple
+/// processes that modify the same prefix never interfere with each other.
+///
+/// ```rust,no_run
+/// # use std::path::Path;
+/// # use rattler::{install::{Installer, Transaction}, package_cache::PackageCache};
+/// # use rattler_conda_types::{PrefixRecord, RepoDataRecord};
+/// # async fn install(transaction: Transaction<PrefixRecord, RepoDataRecord>) {
+/// Installer::new(PackageCache::new("/tmp/pkgs"))
+///     .with_concurrency_limit(10)
+///     .execute(Path::new("/tmp/env"), transaction)
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+pub struct Installer {
+    package_cache: PackageCache,
+    download_client: Option<ClientWithMiddleware>,
+    concurrency_limit: usize,
+    retry_policy: Arc<dyn RetryPolicy + Send + Sync>,
+    reporter: Option<Arc<dyn Reporter>>,
+    execute_link_scripts: bool,
+    rollback_on_failure: bool,
+    lock_timeout: Option<Duration>,
+    compile_pyc: bool,
+    requested_specs: Vec<MatchSpec>,
+    removed_specs: Vec<MatchSpec>,
+}
+
+impl Installer {
+    /// Constructs a new [`Installer`] that stores the packages it downloads in the specified
+    /// package cache.
+    pub fn new(package_cache: PackageCache) -> Self {
+        Self {
+            package_cache,
+            download_client: None,
+            concurrency_limit: 50,
+            retry_policy: Arc::new(default_retry_policy()),
+            reporter: None,
+            execute_link_scripts: false,
+            rollback_on_failure: true,
+            lock_timeout: None,
+            compile_pyc: false,
+            requested_specs: Vec::new(),
+            removed_specs: Vec::new(),
+        }
+    }
+
+    /// Sets the client that is used to download packages. By default a client without any
+    /// middleware is used.
+    #[must_use]
+    pub fn with_download_client(self, download_client: ClientWithMiddleware) -> Self {
+        Self {
+            download_client: Some(download_client),
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of operations that are executed concurrently. Defaults to 50.
+    #[must_use]
+    pub fn with_concurrency_limit(self, concurrency_limit: usize) -> Self {
+        Self {
+            concurrency_limit,
+            ..self
+        }
+    }
+
+    /// Sets the policy that determines whether failed package downloads are retried. Defaults to
+    /// [`default_retry_policy`].
+    #[must_use]
+    pub fn with_retry_policy(self, retry_policy: impl RetryPolicy + Send + Sync + 'static) -> Self {
+        Self {
+            retry_policy: Arc::new(retry_policy),
+            ..self
+        }
+    }
+
+    /// Sets the [`Reporter`] that receives progress updates.
+    #[must_use]
+    pub fn with_reporter(self, reporter: Arc<dyn Reporter>) -> Self {
+        Self {
+            reporter: Some(reporter),
+            ..self
+        }
+    }
+
+    /// Sets whether the `pre-link`, `post-link` and `pre-unlink` scripts of packages are executed.
+    /// Defaults to `false`.
+    ///
+    /// Link scripts can execute arbitrary code, only enable this for packages you trust. The
+    /// messages that scripts leave for the user are returned in
+    /// [`InstallationResult::link_script_messages`].
+    #[must_use]
+    pub fn with_execute_link_scripts(self, execute_link_scripts: bool) -> Self {
+        Self {
+            execute_link_scripts,
+            ..self
+        }
+    }
+
+    /// Sets whether the changes to the prefix are recorded in a [`Journal`] and rolled back when
+    /// the transaction fails. Defaults to `true`.
+    ///
+    /// Disabling this avoids the overhead of the journal but leaves the prefix in an inconsistent
+    /// state when the transaction fails.
+    #[must_use]
+    pub fn with_rollback_on_failure(self, rollback_on_failure: bool) -> Self {
+        Self {
+            rollback_on_failure,
+            ..self
+        }
+    }
+
+    /// Sets how long to wait for another process to release its lock on the prefix before failing
+    /// with [`InstallerError::FailedToLockPrefix`]. By default the installer waits indefinitely.
+    /// While waiting [`Reporter::on_waiting_for_prefix_lock`] is called periodically.
+    #[must_use]
+    pub fn with_lock_timeout(self, lock_timeout: Duration) -> Self {
+        Self {
+            lock_timeout: Some(lock_timeout),
+            ..self
+        }
+    }
+
+    /// Sets whether the python files of noarch python packages are compiled to bytecode after they
+    /// have been linked. Defaults to `false`.
+    ///
+    /// The files of all the packages are compiled with a single invocation of the python
+    /// interpreter of the prefix. The bytecode files are recorded in the [`PrefixRecord`] of the
+    /// package so they are removed together with the package.
+    #[must_use]
+    pub fn with_compile_pyc(self, compile_pyc: bool) -> Self {
+        Self {
+            compile_pyc,
+            ..self
+        }
+    }
+
+    /// Sets the specs the user requested to install or update. They are recorded in the history of
+    /// the prefix as `update specs`.
+    #[must_use]
+    pub fn with_requested_specs(self, requested_specs: Vec<MatchSpec>) -> Self {
+        Self {
+            requested_specs,
+            ..self
+        }
+    }
+
+    /// Sets the specs the user requested to remove. They are recorded in the history of the prefix
+    /// as `remove specs`.
+    #[must_use]
+    pub fn with_removed_specs(self, removed_specs: Vec<MatchSpec>) -> Self {
+        Self {
+            removed_specs,
+            ..self
+        }
+    }
+
+    /// Executes all the operations of the transaction on the prefix at `target_prefix`.
+    ///
+    /// When link scripts are executed, the `pre-unlink` scripts of all the packages that are
+    /// removed run before any of the operations, the `pre-link` script of a package runs right
+    /// before it is linked and the `post-link` scripts run after the prefix has been
+    /// post-processed and the python files have been compiled. Scripts are executed one at a
+    /// time, `pre-unlink` scripts of dependents before those of their dependencies and
+    /// `post-link` scripts of dependencies before those of their dependents.
+    pub async fn execute(
+        &self,
+        target_prefix: &Path,
+        transaction: Transaction<PrefixRecord, RepoDataRecord>,
+    ) -> Result<InstallationResult, InstallerError> {
+        let reporter = self.reporter.as_deref();
+        if let Some(reporter) = reporter {
+            reporter.on_transaction_start(&transaction);
+        }
+
+        // Make sure no other process modifies or reads the prefix while it is being modified. The
+        // lock is released when it is dropped at the end of this function.
+        let lock = self
+            .lock_prefix(target_prefix, PrefixLockMode::Exclusive)
+            .await?;
+
+        // The driver needs to know about the files of the packages that are already installed to
+        // detect clobbering.
+        let installed_packages = collect_prefix_records(&lock).await?;
+        let mut install_driver = InstallDriver::new(100, Some(&installed_packages));
+
+        // Never modify a prefix that was left behind by an interrupted transaction.
+        let journal = if self.rollback_on_failure {
+            let journal =
+                Arc::new(Journal::create(target_prefix).map_err(InstallerError::JournalError)?);
+            install_driver = install_driver.with_journal(journal.clone());
+            Some(journal)
+        } else if Journal::is_interrupted(target_prefix) {
+            return Err(InstallerError::JournalError(JournalError::Interrupted(
+                Journal::path(target_prefix),
+            )));
+        } else {
+            None
+        };
+
+        let install_options = InstallOptions {
+            python_info: transaction.python_info.clone(),
+            platform: Some(transaction.platform),
+            ..InstallOptions::default()
+        };
+        let download_client = self
+            .download_client
+            .clone()
+            .unwrap_or_else(|| ClientWithMiddleware::from(reqwest::Client::new()));
+
+        let link_scripts = self.execute_link_scripts.then(|| LinkScripts {
+            install_driver: &install_driver,
+            target_prefix: target_prefix.to_path_buf(),
+            platform: transaction.platform,
+            messages: Arc::default(),
+        });
+        let records_to_install = transaction
+            .operations
+            .iter()
+            .filter_map(|operation| operation.record_to_install().cloned())
+            .collect::<Vec<_>>();
+        let history_revision = self.history_revision(&transaction);
+
+        let result = async {
+            // Run the pre-unlink scripts before anything is removed, dependents first.
+            if let Some(link_scripts) = &link_scripts {
+                let records_to_remove = PackageRecord::sort_topologically(
+                    transaction
+                        .operations
+                        .iter()
+                        .filter_map(|operation| operation.record_to_remove())
+                        .collect::<Vec<_>>(),
+                );
+                for record in records_to_remove.into_iter().rev() {
+                    link_scripts
+                        .run(
+                            LinkScriptType::PreUnlink,
+                            &record.repodata_record.package_record,
+                            target_prefix,
+                        )
+                        .await?;
+                }
+            }
+
+            self.execute_operations(
+                target_prefix,
+                transaction,
+                &install_driver,
+                &install_options,
+                &download_client,
+                link_scripts.as_ref(),
+            )
+            .await
+        }
+        .await;
+
+        // Perform any post processing that is required. This also has to happen when one of the
+        // operations failed to resolve the files clobbered by the packages that were linked.
+        let post_process_result = async {
+            let prefix_records = collect_prefix_records(&lock).await?;
+            install_driver
+                .post_process(&prefix_records, target_prefix)
+                .map_err(InstallerError::PostProcessFailed)
+        }
+        .await;
+
+        let result = async {
+            result?;
+            post_process_result?;
+
+            // Compile the python files before any post-link script can use them.
+            if let Some(python_info) = install_options
+                .python_info
+                .as_ref()
+                .filter(|_| self.compile_pyc)
+            {
+                let linked_packages = records_to_install
+                    .iter()
+                    .map(|record| record.package_record.name.clone())
+                    .collect();
+                compile_noarch_python_packages(
+                    target_prefix,
+                    &lock,
+                    &install_driver,
+                    python_info,
+                    linked_packages,
+                )
+                .await?;
+            }
+
+            // Run the post-link scripts of the new packages, dependencies first.
+            let mut link_script_messages = Vec::new();
+            if let Some(link_scripts) = link_scripts {
+                for record in PackageRecord::sort_topologically(records_to_install) {
+                    link_scripts
+                        .run(
+                            LinkScriptType::PostLink,
+                            &record.package_record,
+                            target_prefix,
+                        )
+                        .await?;
+                }
+                link_script_messages = std::mem::take(&mut *link_scripts.messages.lock().unwrap());
+            }
+
+            if let Some(history_revision) = history_revision {
+                append_history(target_prefix, &install_driver, history_revision).await?;
+            }
+            Ok(link_script_messages)
+        }
+        .await;
+
+        // Either keep the changes to the prefix or undo all of them.
+        if let Some(journal) = journal {
+            let succeeded = result.is_ok();
+            let journal_result = install_driver
+                .spawn_throttled(move || {
+                    Ok(if succeeded {
+                        journal.commit()
+                    } else {
+                        journal.rollback()
+                    })
+                })
+                .await
+                .map_err(|_err| InstallerError::Cancelled)?;
+            match (&result, journal_result) {
+                (Ok(_), Err(err)) => return Err(InstallerError::JournalError(err)),
+                (Err(err), Err(rollback_err)) => {
+                    tracing::error!("failed to roll back the transaction after: {err}");
+                    return Err(InstallerError::RollbackFailed(rollback_err));
+                }
+                (Err(err), Ok(())) => tracing::warn!("rolled back the transaction after: {err}"),
+                (Ok(_), Ok(())) => {}
+            }
+        }
+        let link_script_messages = result?;
+
+        if let Some(reporter) = reporter {
+            reporter.on_transaction_complete();
+        }
+
+        Ok(InstallationResult {
+            link_script_messages,
+        })
+    }
+
+    /// Executes the operations of the transaction concurrently.
+    async fn execute_operations(
+        &self,
+        target_prefix: &Path,
+        transaction: Transaction<PrefixRecord, RepoDataRecord>,
+        install_driver: &InstallDriver,
+        install_options: &InstallOptions,
+        download_client: &ClientWithMiddleware,
+        link_scripts: Option<&LinkScripts<'_>>,
+    ) -> Result<(), InstallerError> {
+        let reporter = self.reporter.as_deref();
+        stream::iter(transaction.operations.into_iter().enumerate())
+            .map(Ok)
+            .try_for_each_concurrent(self.concurrency_limit, |(idx, operation)| {
+                async move {
+                    // Remove the old package and fetch the new package at the same time.
+                    let unlink = async {
+                        match operation.record_to_remove() {
+                            Some(record) => {
+                                self.unlink(target_prefix, idx, record, install_driver)
+                                    .await
+                            }
+                            None => Ok(()),
+                        }
+                    };
+                    let fetch = async {
+                        match operation.record_to_install() {
+                            Some(record) => self
+                                .fetch(idx, record, download_client)
+                                .await
+                                .map(|package| Some((record, package))),
+                            None => Ok(None),
+                        }
+                    };
+                    let ((), fetched) = tokio::try_join!(unlink, fetch)?;
+
+                    // The package is kept locked in the cache until it has been linked.
+                    if let Some((record, package)) = fetched {
+                        self.link(
+                            target_prefix,
+                            idx,
+                            record.clone(),
+                            package.path().to_path_buf(),
+                            install_driver,
+                            install_options,
+                            link_scripts,
+                        )
+                        .await?;
+                        drop(package);
+                    }
+
+                    if let Some(reporter) = reporter {
+                        reporter.on_operation_complete(idx);
+                    }
+                    Ok(())
+                }
+            })
+            .await
+    }
+
+    /// Reinstalls the packages that [`crate::validation::validate_prefix`] reported as broken from
+    /// the package cache. Files that do not belong to any package are left untouched.
+    pub async fn repair(
+        &self,
+        target_prefix: &Path,
+        report: &PrefixValidationReport,
+        platform: Platform,
+    ) -> Result<InstallationResult, InstallerError> {
+        let lock = self
+            .lock_prefix(target_prefix, PrefixLockMode::Shared)
+            .await?;
+        let installed_packages = collect_prefix_records(&lock).await?;
+        drop(lock);
+
+        let transaction = Transaction::reinstall(
+            &installed_packages,
+            report
+                .broken_packages
+                .iter()
+                .map(|package| package.prefix_record.clone()),
+            platform,
+        )
+        .map_err(InstallerError::FailedToCreateTransaction)?;
+        self.execute(target_prefix, transaction).await
+    }
+
+    /// Constructs the revision that records the transaction in the history of the prefix. Returns
+    /// `None` if the transaction does not change the prefix.
+    fn history_revision(
+        &self,
+        transaction: &Transaction<PrefixRecord, RepoDataRecord>,
+    ) -> Option<HistoryRevision> {
+        if transaction.operations.is_empty() {
+            return None;
+        }
+
+        let mut unlinked = transaction
+            .operations
+            .iter()
+            .filter_map(|operation| operation.record_to_remove())
+            .map(|record| dist_str(&record.repodata_record))
+            .collect::<Vec<_>>();
+        let mut linked = transaction
+            .operations
+            .iter()
+            .filter_map(|operation| operation.record_to_install())
+            .map(dist_str)
+            .collect::<Vec<_>>();
+        unlinked.sort();
+        linked.sort();
+
+        let mut revision = HistoryRevision::new(chrono::Local::now().naive_local());
+        revision.command = Some(std::env::args().collect::<Vec<_>>().join(" "));
+        revision
+            .comments
+            .push(format!("rattler version: {}", env!("CARGO_PKG_VERSION")));
+        revision.changes = unlinked
+            .into_iter()
+            .map(HistoryChange::Unlinked)
+            .chain(linked.into_iter().map(HistoryChange::Linked))
+            .collect();
+        revision.update_specs = self
+            .requested_specs
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        revision.remove_specs = self.removed_specs.iter().map(ToString::to_string).collect();
+        Some(revision)
+    }
+
+    /// Acquires a lock on the prefix, waiting at most [`Installer::with_lock_timeout`].
+    async fn lock_prefix(
+        &self,
+        target_prefix: &Path,
+        mode: PrefixLockMode,
+    ) -> Result<Arc<PrefixLock>, InstallerError> {
+        let target_prefix = target_prefix.to_path_buf();
+        let lock_timeout = self.lock_timeout;
+        let reporter = self.reporter.clone();
+        run_blocking(move || {
+            PrefixLock::acquire(&target_prefix, mode, lock_timeout, |waited| {
+                if let Some(reporter) = &reporter {
+                    reporter.on_waiting_for_prefix_lock(waited);
+                }
+            })
+            .map(Arc::new)
+            .map_err(InstallerError::FailedToLockPrefix)
+        })
+        .await
+    }
+
+    /// Removes a package from the prefix.
+    async fn unlink(
+        &self,
+        target_prefix: &Path,
+        operation: usize,
+        record: &PrefixRecord,
+        install_driver: &InstallDriver,
+    ) -> Result<(), InstallerError> {
+        if let Some(reporter) = &self.reporter {
+            reporter.on_unlink_start(operation, record);
+        }
+
+        // Move the files of the package into the journal so they can be restored.
+        if let Some(journal) = install_driver.journal().cloned() {
+            let paths = record
+                .paths_data
+                .paths
+                .iter()
+                .map(|entry| entry.relative_path.clone())
+                .collect::<Vec<_>>();
+            let conda_meta_path = Path::new("conda-meta").join(record.file_name());
+            install_driver
+                .spawn_throttled(move || {
+                    Ok(paths
+                        .iter()
+                        .try_for_each(|path| {
+                            journal.remove(path).map_err(|err| {
+                                UnlinkError::FailedToDeleteFile(
+                                    path.to_string_lossy().to_string(),
+                                    err,
+                                )
+                            })
+                        })
+                        .and_then(|()| {
+                            journal.record_modify(&conda_meta_path).map_err(|err| {
+                                UnlinkError::FailedToDeleteFile(
+                                    conda_meta_path.to_string_lossy().to_string(),
+                                    err,
+                                )
+                            })
+                        }))
+                })
+                .await
+                .map_err(|_err| InstallerError::Cancelled)?
+                .map_err(|err| InstallerError::UnlinkError(record.file_name(), err))?;
+        }
+
+        unlink_package(target_prefix, record)
+            .await
+            .map_err(|err| InstallerError::UnlinkError(record.file_name(), err))?;
+
+        if let Some(reporter) = &self.reporter {
+            reporter.on_unlink_complete(operation);
+        }
+        Ok(())
+    }
+
+    /// Makes sure the package is available in the package cache and returns the locked directory
+    /// that contains the extracted package. The package is not removed from the cache while the
+    /// returned lock is alive.
+    async fn fetch(
+        &self,
+        operation: usize,
+        record: &RepoDataRecord,
+        download_client: &ClientWithMiddleware,
+    ) -> Result<CacheLock, InstallerError> {
+        let reporter = self.reporter.clone().map(|reporter| {
+            Arc::new(OperationDownloadReporter {
+                reporter,
+                operation,
+                record: record.clone(),
+            }) as Arc<dyn DownloadReporter>
+        });
+        self.package_cache
+            .get_or_fetch_from_url_with_reporter(
+                &record.package_record,
+                record.url.clone(),
+                download_client.clone(),
+                SharedRetryPolicy(self.retry_policy.clone()),
+                reporter,
+            )
+            .await
+            .map_err(|err| InstallerError::FailedToFetch(record.file_name.clone(), err))
+    }
+
+    /// Links an extracted package into the prefix and writes its `conda-meta` file.
+    async fn link(
+        &self,
+        target_prefix: &Path,
+        operation: usize,
+        repodata_record: RepoDataRecord,
+        package_dir: PathBuf,
+        install_driver: &InstallDriver,
+        install_options: &InstallOptions,
+        link_scripts: Option<&LinkScripts<'_>>,
+    ) -> Result<(), InstallerError> {
+        if let Some(reporter) = &self.reporter {
+            reporter.on_link_start(operation, &repodata_record);
+        }
+
+        // The package is not part of the prefix yet so its pre-link script is executed from the
+        // package cache.
+        if let Some(link_scripts) = link_scripts {
+            link_scripts
+                .run(
+                    LinkScriptType::PreLink,
+                    &repodata_record.package_record,
+                    &package_dir,
+                )
+                .await?;
+        }
+
+        let paths = link_package(
+            &package_dir,
+            target_prefix,
+            install_driver,
+            install_options.clone(),
+        )
+        .await
+        .map_err(|err| InstallerError::LinkError(repodata_record.file_name.clone(), err))?;
+
+        let prefix_record = PrefixRecord {
+            repodata_record,
+            package_tarball_full_path: None,
+            extracted_package_dir: Some(package_dir),
+            files: paths
+                .iter()
+                .map(|entry| entry.relative_path.clone())
+                .collect(),
+            paths_data: paths.into(),
+            requested_spec: None,
+            link: None,
+        };
+
+        let conda_meta_path = target_prefix.join("conda-meta");
+        let journal = install_driver.journal().cloned();
+        match tokio::task::spawn_blocking(move || {
+            std::fs::create_dir_all(&conda_meta_path)
+                .and_then(|()| match &journal {
+                    Some(journal) => journal
+                        .record_create(&Path::new("conda-meta").join(prefix_record.file_name())),
+                    None => Ok(()),
+                })
+                .and_then(|()| {
+                    prefix_record
+                        .write_to_path(conda_meta_path.join(prefix_record.file_name()), true)
+                })
+                .map_err(|err| {
+                    InstallerError::FailedToWritePrefixRecord(prefix_record.file_name(), err)
+                })
+        })
+        .await
+        {
+            Ok(result) => result?,
+            Err(err) => {
+                if let Ok(panic) = err.try_into_panic() {
+                    std::panic::resume_unwind(panic);
+                }
+                return Err(InstallerError::Cancelled);
+            }
+        }
+
+        if let Some(reporter) = &self.reporter {
+            reporter.on_link_complete(operation);
+        }
+        Ok(())
+    }
+}
+
+/// Executes the link scripts of packages while a transaction is executed.
+struct LinkScripts<'a> {
+    install_driver: &'a InstallDriver,
+    target_prefix: PathBuf,
+    platform: Platform,
+    messages: Arc<Mutex<Vec<LinkScriptMessage>>>,
+}
+
+impl LinkScripts<'_> {
+    /// Executes the script of the given type of a package, if it has one. Scripts are executed one
+    /// at a time because they all write their messages to the same file in the prefix.
+    async fn run(
+        &self,
+        script_type: LinkScriptType,
+        package_record: &PackageRecord,
+        script_dir: &Path,
+    ) -> Result<(), InstallerError> {
+        let messages = self.messages.clone();
+        let package_record = package_record.clone();
+        let script_dir = script_dir.to_path_buf();
+        let target_prefix = self.target_prefix.clone();
+        let platform = self.platform;
+        self.install_driver
+            .spawn_throttled(move || {
+                let mut messages = messages.lock().unwrap();
+                if let Some(message) = run_link_script(
+                    script_type,
+                    &package_record,
+                    &script_dir,
+                    &target_prefix,
+                    platform,
+                )? {
+                    messages.push(message);
+                }
+                Ok(())
+            })
+            .await
+            .map_err(InstallerError::LinkScriptFailed)
+    }
+}
+
+/// Appends a revision to the history of the prefix.
+async fn append_history(
+    target_prefix: &Path,
+    install_driver: &InstallDriver,
+    revision: HistoryRevision,
+) -> Result<(), InstallerError> {
+    let target_prefix = target_prefix.to_path_buf();
+    let journal = install_driver.journal().cloned();
+    run_blocking(move || {
+        let path = History::path(&target_prefix);
+        let relative_path = Path::new("conda-meta/history");
+        match &journal {
+            Some(journal) if path.exists() => journal.record_modify(relative_path),
+            Some(journal) => journal.record_create(relative_path),
+            None => Ok(()),
+        }
+        .and_then(|()| revision.append_to_path(&path))
+        .map_err(InstallerError::FailedToWriteHistory)
+    })
+    .await
+}
+
+/// Compiles the python files of the noarch python packages that were linked into the prefix and
+/// adds the bytecode files to the records of the packages.
+async fn compile_noarch_python_packages(
+    target_prefix: &Path,
+    lock: &Arc<PrefixLock>,
+    install_driver: &InstallDriver,
+    python_info: &PythonInfo,
+    linked_packages: HashSet<PackageName>,
+) -> Result<(), InstallerError> {
+    // The records are read again because post-processing might have renamed clobbered files.
+    let mut records = collect_prefix_records(lock)
+        .await?
+        .into_iter()
+        .filter(|record| {
+            let package_record = &record.repodata_record.package_record;
+            package_record.noarch.is_python() && linked_packages.contains(&package_record.name)
+        })
+        .collect::<Vec<_>>();
+    if records.is_empty() {
+        return Ok(());
+    }
+
+    let python_files = records
+        .iter()
+        .flat_map(|record| source_files(record, python_info))
+        .collect::<Vec<_>>();
+    let compiled = {
+        let target_prefix = target_prefix.to_path_buf();
+        let python_info = python_info.clone();
+        let journal = install_driver.journal().cloned();
+        install_driver
+            .spawn_throttled(move || {
+                if let Some(journal) = journal {
+                    for path in &python_files {
+                        journal
+                            .record_create(&pyc_path(path, &python_info))
+                            .map_err(InstallError::FailedToWriteJournal)?;
+                    }
+                }
+                compile_python_files(&target_prefix, &python_info, &python_files)
+            })
+            .await
+            .map_err(InstallerError::FailedToCompilePython)?
+            .into_iter()
+            .collect::<HashSet<_>>()
+    };
+
+    // Record the bytecode files in the records of the packages.
+    for record in &mut records {
+        let entries = source_files(record, python_info)
+            .map(|path| pyc_path(&path, python_info))
+            .filter(|path| compiled.contains(path))
+            .map(|path| PathsEntry {
+                relative_path: path,
+                original_path: None,
+                path_type: PathType::PycFile,
+                no_link: false,
+                sha256: None,
+                sha256_in_prefix: None,
+                size_in_bytes: None,
+                prefix_placeholder: None,
+                file_mode: None,
+            })
+            .collect::<Vec<_>>();
+        record
+            .files
+            .extend(entries.iter().map(|entry| entry.relative_path.clone()));
+        record.paths_data.paths.extend(entries);
+    }
+
+    let target_prefix = target_prefix.to_path_buf();
+    let journal = install_driver.journal().cloned();
+    run_blocking(move || {
+        for record in records {
+            let relative_path = Path::new("conda-meta").join(record.file_name());
+            journal
+                .as_ref()
+                .map_or(Ok(()), |journal| journal.record_modify(&relative_path))
+                .and_then(|()| record.write_to_path(target_prefix.join(&relative_path), true))
+                .map_err(|err| {
+                    InstallerError::FailedToWritePrefixRecord(record.file_name(), err)
+                })?;
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// Returns the python source files of a noarch python package that are compiled to bytecode.
+fn source_files<'a>(
+    record: &'a PrefixRecord,
+    python_info: &'a PythonInfo,
+) -> impl Iterator<Item = PathBuf> + 'a {
+    record
+        .paths_data
+        .paths
+        .iter()
+        .filter(|entry| {
+            entry.path_type != PathType::Directory
+                && entry
+                    .relative_path
+                    .starts_with(&python_info.site_packages_path)
+                && entry
+                    .relative_path
+                    .extension()
+                    .map_or(false, |extension| extension == "py")
+        })
+        .map(|entry| entry.relative_path.clone())
+}
+
+/// Reads all the [`PrefixRecord`]s of the packages installed in the locked prefix.
+async fn collect_prefix_records(
+    lock: &Arc<PrefixLock>,
+) -> Result<Vec<PrefixRecord>, InstallerError> {
+    let lock = lock.clone();
+    run_blocking(move || {
+        PrefixRecord::collect_from_locked_prefix(&lock).map_err(InstallerError::FailedToReadPrefix)
+    })
+    .await
+}
+
+/// Runs a blocking function on the blocking thread pool of tokio.
+async fn run_blocking<T: Send + 'static>(
+    f: impl FnOnce() -> Result<T, InstallerError> + Send + 'static,
+) -> Result<T, InstallerError> {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(err) => {
+            if let Ok(panic) = err.try_into_panic() {
+                std::panic::resume_unwind(panic);
+            }
+            Err(InstallerError::Cancelled)
+        }
+    }
+}
+
+/// Forwards the progress of fetching the package of an operation to the [`Reporter`] of the
+/// [`Installer`].
+struct OperationDownloadReporter {
+    reporter: Arc<dyn Reporter>,
+    operation: usize,
+    record: RepoDataRecord,
+}
+
+impl DownloadReporter for OperationDownloadReporter {
+    fn on_download_start(&self) {
+        self.reporter
+            .on_download_start(self.operation, &self.record);
+    }
+
+    fn on_download_progress(&self, bytes: u64, total: Option<u64>) {
+        self.reporter
+            .on_download_progress(self.operation, bytes, total);
+    }
+
+    fn on_download_complete(&self) {
+        self.reporter.on_download_complete(self.operation);
+    }
+
+    fn on_extract_start(&self) {
+        self.reporter.on_extract_start(self.operation);
+    }
+
+    fn on_extract_complete(&self) {
+        self.reporter.on_extract_complete(self.operation);
+    }
+}
+
+/// Allows the retry policy of the [`Installer`] to be used for all the packages it fetches.
+struct SharedRetryPolicy(Arc<dyn RetryPolicy + Send + Sync>);
+
+impl RetryPolicy for SharedRetryPolicy {
+    fn should_retry(&self, n_past_retries: u32) -> RetryDecision {
+        self.0.should_retry(n_past_retries)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Installer, InstallerError, Reporter};
+    use crate::{
+        get_repodata_record,
+        install::{Journal, Transaction, TransactionOperation},
+        package_cache::PackageCache,
+    };
+    use assert_matches::assert_matches;
+    use rattler_conda_types::{
+        history::dist_str, prefix_record::PathType, History, HistoryChange, MatchSpec, PackageName,
+        Platform, PrefixLock, PrefixLockError, PrefixRecord, RepoDataRecord,
+    };
+    use std::{
+        str::FromStr,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    /// Records the events that are reported, except for the download progress.
+    #[derive(Default)]
+    struct EventRecorder(Mutex<Vec<String>>);
+
+    impl EventRecorder {
+        fn push(&self, event: impl Into<String>) {
+            self.0.lock().unwrap().push(event.into());
+        }
+
+        fn take(&self) -> Vec<String> {
+            std::mem::take(&mut self.0.lock().unwrap())
+        }
+    }
+
+    impl Reporter for EventRecorder {
+        fn on_waiting_for_prefix_lock(&self, _waited: Duration) {
+            self.push("waiting_for_prefix_lock");
+        }
+
+        fn on_download_start(&self, _operation: usize, _record: &RepoDataRecord) {
+            self.push("download_start");
+        }
+
+        fn on_download_complete(&self, _operation: usize) {
+            self.push("download_complete");
+        }
+
+        fn on_extract_start(&self, _operation: usize) {
+            self.push("extract_start");
+        }
+
+        fn on_extract_complete(&self, _operation: usize) {
+            self.push("extract_complete");
+        }
+
+        fn on_unlink_start(&self, _operation: usize, _record: &PrefixRecord) {
+            self.push("unlink_start");
+        }
+
+        fn on_unlink_complete(&self, _operation: usize) {
+            self.push("unlink_complete");
+        }
+
+        fn on_link_start(&self, _operation: usize, _record: &RepoDataRecord) {
+            self.push("link_start");
+        }
+
+        fn on_link_complete(&self, _operation: usize) {
+            self.push("link_complete");
+        }
+
+        fn on_transaction_complete(&self) {
+            self.push("transaction_complete");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_install_and_remove() {
+        let target_prefix = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let record = get_repodata_record("ruff-0.0.171-py310h298983d_0.conda");
+
+        let reporter = Arc::new(EventRecorder::default());
+        let installer =
+            Installer::new(PackageCache::new(cache_dir.path())).with_reporter(reporter.clone());
+
+        // Install the package
+        let transaction = Transaction::from_current_and_desired(
+            Vec::<PrefixRecord>::new(),
+            vec![record],
+            Platform::current(),
+        )
+        .unwrap();
+        installer
+            .execute(target_prefix.path(), transaction)
+            .await
+            .unwrap();
+
+        let installed = PrefixRecord::collect_from_prefix(target_prefix.path()).unwrap();
+        assert_eq!(installed.len(), 1);
+        for path in &installed[0].files {
+            assert!(target_prefix.path().join(path).exists());
+        }
+        assert_eq!(
+            reporter.take(),
+            [
+                "download_start",
+                "download_complete",
+                "extract_start",
+                "extract_complete",
+                "link_start",
+                "link_complete",
+                "transaction_complete"
+            ]
+        );
+
+        // Remove the package again
+        let files = installed[0].files.clone();
+        let transaction = Transaction::from_current_and_desired(
+            installed,
+            Vec::<RepoDataRecord>::new(),
+            Platform::current(),
+        )
+        .unwrap();
+        installer
+            .execute(target_prefix.path(), transaction)
+            .await
+            .unwrap();
+
+        assert!(PrefixRecord::collect_from_prefix(target_prefix.path())
+            .unwrap()
+            .is_empty());
+        for path in &files {
+            assert!(!target_prefix.path().join(path).exists());
+        }
+        assert_eq!(
+            reporter.take(),
+            ["unlink_start", "unlink_complete", "transaction_complete"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rollback_on_failure() {
+        let target_prefix = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let record = get_repodata_record("ruff-0.0.171-py310h298983d_0.conda");
+        let installer = Installer::new(PackageCache::new(cache_dir.path()));
+
+        let transaction = Transaction::from_current_and_desired(
+            Vec::<PrefixRecord>::new(),
+            vec![record.clone()],
+            Platform::current(),
+        )
+        .unwrap();
+        installer
+            .execute(target_prefix.path(), transaction)
+            .await
+            .unwrap();
+        let installed = PrefixRecord::collect_from_prefix(target_prefix.path()).unwrap();
+
+        // Replace the package with a package that cannot be fetched.
+        let mut broken = record;
+        broken.package_record.name = PackageName::new_unchecked("broken");
+        broken.file_name = String::from("broken-0.0.171-py310h298983d_0.zip");
+        broken.url = broken.url.join(&broken.file_name).unwrap();
+        let transaction = Transaction::from_current_and_desired(
+            installed.clone(),
+            vec![broken],
+            Platform::current(),
+        )
+        .unwrap();
+        assert_matches!(
+            installer.execute(target_prefix.path(), transaction).await,
+            Err(InstallerError::FailedToFetch(..))
+        );
+
+        // The prefix must be left untouched.
+        let after = PrefixRecord::collect_from_prefix(target_prefix.path()).unwrap();
+        assert_eq!(after.len(), 1);
+        assert_eq!(after[0].file_name(), installed[0].file_name());
+        for path in &installed[0].files {
+            assert!(target_prefix.path().join(path).exists());
+        }
+        assert!(!Journal::is_interrupted(target_prefix.path()));
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_prefix_lock() {
+        let target_prefix = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let record = get_repodata_record("ruff-0.0.171-py310h298983d_0.conda");
+
+        let reporter = Arc::new(EventRecorder::default());
+        let installer = Installer::new(PackageCache::new(cache_dir.path()))
+            .with_reporter(reporter.clone())
+            .with_lock_timeout(Duration::from_millis(200));
+        let transaction = || {
+            Transaction::from_current_and_desired(
+                Vec::<PrefixRecord>::new(),
+                vec![record.clone()],
+                Platform::current(),
+            )
+            .unwrap()
+        };
+
+        // While another process holds the lock the installer waits and eventually gives up.
+        let lock = PrefixLock::exclusive(target_prefix.path()).unwrap();
+        assert_matches!(
+            installer.execute(target_prefix.path(), transaction()).await,
+            Err(InstallerError::FailedToLockPrefix(
+                PrefixLockError::Timeout(..)
+            ))
+        );
+        assert!(reporter
+            .take()
+            .iter()
+            .any(|event| event == "waiting_for_prefix_lock"));
+        assert!(!Journal::is_interrupted(target_prefix.path()));
+
+        // Once the lock is released the transaction can be executed.
+        drop(lock);
+        installer
+            .execute(target_prefix.path(), transaction())
+            .await
+            .unwrap();
+        assert_eq!(
+            PrefixRecord::collect_from_prefix(target_prefix.path())
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_compile_pyc() {
+        let target_prefix = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let python_info = crate::install::compile_pyc::link_system_python(target_prefix.path())
+            .expect("python3 is required to run this test");
+        let installer = Installer::new(PackageCache::new(cache_dir.path())).with_compile_pyc(true);
+
+        let record = get_repodata_record("pytweening-1.0.4-pyhd8ed1ab_0.tar.bz2");
+        let transaction = Transaction {
+            operations: vec![TransactionOperation::Install(record)],
+            python_info: Some(python_info.clone()),
+            current_python_info: None,
+            platform: Platform::current(),
+        };
+        installer
+            .execute(target_prefix.path(), transaction)
+            .await
+            .unwrap();
+
+        // The bytecode files are part of the package.
+        let installed = PrefixRecord::collect_from_prefix(target_prefix.path()).unwrap();
+        let pyc_files = installed[0]
+            .paths_data
+            .paths
+            .iter()
+            .filter(|entry| entry.path_type == PathType::PycFile)
+            .map(|entry| entry.relative_path.clone())
+            .collect::<Vec<_>>();
+        assert!(!pyc_files.is_empty());
+        for path in &pyc_files {
+            assert!(installed[0].files.contains(path));
+            assert!(target_prefix.path().join(path).is_file());
+        }
+
+        // Removing the package also removes the bytecode files and the `__pycache__` directories.
+        let transaction = Transaction::from_current_and_desired(
+            installed,
+            Vec::<RepoDataRecord>::new(),
+            Platform::current(),
+        )
+        .unwrap();
+        installer
+            .execute(target_prefix.path(), transaction)
+            .await
+            .unwrap();
+        for path in &pyc_files {
+            assert!(!target_prefix.path().join(path).parent().unwrap().exists());
+        }
+        assert!(!target_prefix
+            .path()
+            .join(&python_info.site_packages_path)
+            .exists());
+    }
+
+    #[tokio::test]
+    async fn test_history() {
+        let target_prefix = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let record = get_repodata_record("ruff-0.0.171-py310h298983d_0.conda");
+        let installer = Installer::new(PackageCache::new(cache_dir.path()))
+            .with_requested_specs(vec![MatchSpec::from_str("ruff").unwrap()]);
+
+        // Install and remove the package
+        let transaction = Transaction::from_current_and_desired(
+            Vec::<PrefixRecord>::new(),
+            vec![record.clone()],
+            Platform::current(),
+        )
+        .unwrap();
+        installer
+            .execute(target_prefix.path(), transaction)
+            .await
+            .unwrap();
+        let transaction = Transaction::from_current_and_desired(
+            PrefixRecord::collect_from_prefix(target_prefix.path()).unwrap(),
+            Vec::<RepoDataRecord>::new(),
+            Platform::current(),
+        )
+        .unwrap();
+        installer
+            .with_requested_specs(Vec::new())
+            .with_removed_specs(vec![MatchSpec::from_str("ruff").unwrap()])
+            .execute(target_prefix.path(), transaction)
+            .await
+            .unwrap();
+
+        let history = History::from_prefix(target_prefix.path()).unwrap();
+        assert_eq!(history.revisions.len(), 2);
+        assert_eq!(
+            history.revisions[0].changes,
+            [HistoryChange::Linked(dist_str(&record))]
+        );
+        assert_eq!(history.revisions[0].update_specs, ["ruff"]);
+        assert_eq!(
+            history.revisions[1].changes,
+            [HistoryChange::Unlinked(dist_str(&record))]
+        );
+        assert_eq!(history.revisions[1].remove_specs, ["ruff"]);
+
+        // Go back to the first revision
+        let transaction = Transaction::to_revision(
+            &history,
+            0,
+            PrefixRecord::collect_from_prefix(target_prefix.path()).unwrap(),
+            vec![record.clone()],
+            Platform::current(),
+        )
+        .unwrap();
+        assert_matches!(
+            transaction.operations.as_slice(),
+            [TransactionOperation::Install(installed)] if installed == &record
+        );
+        Installer::new(PackageCache::new(cache_dir.path()))
+            .execute(target_prefix.path(), transaction)
+            .await
+            .unwrap();
+        assert_eq!(
+            PrefixRecord::collect_from_prefix(target_prefix.path())
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            History::from_prefix(target_prefix.path())
+                .unwrap()
+                .revisions
+                .len(),
+            3
+        );
+    }
+}
@@ -0,0 +1,444 @@
+//! Implements a journal of the changes made to a prefix to be able to roll them back.
+//!
+//! Before a file in the prefix is created, removed, modified or renamed the change is appended to
+//! the journal. Files that are removed or modified are moved or copied to a backup directory next
+//! to the journal. If a transaction fails the changes are undone in reverse order with
+//! [`Journal::rollback`]. If the process is killed while a transaction is executed the journal
+//! remains on disk, [`Journal::is_interrupted`] detects this and [`Journal::recover`] restores the
+//! previous state of the prefix.
+//!
+//! Entries are written to the journal before the change is made, but the journal is not synced to
+//! disk for every entry. This protects against crashes of the process, not against a power loss.
+
+use rattler_conda_types::{PrefixLock, PrefixLockError};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+/// The directory, relative to the prefix, that contains the journal.
+const JOURNAL_DIR: &str = "conda-meta/.rattler-journal";
+
+/// The name of the file in the journal directory that contains the entries.
+const JOURNAL_FILE: &str = "journal.jsonl";
+
+/// The name of the directory in the journal directory that contains the backups.
+const BACKUP_DIR: &str = "backup";
+
+/// An error that can occur while writing or replaying a [`Journal`].
+#[derive(Debug, thiserror::Error)]
+pub enum JournalError {
+    /// The prefix already contains the journal of a transaction that did not complete.
+    #[error("the prefix contains the journal of an interrupted transaction at '{0}', recover the prefix before modifying it")]
+    Interrupted(PathBuf),
+
+    /// The journal could not be created.
+    #[error("failed to create the journal")]
+    FailedToCreate(#[source] std::io::Error),
+
+    /// The journal could not be read.
+    #[error("failed to read the journal")]
+    FailedToRead(#[source] std::io::Error),
+
+    /// A change to the prefix could not be undone.
+    #[error("failed to restore '{0}'")]
+    FailedToRestore(PathBuf, #[source] std::io::Error),
+
+    /// The prefix could not be locked to recover it.
+    #[error("failed to lock the prefix")]
+    FailedToLockPrefix(#[source] PrefixLockError),
+
+    /// The journal could not be removed after the transaction completed or was rolled back.
+    #[error("failed to remove the journal")]
+    FailedToRemove(#[source] std::io::Error),
+}
+
+/// A single change to the prefix. All paths are relative to the prefix.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum JournalEntry {
+    /// A file is created at `path`.
+    Created { path: PathBuf },
+
+    /// The file at `path` is moved or copied to `backup`.
+    BackedUp { path: PathBuf, backup: PathBuf },
+
+    /// The file at `from` is renamed to `to`.
+    Renamed { from: PathBuf, to: PathBuf },
+}
+
+/// A journal of the changes made to a prefix during a transaction.
+///
+/// The journal is shared between all the tasks that modify the prefix, all methods take `&self`.
+#[derive(Debug)]
+pub struct Journal {
+    target_prefix: PathBuf,
+    state: Mutex<JournalState>,
+}
+
+#[derive(Debug)]
+struct JournalState {
+    file: File,
+    next_backup: usize,
+    closed: bool,
+}
+
+impl Journal {
+    /// Starts a new journal for the given prefix.
+    ///
+    /// Returns [`JournalError::Interrupted`] if the prefix already contains a journal.
+    pub fn create(target_prefix: &Path) -> Result<Self, JournalError> {
+        let journal_dir = Self::path(target_prefix);
+        if Self::is_interrupted(target_prefix) {
+            return Err(JournalError::Interrupted(journal_dir));
+        }
+
+        std::fs::create_dir_all(journal_dir.join(BACKUP_DIR))
+            .map_err(JournalError::FailedToCreate)?;
+        let file = OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .open(journal_dir.join(JOURNAL_FILE))
+            .map_err(JournalError::FailedToCreate)?;
+
+        Ok(Self {
+            target_prefix: target_prefix.to_path_buf(),
+            state: Mutex::new(JournalState {
+                file,
+                next_backup: 0,
+                closed: false,
+            }),
+        })
+    }
+
+    /// Returns the location of the journal of the given prefix.
+    pub fn path(target_prefix: &Path) -> PathBuf {
+        target_prefix.join(JOURNAL_DIR)
+    }
+
+    /// Returns true if the prefix contains the journal of a transaction that did not complete.
+    pub fn is_interrupted(target_prefix: &Path) -> bool {
+        target_prefix.join(JOURNAL_DIR).exists()
+    }
+
+    /// Rolls back the changes of an interrupted transaction. Returns `false` if the prefix does not
+    /// contain the journal of an interrupted transaction.
+    ///
+    /// An exclusive [`PrefixLock`] is acquired on the prefix while it is being recovered.
+    pub fn recover(target_prefix: &Path) -> Result<bool, JournalError> {
+        if !Self::is_interrupted(target_prefix) {
+            return Ok(false);
+        }
+
+        // Another process might still be executing the transaction, or might be recovering the
+        // prefix already.
+        let _lock =
+            PrefixLock::exclusive(target_prefix).map_err(JournalError::FailedToLockPrefix)?;
+        if !Self::is_interrupted(target_prefix) {
+            return Ok(false);
+        }
+
+        let journal_dir = target_prefix.join(JOURNAL_DIR);
+        let entries = match File::open(journal_dir.join(JOURNAL_FILE)) {
+            Ok(file) => read_entries(file)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(JournalError::FailedToRead(err)),
+        };
+        undo(target_prefix, &entries)?;
+
+        std::fs::remove_dir_all(journal_dir).map_err(JournalError::FailedToRemove)?;
+        Ok(true)
+    }
+
+    /// Records that a file is about to be created at `relative_path`. If a file already exists at
+    /// that location it is moved to the backup directory first.
+    pub fn record_create(&self, relative_path: &Path) -> std::io::Result<()> {
+        let mut state = self.lock_open()?;
+        self.backup(&mut state, relative_path, false)?;
+        state.append(&JournalEntry::Created {
+            path: relative_path.to_path_buf(),
+        })
+    }
+
+    /// Removes the file at `relative_path` by moving it to the backup directory. Does nothing if
+    /// the file does not exist.
+    pub fn remove(&self, relative_path: &Path) -> std::io::Result<()> {
+        let mut state = self.lock_open()?;
+        self.backup(&mut state, relative_path, false)
+    }
+
+    /// Records that the file at `relative_path` is about to be modified or removed by someone else
+    /// by copying it to the backup directory. Does nothing if the file does not exist.
+    pub fn record_modify(&self, relative_path: &Path) -> std::io::Result<()> {
+        let mut state = self.lock_open()?;
+        self.backup(&mut state, relative_path, true)
+    }
+
+    /// Records that the file at `from` is about to be renamed to `to`. If a file already exists at
+    /// `to` it is moved to the backup directory first.
+    pub fn record_rename(&self, from: &Path, to: &Path) -> std::io::Result<()> {
+        let mut state = self.lock_open()?;
+        self.backup(&mut state, to, false)?;
+        state.append(&JournalEntry::Renamed {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+        })
+    }
+
+    /// Marks the transaction as complete by removing the journal and all backups.
+    pub fn commit(&self) -> Result<(), JournalError> {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        std::fs::remove_dir_all(Self::path(&self.target_prefix))
+            .map_err(JournalError::FailedToRemove)
+    }
+
+    /// Undoes all the recorded changes and removes the journal.
+    ///
+    /// If this fails the journal is left on disk and the rollback can be retried with
+    /// [`Journal::recover`].
+    pub fn rollback(&self) -> Result<(), JournalError> {
+        // Keep the journal locked so no changes can be recorded while they are being undone.
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+
+        let journal_dir = Self::path(&self.target_prefix);
+        let file =
+            File::open(journal_dir.join(JOURNAL_FILE)).map_err(JournalError::FailedToRead)?;
+        undo(&self.target_prefix, &read_entries(file)?)?;
+        std::fs::remove_dir_all(journal_dir).map_err(JournalError::FailedToRemove)
+    }
+
+    /// Locks the journal to record a change, fails if the journal has already been committed or
+    /// rolled back.
+    fn lock_open(&self) -> std::io::Result<MutexGuard<'_, JournalState>> {
+        let state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(std::io::Error::new(
+                ErrorKind::Other,
+                "the journal has already been committed or rolled back",
+            ));
+        }
+        Ok(state)
+    }
+
+    /// Moves or copies the file at `relative_path` to the backup directory if it exists.
+    fn backup(
+        &self,
+        state: &mut JournalState,
+        relative_path: &Path,
+        copy: bool,
+    ) -> std::io::Result<()> {
+        let path = self.target_prefix.join(relative_path);
+        let metadata = match path.symlink_metadata() {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        let backup = Path::new(JOURNAL_DIR)
+            .join(BACKUP_DIR)
+            .join(state.next_backup.to_string());
+        state.next_backup += 1;
+
+        // Write the entry before touching the file, if we crash in between the missing backup is
+        // simply skipped when the journal is replayed.
+        state.append(&JournalEntry::BackedUp {
+            path: relative_path.to_path_buf(),
+            backup: backup.clone(),
+        })?;
+
+        let backup = self.target_prefix.join(backup);
+        if copy && !metadata.file_type().is_symlink() {
+            std::fs::copy(&path, &backup).map(|_| ())
+        } else if copy {
+            let target = std::fs::read_link(&path)?;
+            create_symlink(&target, &backup)
+        } else {
+            std::fs::rename(&path, &backup)
+        }
+    }
+}
+
+impl JournalState {
+    /// Appends an entry to the journal file.
+    fn append(&mut self, entry: &JournalEntry) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        self.file.write_all(&line)
+    }
+}
+
+/// Reads all the entries of a journal file. The last entry might not have been written completely
+/// if the process was killed, in that case it is ignored.
+fn read_entries(file: File) -> Result<Vec<JournalEntry>, JournalError> {
+    let mut entries = Vec::new();
+    let mut lines = BufReader::new(file).lines().peekable();
+    while let Some(line) = lines.next() {
+        let line = line.map_err(JournalError::FailedToRead)?;
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(_) if lines.peek().is_none() => break,
+            Err(err) => return Err(JournalError::FailedToRead(err.into())),
+        }
+    }
+    Ok(entries)
+}
+
+/// Undoes the changes described by the entries in reverse order. Undoing is idempotent, changes
+/// that have already been undone or that were never made are skipped.
+fn undo(target_prefix: &Path, entries: &[JournalEntry]) -> Result<(), JournalError> {
+    for entry in entries.iter().rev() {
+        match entry {
+            JournalEntry::Created { path } => {
+                let full_path = target_prefix.join(path);
+                match std::fs::remove_file(&full_path) {
+                    Ok(()) => remove_empty_parents(target_prefix, path),
+                    Err(err) if err.kind() == ErrorKind::NotFound => {}
+                    Err(err) => return Err(JournalError::FailedToRestore(path.clone(), err)),
+                }
+            }
+            JournalEntry::BackedUp { path, backup } => {
+                let backup = target_prefix.join(backup);
+                if backup.symlink_metadata().is_ok() {
+                    restore(&backup, &target_prefix.join(path))
+                        .map_err(|err| JournalError::FailedToRestore(path.clone(), err))?;
+                }
+            }
+            JournalEntry::Renamed { from, to } => {
+                let to_path = target_prefix.join(to);
+                if to_path.symlink_metadata().is_ok() {
+                    restore(&to_path, &target_prefix.join(from))
+                        .map_err(|err| JournalError::FailedToRestore(from.clone(), err))?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Moves the file at `source` to `destination`, replacing any file at `destination`.
+fn restore(source: &Path, destination: &Path) -> std::io::Result<()> {
+    if let Some(parent) = destination.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if let Err(err) = std::fs::remove_file(destination) {
+        if err.kind() != ErrorKind::NotFound {
+            return Err(err);
+        }
+    }
+    std::fs::rename(source, destination)
+}
+
+/// Removes the parent directories of a path in the prefix as long as they are empty.
+fn remove_empty_parents(target_prefix: &Path, relative_path: &Path) {
+    for parent in relative_path.ancestors().skip(1) {
+        if parent.as_os_str().is_empty() || std::fs::remove_dir(target_prefix.join(parent)).is_err()
+        {
+            break;
+        }
+    }
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn create_symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Journal, JournalError};
+    use assert_matches::assert_matches;
+    use std::path::Path;
+
+    fn write(prefix: &Path, path: &str, content: &str) {
+        let path = prefix.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    fn read(prefix: &Path, path: &str) -> Option<String> {
+        std::fs::read_to_string(prefix.join(path)).ok()
+    }
+
+    #[test]
+    fn test_rollback() {
+        let prefix = tempfile::tempdir().unwrap();
+        let prefix = prefix.path();
+        write(prefix, "bin/removed", "removed");
+        write(prefix, "bin/overwritten", "old");
+        write(prefix, "conda-meta/foo.json", "{}");
+        write(prefix, "bin/renamed", "renamed");
+
+        let journal = Journal::create(prefix).unwrap();
+        journal.remove(Path::new("bin/removed")).unwrap();
+        journal.record_create(Path::new("bin/overwritten")).unwrap();
+        write(prefix, "bin/overwritten", "new");
+        journal.record_create(Path::new("lib/new/file")).unwrap();
+        write(prefix, "lib/new/file", "new");
+        journal
+            .record_modify(Path::new("conda-meta/foo.json"))
+            .unwrap();
+        std::fs::remove_file(prefix.join("conda-meta/foo.json")).unwrap();
+        journal
+            .record_rename(Path::new("bin/renamed"), Path::new("bin/renamed2"))
+            .unwrap();
+        std::fs::rename(prefix.join("bin/renamed"), prefix.join("bin/renamed2")).unwrap();
+
+        assert_eq!(read(prefix, "bin/removed"), None);
+        assert_matches!(Journal::create(prefix), Err(JournalError::Interrupted(_)));
+
+        journal.rollback().unwrap();
+        assert_eq!(read(prefix, "bin/removed").as_deref(), Some("removed"));
+        assert_eq!(read(prefix, "bin/overwritten").as_deref(), Some("old"));
+        assert_eq!(read(prefix, "conda-meta/foo.json").as_deref(), Some("{}"));
+        assert_eq!(read(prefix, "bin/renamed").as_deref(), Some("renamed"));
+        assert_eq!(read(prefix, "bin/renamed2"), None);
+        assert!(!prefix.join("lib").exists());
+        assert!(!Journal::is_interrupted(prefix));
+    }
+
+    #[test]
+    fn test_recover_interrupted() {
+        let prefix = tempfile::tempdir().unwrap();
+        let prefix = prefix.path();
+        write(prefix, "bin/removed", "removed");
+
+        let journal = Journal::create(prefix).unwrap();
+        journal.remove(Path::new("bin/removed")).unwrap();
+        journal.record_create(Path::new("bin/new")).unwrap();
+        write(prefix, "bin/new", "new");
+
+        // Simulate a crash by dropping the journal without committing it.
+        drop(journal);
+        assert!(Journal::is_interrupted(prefix));
+
+        assert!(Journal::recover(prefix).unwrap());
+        assert_eq!(read(prefix, "bin/removed").as_deref(), Some("removed"));
+        assert_eq!(read(prefix, "bin/new"), None);
+        assert!(!Journal::is_interrupted(prefix));
+        assert!(!Journal::recover(prefix).unwrap());
+    }
+
+    #[test]
+    fn test_commit() {
+        let prefix = tempfile::tempdir().unwrap();
+        let prefix = prefix.path();
+        write(prefix, "bin/removed", "removed");
+
+        let journal = Journal::create(prefix).unwrap();
+        journal.remove(Path::new("bin/removed")).unwrap();
+        journal.commit().unwrap();
+
+        assert_eq!(read(prefix, "bin/removed"), None);
+        assert!(!Journal::is_interrupted(prefix));
+    }
+}
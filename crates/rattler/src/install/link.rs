@@ -147,18 +147,8 @@ pub fn link_file(
     let source_path = package_dir.join(&path_json_entry.relative_path);
 
     // Determine the destination path
-    let destination_relative_path = if noarch_type.is_python() {
-        match target_python {
-            Some(python_info) => {
-                python_info.get_python_noarch_target_path(&path_json_entry.relative_path)
-            }
-            None => return Err(LinkFileError::MissingPythonInfo),
-        }
-    } else if let Some(clobber_rename) = clobber_rename {
-        clobber_rename.into()
-    } else {
-        path_json_entry.relative_path.as_path().into()
-    };
+    let destination_relative_path =
+        destination_relative_path(noarch_type, path_json_entry, target_python, clobber_rename)?;
 
     let destination_path = target_dir.join(&destination_relative_path);
 
@@ -308,6 +298,28 @@ pub fn link_file(
     })
 }
 
+/// Returns the path, relative to the target directory, to which [`link_file`] links a file of a
+/// package.
+pub(crate) fn destination_relative_path<'a>(
+    noarch_type: NoArchType,
+    path_json_entry: &'a PathsEntry,
+    target_python: Option<&PythonInfo>,
+    clobber_rename: Option<&'a PathBuf>,
+) -> Result<Cow<'a, Path>, LinkFileError> {
+    if noarch_type.is_python() {
+        match target_python {
+            Some(python_info) => {
+                Ok(python_info.get_python_noarch_target_path(&path_json_entry.relative_path))
+            }
+            None => Err(LinkFileError::MissingPythonInfo),
+        }
+    } else if let Some(clobber_rename) = clobber_rename {
+        Ok(clobber_rename.into())
+    } else {
+        Ok(path_json_entry.relative_path.as_path().into())
+    }
+}
+
 /// Either a memory mapped file or the complete contents of a file read to memory.
 enum MmapOrBytes {
     Mmap(Mmap),
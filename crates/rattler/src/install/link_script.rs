@@ -0,0 +1,271 @@
+//! Executes the link scripts of packages.
+//!
+//! Packages can contain scripts that are executed before they are linked into a prefix
+//! (`pre-link`), after they have been linked into a prefix (`post-link`) or before they are removed
+//! from a prefix (`pre-unlink`). The scripts are stored as `bin/.<name>-<action>.sh` on unix and as
+//! `Scripts/.<name>-<action>.bat` on Windows. Scripts can leave messages for the user by appending
+//! them to the `$PREFIX/.messages.txt` file.
+
+use super::InstallError;
+use rattler_conda_types::{PackageName, PackageRecord, Platform};
+use std::{
+    fmt::{Display, Formatter},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// The different kinds of link scripts a package can contain.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum LinkScriptType {
+    /// Executed before the package is linked. The script is executed from the extracted package
+    /// directory because the package is not part of the prefix yet.
+    PreLink,
+
+    /// Executed after the package has been linked into the prefix.
+    PostLink,
+
+    /// Executed before the package is removed from the prefix.
+    PreUnlink,
+}
+
+impl LinkScriptType {
+    /// Returns the name of the action as it is used in the file name of the script.
+    pub fn action(self) -> &'static str {
+        match self {
+            LinkScriptType::PreLink => "pre-link",
+            LinkScriptType::PostLink => "post-link",
+            LinkScriptType::PreUnlink => "pre-unlink",
+        }
+    }
+
+    /// Returns the location of the script for the given package, relative to the prefix or the
+    /// extracted package directory.
+    pub fn script_path(self, package_name: &PackageName, platform: Platform) -> PathBuf {
+        if platform.is_windows() {
+            Path::new("Scripts").join(format!(
+                ".{}-{}.bat",
+                package_name.as_normalized(),
+                self.action()
+            ))
+        } else {
+            Path::new("bin").join(format!(
+                ".{}-{}.sh",
+                package_name.as_normalized(),
+                self.action()
+            ))
+        }
+    }
+}
+
+impl Display for LinkScriptType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.action())
+    }
+}
+
+/// A message that a link script left for the user in the `.messages.txt` file.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LinkScriptMessage {
+    /// The name of the package that contains the script
+    pub package_name: PackageName,
+
+    /// The type of the script that left the message
+    pub script_type: LinkScriptType,
+
+    /// The contents of the `.messages.txt` file
+    pub message: String,
+}
+
+/// Executes the link script of the given type of a package if the package has such a script.
+///
+/// The script is looked up in `script_dir`, which is the target prefix for `post-link` and
+/// `pre-unlink` scripts and the extracted package directory for `pre-link` scripts. The script is
+/// executed with the same environment variables that conda sets (`PREFIX`, `PKG_NAME`,
+/// `PKG_VERSION`, `PKG_BUILDNUM`, ...) and with the binary directories of the prefix prepended to
+/// `PATH`.
+///
+/// Returns the message the script left in `$PREFIX/.messages.txt`, if any. A script that cannot be
+/// executed or that exits with a non-zero exit code results in an error.
+pub fn run_link_script(
+    script_type: LinkScriptType,
+    package_record: &PackageRecord,
+    script_dir: &Path,
+    target_prefix: &Path,
+    platform: Platform,
+) -> Result<Option<LinkScriptMessage>, InstallError> {
+    let script_path = script_dir.join(script_type.script_path(&package_record.name, platform));
+    if !script_path.is_file() {
+        return Ok(None);
+    }
+
+    let package_name = package_record.name.as_normalized().to_owned();
+    let failed_to_run =
+        |err| InstallError::FailedToRunLinkScript(package_name.clone(), script_type, err);
+
+    // Remove any leftover messages so only the messages of this script are reported.
+    let messages_path = target_prefix.join(".messages.txt");
+    if let Err(err) = std::fs::remove_file(&messages_path) {
+        if err.kind() != std::io::ErrorKind::NotFound {
+            return Err(failed_to_run(err));
+        }
+    }
+
+    let mut command = if platform.is_windows() {
+        let mut command = Command::new("cmd.exe");
+        command.arg("/d").arg("/c").arg(&script_path);
+        command
+    } else {
+        let mut command = Command::new("bash");
+        command.arg("-x").arg(&script_path);
+        command
+    };
+
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    let path = std::env::join_paths(
+        prefix_binary_paths(target_prefix, platform)
+            .into_iter()
+            .chain(std::env::split_paths(&path)),
+    )
+    .map_err(|err| failed_to_run(std::io::Error::new(std::io::ErrorKind::InvalidInput, err)))?;
+
+    let output = command
+        .env("ROOT_PREFIX", target_prefix)
+        .env("PREFIX", target_prefix)
+        .env("PKG_NAME", &package_name)
+        .env("PKG_VERSION", package_record.version.to_string())
+        .env("PKG_BUILDNUM", package_record.build_number.to_string())
+        .env("PKG_BUILD_STRING", &package_record.build)
+        .env("PATH", path)
+        .output()
+        .map_err(failed_to_run)?;
+
+    if !output.status.success() {
+        return Err(InstallError::LinkScriptFailed {
+            package_name,
+            script_type,
+            status: output.status,
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        });
+    }
+
+    // Collect the messages the script left for the user
+    let message = match std::fs::read_to_string(&messages_path) {
+        Ok(message) => {
+            std::fs::remove_file(&messages_path).map_err(failed_to_run)?;
+            message
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(failed_to_run(err)),
+    };
+
+    if message.trim().is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(LinkScriptMessage {
+        package_name: package_record.name.clone(),
+        script_type,
+        message,
+    }))
+}
+
+/// Returns the directories of the prefix that contain executables, these are added to `PATH`.
+fn prefix_binary_paths(target_prefix: &Path, platform: Platform) -> Vec<PathBuf> {
+    if platform.is_windows() {
+        vec![
+            target_prefix.to_path_buf(),
+            target_prefix.join("Library/mingw-w64/bin"),
+            target_prefix.join("Library/usr/bin"),
+            target_prefix.join("Library/bin"),
+            target_prefix.join("Scripts"),
+            target_prefix.join("bin"),
+        ]
+    } else {
+        vec![target_prefix.join("bin")]
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::{run_link_script, LinkScriptType};
+    use crate::install::InstallError;
+    use assert_matches::assert_matches;
+    use rattler_conda_types::{PackageName, PackageRecord, Platform, Version};
+    use std::path::Path;
+
+    fn write_script(target_prefix: &Path, script_type: LinkScriptType, content: &str) {
+        let path = target_prefix
+            .join(script_type.script_path(&PackageName::new_unchecked("foo"), Platform::Linux64));
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn test_post_link_script() {
+        let target_prefix = tempfile::tempdir().unwrap();
+        let mut record = PackageRecord::new(
+            PackageName::new_unchecked("foo"),
+            "1.2.3".parse::<Version>().unwrap(),
+            "h123_4".to_string(),
+        );
+        record.build_number = 4;
+
+        // Without a script nothing happens
+        let message = run_link_script(
+            LinkScriptType::PostLink,
+            &record,
+            target_prefix.path(),
+            target_prefix.path(),
+            Platform::Linux64,
+        )
+        .unwrap();
+        assert!(message.is_none());
+
+        write_script(
+            target_prefix.path(),
+            LinkScriptType::PostLink,
+            "echo \"$PKG_NAME $PKG_VERSION $PKG_BUILDNUM\" >> \"$PREFIX/.messages.txt\"\n",
+        );
+        let message = run_link_script(
+            LinkScriptType::PostLink,
+            &record,
+            target_prefix.path(),
+            target_prefix.path(),
+            Platform::Linux64,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(message.message, "foo 1.2.3 4\n");
+        assert_eq!(message.script_type, LinkScriptType::PostLink);
+        assert!(!target_prefix.path().join(".messages.txt").exists());
+    }
+
+    #[test]
+    fn test_failing_pre_unlink_script() {
+        let target_prefix = tempfile::tempdir().unwrap();
+        let record = PackageRecord::new(
+            PackageName::new_unchecked("foo"),
+            "1.0".parse::<Version>().unwrap(),
+            "0".to_string(),
+        );
+
+        write_script(
+            target_prefix.path(),
+            LinkScriptType::PreUnlink,
+            "echo oops >&2\nexit 3\n",
+        );
+        let err = run_link_script(
+            LinkScriptType::PreUnlink,
+            &record,
+            target_prefix.path(),
+            target_prefix.path(),
+            Platform::Linux64,
+        )
+        .unwrap_err();
+        assert_matches!(err, InstallError::LinkScriptFailed { script_type: LinkScriptType::PreUnlink, status, stderr, .. } => {
+            assert_eq!(status.code(), Some(3));
+            assert!(stderr.contains("oops"));
+        });
+    }
+}
@@ -13,18 +13,29 @@
 //! might contain a file that should be linked into the target directory. The `paths.json` file
 //! also contains a SHA256 hash for each file. This hash is used to verify that the file was not
 //! tampered with.
+//!
+//! To execute a complete [`Transaction`], which also involves downloading packages and removing
+//! packages from the prefix, use the [`Installer`].
 pub mod apple_codesign;
 mod clobber_registry;
+mod compile_pyc;
 mod driver;
 mod entry_point;
+mod installer;
+mod journal;
 pub mod link;
+mod link_script;
 mod python;
 mod transaction;
 pub mod unlink;
 
 pub use crate::install::entry_point::{get_windows_launcher, python_entry_point_template};
+pub use compile_pyc::{compile_python_files, pyc_path};
 pub use driver::InstallDriver;
+pub use installer::{InstallationResult, Installer, InstallerError, Reporter};
+pub use journal::{Journal, JournalError};
 pub use link::{link_file, LinkFileError};
+pub use link_script::{run_link_script, LinkScriptMessage, LinkScriptType};
 pub use transaction::{Transaction, TransactionError, TransactionOperation};
 pub use unlink::unlink_package;
 
@@ -92,6 +103,37 @@ pub enum InstallError {
     /// Post-processing involves removing clobbered paths.
     #[error("failed to post process the environment (unclobbering)")]
     PostProcessFailed(#[source] std::io::Error),
+
+    /// The python interpreter of the prefix could not be executed to compile bytecode.
+    #[error("failed to run python to compile bytecode")]
+    FailedToCompilePython(#[source] std::io::Error),
+
+    /// A change to the prefix could not be recorded in the [`Journal`].
+    #[error("failed to write to the journal")]
+    FailedToWriteJournal(#[source] std::io::Error),
+
+    /// A link script of a package could not be executed.
+    #[error("failed to run the {1} script of '{0}'")]
+    FailedToRunLinkScript(String, LinkScriptType, #[source] std::io::Error),
+
+    /// A link script of a package exited with a non-zero exit code.
+    #[error("the {script_type} script of '{package_name}' failed ({status})\nstdout:\n{stdout}\nstderr:\n{stderr}")]
+    LinkScriptFailed {
+        /// The name of the package that contains the script
+        package_name: String,
+
+        /// The type of the script that failed
+        script_type: LinkScriptType,
+
+        /// The exit status of the script
+        status: std::process::ExitStatus,
+
+        /// The output the script wrote to stdout
+        stdout: String,
+
+        /// The output the script wrote to stderr
+        stderr: String,
+    },
 }
 
 impl From<JoinError> for InstallError {
@@ -280,6 +322,7 @@ pub async fn link_package(
         let target_dir = target_dir.to_owned();
         let target_prefix = target_prefix.clone();
         let python_info = python_info.clone();
+        let journal = driver.journal().cloned();
 
         let clobber_rename = clobber_paths.get(&entry.relative_path).cloned();
         // Spawn a task to link the specific file. Note that these tasks are throttled by the
@@ -294,6 +337,22 @@ pub async fn link_package(
                 return;
             }
 
+            // Record the file in the journal before it is created so it can be rolled back.
+            if let Some(journal) = journal.as_deref() {
+                let journal_result = link::destination_relative_path(
+                    index_json.noarch,
+                    &entry,
+                    python_info.as_deref(),
+                    clobber_rename.as_ref(),
+                )
+                .map_err(|e| InstallError::FailedToLink(entry.relative_path.clone(), e))
+                .and_then(|path| journal_create(Some(journal), [path.as_ref()]));
+                if let Err(e) = journal_result {
+                    let _ = tx.blocking_send(Err(e));
+                    return;
+                }
+            }
+
             let linked_file_result = match link_file(
                 index_json.noarch,
                 &entry,
@@ -322,6 +381,13 @@ pub async fn link_package(
                         sha256: entry.sha256,
                         sha256_in_prefix: Some(result.sha256),
                         size_in_bytes: Some(result.file_size),
+                        file_mode: entry
+                            .prefix_placeholder
+                            .as_ref()
+                            .map(|placeholder| placeholder.file_mode),
+                        prefix_placeholder: entry
+                            .prefix_placeholder
+                            .map(|placeholder| placeholder.placeholder),
                     },
                 )),
                 Err(e) => Err(InstallError::FailedToLink(entry.relative_path.clone(), e)),
@@ -359,6 +425,7 @@ pub async fn link_package(
             let python_info = python_info.clone();
             let target_dir = target_dir.to_owned();
             let target_prefix = target_prefix.clone();
+            let journal = driver.journal().cloned();
 
             if platform.is_windows() {
                 driver.spawn_throttled_and_forget(move || {
@@ -368,6 +435,23 @@ pub async fn link_package(
                         return;
                     }
 
+                    if let Err(e) = journal_create(
+                        journal.as_deref(),
+                        [
+                            python_info
+                                .bin_dir
+                                .join(format!("{}-script.py", &entry_point.command))
+                                .as_path(),
+                            python_info
+                                .bin_dir
+                                .join(format!("{}.exe", &entry_point.command))
+                                .as_path(),
+                        ],
+                    ) {
+                        let _ = tx.blocking_send(Err(e));
+                        return;
+                    }
+
                     match create_windows_python_entry_point(
                         &target_dir,
                         &target_prefix,
@@ -394,6 +478,14 @@ pub async fn link_package(
                         return;
                     }
 
+                    if let Err(e) = journal_create(
+                        journal.as_deref(),
+                        [python_info.bin_dir.join(&entry_point.command).as_path()],
+                    ) {
+                        let _ = tx.blocking_send(Err(e));
+                        return;
+                    }
+
                     let result = match create_unix_python_entry_point(
                         &target_dir,
                         &target_prefix,
@@ -459,6 +551,22 @@ pub async fn link_package(
     Ok(paths)
 }
 
+/// Records in the journal, if any, that files are about to be created at the given paths.
+fn journal_create<'a>(
+    journal: Option<&Journal>,
+    relative_paths: impl IntoIterator<Item = &'a Path>,
+) -> Result<(), InstallError> {
+    let Some(journal) = journal else {
+        return Ok(());
+    };
+    for relative_path in relative_paths {
+        journal
+            .record_create(relative_path)
+            .map_err(InstallError::FailedToWriteJournal)?;
+    }
+    Ok(())
+}
+
 /// A helper function that reads the `paths.json` file from a package unless it has already been
 /// provided, in which case it is returned immediately.
 async fn read_paths_json(
@@ -708,7 +816,7 @@ mod test {
 
                     // Install the package to the prefix
                     link_package(
-                        &package_dir,
+                        package_dir.path(),
                         prefix_path,
                         install_driver,
                         InstallOptions {
@@ -1,8 +1,10 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use crate::install::python::PythonInfoError;
 use crate::install::PythonInfo;
-use rattler_conda_types::{PackageRecord, Platform};
+use rattler_conda_types::{
+    history::dist_str, History, PackageRecord, Platform, PrefixRecord, RepoDataRecord,
+};
 
 /// Error that occurred during creation of a Transaction
 #[derive(Debug, thiserror::Error)]
@@ -10,6 +12,14 @@ pub enum TransactionError {
     /// An error that happens if the python version could not be parsed.
     #[error(transparent)]
     PythonInfoError(#[from] PythonInfoError),
+
+    /// The history of the prefix does not contain the requested revision.
+    #[error("the history of the prefix does not contain revision {0}")]
+    UnknownRevision(usize),
+
+    /// A package of the requested revision is neither installed nor available.
+    #[error("the package '{0}' of the requested revision is not available")]
+    PackageNotFound(String),
 }
 
 /// Describes an operation to perform
@@ -157,6 +167,86 @@ impl<Old: AsRef<PackageRecord>, New: AsRef<PackageRecord>> Transaction<Old, New>
     }
 }
 
+impl<Old: AsRef<PackageRecord>, New> Transaction<Old, New> {
+    /// Constructs a [`Transaction`] that reinstalls the given packages of an environment, for
+    /// instance to repair packages whose files have been modified. `current` are all the packages
+    /// in the environment, they are used to determine the version of python.
+    pub fn reinstall(
+        current: impl IntoIterator<Item = impl AsRef<PackageRecord>>,
+        reinstall: impl IntoIterator<Item = Old>,
+        platform: Platform,
+    ) -> Result<Self, TransactionError> {
+        let python_info = find_python_info(current, platform)?;
+        Ok(Self {
+            operations: reinstall
+                .into_iter()
+                .map(TransactionOperation::Reinstall)
+                .collect(),
+            current_python_info: python_info.clone(),
+            python_info,
+            platform,
+        })
+    }
+}
+
+impl Transaction<PrefixRecord, RepoDataRecord> {
+    /// Constructs a [`Transaction`] that restores the packages of a prefix to the state they were
+    /// in after the revision with the given number was made, see
+    /// [`History::packages_at_revision`].
+    ///
+    /// `current` are the packages that are currently installed in the prefix. Packages of the
+    /// revision that are not installed are looked up in `available`, for instance the records of
+    /// the channels the packages were installed from. Packages are matched by name, version and
+    /// build string, the channel they came from is ignored.
+    pub fn to_revision(
+        history: &History,
+        revision: usize,
+        current: Vec<PrefixRecord>,
+        available: impl IntoIterator<Item = RepoDataRecord>,
+        platform: Platform,
+    ) -> Result<Self, TransactionError> {
+        let packages = history
+            .packages_at_revision(revision)
+            .ok_or(TransactionError::UnknownRevision(revision))?;
+
+        let installed = current
+            .iter()
+            .map(|record| {
+                let dist = dist_str(&record.repodata_record);
+                (package_part(&dist).to_owned(), &record.repodata_record)
+            })
+            .collect::<HashMap<_, _>>();
+        let mut available = available
+            .into_iter()
+            .map(|record| (package_part(&dist_str(&record)).to_owned(), record))
+            .collect::<HashMap<_, _>>();
+
+        let desired = packages
+            .into_iter()
+            .map(|dist| {
+                let key = package_part(&dist);
+                installed
+                    .get(key)
+                    .copied()
+                    .cloned()
+                    .or_else(|| available.remove(key))
+                    .ok_or(TransactionError::PackageNotFound(dist))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Self::from_current_and_desired(
+            current,
+            PackageRecord::sort_topologically(desired),
+            platform,
+        )
+    }
+}
+
+/// Returns the `<name>-<version>-<build>` part of a dist string.
+fn package_part(dist: &str) -> &str {
+    dist.rsplit_once("::").map_or(dist, |(_, package)| package)
+}
+
 /// Determine the version of Python used by a set of packages. Returns `None` if none of the
 /// packages refers to a Python installation.
 fn find_python_info(
@@ -13,7 +13,10 @@
 use std::path::PathBuf;
 
 pub mod install;
+pub mod pack;
 pub mod package_cache;
+#[cfg(feature = "pubgrub")]
+pub mod range;
 pub mod validation;
 
 /// A helper function that returns a [`Channel`] instance that points to an empty channel on disk
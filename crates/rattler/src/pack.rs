@@ -0,0 +1,555 @@
+//! Functionality to pack an installed prefix into an archive and to unpack it at another location.
+//!
+//! Files in which the prefix placeholder of a package was replaced with the target prefix during
+//! installation cannot simply be copied to another location. [`pack_prefix`] therefore restores
+//! the original prefix placeholders of these files, as recorded in the `paths_data` of the
+//! [`PrefixRecord`]s, before they are added to the archive. [`unpack_prefix`] extracts such an
+//! archive and replaces the placeholders with the new location of the prefix, just like when a
+//! package is installed.
+//!
+//! The archive is an uncompressed tarball that contains the files of all the packages installed in
+//! the prefix together with the `conda-meta` records and history of the prefix. Files that are not
+//! owned by any package are not included.
+
+use crate::install::apple_codesign::codesign;
+use crate::install::link::{
+    copy_and_replace_placeholders, copy_and_replace_textual_placeholder, LinkFileError,
+};
+use rattler_conda_types::package::FileMode;
+use rattler_conda_types::prefix_record::{PathType, PathsEntry};
+use rattler_conda_types::{History, Platform, PrefixLock, PrefixLockError, PrefixRecord};
+use rattler_digest::{HashingWriter, Sha256};
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// The placeholder that is written in place of the prefix in python entry points. Entry points are
+/// generated during installation so they do not have a placeholder of their own.
+const ENTRY_POINT_PLACEHOLDER: &str = "/opt/anaconda1anaconda2anaconda3";
+
+/// An error that can occur when packing or unpacking a prefix.
+#[derive(Debug, thiserror::Error)]
+pub enum PackError {
+    /// The prefix could not be locked.
+    #[error("failed to lock the prefix")]
+    FailedToLockPrefix(#[source] PrefixLockError),
+
+    /// The records in the `conda-meta` directory could not be read.
+    #[error("failed to read the prefix records")]
+    FailedToReadPrefixRecords(#[source] std::io::Error),
+
+    /// A file of the prefix could not be added to the archive.
+    #[error("failed to pack '{0}'")]
+    FailedToPackFile(PathBuf, #[source] std::io::Error),
+
+    /// The archive could not be written.
+    #[error("failed to write the archive")]
+    FailedToWriteArchive(#[source] std::io::Error),
+
+    /// The archive could not be extracted.
+    #[error("failed to unpack the archive")]
+    FailedToUnpackArchive(#[source] std::io::Error),
+
+    /// The target prefix of [`unpack_prefix`] already contains packages.
+    #[error("the prefix '{0}' already contains packages")]
+    PrefixNotEmpty(PathBuf),
+
+    /// The prefix placeholder in a file could not be replaced with the new location of the prefix.
+    #[error("failed to relocate '{0}'")]
+    FailedToRelocateFile(PathBuf, #[source] std::io::Error),
+
+    /// A binary could not be signed after it was relocated.
+    #[error("failed to sign '{0}'")]
+    FailedToSignFile(PathBuf, #[source] LinkFileError),
+
+    /// An updated prefix record could not be written.
+    #[error("failed to write prefix record '{0}'")]
+    FailedToWritePrefixRecord(PathBuf, #[source] std::io::Error),
+}
+
+/// Packs the prefix at `target_prefix` into a tarball that is written to `writer`. The tarball can
+/// be unpacked at any other location with [`unpack_prefix`].
+///
+/// Files in which the prefix placeholder was replaced during installation are written with the
+/// original placeholder restored. This requires that the prefix is still located at the path it
+/// was installed to. Files that were installed as symbolic links to the package cache are
+/// included as regular files.
+///
+/// A shared [`PrefixLock`] is held on the prefix while it is packed.
+pub fn pack_prefix(target_prefix: &Path, writer: impl Write) -> Result<(), PackError> {
+    let lock = PrefixLock::shared(target_prefix).map_err(PackError::FailedToLockPrefix)?;
+    let mut prefix_records = PrefixRecord::collect_from_locked_prefix(&lock)
+        .map_err(PackError::FailedToReadPrefixRecords)?;
+    prefix_records.sort_by_cached_key(PrefixRecord::file_name);
+
+    let prefix_str = target_prefix.to_string_lossy();
+    let platform = Platform::current();
+    let mut builder = tar::Builder::new(writer);
+    builder.follow_symlinks(false);
+
+    for record in &prefix_records {
+        for entry in &record.paths_data.paths {
+            pack_entry(&mut builder, target_prefix, &prefix_str, platform, entry)
+                .map_err(|err| PackError::FailedToPackFile(entry.relative_path.clone(), err))?;
+        }
+    }
+
+    // Add the records of the packages and the history of the prefix.
+    let conda_meta = Path::new("conda-meta");
+    let history_path = History::path(target_prefix);
+    let metadata_files = prefix_records
+        .iter()
+        .map(|record| conda_meta.join(record.file_name()))
+        .chain(history_path.is_file().then(|| conda_meta.join("history")));
+    for relative_path in metadata_files {
+        builder
+            .append_path_with_name(target_prefix.join(&relative_path), &relative_path)
+            .map_err(|err| PackError::FailedToPackFile(relative_path, err))?;
+    }
+
+    builder
+        .into_inner()
+        .and_then(|mut writer| writer.flush())
+        .map_err(PackError::FailedToWriteArchive)
+}
+
+/// Adds a single file of a package to the archive.
+fn pack_entry(
+    builder: &mut tar::Builder<impl Write>,
+    target_prefix: &Path,
+    prefix_str: &str,
+    platform: Platform,
+    entry: &PathsEntry,
+) -> std::io::Result<()> {
+    let path = target_prefix.join(&entry.relative_path);
+    let metadata = std::fs::symlink_metadata(&path)?;
+
+    // Directories and symbolic links that are part of the package are stored as is.
+    if metadata.is_dir() || (metadata.is_symlink() && entry.path_type == PathType::SoftLink) {
+        return builder.append_path_with_name(&path, &entry.relative_path);
+    }
+
+    let Some((placeholder, file_mode)) = entry_placeholder(entry) else {
+        return builder.append_file(&entry.relative_path, &mut File::open(&path)?);
+    };
+
+    let contents = std::fs::read(&path)?;
+    let mut writer = HashingWriter::<_, Sha256>::new(Vec::with_capacity(contents.len()));
+    restore_placeholders(
+        &contents,
+        &mut writer,
+        placeholder,
+        &linked_prefix(prefix_str, platform),
+        file_mode,
+    )?;
+    let (restored, hash) = writer.finalize();
+    if entry.path_type != PathType::UnixPythonEntryPoint
+        && entry.path_type != PathType::WindowsPythonEntryPointScript
+        && entry.sha256.map_or(false, |sha256| sha256 != hash)
+    {
+        tracing::warn!(
+            "the restored contents of '{}' do not match the original file",
+            entry.relative_path.display()
+        );
+    }
+
+    let mut header = tar::Header::new_gnu();
+    header.set_metadata(&std::fs::metadata(&path)?);
+    header.set_size(restored.len() as u64);
+    builder.append_data(&mut header, &entry.relative_path, restored.as_slice())
+}
+
+/// Unpacks a tarball that was created with [`pack_prefix`] into `target_prefix` and replaces the
+/// prefix placeholders in the unpacked files with `target_prefix`. The `sha256_in_prefix` of the
+/// relocated files is updated in the records of the prefix. Returns the records of the packages
+/// in the prefix.
+///
+/// `target_prefix` must not already contain packages. An exclusive [`PrefixLock`] is held on the
+/// prefix while it is unpacked.
+pub fn unpack_prefix(
+    reader: impl Read,
+    target_prefix: &Path,
+) -> Result<Vec<PrefixRecord>, PackError> {
+    let lock = PrefixLock::exclusive(target_prefix).map_err(PackError::FailedToLockPrefix)?;
+    let existing_records = PrefixRecord::collect_from_locked_prefix(&lock)
+        .map_err(PackError::FailedToReadPrefixRecords)?;
+    if !existing_records.is_empty() {
+        return Err(PackError::PrefixNotEmpty(target_prefix.to_path_buf()));
+    }
+
+    let mut archive = tar::Archive::new(reader);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_mtime(true);
+    archive
+        .unpack(target_prefix)
+        .map_err(PackError::FailedToUnpackArchive)?;
+
+    let mut prefix_records = PrefixRecord::collect_from_locked_prefix(&lock)
+        .map_err(PackError::FailedToReadPrefixRecords)?;
+    let prefix_str = target_prefix.to_string_lossy();
+    let platform = Platform::current();
+    for record in &mut prefix_records {
+        let mut modified = false;
+        for entry in &mut record.paths_data.paths {
+            let Some((placeholder, file_mode)) = entry_placeholder(entry) else {
+                continue;
+            };
+
+            let path = target_prefix.join(&entry.relative_path);
+            let relocate = || -> std::io::Result<_> {
+                let contents = std::fs::read(&path)?;
+                let mut writer =
+                    HashingWriter::<_, Sha256>::new(Vec::with_capacity(contents.len()));
+                copy_and_replace_placeholders(
+                    &contents,
+                    &mut writer,
+                    placeholder,
+                    &linked_prefix(&prefix_str, platform),
+                    file_mode,
+                )?;
+                let (relocated, hash) = writer.finalize();
+                std::fs::write(&path, relocated)?;
+                Ok(hash)
+            };
+            let hash = relocate()
+                .map_err(|err| PackError::FailedToRelocateFile(entry.relative_path.clone(), err))?;
+
+            // Binaries have to be signed again after they were modified on Apple Silicon.
+            if platform == Platform::OsxArm64
+                && file_mode == FileMode::Binary
+                && is_executable(&path)
+            {
+                codesign(&path)
+                    .map_err(|err| PackError::FailedToSignFile(entry.relative_path.clone(), err))?;
+            }
+
+            entry.sha256_in_prefix = Some(hash);
+            modified = true;
+        }
+
+        if modified {
+            let path = target_prefix.join("conda-meta").join(record.file_name());
+            record
+                .write_to_path(&path, true)
+                .map_err(|err| PackError::FailedToWritePrefixRecord(path, err))?;
+        }
+    }
+
+    Ok(prefix_records)
+}
+
+/// Returns the placeholder of the prefix in the given file and how it should be replaced, or
+/// `None` if the file does not contain the prefix.
+fn entry_placeholder(entry: &PathsEntry) -> Option<(&str, FileMode)> {
+    match entry.path_type {
+        PathType::UnixPythonEntryPoint | PathType::WindowsPythonEntryPointScript => {
+            Some((ENTRY_POINT_PLACEHOLDER, FileMode::Text))
+        }
+        _ => Some((
+            entry.prefix_placeholder.as_deref()?,
+            entry.file_mode.unwrap_or(FileMode::Text),
+        )),
+    }
+}
+
+/// Returns the prefix as it was written in place of the placeholder of a file. Linking uses
+/// forward-slashes on Windows, see `link_file`.
+fn linked_prefix(prefix_str: &str, platform: Platform) -> Cow<'_, str> {
+    if platform.is_windows() {
+        Cow::Owned(prefix_str.replace('\\', "/"))
+    } else {
+        Cow::Borrowed(prefix_str)
+    }
+}
+
+/// Returns true if the file at the given path is executable.
+#[allow(unused_variables)]
+fn is_executable(path: &Path) -> bool {
+    #[cfg(windows)]
+    return false;
+    #[cfg(unix)]
+    return std::fs::metadata(path).map_or(false, |metadata| {
+        std::os::unix::fs::PermissionsExt::mode(&metadata.permissions()) & 0o111 != 0
+    });
+}
+
+/// The inverse of [`copy_and_replace_placeholders`]: copies `source_bytes` to `destination` and
+/// replaces the `target_prefix` that was written in place of the `prefix_placeholder` with the
+/// `prefix_placeholder` again.
+fn restore_placeholders(
+    source_bytes: &[u8],
+    destination: impl Write,
+    prefix_placeholder: &str,
+    target_prefix: &str,
+    file_mode: FileMode,
+) -> Result<(), std::io::Error> {
+    match file_mode {
+        FileMode::Text => copy_and_replace_textual_placeholder(
+            source_bytes,
+            destination,
+            target_prefix,
+            prefix_placeholder,
+        ),
+        FileMode::Binary => restore_cstring_placeholder(
+            source_bytes,
+            destination,
+            prefix_placeholder,
+            target_prefix,
+        ),
+    }
+}
+
+/// The inverse of [`crate::install::link::copy_and_replace_cstring_placeholder`]. Every c-style
+/// string that starts with `target_prefix` and is followed by the nul padding that was added when
+/// the placeholder was replaced is restored to the string with the `prefix_placeholder`. The
+/// length of the output matches the input.
+fn restore_cstring_placeholder(
+    mut source_bytes: &[u8],
+    mut destination: impl Write,
+    prefix_placeholder: &str,
+    target_prefix: &str,
+) -> Result<(), std::io::Error> {
+    let old_prefix = target_prefix.as_bytes();
+    let new_prefix = prefix_placeholder.as_bytes();
+
+    // When the placeholder was replaced by a shorter prefix the string was padded with nul
+    // terminators. Without that padding there is no room to restore the placeholder.
+    let padding = new_prefix.len().saturating_sub(old_prefix.len());
+
+    while let Some(index) = memchr::memmem::find(source_bytes, old_prefix) {
+        // Find the end of the c-style string. The nul terminator basically.
+        let mut end = index + old_prefix.len();
+        while end < source_bytes.len() && source_bytes[end] != b'\0' {
+            end += 1;
+        }
+
+        let has_padding = source_bytes.len() >= end + padding
+            && source_bytes[end..end + padding].iter().all(|&b| b == b'\0');
+        if old_prefix.len() > new_prefix.len() || !has_padding {
+            // This string was not written by replacing the placeholder, keep it as is.
+            destination.write_all(&source_bytes[..end])?;
+            source_bytes = &source_bytes[end..];
+            continue;
+        }
+
+        // Write all bytes up to the prefix, then the placeholder followed by the suffix. The
+        // padding is dropped.
+        destination.write_all(&source_bytes[..index])?;
+        destination.write_all(new_prefix)?;
+        destination.write_all(&source_bytes[index + old_prefix.len()..end])?;
+
+        source_bytes = &source_bytes[end + padding..];
+    }
+
+    // The prefix was not found in the (remaining) source bytes. Write the rest of the bytes.
+    destination.write_all(source_bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        linked_prefix, pack_prefix, restore_cstring_placeholder, restore_placeholders,
+        unpack_prefix, PackError,
+    };
+    use crate::install::link::{
+        copy_and_replace_cstring_placeholder, copy_and_replace_placeholders,
+        copy_and_replace_textual_placeholder,
+    };
+    use crate::validation::validate_prefix;
+    use assert_matches::assert_matches;
+    use rattler_conda_types::package::FileMode;
+    use rattler_conda_types::prefix_record::{PathType, PathsEntry};
+    use rattler_conda_types::{PackageName, PackageRecord, Platform, PrefixRecord, RepoDataRecord};
+    use rattler_digest::{compute_bytes_digest, Sha256};
+    use rstest::rstest;
+    use std::io::Read;
+    use std::path::{Path, PathBuf};
+
+    const PLACEHOLDER: &str = "/opt/placeholder_placeholder_placeholder_placeholder";
+
+    #[rstest]
+    #[case(
+        b"abc\x00/opt/placeholder_placeholder_placeholder_placeholder/bin/python\x00def",
+        PLACEHOLDER,
+        "/short"
+    )]
+    #[case(b"/opt/placeholder_placeholder_placeholder_placeholder/lib\x00/opt/placeholder_placeholder_placeholder_placeholder\x00", PLACEHOLDER, "/short")]
+    #[case(b"no placeholder", PLACEHOLDER, "/short")]
+    fn test_restore_cstring_placeholder(
+        #[case] original: &[u8],
+        #[case] placeholder: &str,
+        #[case] target_prefix: &str,
+    ) {
+        let mut replaced = Vec::new();
+        copy_and_replace_cstring_placeholder(original, &mut replaced, placeholder, target_prefix)
+            .unwrap();
+
+        let mut restored = Vec::new();
+        restore_cstring_placeholder(&replaced, &mut restored, placeholder, target_prefix).unwrap();
+        assert_eq!(restored, original);
+    }
+
+    #[test]
+    fn test_restore_cstring_placeholder_without_padding() {
+        // A string that starts with the prefix but was not the result of a replacement.
+        let mut restored = Vec::new();
+        restore_cstring_placeholder(b"/short/lib\x00abc", &mut restored, PLACEHOLDER, "/short")
+            .unwrap();
+        assert_eq!(restored, b"/short/lib\x00abc");
+    }
+
+    /// Writes a file into the prefix as if the package was installed there, and returns its entry.
+    fn install_file(
+        prefix: &Path,
+        relative_path: &str,
+        contents: &[u8],
+        placeholder: Option<FileMode>,
+    ) -> PathsEntry {
+        let prefix_str = prefix.to_str().unwrap();
+        let mut installed = Vec::new();
+        match placeholder {
+            Some(FileMode::Text) => copy_and_replace_textual_placeholder(
+                contents,
+                &mut installed,
+                PLACEHOLDER,
+                prefix_str,
+            )
+            .unwrap(),
+            Some(FileMode::Binary) => copy_and_replace_cstring_placeholder(
+                contents,
+                &mut installed,
+                PLACEHOLDER,
+                prefix_str,
+            )
+            .unwrap(),
+            None => installed.extend_from_slice(contents),
+        }
+
+        let path = prefix.join(relative_path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, &installed).unwrap();
+        PathsEntry {
+            relative_path: PathBuf::from(relative_path),
+            original_path: None,
+            path_type: PathType::HardLink,
+            no_link: false,
+            sha256: Some(compute_bytes_digest::<Sha256>(contents)),
+            sha256_in_prefix: Some(compute_bytes_digest::<Sha256>(&installed)),
+            size_in_bytes: Some(contents.len() as u64),
+            prefix_placeholder: placeholder.map(|_| PLACEHOLDER.to_owned()),
+            file_mode: placeholder,
+        }
+    }
+
+    #[test]
+    fn test_pack_unpack() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let prefix = temp_dir.path().join("env");
+        let text = format!("#!/bin/sh\nexec {PLACEHOLDER}/bin/python \"$@\"\n");
+        let binary = format!("\x7fELF\x00{PLACEHOLDER}/lib\x00rest").into_bytes();
+        let paths = vec![
+            install_file(&prefix, "bin/script", text.as_bytes(), Some(FileMode::Text)),
+            install_file(&prefix, "lib/libfoo.so", &binary, Some(FileMode::Binary)),
+            install_file(&prefix, "share/readme.md", b"# foo", None),
+        ];
+        let package_record = PackageRecord::new(
+            PackageName::new_unchecked("foo"),
+            "1.0".parse::<rattler_conda_types::Version>().unwrap(),
+            "0".to_owned(),
+        );
+        let repodata_record = RepoDataRecord {
+            package_record,
+            file_name: "foo-1.0-0.conda".to_owned(),
+            url: "https://conda.anaconda.org/conda-forge/noarch/foo-1.0-0.conda"
+                .parse()
+                .unwrap(),
+            channel: "https://conda.anaconda.org/conda-forge/".to_owned(),
+        };
+        let record =
+            PrefixRecord::from_repodata_record(repodata_record, None, None, paths, None, None);
+        std::fs::create_dir_all(prefix.join("conda-meta")).unwrap();
+        record
+            .write_to_path(prefix.join("conda-meta").join(record.file_name()), true)
+            .unwrap();
+
+        let mut archive = Vec::new();
+        pack_prefix(&prefix, &mut archive).unwrap();
+
+        // The files in the archive contain the original placeholders.
+        let mut packed_files = Vec::new();
+        for entry in tar::Archive::new(archive.as_slice()).entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().into_owned();
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents).unwrap();
+            if path == Path::new("bin/script") {
+                assert_eq!(contents, text.as_bytes());
+            } else if path == Path::new("lib/libfoo.so") {
+                assert_eq!(contents, binary);
+            }
+            packed_files.push(path);
+        }
+        assert_eq!(
+            packed_files,
+            vec![
+                PathBuf::from("bin/script"),
+                PathBuf::from("lib/libfoo.so"),
+                PathBuf::from("share/readme.md"),
+                PathBuf::from("conda-meta/foo-1.0-0.json"),
+            ]
+        );
+
+        // Unpacking replaces the placeholders with the new location.
+        let new_prefix = temp_dir.path().join("relocated-env");
+        let records = unpack_prefix(archive.as_slice(), &new_prefix).unwrap();
+        assert_eq!(records.len(), 1);
+        let script = std::fs::read_to_string(new_prefix.join("bin/script")).unwrap();
+        assert_eq!(
+            script,
+            text.replace(PLACEHOLDER, new_prefix.to_str().unwrap())
+        );
+        assert!(!script.contains(prefix.to_str().unwrap()));
+
+        let report = validate_prefix(&new_prefix, Platform::current()).unwrap();
+        assert!(report.is_valid(), "{report:?}");
+
+        // Unpacking into a prefix that already contains packages fails.
+        assert_matches!(
+            unpack_prefix(archive.as_slice(), &new_prefix),
+            Err(PackError::PrefixNotEmpty(_))
+        );
+
+        // A Windows prefix is written with forward-slashes in all file modes, restoring the
+        // placeholders has to look for the same prefix.
+        let windows_prefix = r"C:\Users\foo\env";
+        let linked = linked_prefix(windows_prefix, Platform::Win64);
+        assert_eq!(linked, "C:/Users/foo/env");
+        for (contents, file_mode) in [
+            (text.as_bytes(), FileMode::Text),
+            (binary.as_slice(), FileMode::Binary),
+        ] {
+            let mut installed = Vec::new();
+            copy_and_replace_placeholders(
+                contents,
+                &mut installed,
+                PLACEHOLDER,
+                &linked,
+                file_mode,
+            )
+            .unwrap();
+            assert!(memchr::memmem::find(&installed, linked.as_bytes()).is_some());
+
+            let mut restored = Vec::new();
+            restore_placeholders(&installed, &mut restored, PLACEHOLDER, &linked, file_mode)
+                .unwrap();
+            assert_eq!(restored, contents);
+        }
+        assert_eq!(
+            linked_prefix(windows_prefix, Platform::Linux64),
+            windows_prefix
+        );
+    }
+}
@@ -2,20 +2,33 @@
 
 use crate::validation::validate_package_directory;
 use chrono::Utc;
+use digest::Digest;
+use futures::StreamExt;
 use fxhash::FxHashMap;
 use itertools::Itertools;
-use rattler_conda_types::{package::ArchiveIdentifier, PackageRecord};
+use rattler_conda_types::{
+    package::{ArchiveIdentifier, ArchiveType},
+    FileLockMode, LockedFile, PackageRecord, RepoDataRecord,
+};
+use rattler_digest::{Md5, Md5Hash, Sha256, Sha256Hash};
 use rattler_networking::retry_policies::{DoNotRetryPolicy, RetryDecision, RetryPolicy};
 use rattler_package_streaming::ExtractError;
 use reqwest::StatusCode;
 use std::error::Error;
 use std::{
+    collections::{HashMap, HashSet},
     fmt::{Display, Formatter},
     future::Future,
-    path::PathBuf,
-    sync::{Arc, Mutex},
+    hash::{Hash, Hasher},
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, SystemTime},
 };
+use tempfile::NamedTempFile;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 use tokio::sync::broadcast;
+use tokio_util::io::StreamReader;
 use tracing::Instrument;
 use url::Url;
 
@@ -31,13 +44,67 @@ pub struct PackageCache {
 }
 
 /// Provides a unique identifier for packages in the cache.
-/// TODO: This could not be unique over multiple subdir. How to handle?
-/// TODO: Wouldn't it be better to cache based on hashes?
-#[derive(Debug, Hash, Clone, Eq, PartialEq)]
+///
+/// If the SHA256 hash of the package archive is known the package is identified by its hash,
+/// otherwise by its name, version and build string. The hashes of the key are also used to verify
+/// downloaded package archives, see [`PackageCache::get_or_fetch_from_url`].
+#[derive(Debug, Clone)]
 pub struct CacheKey {
     name: String,
     version: String,
     build_string: String,
+    sha256: Option<Sha256Hash>,
+    md5: Option<Md5Hash>,
+}
+
+impl CacheKey {
+    /// Sets the SHA256 hash of the package archive. This changes the identity of the key.
+    pub fn with_sha256(mut self, sha256: Sha256Hash) -> Self {
+        self.sha256 = Some(sha256);
+        self
+    }
+
+    /// Sets the MD5 hash of the package archive. The MD5 hash is only used to verify the archive,
+    /// it is not part of the identity of the key.
+    pub fn with_md5(mut self, md5: Md5Hash) -> Self {
+        self.md5 = Some(md5);
+        self
+    }
+
+    /// Returns the SHA256 hash of the package archive, if it is known.
+    pub fn sha256(&self) -> Option<&Sha256Hash> {
+        self.sha256.as_ref()
+    }
+
+    /// Returns the MD5 hash of the package archive, if it is known.
+    pub fn md5(&self) -> Option<&Md5Hash> {
+        self.md5.as_ref()
+    }
+
+    /// Returns the name of the directory of the package if its SHA256 hash is not known.
+    fn unhashed_name(&self) -> String {
+        format!("{}-{}-{}", &self.name, &self.version, &self.build_string)
+    }
+}
+
+impl PartialEq for CacheKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.version == other.version
+            && self.build_string == other.build_string
+            && self.sha256 == other.sha256
+    }
+}
+
+impl Eq for CacheKey {}
+
+impl Hash for CacheKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+        self.version.hash(state);
+        self.build_string.hash(state);
+        self.sha256.hash(state);
+    }
 }
 
 impl From<ArchiveIdentifier> for CacheKey {
@@ -46,6 +113,8 @@ impl From<ArchiveIdentifier> for CacheKey {
             name: pkg.name,
             version: pkg.version,
             build_string: pkg.build_string,
+            sha256: None,
+            md5: None,
         }
     }
 }
@@ -56,26 +125,183 @@ impl From<&PackageRecord> for CacheKey {
             name: record.name.as_normalized().to_string(),
             version: record.version.to_string(),
             build_string: record.build.clone(),
+            sha256: record.sha256,
+            md5: record.md5,
         }
     }
 }
 
+impl From<&RepoDataRecord> for CacheKey {
+    fn from(record: &RepoDataRecord) -> Self {
+        Self::from(&record.package_record)
+    }
+}
+
+/// Formats the key as the name of the directory of the package in the cache. This is
+/// `<name>-<version>-<build>`, followed by the first 16 hexadecimal characters of the SHA256 hash
+/// if it is known. Only using a part of the hash keeps paths short, which matters on Windows.
 impl Display for CacheKey {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}-{}-{}", &self.name, &self.version, &self.build_string)
+        write!(f, "{}", self.unhashed_name())?;
+        if let Some(sha256) = &self.sha256 {
+            write!(f, "-{}", &format!("{sha256:x}")[..16])?;
+        }
+        Ok(())
     }
 }
 
 #[derive(Default)]
 struct PackageCacheInner {
-    path: PathBuf,
+    layers: Vec<CacheLayer>,
     packages: FxHashMap<CacheKey, Arc<Mutex<Package>>>,
 }
 
 #[derive(Default)]
 struct Package {
-    path: Option<PathBuf>,
-    inflight: Option<broadcast::Sender<Result<PathBuf, PackageCacheError>>>,
+    /// The directory of the package and, if it is stored in a writable layer, the lock that keeps
+    /// it in the cache for as long as a returned [`CacheLock`] is alive.
+    path: Option<(PathBuf, Option<Weak<LockedFile>>)>,
+    inflight: Option<broadcast::Sender<Result<CacheLock, PackageCacheError>>>,
+}
+
+impl Package {
+    /// Returns the package if it is known to be in the cache and still locked.
+    fn cached(&self) -> Option<CacheLock> {
+        let (path, lock) = self.path.as_ref()?;
+        let lock = match lock {
+            Some(lock) => Some(lock.upgrade()?),
+            None => None,
+        };
+        Some(CacheLock {
+            path: path.clone(),
+            lock,
+        })
+    }
+}
+
+/// A package in the cache that is in use, returned by [`PackageCache::get_or_fetch`].
+///
+/// As long as this value or one of its clones is alive the package is not removed by
+/// [`PackageCache::clean`], not even by other processes. Keep it around until the files of the
+/// package are no longer needed, for instance until they are linked into a prefix.
+#[derive(Debug, Clone)]
+pub struct CacheLock {
+    path: PathBuf,
+
+    /// The shared lock on the lock file of the package, `None` if the package is stored in a
+    /// read-only layer which is never cleaned.
+    lock: Option<Arc<LockedFile>>,
+}
+
+impl CacheLock {
+    /// Returns the directory that contains the extracted package.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for CacheLock {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// A directory that stores extracted packages. A [`PackageCache`] consists of one or more layers,
+/// see [`PackageCache::with_layers`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CacheLayer {
+    path: PathBuf,
+    writable: bool,
+}
+
+impl CacheLayer {
+    /// Constructs a layer that packages can be fetched into.
+    pub fn writable(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            writable: true,
+        }
+    }
+
+    /// Constructs a layer that packages are only read from, for instance a cache that is shared
+    /// between the users of a machine.
+    ///
+    /// Packages are linked from a read-only layer like from any other layer. If hard links or
+    /// reflinks to the layer cannot be created, for instance because it is on another filesystem,
+    /// the files are copied instead.
+    pub fn read_only(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            writable: false,
+        }
+    }
+
+    /// Returns the directory of the layer.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns true if packages can be fetched into this layer.
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+}
+
+/// A package in the cache, see [`PackageCache::inventory`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CacheEntry {
+    /// The directory that contains the extracted package.
+    pub path: PathBuf,
+
+    /// The total size of the files of the package in bytes.
+    pub size: u64,
+
+    /// The last time the package was returned by a [`PackageCache`].
+    pub last_access: SystemTime,
+}
+
+/// Determines which packages are removed by [`PackageCache::clean`].
+#[derive(Debug, Clone)]
+pub enum CleanPolicy {
+    /// Removes the least recently used packages until the total size of the cache does not exceed
+    /// the given number of bytes.
+    MaxSize(u64),
+
+    /// Removes all packages except the given ones, for instance the packages that are referenced
+    /// by a set of prefixes or lock files.
+    KeepOnly(Vec<CacheKey>),
+}
+
+/// The packages that were removed by [`PackageCache::clean`].
+#[derive(Debug, Clone, Default)]
+pub struct CleanReport {
+    /// The packages that were removed from the cache.
+    pub removed: Vec<CacheEntry>,
+
+    /// The packages that should have been removed but were kept because they are in use.
+    pub in_use: Vec<CacheEntry>,
+}
+
+/// Receives progress updates while a package is downloaded and extracted by
+/// [`PackageCache::get_or_fetch_from_url_with_reporter`]. All methods have an implementation that
+/// does nothing, implementors only have to override the events they are interested in.
+pub trait DownloadReporter: Send + Sync {
+    /// Called when the package starts to be downloaded. If the download fails and is retried this
+    /// is called again.
+    fn on_download_start(&self) {}
+
+    /// Called when bytes of the package have been downloaded. `bytes` is the total number of bytes
+    /// downloaded so far, `total` is the size of the package archive if it is known.
+    fn on_download_progress(&self, _bytes: u64, _total: Option<u64>) {}
+
+    /// Called when the package has been downloaded completely and the archive has been verified.
+    fn on_download_complete(&self) {}
+
+    /// Called when the package starts to be extracted into the cache.
+    fn on_extract_start(&self) {}
+
+    /// Called when the package has been extracted into the cache.
+    fn on_extract_complete(&self) {}
 }
 
 /// An error that might be returned from one of the caching function of the [`PackageCache`].
@@ -84,33 +310,61 @@ pub enum PackageCacheError {
     /// An error occurred while fetching the package.
     #[error(transparent)]
     FetchError(#[from] Arc<dyn std::error::Error + Send + Sync + 'static>),
+
+    /// The package is not in the cache and none of the layers of the cache is writable.
+    #[error("the package is not cached and none of the package cache layers is writable")]
+    NoWritableLayer,
+
+    /// An IO error occurred while storing a package in the cache.
+    #[error("{0}")]
+    IoError(String, #[source] Arc<std::io::Error>),
 }
 
 impl PackageCache {
     /// Constructs a new [`PackageCache`] located at the specified path.
     pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self::with_layers([CacheLayer::writable(path)])
+    }
+
+    /// Constructs a new [`PackageCache`] that consists of the given layers.
+    ///
+    /// Packages are looked up in every layer in the given order, the first layer that contains a
+    /// valid copy of a package is used. Packages that are not found are fetched into the first
+    /// writable layer.
+    pub fn with_layers(layers: impl IntoIterator<Item = CacheLayer>) -> Self {
         Self {
             inner: Arc::new(Mutex::new(PackageCacheInner {
-                path: path.into(),
+                layers: layers.into_iter().collect(),
                 packages: FxHashMap::default(),
             })),
         }
     }
 
-    /// Returns the directory that contains the specified package.
+    /// Returns the layers of the cache in the order in which they are searched.
+    pub fn layers(&self) -> Vec<CacheLayer> {
+        self.inner.lock().unwrap().layers.clone()
+    }
+
+    /// Returns the directory that contains the specified package, together with a lock that keeps
+    /// the package in the cache while it is in use, see [`CacheLock`].
     ///
-    /// If the package was previously successfully fetched and stored in the cache the directory
-    /// containing the data is returned immediately. If the package was not previously fetch the
-    /// filesystem is checked to see if a directory with valid package content exists. Otherwise,
-    /// the user provided `fetch` function is called to populate the cache.
+    /// If the package was previously successfully fetched and is still locked the directory
+    /// containing the data is returned immediately. Otherwise the layers of the cache are checked
+    /// to see if a directory with valid package content exists. If none does, the user provided
+    /// `fetch` function is called to populate the first writable layer.
     ///
     /// If the package is already being fetched by another task/thread the request is coalesced. No
-    /// duplicate fetch is performed.
+    /// duplicate fetch is performed. Processes that share the cache directory coordinate through a
+    /// lock file per package, a process that finds the package locked waits for the other process
+    /// to finish fetching it.
+    ///
+    /// The `fetch` function is called with a temporary directory which is moved into place once the
+    /// package is complete, other processes never observe a partially populated package.
     pub async fn get_or_fetch<F, Fut, E>(
         &self,
         pkg: impl Into<CacheKey>,
         fetch: F,
-    ) -> Result<PathBuf, PackageCacheError>
+    ) -> Result<CacheLock, PackageCacheError>
     where
         F: (FnOnce(PathBuf) -> Fut) + Send + 'static,
         Fut: Future<Output = Result<(), E>> + Send + 'static,
@@ -119,11 +373,11 @@ impl PackageCache {
         let cache_key = pkg.into();
 
         // Get the package entry
-        let (package, pkg_cache_dir) = {
+        let (package, layers, dir_name) = {
             let mut inner = self.inner.lock().unwrap();
-            let destination = inner.path.join(cache_key.to_string());
+            let dir_name = cache_key.to_string();
             let package = inner.packages.entry(cache_key).or_default().clone();
-            (package, destination)
+            (package, inner.layers.clone(), dir_name)
         };
 
         let mut rx = {
@@ -131,8 +385,8 @@ impl PackageCache {
             let mut inner = package.lock().unwrap();
 
             // If there exists an existing value in our cache, we can return that.
-            if let Some(path) = inner.path.as_ref() {
-                return Ok(path.clone());
+            if let Some(cached) = inner.cached() {
+                return Ok(cached);
             }
 
             // Is there an in-flight requests for the package?
@@ -145,10 +399,8 @@ impl PackageCache {
 
                 let package = package.clone();
                 tokio::spawn(async move {
-                    let result = validate_or_fetch_to_cache(pkg_cache_dir.clone(), fetch)
-                        .instrument(
-                            tracing::debug_span!("validating", path = %pkg_cache_dir.display()),
-                        )
+                    let result = validate_or_fetch_to_cache(&layers, &dir_name, fetch)
+                        .instrument(tracing::debug_span!("validating", package = %dir_name))
                         .await;
 
                     {
@@ -157,9 +409,12 @@ impl PackageCache {
                         package.inflight = None;
 
                         match result {
-                            Ok(_) => {
-                                package.path.replace(pkg_cache_dir.clone());
-                                let _ = tx.send(Ok(pkg_cache_dir));
+                            Ok(cache_lock) => {
+                                package.path = Some((
+                                    cache_lock.path.clone(),
+                                    cache_lock.lock.as_ref().map(Arc::downgrade),
+                                ));
+                                let _ = tx.send(Ok(cache_lock));
                             }
                             Err(e) => {
                                 let _ = tx.send(Err(e));
@@ -175,16 +430,210 @@ impl PackageCache {
         rx.recv().await.expect("in-flight request has died")
     }
 
+    /// Returns all the packages that are stored in the writable layers of the cache, ordered by
+    /// their path.
+    ///
+    /// The last access time of a package is the modification time of its directory, which is
+    /// updated every time the package is validated or fetched by a [`PackageCache`].
+    pub fn inventory(&self) -> Result<Vec<CacheEntry>, std::io::Error> {
+        let mut entries = Vec::new();
+        for layer_path in self.writable_layer_paths() {
+            let read_dir = match std::fs::read_dir(&layer_path) {
+                Ok(read_dir) => read_dir,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+
+            for entry in read_dir {
+                let entry = entry?;
+
+                // Hidden entries are temporary files of the cache itself.
+                if !entry.file_type()?.is_dir()
+                    || entry.file_name().to_string_lossy().starts_with('.')
+                {
+                    continue;
+                }
+
+                let path = entry.path();
+                entries.push(CacheEntry {
+                    size: directory_size(&path)?,
+                    last_access: entry.metadata()?.modified()?,
+                    path,
+                });
+            }
+        }
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(entries)
+    }
+
+    /// Returns the directories of the layers that packages can be fetched into.
+    fn writable_layer_paths(&self) -> Vec<PathBuf> {
+        self.inner
+            .lock()
+            .unwrap()
+            .layers
+            .iter()
+            .filter(|layer| layer.writable)
+            .map(|layer| layer.path.clone())
+            .collect()
+    }
+
+    /// Removes packages from the writable layers of the cache according to the given policy.
+    ///
+    /// Packages that are currently being fetched or validated by [`PackageCache::get_or_fetch`], or
+    /// for which a [`CacheLock`] is alive, are never removed because they are in use. A package is
+    /// only removed if its lock file can be locked exclusively, so packages that are fetched or
+    /// used by other processes are kept as well. The lock files of removed packages, and lock
+    /// files that no longer belong to a package, are removed.
+    pub fn clean(&self, policy: CleanPolicy) -> Result<CleanReport, std::io::Error> {
+        let mut entries = self.inventory()?;
+        let mut report = CleanReport::default();
+        let mut trash = Vec::new();
+
+        // Also remove the packages that a previous clean failed to remove and the lock files of
+        // packages that are no longer in the cache.
+        let layer_paths = self.writable_layer_paths();
+        let mut orphaned_lock_files = Vec::new();
+        for layer_path in &layer_paths {
+            if let Ok(read_dir) = std::fs::read_dir(layer_path) {
+                for entry in read_dir.filter_map(Result::ok) {
+                    let file_name = entry.file_name().to_string_lossy().into_owned();
+                    if file_name.starts_with(".trash-") {
+                        trash.push(entry.path());
+                    } else if let Some(dir_name) = file_name.strip_suffix(".lock") {
+                        let package_dir = layer_path.join(dir_name);
+                        if !package_dir.exists() {
+                            orphaned_lock_files.push((entry.path(), package_dir));
+                        }
+                    }
+                }
+            }
+        }
+
+        {
+            // No new fetches can start while the lock is held.
+            let inner = self.inner.lock().unwrap();
+            let packages = inner
+                .packages
+                .iter()
+                .flat_map(|(key, package)| {
+                    let dir_name = key.to_string();
+                    layer_paths
+                        .iter()
+                        .map(move |layer_path| (layer_path.join(&dir_name), package))
+                })
+                .collect::<HashMap<_, _>>();
+
+            let mut remove = |entry: CacheEntry, report: &mut CleanReport| {
+                // Make sure the package cannot be fetched while it is moved out of the way.
+                let package = packages
+                    .get(&entry.path)
+                    .map(|package| package.lock().unwrap());
+                if package.as_ref().map_or(false, |package| {
+                    package.inflight.is_some() || package.cached().is_some()
+                }) {
+                    report.in_use.push(entry);
+                    return false;
+                }
+
+                // Other processes hold the lock of the package while they fetch or use it.
+                let lock_path = package_lock_path(&entry.path);
+                let lock = match try_lock_package(&lock_path) {
+                    Ok(Some(lock)) => lock,
+                    Ok(None) => {
+                        report.in_use.push(entry);
+                        return false;
+                    }
+                    Err(e) => {
+                        tracing::warn!("failed to lock {}: {e}", lock_path.display());
+                        return false;
+                    }
+                };
+
+                // Move the package to a temporary location so it can be removed without holding
+                // the lock.
+                let trash_path = entry
+                    .path
+                    .with_file_name(format!(".trash-{}", uuid::Uuid::new_v4()));
+                match std::fs::rename(&entry.path, &trash_path) {
+                    Ok(()) => {
+                        remove_lock_file(lock);
+                        trash.push(trash_path);
+                        report.removed.push(entry);
+                        true
+                    }
+                    Err(e) => {
+                        tracing::warn!("failed to remove {}: {e}", entry.path.display());
+                        false
+                    }
+                }
+            };
+
+            match policy {
+                CleanPolicy::MaxSize(max_size) => {
+                    // Remove the least recently used packages first.
+                    entries.sort_by_key(|entry| entry.last_access);
+                    let mut total_size = entries.iter().map(|entry| entry.size).sum::<u64>();
+                    for entry in entries {
+                        if total_size <= max_size {
+                            break;
+                        }
+                        let size = entry.size;
+                        if remove(entry, &mut report) {
+                            total_size -= size;
+                        }
+                    }
+                }
+                CleanPolicy::KeepOnly(keys) => {
+                    // Packages without a hash are also stored without one, see `CacheKey`.
+                    let keep = keys
+                        .iter()
+                        .flat_map(|key| [key.to_string(), key.unhashed_name()])
+                        .flat_map(|name| layer_paths.iter().map(move |path| path.join(&name)))
+                        .collect::<HashSet<_>>();
+                    for entry in entries {
+                        if !keep.contains(&entry.path) {
+                            remove(entry, &mut report);
+                        }
+                    }
+                }
+            }
+
+            for (lock_path, package_dir) in orphaned_lock_files {
+                // Another process might be fetching the package right now.
+                match try_lock_package(&lock_path) {
+                    Ok(Some(lock)) => {
+                        if !package_dir.exists() {
+                            remove_lock_file(lock);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => tracing::warn!("failed to lock {}: {e}", lock_path.display()),
+                }
+            }
+        }
+
+        for path in trash {
+            match std::fs::remove_dir_all(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+
+        Ok(report)
+    }
+
     /// Returns the directory that contains the specified package.
     ///
     /// This is a convenience wrapper around `get_or_fetch` which fetches the package from the given
-    /// URL if the package could not be found in the cache.
+    /// URL if the package could not be found in the cache. The downloaded archive is verified
+    /// against the hashes of the [`CacheKey`] before it is extracted.
     pub async fn get_or_fetch_from_url(
         &self,
         pkg: impl Into<CacheKey>,
         url: Url,
         client: reqwest_middleware::ClientWithMiddleware,
-    ) -> Result<PathBuf, PackageCacheError> {
+    ) -> Result<CacheLock, PackageCacheError> {
         self.get_or_fetch_from_url_with_retry(pkg, url, client, DoNotRetryPolicy)
             .await
     }
@@ -192,23 +641,55 @@ impl PackageCache {
     /// Returns the directory that contains the specified package.
     ///
     /// This is a convenience wrapper around `get_or_fetch` which fetches the package from the given
-    /// URL if the package could not be found in the cache.
+    /// URL if the package could not be found in the cache. The downloaded archive is verified
+    /// against the hashes of the [`CacheKey`] before it is extracted. Failed downloads are retried
+    /// according to the `retry_policy`.
+    ///
+    /// The archive is downloaded to a partial file that is kept between attempts. If the server
+    /// supports range requests, a retry only downloads the part of the archive that is missing.
     pub async fn get_or_fetch_from_url_with_retry(
         &self,
         pkg: impl Into<CacheKey>,
         url: Url,
         client: reqwest_middleware::ClientWithMiddleware,
         retry_policy: impl RetryPolicy + Send + 'static,
-    ) -> Result<PathBuf, PackageCacheError> {
-        self.get_or_fetch(pkg, move |destination| async move {
+    ) -> Result<CacheLock, PackageCacheError> {
+        self.get_or_fetch_from_url_with_reporter(pkg, url, client, retry_policy, None)
+            .await
+    }
+
+    /// Returns the directory that contains the specified package.
+    ///
+    /// This is the same as [`PackageCache::get_or_fetch_from_url_with_retry`], except that the
+    /// progress of the download and the extraction of the package is reported to `reporter`.
+    pub async fn get_or_fetch_from_url_with_reporter(
+        &self,
+        pkg: impl Into<CacheKey>,
+        url: Url,
+        client: reqwest_middleware::ClientWithMiddleware,
+        retry_policy: impl RetryPolicy + Send + 'static,
+        reporter: Option<Arc<dyn DownloadReporter>>,
+    ) -> Result<CacheLock, PackageCacheError> {
+        let cache_key = pkg.into();
+        let sha256 = cache_key.sha256;
+        let md5 = cache_key.md5;
+        self.get_or_fetch(cache_key, move |destination| async move {
+            let archive_type = ArchiveType::try_from(Path::new(url.path()))
+                .ok_or(ExtractError::UnsupportedArchiveType)?;
+            let archive = create_partial_file(&destination).await?;
             let mut current_try = 0;
             loop {
                 current_try += 1;
                 tracing::debug!("downloading {} to {}", &url, destination.display());
-                let result = rattler_package_streaming::reqwest::tokio::extract(
+                let result = download_and_extract(
                     client.clone(),
                     url.clone(),
+                    archive_type,
+                    sha256.as_ref(),
+                    md5.as_ref(),
+                    archive.path(),
                     &destination,
+                    reporter.as_deref(),
                 )
                 .await;
 
@@ -216,16 +697,7 @@ impl PackageCache {
                 let Err(err) = result else { return Ok(()); };
 
                 // Only retry on certain errors.
-                if !matches!(
-                    &err,
-                    ExtractError::IoError(_) | ExtractError::CouldNotCreateDestination(_)
-                ) && !matches!(&err, ExtractError::ReqwestError(err) if
-                    err.is_timeout() ||
-                    err.is_connect() ||
-                    err
-                        .status()
-                        .map_or(false, |status| status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::REQUEST_TIMEOUT)
-                ) {
+                if !is_retryable_extract_error(&err) {
                     return Err(err);
                 }
 
@@ -253,68 +725,537 @@ impl PackageCache {
     }
 }
 
-/// Validates that the package that is currently stored is a valid package and otherwise calls the
-/// `fetch` method to populate the cache.
+/// Returns the total size of the files in the given directory.
+fn directory_size(path: &Path) -> Result<u64, std::io::Error> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() {
+            directory_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+    Ok(size)
+}
+
+/// Marks the package at the given path as used now, see [`PackageCache::inventory`].
+fn touch(path: &Path) {
+    if let Err(e) = filetime::set_file_mtime(path, filetime::FileTime::now()) {
+        tracing::debug!(
+            "failed to update the access time of {}: {e}",
+            path.display()
+        );
+    }
+}
+
+/// Downloads the package archive at `url` to the partial file at `archive`, verifies it against
+/// the expected hashes and extracts it to `destination`.
+#[allow(clippy::too_many_arguments)]
+async fn download_and_extract(
+    client: reqwest_middleware::ClientWithMiddleware,
+    url: Url,
+    archive_type: ArchiveType,
+    sha256: Option<&Sha256Hash>,
+    md5: Option<&Md5Hash>,
+    archive: &Path,
+    destination: &Path,
+    reporter: Option<&dyn DownloadReporter>,
+) -> Result<(), ExtractError> {
+    if let Some(reporter) = reporter {
+        reporter.on_download_start();
+    }
+    resume_download(client, url, archive, reporter).await?;
+    if let Err(err) = verify_archive(archive, sha256, md5).await {
+        // The archive is corrupt, it cannot be resumed.
+        tokio::fs::File::create(archive).await?;
+        return Err(err);
+    }
+
+    if let Some(reporter) = reporter {
+        reporter.on_download_complete();
+        reporter.on_extract_start();
+    }
+    extract_archive(archive, archive_type, destination).await?;
+    if let Some(reporter) = reporter {
+        reporter.on_extract_complete();
+    }
+    Ok(())
+}
+
+/// Creates an empty file next to `destination` that a package archive can be downloaded to. The
+/// file is deleted when the returned value is dropped.
+async fn create_partial_file(destination: &Path) -> Result<NamedTempFile, ExtractError> {
+    let dir = destination.parent().unwrap_or(destination);
+    tokio::fs::create_dir_all(dir)
+        .await
+        .map_err(ExtractError::CouldNotCreateDestination)?;
+    tempfile::Builder::new()
+        .prefix(".partial-")
+        .tempfile_in(dir)
+        .map_err(ExtractError::CouldNotCreateDestination)
+}
+
+/// Downloads the file at `url` to `path`. If `path` already contains the start of the file, for
+/// instance because a previous attempt was interrupted, only the remaining part is requested with
+/// a `Range` request. Servers that do not support range requests send the whole file, in which
+/// case the download starts over.
+async fn resume_download(
+    client: reqwest_middleware::ClientWithMiddleware,
+    url: Url,
+    path: &Path,
+    reporter: Option<&dyn DownloadReporter>,
+) -> Result<(), ExtractError> {
+    let mut file = tokio::fs::OpenOptions::new().write(true).open(path).await?;
+    let offset = file.metadata().await?.len();
+
+    // Files on disk are never interrupted, there is nothing to resume.
+    if url.scheme() == "file" {
+        file.set_len(0).await?;
+        let total = url
+            .to_file_path()
+            .ok()
+            .and_then(|path| std::fs::metadata(path).ok())
+            .map(|metadata| metadata.len());
+        let reader = rattler_package_streaming::reqwest::tokio::get_reader(url, client).await?;
+        return copy_with_progress(reader, &mut file, 0, total, reporter).await;
+    }
+
+    let mut request = client.get(url.clone());
+    if offset > 0 {
+        request = request.header(reqwest::header::RANGE, format!("bytes={offset}-"));
+    }
+    let response = request.send().await?;
+
+    // The file only ever contains bytes that were received from the server, so if the range
+    // cannot be satisfied the file is already complete.
+    if offset > 0 && response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        return Ok(());
+    }
+    let response = response.error_for_status()?;
+    let start = if offset > 0 && response.status() == StatusCode::PARTIAL_CONTENT {
+        tracing::debug!("resuming the download of {url} at byte {offset}");
+        file.seek(SeekFrom::Start(offset)).await?;
+        offset
+    } else {
+        file.set_len(0).await?;
+        0
+    };
+    let total = response.content_length().map(|length| start + length);
+
+    // An interrupted connection is an IO error, which makes it retryable.
+    let stream = response
+        .bytes_stream()
+        .map(|chunk| chunk.map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err)));
+    copy_with_progress(StreamReader::new(stream), &mut file, start, total, reporter).await
+}
+
+/// Appends everything read from `reader` to `file`. The number of bytes in the file is reported
+/// after every chunk, `bytes` is the number of bytes the file already contains.
+async fn copy_with_progress(
+    reader: impl AsyncRead,
+    file: &mut tokio::fs::File,
+    mut bytes: u64,
+    total: Option<u64>,
+    reporter: Option<&dyn DownloadReporter>,
+) -> Result<(), ExtractError> {
+    tokio::pin!(reader);
+    let mut buf = vec![0; 64 * 1024];
+    let result = async {
+        loop {
+            let bytes_read = reader.read(&mut buf).await?;
+            if bytes_read == 0 {
+                return Ok(());
+            }
+            file.write_all(&buf[..bytes_read]).await?;
+            bytes += bytes_read as u64;
+            if let Some(reporter) = reporter {
+                reporter.on_download_progress(bytes, total);
+            }
+        }
+    }
+    .await;
+
+    // Make sure everything that was received ends up in the file, so a next attempt can resume
+    // from it.
+    file.flush().await?;
+    result
+}
+
+/// Verifies the package archive at `path` against the expected hashes.
+async fn verify_archive(
+    path: &Path,
+    sha256: Option<&Sha256Hash>,
+    md5: Option<&Md5Hash>,
+) -> Result<(), ExtractError> {
+    if sha256.is_none() && md5.is_none() {
+        return Ok(());
+    }
+
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut sha256_hasher = Sha256::default();
+    let mut md5_hasher = Md5::default();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let bytes_read = file.read(&mut buf).await?;
+        if bytes_read == 0 {
+            break;
+        }
+        sha256_hasher.update(&buf[..bytes_read]);
+        md5_hasher.update(&buf[..bytes_read]);
+    }
+    verify_hashes(
+        &sha256_hasher.finalize(),
+        &md5_hasher.finalize(),
+        sha256,
+        md5,
+    )
+}
+
+/// Returns an error if the hashes of an archive do not match the expected hashes.
+fn verify_hashes(
+    actual_sha256: &Sha256Hash,
+    actual_md5: &Md5Hash,
+    sha256: Option<&Sha256Hash>,
+    md5: Option<&Md5Hash>,
+) -> Result<(), ExtractError> {
+    if let Some(expected) = sha256.filter(|&expected| expected != actual_sha256) {
+        return Err(ExtractError::HashMismatch {
+            algorithm: "sha256",
+            expected: format!("{expected:x}"),
+            actual: format!("{actual_sha256:x}"),
+        });
+    }
+    if let Some(expected) = md5.filter(|&expected| expected != actual_md5) {
+        return Err(ExtractError::HashMismatch {
+            algorithm: "md5",
+            expected: format!("{expected:x}"),
+            actual: format!("{actual_md5:x}"),
+        });
+    }
+    Ok(())
+}
+
+/// Extracts the package archive at `archive` to `destination`.
+async fn extract_archive(
+    archive: &Path,
+    archive_type: ArchiveType,
+    destination: &Path,
+) -> Result<(), ExtractError> {
+    match archive_type {
+        ArchiveType::TarBz2 => {
+            rattler_package_streaming::tokio::fs::extract_tar_bz2(archive, destination).await?;
+        }
+        ArchiveType::Conda => {
+            rattler_package_streaming::tokio::fs::extract_conda(archive, destination).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if fetching a package that failed with the given error might succeed when it is
+/// tried again.
+fn is_retryable_extract_error(err: &ExtractError) -> bool {
+    matches!(
+        err,
+        ExtractError::IoError(_) | ExtractError::CouldNotCreateDestination(_)
+    ) || matches!(err, ExtractError::ReqwestError(err) if
+        err.is_timeout() ||
+        err.is_connect() ||
+        err
+            .status()
+            .map_or(false, |status| status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::REQUEST_TIMEOUT)
+    )
+}
+
+/// Looks for a valid copy of the package in the directory `dir_name` of every layer, in order. If
+/// none of the layers contains one, the package is fetched into the first writable layer. Returns
+/// the directory that contains the package, locked so that it is not removed while it is in use.
 async fn validate_or_fetch_to_cache<F, Fut, E>(
-    path: PathBuf,
+    layers: &[CacheLayer],
+    dir_name: &str,
     fetch: F,
-) -> Result<(), PackageCacheError>
+) -> Result<CacheLock, PackageCacheError>
 where
     F: FnOnce(PathBuf) -> Fut + Send,
     Fut: Future<Output = Result<(), E>> + 'static,
     E: std::error::Error + Send + Sync + 'static,
 {
-    // If the directory already exists validate the contents of the package
-    if path.is_dir() {
-        let path_inner = path.clone();
-        match tokio::task::spawn_blocking(move || validate_package_directory(&path_inner)).await {
-            Ok(Ok(_)) => {
-                tracing::debug!("validation succeeded");
-                return Ok(());
-            }
-            Ok(Err(e)) => {
-                tracing::warn!("validation failed: {e}",);
-                if let Some(cause) = e.source() {
-                    tracing::debug!(
-                        "  Caused by: {}",
-                        std::iter::successors(Some(cause), |e| (*e).source())
-                            .format("\n  Caused by: ")
-                    );
-                }
-            }
-            Err(e) => {
-                if let Ok(panic) = e.try_into_panic() {
-                    std::panic::resume_unwind(panic)
-                }
+    let fetch_layer = layers.iter().find(|layer| layer.writable);
+
+    // If the directory already exists in one of the layers validate the contents of the package
+    for layer in layers {
+        let path = layer.path.join(dir_name);
+
+        // Packages in writable layers are locked before they are validated, so they cannot be
+        // removed by another process after they have been found valid. Locking the layer that
+        // the package would be fetched into also waits for other processes that are fetching it.
+        let lock = if layer.writable && (path.is_dir() || fetch_layer == Some(layer)) {
+            Some(lock_package(&layer.path, dir_name, FileLockMode::Shared).await?)
+        } else {
+            None
+        };
+        if path.is_dir() && validate_package(path.clone()).await {
+            if layer.writable {
+                touch(&path);
             }
+            return Ok(CacheLock {
+                path,
+                lock: lock.map(Arc::new),
+            });
         }
     }
 
     // Otherwise, defer to populate method to fill our cache.
-    fetch(path)
-        .await
-        .map_err(|e| PackageCacheError::FetchError(Arc::new(e)))
+    let layer = fetch_layer.ok_or(PackageCacheError::NoWritableLayer)?;
+    let path = layer.path.join(dir_name);
+
+    let lock_path = package_lock_path(&path);
+    let mut fetch = Some(fetch);
+    loop {
+        // Other processes might populate the same cache at the same time. Only the one that holds
+        // the exclusive lock of the package fetches it. The lock is not waited for, because it
+        // cannot be taken while the package is in use, possibly by the caller itself.
+        let lock = try_lock_package(&lock_path)
+            .map_err(|e| {
+                PackageCacheError::IoError(
+                    format!("failed to lock {}", lock_path.display()),
+                    Arc::new(e),
+                )
+            })?
+            .filter(|lock| !is_lock_file_removed(lock));
+        let fetched = lock.is_some();
+        if let Some(lock) = lock {
+            if !(path.is_dir() && validate_package(path.clone()).await) {
+                // The package can only be fetched once, if it is removed again by another process
+                // before it is locked for use the caller has to retry.
+                let fetch = fetch.take().ok_or_else(|| {
+                    PackageCacheError::IoError(
+                        format!(
+                            "{} was removed from the cache by another process",
+                            path.display()
+                        ),
+                        Arc::new(std::io::ErrorKind::NotFound.into()),
+                    )
+                })?;
+                fetch_to_cache(layer, &path, dir_name, fetch).await?;
+            }
+            touch(&path);
+            drop(lock);
+        }
+
+        // Waits for another process that is fetching the package, the package stays locked while
+        // it is in use.
+        let lock = lock_package(&layer.path, dir_name, FileLockMode::Shared).await?;
+        if path.is_dir() && (fetched || validate_package(path.clone()).await) {
+            return Ok(CacheLock {
+                path,
+                lock: Some(Arc::new(lock)),
+            });
+        }
+        drop(lock);
+
+        // The package is invalid but in use, or the fetch of another process failed.
+        if !fetched {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
+
+/// Fetches the package into the directory `path` of the writable `layer`. The caller must hold the
+/// exclusive lock of the package.
+async fn fetch_to_cache<F, Fut, E>(
+    layer: &CacheLayer,
+    path: &Path,
+    dir_name: &str,
+    fetch: F,
+) -> Result<(), PackageCacheError>
+where
+    F: FnOnce(PathBuf) -> Fut + Send,
+    Fut: Future<Output = Result<(), E>> + 'static,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    // Fetch the package into a temporary directory and only move it into place once it is
+    // complete. This ensures that no process ever observes a partially populated package.
+    let temp_path = layer
+        .path
+        .join(format!(".tmp-{dir_name}-{}", uuid::Uuid::new_v4()));
+    if let Err(e) = fetch(temp_path.clone()).await {
+        remove_dir_if_exists(&temp_path);
+        return Err(PackageCacheError::FetchError(Arc::new(e)));
+    }
+
+    // The existing directory is invalid, move it out of the way first because a directory cannot
+    // be renamed onto a directory that is not empty.
+    if path.exists() {
+        let trash_path = layer.path.join(format!(".trash-{}", uuid::Uuid::new_v4()));
+        std::fs::rename(path, &trash_path).map_err(|e| {
+            PackageCacheError::IoError(format!("failed to remove {}", path.display()), Arc::new(e))
+        })?;
+        remove_dir_if_exists(&trash_path);
+    }
+    if let Err(e) = std::fs::rename(&temp_path, path) {
+        remove_dir_if_exists(&temp_path);
+        return Err(PackageCacheError::IoError(
+            format!("failed to move the package to {}", path.display()),
+            Arc::new(e),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Returns the location of the lock file of the package stored in `package_dir`.
+fn package_lock_path(package_dir: &Path) -> PathBuf {
+    let mut file_name = package_dir.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".lock");
+    package_dir.with_file_name(file_name)
+}
+
+/// Acquires the lock of the package with the given directory name in the layer at `layer_path`,
+/// waiting for as long as another process holds a conflicting lock. The exclusive lock is held
+/// while the package is fetched, shared locks while it is in use. The lock is released when the
+/// returned file is dropped.
+async fn lock_package(
+    layer_path: &Path,
+    dir_name: &str,
+    mode: FileLockMode,
+) -> Result<LockedFile, PackageCacheError> {
+    let lock_path = package_lock_path(&layer_path.join(dir_name));
+    tokio::task::spawn_blocking(move || loop {
+        let lock = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path)
+            .and_then(|file| LockedFile::lock(file, &lock_path, mode, "package cache entry"))
+            .map_err(|e| {
+                PackageCacheError::IoError(
+                    format!("failed to lock {}", lock_path.display()),
+                    Arc::new(e),
+                )
+            })?;
+
+        // `PackageCache::clean` removes the lock file of a package that it evicts. If that
+        // happened while waiting for the lock, the lock has to be taken on the new file instead.
+        if !is_lock_file_removed(&lock) {
+            return Ok(lock);
+        }
+    })
+    .await
+    .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+}
+
+/// Tries to acquire the lock of a package without waiting. Returns `None` if another process holds
+/// the lock.
+fn try_lock_package(lock_path: &Path) -> std::io::Result<Option<LockedFile>> {
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(lock_path)?;
+    Ok(LockedFile::try_lock(file, lock_path, FileLockMode::Exclusive)?.ok())
+}
+
+/// Removes the lock file of a package that is no longer in the cache, failures are only logged.
+/// The file is removed before the lock is released, see [`lock_package`].
+fn remove_lock_file(lock: LockedFile) {
+    match std::fs::remove_file(lock.path()) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            tracing::warn!("failed to remove {}: {e}", lock.path().display());
+        }
+        _ => {}
+    }
+}
+
+/// Returns true if the lock file was removed or replaced by another file after it was opened.
+fn is_lock_file_removed(lock: &LockedFile) -> bool {
+    let Ok(file) = lock
+        .file()
+        .try_clone()
+        .and_then(same_file::Handle::from_file)
+    else {
+        return false;
+    };
+    match same_file::Handle::from_path(lock.path()) {
+        Ok(current) => current != file,
+        Err(e) => e.kind() == std::io::ErrorKind::NotFound,
+    }
+}
+
+/// Removes a directory that is no longer needed, failures are only logged.
+fn remove_dir_if_exists(path: &Path) {
+    match std::fs::remove_dir_all(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            tracing::warn!("failed to remove {}: {e}", path.display());
+        }
+        _ => {}
+    }
+}
+
+/// Returns true if the directory contains a valid package.
+async fn validate_package(path: PathBuf) -> bool {
+    let display_path = path.display().to_string();
+    match tokio::task::spawn_blocking(move || validate_package_directory(&path)).await {
+        Ok(Ok(_)) => {
+            tracing::debug!("validation of {display_path} succeeded");
+            true
+        }
+        Ok(Err(e)) => {
+            tracing::warn!("validation of {display_path} failed: {e}",);
+            if let Some(cause) = e.source() {
+                tracing::debug!(
+                    "  Caused by: {}",
+                    std::iter::successors(Some(cause), |e| (*e).source()).format("\n  Caused by: ")
+                );
+            }
+            false
+        }
+        Err(e) => match e.try_into_panic() {
+            Ok(panic) => std::panic::resume_unwind(panic),
+            Err(_) => false,
+        },
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::PackageCache;
+    use super::{CacheKey, CacheLayer, CleanPolicy, PackageCache, PackageCacheError};
     use crate::{get_test_data_dir, validation::validate_package_directory};
     use assert_matches::assert_matches;
     use axum::{
+        body::{boxed, Bytes, HttpBody, StreamBody},
         extract::State,
-        http::{Request, StatusCode},
+        http::{header, Request, StatusCode},
         middleware,
         middleware::Next,
         response::Response,
         routing::get_service,
         Router,
     };
+    use futures::stream;
     use rattler_conda_types::package::{ArchiveIdentifier, PackageFile, PathsJson};
+    use rattler_conda_types::{LockedFile, PackageName, PackageRecord, Version};
+    use rattler_digest::{
+        compute_bytes_digest, compute_file_digest, parse_digest_from_hex, Md5, Sha256,
+    };
     use rattler_networking::retry_policies::{DoNotRetryPolicy, ExponentialBackoffBuilder};
-    use std::{fs::File, net::SocketAddr, path::Path, sync::Arc};
+    use rattler_package_streaming::ExtractError;
+    use std::{
+        fs::File,
+        net::SocketAddr,
+        path::{Path, PathBuf},
+        process::Command,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
     use tempfile::tempdir;
-    use tokio::sync::Mutex;
+    use tokio::sync::{oneshot, Mutex};
     use tower_http::services::ServeDir;
     use url::Url;
 
@@ -352,13 +1293,445 @@ mod test {
             .unwrap();
 
         // Validate the contents of the package
-        let (_, current_paths) = validate_package_directory(&package_dir).unwrap();
+        let (_, current_paths) = validate_package_directory(package_dir.path()).unwrap();
 
         // Make sure that the paths are the same as what we would expect from the original tar
         // archive.
         assert_eq!(current_paths, paths);
     }
 
+    #[tokio::test]
+    pub async fn test_layered_cache() {
+        let tar_archive_path =
+            get_test_data_dir().join("ros-noetic-rosbridge-suite-0.11.14-py39h6fdeb60_14.tar.bz2");
+        let cache_key =
+            CacheKey::from(ArchiveIdentifier::try_from_path(&tar_archive_path).unwrap());
+
+        // Populate a read-only layer with the package.
+        let shared_dir = tempdir().unwrap();
+        let shared_package_dir = shared_dir.path().join(cache_key.to_string());
+        rattler_package_streaming::fs::extract(&tar_archive_path, &shared_package_dir).unwrap();
+
+        // The package is found in the read-only layer, even though it comes after the writable one.
+        let user_dir = tempdir().unwrap();
+        let cache = PackageCache::with_layers([
+            CacheLayer::writable(user_dir.path()),
+            CacheLayer::read_only(shared_dir.path()),
+        ]);
+        let package_dir = cache
+            .get_or_fetch(cache_key.clone(), |_| async {
+                Err::<(), _>(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    "the package should not be fetched",
+                ))
+            })
+            .await
+            .unwrap();
+        assert_eq!(package_dir.path(), shared_package_dir);
+
+        // Packages that are not in any layer are fetched into the first writable layer.
+        let cache = PackageCache::with_layers([
+            CacheLayer::read_only(shared_dir.path()),
+            CacheLayer::writable(user_dir.path()),
+        ]);
+        let package_dir = cache
+            .get_or_fetch(
+                ArchiveIdentifier::try_from_filename("foo-1.0-0.tar.bz2").unwrap(),
+                |destination| async move { std::fs::create_dir_all(destination) },
+            )
+            .await
+            .unwrap();
+        assert_eq!(package_dir.path(), user_dir.path().join("foo-1.0-0"));
+
+        // Without a writable layer missing packages cannot be fetched.
+        let cache = PackageCache::with_layers([CacheLayer::read_only(shared_dir.path())]);
+        let result = cache
+            .get_or_fetch(
+                ArchiveIdentifier::try_from_filename("foo-1.0-0.tar.bz2").unwrap(),
+                |destination| async move { std::fs::create_dir_all(destination) },
+            )
+            .await;
+        assert_matches!(result, Err(PackageCacheError::NoWritableLayer));
+        assert!(!shared_dir.path().join("foo-1.0-0").exists());
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let record = PackageRecord::new(
+            PackageName::new_unchecked("foo"),
+            "1.0".parse::<Version>().unwrap(),
+            "py_0".to_owned(),
+        );
+        let key = CacheKey::from(&record);
+        assert_eq!(key.to_string(), "foo-1.0-py_0");
+
+        let sha256 = parse_digest_from_hex::<Sha256>(
+            "1d1c4a4e2f7d2a4c4c6d3f0b8c0a3e3f4b7e8a0e9f8c7b6a5d4c3b2a1f0e9d8c",
+        )
+        .unwrap();
+        let hashed_key = key.clone().with_sha256(sha256);
+        assert_eq!(hashed_key.to_string(), "foo-1.0-py_0-1d1c4a4e2f7d2a4c");
+        assert_ne!(key, hashed_key);
+
+        // The md5 hash is only used for verification.
+        let md5 = parse_digest_from_hex::<Md5>("4f6ae6a2e4b3c0e4e3d2c1b0a9f8e7d6").unwrap();
+        assert_eq!(hashed_key.clone().with_md5(md5), hashed_key);
+    }
+
+    #[tokio::test]
+    pub async fn test_hash_mismatch() {
+        let archive_name = "ros-noetic-rosbridge-suite-0.11.14-py39h6fdeb60_14.tar.bz2";
+        let archive_path = get_test_data_dir().join(archive_name);
+        let packages_dir = tempdir().unwrap();
+        let cache = PackageCache::new(packages_dir.path());
+
+        let cache_key = CacheKey::from(ArchiveIdentifier::try_from_filename(archive_name).unwrap())
+            .with_sha256(compute_bytes_digest::<Sha256>(b"not the archive"));
+        let result = cache
+            .get_or_fetch_from_url(
+                cache_key.clone(),
+                Url::from_file_path(&archive_path).unwrap(),
+                reqwest::Client::default().into(),
+            )
+            .await;
+
+        // The archive is rejected before it is extracted.
+        assert_matches!(result, Err(_));
+        assert!(!packages_dir.path().join(cache_key.to_string()).exists());
+        assert_eq!(
+            dir_entries(packages_dir.path()),
+            [format!("{cache_key}.lock")]
+        );
+
+        // With the correct hash the package is extracted.
+        let cache_key = CacheKey::from(ArchiveIdentifier::try_from_filename(archive_name).unwrap())
+            .with_sha256(compute_file_digest::<Sha256>(&archive_path).unwrap());
+        let package_dir = cache
+            .get_or_fetch_from_url(
+                cache_key,
+                Url::from_file_path(&archive_path).unwrap(),
+                reqwest::Client::default().into(),
+            )
+            .await
+            .unwrap();
+        validate_package_directory(package_dir.path()).unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn test_clean() {
+        let packages_dir = tempdir().unwrap();
+        let cache = PackageCache::new(packages_dir.path());
+
+        // Create three packages of 100 bytes that were used one after the other.
+        for (idx, name) in ["a-1-0", "b-1-0", "c-1-0"].into_iter().enumerate() {
+            let package_dir = packages_dir.path().join(name);
+            std::fs::create_dir_all(package_dir.join("info")).unwrap();
+            std::fs::write(package_dir.join("info/index.json"), [b' '; 100]).unwrap();
+            filetime::set_file_mtime(
+                &package_dir,
+                filetime::FileTime::from_unix_time(1_000_000 + idx as i64, 0),
+            )
+            .unwrap();
+        }
+        let inventory = cache.inventory().unwrap();
+        assert_eq!(
+            inventory
+                .iter()
+                .map(|entry| entry.path.clone())
+                .collect::<Vec<_>>(),
+            ["a-1-0", "b-1-0", "c-1-0"].map(|name| packages_dir.path().join(name))
+        );
+        assert!(inventory.iter().all(|entry| entry.size == 100));
+
+        // Start fetching the least recently used package but do not finish it yet.
+        let (started_tx, started_rx) = oneshot::channel();
+        let (finish_tx, finish_rx) = oneshot::channel::<()>();
+        let fetch = tokio::spawn({
+            let cache = cache.clone();
+            async move {
+                cache
+                    .get_or_fetch(
+                        ArchiveIdentifier::try_from_filename("a-1-0.tar.bz2").unwrap(),
+                        move |destination| async move {
+                            started_tx.send(()).unwrap();
+                            let _ = finish_rx.await;
+                            std::fs::create_dir_all(destination)
+                        },
+                    )
+                    .await
+            }
+        });
+        started_rx.await.unwrap();
+
+        // The package that is being fetched is kept, even though it is the least recently used.
+        let report = cache.clean(CleanPolicy::MaxSize(150)).unwrap();
+        assert_eq!(report.in_use, inventory[..1]);
+        assert_eq!(report.removed, inventory[1..]);
+        assert!(packages_dir.path().join("a-1-0").is_dir());
+        assert!(!packages_dir.path().join("b-1-0").exists());
+        assert!(!packages_dir.path().join("c-1-0").exists());
+
+        finish_tx.send(()).unwrap();
+        fetch.await.unwrap().unwrap();
+
+        // Another instance of the cache, e.g. in another process, can remove packages that are
+        // not referenced. It also removes their lock files and lock files without a package.
+        for name in ["d-1-0", "e-1-0"] {
+            std::fs::create_dir_all(packages_dir.path().join(name)).unwrap();
+            std::fs::write(packages_dir.path().join(format!("{name}.lock")), "").unwrap();
+        }
+        std::fs::write(packages_dir.path().join("f-1-0.lock"), "").unwrap();
+
+        // Packages that are locked by another process are in use.
+        let lock = LockedFile::open_rw(packages_dir.path().join("e-1-0.lock"), "test").unwrap();
+        let report = PackageCache::new(packages_dir.path())
+            .clean(CleanPolicy::KeepOnly(vec![CacheKey::from(
+                ArchiveIdentifier::try_from_filename("a-1-0.conda").unwrap(),
+            )]))
+            .unwrap();
+        assert_eq!(
+            report
+                .removed
+                .iter()
+                .map(|entry| entry.path.clone())
+                .collect::<Vec<_>>(),
+            [packages_dir.path().join("d-1-0")]
+        );
+        assert_eq!(
+            report
+                .in_use
+                .iter()
+                .map(|entry| entry.path.clone())
+                .collect::<Vec<_>>(),
+            [packages_dir.path().join("e-1-0")]
+        );
+        assert_eq!(
+            dir_entries(packages_dir.path()),
+            ["a-1-0", "a-1-0.lock", "e-1-0", "e-1-0.lock"]
+        );
+
+        // Once the lock is released the package can be removed as well.
+        drop(lock);
+        let report = PackageCache::new(packages_dir.path())
+            .clean(CleanPolicy::KeepOnly(Vec::new()))
+            .unwrap();
+        assert_eq!(report.removed.len(), 2);
+        assert!(report.in_use.is_empty());
+        assert!(dir_entries(packages_dir.path()).is_empty());
+    }
+
+    #[tokio::test]
+    pub async fn test_clean_keeps_used_packages() {
+        let packages_dir = tempdir().unwrap();
+        let cache = PackageCache::new(packages_dir.path());
+        let package = cache
+            .get_or_fetch(
+                ArchiveIdentifier::try_from_filename("a-1-0.tar.bz2").unwrap(),
+                |destination| async move { std::fs::create_dir_all(destination) },
+            )
+            .await
+            .unwrap();
+
+        // Neither this instance nor another one removes a package while it is in use.
+        for cache in [cache.clone(), PackageCache::new(packages_dir.path())] {
+            let report = cache.clean(CleanPolicy::KeepOnly(Vec::new())).unwrap();
+            assert!(report.removed.is_empty());
+            assert_eq!(
+                report
+                    .in_use
+                    .iter()
+                    .map(|entry| entry.path.clone())
+                    .collect::<Vec<_>>(),
+                [package.path().to_path_buf()]
+            );
+        }
+
+        // Once the package is no longer used it can be removed.
+        drop(package);
+        let report = PackageCache::new(packages_dir.path())
+            .clean(CleanPolicy::KeepOnly(Vec::new()))
+            .unwrap();
+        assert_eq!(report.removed.len(), 1);
+        assert!(dir_entries(packages_dir.path()).is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    pub async fn test_concurrent_fetch() {
+        let tar_archive_path =
+            get_test_data_dir().join("ros-noetic-rosbridge-suite-0.11.14-py39h6fdeb60_14.tar.bz2");
+        let cache_key =
+            CacheKey::from(ArchiveIdentifier::try_from_path(&tar_archive_path).unwrap());
+        let packages_dir = tempdir().unwrap();
+        let package_dir = packages_dir.path().join(cache_key.to_string());
+
+        // Watch the package directory while it is being populated, it must never be observed in
+        // an incomplete state.
+        let done = Arc::new(AtomicBool::new(false));
+        let observer = std::thread::spawn({
+            let package_dir = package_dir.clone();
+            let done = done.clone();
+            move || {
+                let mut observed = false;
+                loop {
+                    let finished = done.load(Ordering::SeqCst);
+                    if package_dir.is_dir() {
+                        validate_package_directory(&package_dir).unwrap();
+                        observed = true;
+                    }
+                    if finished {
+                        return observed;
+                    }
+                }
+            }
+        });
+
+        // Separate instances of the cache do not share any state, just like separate processes.
+        let fetch_count = Arc::new(AtomicUsize::new(0));
+        let fetches = (0..2).map(|_| {
+            let cache = PackageCache::new(packages_dir.path());
+            let cache_key = cache_key.clone();
+            let tar_archive_path = tar_archive_path.clone();
+            let fetch_count = fetch_count.clone();
+            async move {
+                cache
+                    .get_or_fetch(cache_key, move |destination| async move {
+                        fetch_count.fetch_add(1, Ordering::SeqCst);
+                        rattler_package_streaming::tokio::fs::extract(
+                            &tar_archive_path,
+                            &destination,
+                        )
+                        .await?;
+
+                        // Give the other fetch and the observer time to look at the cache.
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        Ok::<_, ExtractError>(())
+                    })
+                    .await
+            }
+        });
+        let results = futures::future::join_all(fetches).await;
+        done.store(true, Ordering::SeqCst);
+        assert!(observer.join().unwrap());
+
+        // The package is only fetched once, the other fetch waits for it.
+        for result in results {
+            assert_eq!(result.unwrap().path(), package_dir);
+        }
+        assert_eq!(fetch_count.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            dir_entries(packages_dir.path()),
+            [cache_key.to_string(), format!("{cache_key}.lock")]
+        );
+    }
+
+    /// When set, the test binary was re-invoked by `test_concurrent_fetch_multi_process` and the
+    /// variable contains the role of the process.
+    const MULTI_PROCESS_ROLE_ENV: &str = "RATTLER_TEST_PACKAGE_CACHE_ROLE";
+
+    /// The directory of the package cache shared by the processes.
+    const MULTI_PROCESS_PACKAGES_DIR_ENV: &str = "RATTLER_TEST_PACKAGE_CACHE_PACKAGES_DIR";
+
+    /// A directory in which the processes record what they did.
+    const MULTI_PROCESS_STATE_DIR_ENV: &str = "RATTLER_TEST_PACKAGE_CACHE_STATE_DIR";
+
+    #[tokio::test]
+    pub async fn test_concurrent_fetch_multi_process() {
+        let tar_archive_path =
+            get_test_data_dir().join("ros-noetic-rosbridge-suite-0.11.14-py39h6fdeb60_14.tar.bz2");
+        let cache_key =
+            CacheKey::from(ArchiveIdentifier::try_from_path(&tar_archive_path).unwrap());
+
+        if let Ok(role) = std::env::var(MULTI_PROCESS_ROLE_ENV) {
+            let packages_dir =
+                PathBuf::from(std::env::var(MULTI_PROCESS_PACKAGES_DIR_ENV).unwrap());
+            let state_dir = PathBuf::from(std::env::var(MULTI_PROCESS_STATE_DIR_ENV).unwrap());
+            let package_dir = packages_dir.join(cache_key.to_string());
+            match role.as_str() {
+                "fetch" => {
+                    let fetched_dir = PackageCache::new(&packages_dir)
+                        .get_or_fetch(cache_key, move |destination| async move {
+                            let marker = format!("fetched-by-{}", std::process::id());
+                            std::fs::write(state_dir.join(marker), "")?;
+                            rattler_package_streaming::tokio::fs::extract(
+                                &tar_archive_path,
+                                &destination,
+                            )
+                            .await?;
+
+                            // Give the other processes time to look at the cache.
+                            tokio::time::sleep(Duration::from_millis(500)).await;
+                            Ok::<_, ExtractError>(())
+                        })
+                        .await
+                        .unwrap();
+                    assert_eq!(fetched_dir.path(), package_dir);
+                }
+                "observe" => {
+                    // The package directory must never be observed in an incomplete state.
+                    let mut observed = false;
+                    loop {
+                        let finished = state_dir.join("done").exists();
+                        if package_dir.is_dir() {
+                            validate_package_directory(&package_dir).unwrap();
+                            observed = true;
+                        }
+                        if finished {
+                            break;
+                        }
+                    }
+                    assert!(observed);
+                }
+                role => panic!("unknown role {role}"),
+            }
+            return;
+        }
+
+        // Re-invoke this test in separate processes that share the cache.
+        let packages_dir = tempdir().unwrap();
+        let state_dir = tempdir().unwrap();
+        let spawn = |role: &str| {
+            Command::new(std::env::current_exe().unwrap())
+                .args([
+                    "--exact",
+                    "package_cache::test::test_concurrent_fetch_multi_process",
+                    "--nocapture",
+                ])
+                .env(MULTI_PROCESS_ROLE_ENV, role)
+                .env(MULTI_PROCESS_PACKAGES_DIR_ENV, packages_dir.path())
+                .env(MULTI_PROCESS_STATE_DIR_ENV, state_dir.path())
+                .spawn()
+                .unwrap()
+        };
+        let mut observer = spawn("observe");
+        let fetchers = [spawn("fetch"), spawn("fetch")];
+        for mut fetcher in fetchers {
+            assert!(fetcher.wait().unwrap().success());
+        }
+        std::fs::write(state_dir.path().join("done"), "").unwrap();
+        assert!(observer.wait().unwrap().success());
+
+        // The package is only fetched by one of the processes, the other waits for it.
+        let fetched_by = dir_entries(state_dir.path())
+            .into_iter()
+            .filter(|entry| entry.starts_with("fetched-by-"))
+            .count();
+        assert_eq!(fetched_by, 1);
+        assert_eq!(
+            dir_entries(packages_dir.path()),
+            [cache_key.to_string(), format!("{cache_key}.lock")]
+        );
+    }
+
+    /// Returns the sorted names of the entries in the given directory.
+    fn dir_entries(path: &Path) -> Vec<String> {
+        let mut entries = std::fs::read_dir(path)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        entries.sort();
+        entries
+    }
+
     /// A helper middleware function that fails the first two requests.
     async fn fail_the_first_two_requests<B>(
         State(count): State<Arc<Mutex<i32>>>,
@@ -381,6 +1754,92 @@ mod test {
         Ok(next.run(req).await)
     }
 
+    /// A helper middleware function that only sends the first half of the first response, as if
+    /// the connection was interrupted. The `Range` headers of all requests are recorded.
+    async fn interrupt_the_first_request<B>(
+        State(ranges): State<Arc<Mutex<Vec<Option<String>>>>>,
+        req: Request<B>,
+        next: Next<B>,
+    ) -> Response {
+        let range = req
+            .headers()
+            .get(header::RANGE)
+            .map(|value| value.to_str().unwrap().to_owned());
+        let is_first_request = {
+            let mut ranges = ranges.lock().await;
+            ranges.push(range);
+            ranges.len() == 1
+        };
+
+        let response = next.run(req).await;
+        if !is_first_request {
+            return response;
+        }
+
+        let (parts, mut body) = response.into_parts();
+        let mut bytes = Vec::new();
+        while let Some(chunk) = body.data().await {
+            bytes.extend_from_slice(&chunk.unwrap());
+        }
+        bytes.truncate(bytes.len() / 2);
+        let body = stream::iter([
+            Ok(Bytes::from(bytes)),
+            Err(std::io::Error::new(
+                std::io::ErrorKind::ConnectionReset,
+                "connection interrupted",
+            )),
+        ]);
+        Response::from_parts(parts, boxed(StreamBody::new(body)))
+    }
+
+    #[tokio::test]
+    pub async fn test_resume_interrupted_download() {
+        let archive_name = "ros-noetic-rosbridge-suite-0.11.14-py39h6fdeb60_14.tar.bz2";
+        let archive_path = get_test_data_dir().join(archive_name);
+        let archive_size = std::fs::metadata(&archive_path).unwrap().len();
+
+        // Construct a server that interrupts the first download.
+        let requested_ranges = Arc::new(Mutex::new(Vec::new()));
+        let router = Router::new()
+            .route_service("/*key", get_service(ServeDir::new(get_test_data_dir())))
+            .layer(middleware::from_fn_with_state(
+                requested_ranges.clone(),
+                interrupt_the_first_request,
+            ));
+        let addr = SocketAddr::new([127, 0, 0, 1].into(), 0);
+        let server = axum::Server::bind(&addr).serve(router.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let packages_dir = tempdir().unwrap();
+        let cache = PackageCache::new(packages_dir.path());
+        let server_url = Url::parse(&format!("http://localhost:{}", addr.port())).unwrap();
+        let cache_key = CacheKey::from(ArchiveIdentifier::try_from_filename(archive_name).unwrap())
+            .with_sha256(compute_file_digest::<Sha256>(&archive_path).unwrap());
+        let package_dir = cache
+            .get_or_fetch_from_url_with_retry(
+                cache_key.clone(),
+                server_url.join(archive_name).unwrap(),
+                reqwest::Client::default().into(),
+                ExponentialBackoffBuilder::default().build_with_max_retries(3),
+            )
+            .await
+            .unwrap();
+        validate_package_directory(package_dir.path()).unwrap();
+
+        // The retry only requested the part of the archive that was not received yet.
+        assert_eq!(
+            *requested_ranges.lock().await,
+            [None, Some(format!("bytes={}-", archive_size / 2))]
+        );
+
+        // The partial file is removed.
+        assert_eq!(
+            dir_entries(packages_dir.path()),
+            [cache_key.to_string(), format!("{cache_key}.lock")]
+        );
+    }
+
     #[tokio::test]
     pub async fn test_flaky_package_cache() {
         let static_dir = get_test_data_dir();
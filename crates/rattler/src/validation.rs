@@ -9,10 +9,20 @@
 //! (deprecated) `files` file as well as optionally a `has_prefix` and some other files. If the
 //! `paths.json` file is missing these deprecated files are used instead to reconstruct a
 //! [`PathsJson`] object. See [`PathsJson::from_deprecated_package_directory`] for more information.
+//!
+//! The [`validate_prefix`] function performs a similar check for an environment. It validates
+//! that the files of all the packages installed in a prefix match the information stored in the
+//! `conda-meta` directory of the prefix.
 
-use rattler_conda_types::package::{IndexJson, PackageFile, PathType, PathsEntry, PathsJson};
-use rattler_digest::compute_file_digest;
+use crate::install::link::copy_and_replace_placeholders;
+use rattler_conda_types::package::{
+    IndexJson, PackageFile, PathType, PathsEntry, PathsJson, PrefixPlaceholder,
+};
+use rattler_conda_types::{prefix_record, Platform, PrefixLock, PrefixLockError, PrefixRecord};
+use rattler_digest::{compute_file_digest, HashingWriter, Sha256, Sha256Hash};
 use std::{
+    borrow::Cow,
+    collections::HashSet,
     fs::Metadata,
     io::ErrorKind,
     path::{Path, PathBuf},
@@ -62,6 +72,10 @@ pub enum PackageEntryValidationError {
     #[error("expected a directory")]
     ExpectedDirectory,
 
+    /// The file is not a regular file.
+    #[error("expected a regular file")]
+    ExpectedFile,
+
     /// The size of the file does not match the expected size.
     #[error("incorrect size, expected {0} but file on disk is {1}")]
     IncorrectSize(u64, u64),
@@ -223,14 +237,297 @@ fn validate_package_directory_entry(
     }
 }
 
+/// An error that is returned by [`validate_prefix`] if the prefix could not be validated.
+#[derive(Debug, thiserror::Error)]
+pub enum PrefixValidationError {
+    /// The prefix could not be locked.
+    #[error("failed to lock the prefix")]
+    FailedToLockPrefix(#[source] PrefixLockError),
+
+    /// The records of the installed packages could not be read from the `conda-meta` directory.
+    #[error("failed to read the packages installed in the prefix")]
+    ReadPrefixRecordsError(#[source] std::io::Error),
+
+    /// A directory of the prefix could not be read.
+    #[error("failed to read directory '{0}'")]
+    ReadDirectoryError(PathBuf, #[source] std::io::Error),
+}
+
+/// The result of validating a prefix with [`validate_prefix`].
+#[derive(Debug, Default)]
+pub struct PrefixValidationReport {
+    /// The installed packages that have missing or modified files.
+    pub broken_packages: Vec<BrokenPackage>,
+
+    /// Files in the prefix that do not belong to any of the installed packages, relative to the
+    /// prefix.
+    pub unexpected_files: Vec<PathBuf>,
+}
+
+impl PrefixValidationReport {
+    /// Returns true if none of the installed packages are broken. Unexpected files are not
+    /// considered an error.
+    pub fn is_valid(&self) -> bool {
+        self.broken_packages.is_empty()
+    }
+}
+
+/// An installed package whose files do not match the information in its [`PrefixRecord`].
+#[derive(Debug)]
+pub struct BrokenPackage {
+    /// The record of the package
+    pub prefix_record: PrefixRecord,
+
+    /// The files of the package that do not exist, relative to the prefix.
+    pub missing_files: Vec<PathBuf>,
+
+    /// The files of the package that have been modified, relative to the prefix, and how they
+    /// differ.
+    pub modified_files: Vec<(PathBuf, PackageEntryValidationError)>,
+}
+
+/// Determine whether the files of the packages installed in a prefix match the information in the
+/// `conda-meta` directory of the prefix.
+///
+/// Every file is compared against the size, SHA256 hash and path type recorded when the package
+/// was linked. Files in which the prefix placeholder was replaced are compared against the hash of
+/// the file in the prefix (`sha256_in_prefix`). If that hash was not recorded it is computed from
+/// the extracted package in the package cache, if it is still available. `platform` is the
+/// platform the prefix was installed for.
+///
+/// Files in the prefix that are not owned by any package are reported as unexpected, except for
+/// `__pycache__` directories that Python creates at runtime.
+///
+/// A shared [`PrefixLock`] is held on the prefix while it is validated.
+pub fn validate_prefix(
+    target_prefix: &Path,
+    platform: Platform,
+) -> Result<PrefixValidationReport, PrefixValidationError> {
+    // Make sure the prefix is not modified while it is being validated.
+    let lock =
+        PrefixLock::shared(target_prefix).map_err(PrefixValidationError::FailedToLockPrefix)?;
+    let prefix_records = PrefixRecord::collect_from_locked_prefix(&lock)
+        .map_err(PrefixValidationError::ReadPrefixRecordsError)?;
+
+    let mut report = PrefixValidationReport::default();
+    let mut owned_paths = HashSet::new();
+    for prefix_record in prefix_records {
+        // The `paths.json` of the package is only required to find the prefix placeholders of files
+        // for which the hash in the prefix was not recorded.
+        let package_dir = prefix_record.extracted_package_dir.as_deref();
+        let package_paths = package_dir
+            .filter(|_| {
+                prefix_record
+                    .paths_data
+                    .paths
+                    .iter()
+                    .any(|entry| entry.sha256_in_prefix.is_none())
+            })
+            .and_then(|package_dir| {
+                PathsJson::from_package_directory_with_deprecated_fallback(package_dir).ok()
+            });
+
+        let mut missing_files = Vec::new();
+        let mut modified_files = Vec::new();
+        for entry in &prefix_record.paths_data.paths {
+            owned_paths.insert(entry.relative_path.clone());
+            let placeholder = package_paths.as_ref().and_then(|paths| {
+                let original_path = entry.original_path.as_ref().unwrap_or(&entry.relative_path);
+                paths
+                    .paths
+                    .iter()
+                    .find(|package_entry| &package_entry.relative_path == original_path)
+                    .and_then(|package_entry| {
+                        Some((
+                            package_entry.prefix_placeholder.as_ref()?,
+                            &package_entry.relative_path,
+                        ))
+                    })
+            });
+
+            match validate_prefix_entry(
+                target_prefix,
+                entry,
+                package_dir.zip(placeholder),
+                platform,
+            ) {
+                Ok(()) => {}
+                Err(PackageEntryValidationError::NotFound) => {
+                    missing_files.push(entry.relative_path.clone());
+                }
+                Err(err) => modified_files.push((entry.relative_path.clone(), err)),
+            }
+        }
+
+        if !missing_files.is_empty() || !modified_files.is_empty() {
+            report.broken_packages.push(BrokenPackage {
+                prefix_record,
+                missing_files,
+                modified_files,
+            });
+        }
+    }
+
+    collect_unexpected_files(
+        target_prefix,
+        Path::new(""),
+        &owned_paths,
+        &mut report.unexpected_files,
+    )?;
+    report.unexpected_files.sort();
+
+    Ok(report)
+}
+
+/// Determine whether the information in the [`prefix_record::PathsEntry`] matches the file in the
+/// prefix. `placeholder` is the package directory, the prefix placeholder and the path of the file
+/// in the package, if the file contains a placeholder.
+fn validate_prefix_entry(
+    target_prefix: &Path,
+    entry: &prefix_record::PathsEntry,
+    placeholder: Option<(&Path, (&PrefixPlaceholder, &PathBuf))>,
+    platform: Platform,
+) -> Result<(), PackageEntryValidationError> {
+    let path = target_prefix.join(&entry.relative_path);
+
+    // Get the metadata for the entry
+    let metadata = match std::fs::symlink_metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Err(PackageEntryValidationError::NotFound);
+        }
+        Err(e) => return Err(PackageEntryValidationError::GetMetadataFailed(e)),
+    };
+
+    match entry.path_type {
+        prefix_record::PathType::Directory => {
+            return if metadata.is_dir() {
+                Ok(())
+            } else {
+                Err(PackageEntryValidationError::ExpectedDirectory)
+            };
+        }
+        // Symbolic links are copied if they are not supported, so a file is also valid.
+        prefix_record::PathType::SoftLink if metadata.is_symlink() => return Ok(()),
+        _ if !metadata.is_file() => return Err(PackageEntryValidationError::ExpectedFile),
+        _ => {}
+    }
+
+    // Determine the expected hash of the file and whether it was patched during linking.
+    let (expected_hash, patched) = match (entry.sha256_in_prefix, placeholder) {
+        (Some(hash), _) => (Some(hash), entry.sha256 != Some(hash)),
+        (None, Some((package_dir, (placeholder, package_path)))) => {
+            let hash = compute_patched_digest(
+                &package_dir.join(package_path),
+                placeholder,
+                target_prefix,
+                platform,
+            )?;
+            (Some(hash), true)
+        }
+        (None, None) => (entry.sha256, false),
+    };
+
+    // The recorded size is the size of the file in the package, which differs from the size of
+    // the file in the prefix if the prefix placeholder was replaced.
+    if let Some(size_in_bytes) = entry.size_in_bytes.filter(|_| !patched) {
+        if size_in_bytes != metadata.len() {
+            return Err(PackageEntryValidationError::IncorrectSize(
+                size_in_bytes,
+                metadata.len(),
+            ));
+        }
+    }
+
+    if let Some(expected_hash) = expected_hash {
+        let hash = compute_file_digest::<Sha256>(&path)?;
+        if expected_hash != hash {
+            return Err(PackageEntryValidationError::HashMismatch(
+                format!("{expected_hash:x}"),
+                format!("{hash:x}"),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Computes the hash a file from a package has after its prefix placeholder has been replaced
+/// with the target prefix.
+fn compute_patched_digest(
+    source_path: &Path,
+    placeholder: &PrefixPlaceholder,
+    target_prefix: &Path,
+    platform: Platform,
+) -> Result<Sha256Hash, PackageEntryValidationError> {
+    let source = std::fs::read(source_path)?;
+    let target_prefix = target_prefix.to_string_lossy();
+
+    // Linking uses forward-slashes on Windows, see `link_file`.
+    let target_prefix = if platform.is_windows() {
+        Cow::Owned(target_prefix.replace('\\', "/"))
+    } else {
+        target_prefix
+    };
+
+    let mut writer = HashingWriter::<_, Sha256>::new(std::io::sink());
+    copy_and_replace_placeholders(
+        &source,
+        &mut writer,
+        &placeholder.placeholder,
+        &target_prefix,
+        placeholder.file_mode,
+    )?;
+    Ok(writer.finalize().1)
+}
+
+/// Recursively collects the files in the prefix that are not part of `owned_paths`.
+fn collect_unexpected_files(
+    target_prefix: &Path,
+    relative_dir: &Path,
+    owned_paths: &HashSet<PathBuf>,
+    unexpected_files: &mut Vec<PathBuf>,
+) -> Result<(), PrefixValidationError> {
+    let dir = target_prefix.join(relative_dir);
+    let read_dir = std::fs::read_dir(&dir)
+        .map_err(|err| PrefixValidationError::ReadDirectoryError(dir.clone(), err))?;
+    for entry in read_dir {
+        let entry =
+            entry.map_err(|err| PrefixValidationError::ReadDirectoryError(dir.clone(), err))?;
+        let relative_path = relative_dir.join(entry.file_name());
+        if owned_paths.contains(&relative_path) {
+            continue;
+        }
+
+        let file_type = entry
+            .file_type()
+            .map_err(|err| PrefixValidationError::ReadDirectoryError(dir.clone(), err))?;
+        if file_type.is_dir() {
+            if relative_path == Path::new("conda-meta") || entry.file_name() == "__pycache__" {
+                continue;
+            }
+            collect_unexpected_files(target_prefix, &relative_path, owned_paths, unexpected_files)?;
+        } else {
+            unexpected_files.push(relative_path);
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use super::{
-        validate_package_directory, validate_package_directory_from_paths,
+        validate_package_directory, validate_package_directory_from_paths, validate_prefix,
         PackageEntryValidationError, PackageValidationError,
     };
+    use crate::{
+        get_repodata_record,
+        install::{Installer, Transaction},
+        package_cache::PackageCache,
+    };
     use assert_matches::assert_matches;
     use rattler_conda_types::package::{PackageFile, PathType, PathsJson};
+    use rattler_conda_types::{prefix_record, Platform, PrefixRecord};
     use rstest::rstest;
     use std::{
         io::Write,
@@ -332,6 +629,60 @@ mod test {
         );
     }
 
+    #[tokio::test]
+    async fn test_validate_prefix() {
+        let target_prefix = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let transaction = Transaction::from_current_and_desired(
+            Vec::<PrefixRecord>::new(),
+            vec![get_repodata_record("ruff-0.0.171-py310h298983d_0.conda")],
+            Platform::current(),
+        )
+        .unwrap();
+        let installer = Installer::new(PackageCache::new(cache_dir.path()));
+        installer
+            .execute(target_prefix.path(), transaction)
+            .await
+            .unwrap();
+
+        // A freshly installed prefix is valid
+        let report = validate_prefix(target_prefix.path(), Platform::current()).unwrap();
+        assert!(report.is_valid());
+        assert!(report.unexpected_files.is_empty());
+
+        // Break the prefix
+        let installed = PrefixRecord::collect_from_prefix(target_prefix.path()).unwrap();
+        let files = installed[0]
+            .paths_data
+            .paths
+            .iter()
+            .filter(|entry| entry.path_type == prefix_record::PathType::HardLink)
+            .map(|entry| entry.relative_path.clone())
+            .collect::<Vec<_>>();
+        std::fs::remove_file(target_prefix.path().join(&files[0])).unwrap();
+        std::fs::write(target_prefix.path().join(&files[1]), "modified").unwrap();
+        std::fs::write(target_prefix.path().join("unexpected.txt"), "unexpected").unwrap();
+
+        let report = validate_prefix(target_prefix.path(), Platform::current()).unwrap();
+        assert!(!report.is_valid());
+        assert_eq!(report.broken_packages.len(), 1);
+        assert_eq!(report.broken_packages[0].missing_files, [files[0].clone()]);
+        assert_matches!(
+            report.broken_packages[0].modified_files.as_slice(),
+            [(path, PackageEntryValidationError::IncorrectSize(..) | PackageEntryValidationError::HashMismatch(..))] if path == &files[1]
+        );
+        assert_eq!(report.unexpected_files, [PathBuf::from("unexpected.txt")]);
+
+        // Repair the prefix by reinstalling the broken package
+        installer
+            .repair(target_prefix.path(), &report, Platform::current())
+            .await
+            .unwrap();
+        let report = validate_prefix(target_prefix.path(), Platform::current()).unwrap();
+        assert!(report.is_valid());
+        assert_eq!(report.unexpected_files, [PathBuf::from("unexpected.txt")]);
+    }
+
     #[test]
     fn test_missing_metadata() {
         let temp_dir = tempfile::tempdir().unwrap();
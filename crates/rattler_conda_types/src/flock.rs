@@ -0,0 +1,455 @@
+//! Defines [`LockedFile`], an advisory file lock that is used to coordinate access to files and
+//! directories between processes, e.g. to the repodata cache, the package cache and prefixes.
+//!
+//! Locks are only respected by processes that also use these locks. File locks are not reliable
+//! on network file systems, on NFS mounts and on file systems that do not support locking no lock
+//! is taken at all.
+
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+/// The kind of access a [`LockedFile`] grants.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum FileLockMode {
+    /// Multiple processes can hold a shared lock on the same file at the same time.
+    Shared,
+
+    /// Only a single process can hold an exclusive lock on a file.
+    Exclusive,
+}
+
+/// A file with an advisory lock on it. The lock is released when this instance is dropped.
+#[derive(Debug)]
+pub struct LockedFile {
+    file: File,
+    path: PathBuf,
+    mode: FileLockMode,
+
+    /// False if the file system does not support locking, in which case there is nothing to
+    /// unlock.
+    locked: bool,
+}
+
+impl LockedFile {
+    /// Opens exclusive access to a file, returning the locked version of a file.
+    ///
+    /// This function will create a file at `path` if it doesn't already exist (including
+    /// intermediate directories), and then it will acquire an exclusive lock on `path`. If the
+    /// process must block waiting for the lock, the `msg` is logged.
+    ///
+    /// The returned file can be accessed to look at the path and also has read/write access to the
+    /// underlying file.
+    pub fn open_rw(path: impl AsRef<Path>, msg: &str) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create(true);
+
+        // If we fail because of NotFound it's likely because an intermediate directory didn't
+        // exist, so try to create the directory and then continue.
+        let file = options.open(path).or_else(|e| {
+            if e.kind() == io::ErrorKind::NotFound {
+                std::fs::create_dir_all(path.parent().unwrap())?;
+                options.open(path)
+            } else {
+                Err(e)
+            }
+        })?;
+
+        Self::lock(file, path, FileLockMode::Exclusive, msg)
+    }
+
+    /// Opens shared access to a file, returning the locked version of a file.
+    ///
+    /// This function will fail if `path` doesn't already exist, but if it does then it will
+    /// acquire a shared lock on `path`. If the process must block waiting for the lock, the `msg`
+    /// is logged.
+    ///
+    /// The returned file can be accessed to look at the path and also has read access to the
+    /// underlying file. Any writes to the file will return an error.
+    pub fn open_ro(path: impl AsRef<Path>, msg: &str) -> io::Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new().read(true).open(path)?;
+        Self::lock(file, path, FileLockMode::Shared, msg)
+    }
+
+    /// Locks an already opened `file`, blocking for as long as another process holds a
+    /// conflicting lock. `path` must be the location of the file. If the process must block
+    /// waiting for the lock, the `msg` is logged.
+    pub fn lock(
+        file: File,
+        path: impl Into<PathBuf>,
+        mode: FileLockMode,
+        msg: &str,
+    ) -> io::Result<Self> {
+        match Self::try_lock(file, path, mode)? {
+            Ok(locked) => Ok(locked),
+            Err((file, path)) => {
+                tracing::info!("waiting for file lock on {}", msg);
+                match mode {
+                    FileLockMode::Shared => sys::lock_shared(&file)?,
+                    FileLockMode::Exclusive => sys::lock_exclusive(&file)?,
+                }
+                Ok(Self {
+                    file,
+                    path,
+                    mode,
+                    locked: true,
+                })
+            }
+        }
+    }
+
+    /// Tries to lock an already opened `file` without blocking. `path` must be the location of the
+    /// file.
+    ///
+    /// If another process holds a conflicting lock, the file and the path are handed back so the
+    /// caller can decide whether to try again later.
+    #[allow(clippy::type_complexity)]
+    pub fn try_lock(
+        file: File,
+        path: impl Into<PathBuf>,
+        mode: FileLockMode,
+    ) -> io::Result<Result<Self, (File, PathBuf)>> {
+        let path = path.into();
+
+        // File locking on Unix is currently implemented via `flock`, which is known to be broken
+        // on NFS. We could in theory just ignore errors that happen on NFS, but apparently the
+        // failure mode [1] for `flock` on NFS is **blocking forever**, even if the "non-blocking"
+        // flag is passed!
+        //
+        // As a result, we just skip all file locks entirely on NFS mounts. That should avoid
+        // calling any `flock` functions at all, and it wouldn't work there anyway.
+        //
+        // [1]: https://github.com/rust-lang/cargo/issues/2615
+        if is_on_nfs_mount(&path) {
+            return Ok(Ok(Self {
+                file,
+                path,
+                mode,
+                locked: false,
+            }));
+        }
+
+        let result = match mode {
+            FileLockMode::Shared => sys::try_lock_shared(&file),
+            FileLockMode::Exclusive => sys::try_lock_exclusive(&file),
+        };
+        match result {
+            Ok(()) => Ok(Ok(Self {
+                file,
+                path,
+                mode,
+                locked: true,
+            })),
+
+            // In addition to ignoring NFS which is commonly not working we also just ignore
+            // locking on filesystems that look like they don't implement file locking.
+            Err(e) if sys::error_unsupported(&e) => Ok(Ok(Self {
+                file,
+                path,
+                mode,
+                locked: false,
+            })),
+
+            Err(e) if sys::error_contended(&e) => Ok(Err((file, path))),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns the underlying file handle of this lock.
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Returns the location of the locked file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the parent path containing this file
+    pub fn parent(&self) -> &Path {
+        self.path.parent().unwrap()
+    }
+
+    /// Returns the kind of access this lock grants.
+    pub fn mode(&self) -> FileLockMode {
+        self.mode
+    }
+}
+
+impl Read for LockedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Seek for LockedFile {
+    fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+        self.file.seek(to)
+    }
+}
+
+impl Write for LockedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for LockedFile {
+    fn drop(&mut self) {
+        if self.locked {
+            let _ = sys::unlock(&self.file);
+        }
+    }
+}
+
+#[cfg(all(target_os = "linux", not(target_env = "musl")))]
+fn is_on_nfs_mount(path: &Path) -> bool {
+    use std::ffi::CString;
+    use std::mem;
+    use std::os::unix::prelude::*;
+
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+
+    unsafe {
+        let mut buf: libc::statfs = mem::zeroed();
+        let r = libc::statfs(path.as_ptr(), &mut buf);
+
+        r == 0 && buf.f_type as u32 == libc::NFS_SUPER_MAGIC as u32
+    }
+}
+
+#[cfg(any(not(target_os = "linux"), target_env = "musl"))]
+fn is_on_nfs_mount(_path: &Path) -> bool {
+    false
+}
+
+/// Platform specific file locking, taken from:
+/// <https://github.com/rust-lang/cargo/blob/39c13e67a5962466cc7253d41bc1099bbcb224c3/src/cargo/util/flock.rs>
+///
+/// Under MIT license:
+///
+/// Permission is hereby granted, free of charge, to any
+/// person obtaining a copy of this software and associated
+/// documentation files (the "Software"), to deal in the
+/// Software without restriction, including without
+/// limitation the rights to use, copy, modify, merge,
+/// publish, distribute, sublicense, and/or sell copies of
+/// the Software, and to permit persons to whom the Software
+/// is furnished to do so, subject to the following
+/// conditions:
+///
+/// The above copyright notice and this permission notice
+/// shall be included in all copies or substantial portions
+/// of the Software.
+///
+/// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF
+/// ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED
+/// TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A
+/// PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT
+/// SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+/// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+/// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR
+/// IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+/// DEALINGS IN THE SOFTWARE.
+#[cfg(unix)]
+mod sys {
+    use std::fs::File;
+    use std::io::{Error, Result};
+    use std::os::unix::io::AsRawFd;
+
+    pub(super) fn lock_shared(file: &File) -> Result<()> {
+        flock(file, libc::LOCK_SH)
+    }
+
+    pub(super) fn lock_exclusive(file: &File) -> Result<()> {
+        flock(file, libc::LOCK_EX)
+    }
+
+    pub(super) fn try_lock_shared(file: &File) -> Result<()> {
+        flock(file, libc::LOCK_SH | libc::LOCK_NB)
+    }
+
+    pub(super) fn try_lock_exclusive(file: &File) -> Result<()> {
+        flock(file, libc::LOCK_EX | libc::LOCK_NB)
+    }
+
+    pub(super) fn unlock(file: &File) -> Result<()> {
+        flock(file, libc::LOCK_UN)
+    }
+
+    pub(super) fn error_contended(err: &Error) -> bool {
+        err.raw_os_error().map_or(false, |x| x == libc::EWOULDBLOCK)
+    }
+
+    pub(super) fn error_unsupported(err: &Error) -> bool {
+        match err.raw_os_error() {
+            // Unfortunately, depending on the target, these may or may not be the same.
+            // For targets in which they are the same, the duplicate pattern causes a warning.
+            #[allow(unreachable_patterns)]
+            Some(libc::ENOTSUP | libc::EOPNOTSUPP | libc::ENOSYS) => true,
+            _ => false,
+        }
+    }
+
+    #[cfg(not(target_os = "solaris"))]
+    fn flock(file: &File, flag: libc::c_int) -> Result<()> {
+        let ret = unsafe { libc::flock(file.as_raw_fd(), flag) };
+        if ret < 0 {
+            Err(Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    #[cfg(target_os = "solaris")]
+    fn flock(file: &File, flag: libc::c_int) -> Result<()> {
+        // Solaris lacks flock(), so try to emulate using fcntl()
+        let mut flock = libc::flock {
+            l_type: 0,
+            l_whence: 0,
+            l_start: 0,
+            l_len: 0,
+            l_sysid: 0,
+            l_pid: 0,
+            l_pad: [0, 0, 0, 0],
+        };
+        flock.l_type = if flag & libc::LOCK_UN != 0 {
+            libc::F_UNLCK
+        } else if flag & libc::LOCK_EX != 0 {
+            libc::F_WRLCK
+        } else if flag & libc::LOCK_SH != 0 {
+            libc::F_RDLCK
+        } else {
+            panic!("unexpected flock() operation")
+        };
+
+        let mut cmd = libc::F_SETLKW;
+        if (flag & libc::LOCK_NB) != 0 {
+            cmd = libc::F_SETLK;
+        }
+
+        let ret = unsafe { libc::fcntl(file.as_raw_fd(), cmd, &flock) };
+
+        if ret < 0 {
+            Err(Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::fs::File;
+    use std::io::{Error, Result};
+    use std::mem;
+    use std::os::windows::io::AsRawHandle;
+
+    use windows_sys::Win32::Foundation::HANDLE;
+    use windows_sys::Win32::Foundation::{ERROR_INVALID_FUNCTION, ERROR_LOCK_VIOLATION};
+    use windows_sys::Win32::Storage::FileSystem::{
+        LockFileEx, UnlockFile, LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY,
+    };
+
+    pub(super) fn lock_shared(file: &File) -> Result<()> {
+        lock_file(file, 0)
+    }
+
+    pub(super) fn lock_exclusive(file: &File) -> Result<()> {
+        lock_file(file, LOCKFILE_EXCLUSIVE_LOCK)
+    }
+
+    pub(super) fn try_lock_shared(file: &File) -> Result<()> {
+        lock_file(file, LOCKFILE_FAIL_IMMEDIATELY)
+    }
+
+    pub(super) fn try_lock_exclusive(file: &File) -> Result<()> {
+        lock_file(file, LOCKFILE_EXCLUSIVE_LOCK | LOCKFILE_FAIL_IMMEDIATELY)
+    }
+
+    pub(super) fn error_contended(err: &Error) -> bool {
+        err.raw_os_error()
+            .map_or(false, |x| x == ERROR_LOCK_VIOLATION as i32)
+    }
+
+    pub(super) fn error_unsupported(err: &Error) -> bool {
+        err.raw_os_error()
+            .map_or(false, |x| x == ERROR_INVALID_FUNCTION as i32)
+    }
+
+    pub(super) fn unlock(file: &File) -> Result<()> {
+        unsafe {
+            let ret = UnlockFile(file.as_raw_handle() as HANDLE, 0, 0, !0, !0);
+            if ret == 0 {
+                Err(Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    fn lock_file(file: &File, flags: u32) -> Result<()> {
+        unsafe {
+            let mut overlapped = mem::zeroed();
+            let ret = LockFileEx(
+                file.as_raw_handle() as HANDLE,
+                flags,
+                0,
+                !0,
+                !0,
+                &mut overlapped,
+            );
+            if ret == 0 {
+                Err(Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FileLockMode, LockedFile};
+    use std::fs::OpenOptions;
+
+    #[test]
+    fn test_try_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested/file.lock");
+        let open = || OpenOptions::new().read(true).open(&path).unwrap();
+
+        // Opening for writing creates the file and its parent directories.
+        let exclusive = LockedFile::open_rw(&path, "test").unwrap();
+        assert_eq!(exclusive.mode(), FileLockMode::Exclusive);
+        assert!(LockedFile::try_lock(open(), &path, FileLockMode::Shared)
+            .unwrap()
+            .is_err());
+        drop(exclusive);
+
+        // Shared locks don't exclude each other, but they do exclude an exclusive lock.
+        let first = LockedFile::open_ro(&path, "test").unwrap();
+        let second = LockedFile::try_lock(open(), &path, FileLockMode::Shared)
+            .unwrap()
+            .unwrap();
+        let (file, _) = LockedFile::try_lock(open(), &path, FileLockMode::Exclusive)
+            .unwrap()
+            .unwrap_err();
+        drop(first);
+        drop(second);
+        LockedFile::try_lock(file, &path, FileLockMode::Exclusive)
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -0,0 +1,497 @@
+//! Defines [`History`], the contents of the `conda-meta/history` file of a prefix.
+//!
+//! Every time a prefix is modified conda appends a revision to the history file. A revision
+//! starts with a header that contains the time of the modification, followed by comments and the
+//! packages that were linked (`+`) or unlinked (`-`):
+//!
+//! ```text
+//! ==> 2023-10-18 12:34:56 <==
+//! # cmd: conda install python=3.11
+//! # conda version: 23.9.0
+//! -conda-forge/linux-64::python-3.10.12-hd12c33a_0_cpython
+//! +conda-forge/linux-64::python-3.11.6-hab00c5b_0_cpython
+//! # update specs: ['python=3.11']
+//! ```
+//!
+//! Packages are identified by their "dist string": `<channel>/<subdir>::<name>-<version>-<build>`.
+//! Very old history files list all the packages of the prefix without a `+` or `-` prefix.
+
+use crate::{ChannelConfig, RepoDataRecord};
+use chrono::NaiveDateTime;
+use std::{
+    collections::BTreeSet,
+    fmt::{Display, Formatter},
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// The format of the timestamp in the header of a revision.
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// The contents of the `conda-meta/history` file of a prefix, see the [module level
+/// documentation](self).
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct History {
+    /// All revisions in the order in which they were made. The index of a revision is its revision
+    /// number.
+    pub revisions: Vec<HistoryRevision>,
+}
+
+/// A single modification of a prefix.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HistoryRevision {
+    /// The local time at which the prefix was modified.
+    pub timestamp: NaiveDateTime,
+
+    /// The command that modified the prefix (`# cmd: ...`).
+    pub command: Option<String>,
+
+    /// The packages that were linked or unlinked.
+    pub changes: Vec<HistoryChange>,
+
+    /// The specs the user requested to install or update (`# update specs: [...]`). conda also
+    /// writes `install` and `create` specs, these are stored here as well.
+    pub update_specs: Vec<String>,
+
+    /// The specs the user requested to remove (`# remove specs: [...]`).
+    pub remove_specs: Vec<String>,
+
+    /// Specs that were relaxed by the solver (`# neutered specs: [...]`).
+    pub neutered_specs: Vec<String>,
+
+    /// Any other comments, without the leading `#`, for instance `conda version: 23.9.0`.
+    pub comments: Vec<String>,
+}
+
+/// A change to the packages of a prefix recorded in a [`HistoryRevision`]. The packages are
+/// identified by their dist string.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum HistoryChange {
+    /// The package was linked into the prefix (`+<dist>`).
+    Linked(String),
+
+    /// The package was removed from the prefix (`-<dist>`).
+    Unlinked(String),
+
+    /// The package is part of the prefix. Old versions of conda list all the packages of the
+    /// prefix instead of the changes.
+    Present(String),
+}
+
+/// An error that can occur when parsing a [`History`].
+#[derive(Debug, thiserror::Error)]
+pub enum ParseHistoryError {
+    /// The file contains a line before the header of the first revision.
+    #[error("expected a revision header but found '{0}'")]
+    MissingHeader(String),
+
+    /// The timestamp of a revision header could not be parsed.
+    #[error("invalid timestamp '{0}'")]
+    InvalidTimestamp(String, #[source] chrono::ParseError),
+
+    /// An IO error occurred
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+}
+
+impl History {
+    /// Returns the location of the history file of the given prefix.
+    pub fn path(prefix: &Path) -> PathBuf {
+        prefix.join("conda-meta/history")
+    }
+
+    /// Reads the history of the given prefix. A prefix without a history file has an empty
+    /// history.
+    pub fn from_prefix(prefix: &Path) -> Result<Self, ParseHistoryError> {
+        match File::open(Self::path(prefix)) {
+            Ok(file) => Self::from_reader(file),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Parses a history file from a reader.
+    pub fn from_reader(mut reader: impl Read) -> Result<Self, ParseHistoryError> {
+        let mut str = String::new();
+        reader.read_to_string(&mut str)?;
+        Self::from_str(&str)
+    }
+
+    /// Parses a history file from a file.
+    pub fn from_path(path: &Path) -> Result<Self, ParseHistoryError> {
+        Self::from_reader(File::open(path)?)
+    }
+
+    /// Returns the dist strings of the packages that were part of the prefix after the revision
+    /// with the given number was made, or `None` if there is no such revision.
+    pub fn packages_at_revision(&self, revision: usize) -> Option<BTreeSet<String>> {
+        let mut packages = BTreeSet::new();
+        for revision in self.revisions.get(..=revision)? {
+            // A revision that only lists packages lists all the packages of the prefix.
+            let is_listing = !revision.changes.is_empty()
+                && revision
+                    .changes
+                    .iter()
+                    .all(|change| matches!(change, HistoryChange::Present(_)));
+            if is_listing {
+                packages.clear();
+            }
+
+            for change in &revision.changes {
+                match change {
+                    HistoryChange::Linked(dist) | HistoryChange::Present(dist) => {
+                        packages.insert(dist.clone());
+                    }
+                    HistoryChange::Unlinked(dist) => {
+                        packages.remove(dist);
+                    }
+                }
+            }
+        }
+        Some(packages)
+    }
+}
+
+impl FromStr for History {
+    type Err = ParseHistoryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut revisions: Vec<HistoryRevision> = Vec::new();
+        for line in s.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            if let Some(timestamp) = line
+                .strip_prefix("==>")
+                .and_then(|line| line.strip_suffix("<=="))
+            {
+                let timestamp = timestamp.trim();
+                revisions.push(HistoryRevision::new(
+                    NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).map_err(|err| {
+                        ParseHistoryError::InvalidTimestamp(timestamp.to_owned(), err)
+                    })?,
+                ));
+                continue;
+            }
+
+            let Some(revision) = revisions.last_mut() else {
+                return Err(ParseHistoryError::MissingHeader(line.to_owned()));
+            };
+
+            if let Some(comment) = line.strip_prefix('#') {
+                revision.parse_comment(comment.trim());
+            } else if let Some(dist) = line.strip_prefix('+') {
+                revision
+                    .changes
+                    .push(HistoryChange::Linked(dist.to_owned()));
+            } else if let Some(dist) = line.strip_prefix('-') {
+                revision
+                    .changes
+                    .push(HistoryChange::Unlinked(dist.to_owned()));
+            } else {
+                revision
+                    .changes
+                    .push(HistoryChange::Present(line.to_owned()));
+            }
+        }
+
+        Ok(Self { revisions })
+    }
+}
+
+impl Display for History {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for revision in &self.revisions {
+            write!(f, "{revision}")?;
+        }
+        Ok(())
+    }
+}
+
+impl HistoryRevision {
+    /// Constructs an empty revision that was made at the given time.
+    pub fn new(timestamp: NaiveDateTime) -> Self {
+        Self {
+            timestamp,
+            command: None,
+            changes: Vec::new(),
+            update_specs: Vec::new(),
+            remove_specs: Vec::new(),
+            neutered_specs: Vec::new(),
+            comments: Vec::new(),
+        }
+    }
+
+    /// Appends this revision to the history file at the specified location. The file is created
+    /// if it does not exist yet.
+    pub fn append_to_path(&self, path: &Path) -> Result<(), std::io::Error> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.write_all(self.to_string().as_bytes())
+    }
+
+    /// Parses a comment line, without the leading `#`.
+    fn parse_comment(&mut self, comment: &str) {
+        if let Some(command) = comment.strip_prefix("cmd:") {
+            self.command = Some(command.trim().to_owned());
+            return;
+        }
+
+        if let Some((action, specs)) = comment.split_once("specs:") {
+            let specs = parse_specs(specs);
+            match action.trim() {
+                "update" | "install" | "create" => {
+                    self.update_specs.extend(specs);
+                    return;
+                }
+                "remove" => {
+                    self.remove_specs.extend(specs);
+                    return;
+                }
+                "neutered" => {
+                    self.neutered_specs.extend(specs);
+                    return;
+                }
+                _ => {}
+            }
+        }
+
+        self.comments.push(comment.to_owned());
+    }
+}
+
+impl Display for HistoryRevision {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "==> {} <==", self.timestamp.format(TIMESTAMP_FORMAT))?;
+        if let Some(command) = &self.command {
+            writeln!(f, "# cmd: {command}")?;
+        }
+        for comment in &self.comments {
+            writeln!(f, "# {comment}")?;
+        }
+        for change in &self.changes {
+            match change {
+                HistoryChange::Linked(dist) => writeln!(f, "+{dist}")?,
+                HistoryChange::Unlinked(dist) => writeln!(f, "-{dist}")?,
+                HistoryChange::Present(dist) => writeln!(f, "{dist}")?,
+            }
+        }
+        for (action, specs) in [
+            ("update", &self.update_specs),
+            ("remove", &self.remove_specs),
+            ("neutered", &self.neutered_specs),
+        ] {
+            if !specs.is_empty() {
+                writeln!(f, "# {action} specs: {}", format_specs(specs))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the dist string that identifies the package of a record in a history file, e.g.
+/// `conda-forge/linux-64::python-3.11.6-hab00c5b_0_cpython`. Channels hosted on the default
+/// channel alias are referred to by their name, other channels by their url.
+pub fn dist_str(record: &RepoDataRecord) -> String {
+    let channel_alias = ChannelConfig::default().channel_alias;
+    let channel = record.channel.trim_end_matches('/');
+    let channel = channel
+        .strip_prefix(channel_alias.as_str().trim_end_matches('/'))
+        .map_or(channel, |name| name.trim_start_matches('/'));
+    let package_record = &record.package_record;
+    format!(
+        "{channel}/{}::{}-{}-{}",
+        package_record.subdir,
+        package_record.name.as_normalized(),
+        package_record.version,
+        package_record.build
+    )
+}
+
+/// Parses the specs of a `# <action> specs:` comment. conda writes them as a Python list of
+/// strings, very old versions write a comma separated list.
+fn parse_specs(specs: &str) -> Vec<String> {
+    let specs = specs.trim();
+    let Some(list) = specs
+        .strip_prefix('[')
+        .and_then(|specs| specs.strip_suffix(']'))
+    else {
+        return specs
+            .split(',')
+            .map(str::trim)
+            .filter(|spec| !spec.is_empty())
+            .map(ToOwned::to_owned)
+            .collect();
+    };
+
+    let mut result = Vec::new();
+    let mut rest = list.trim_start();
+    while let Some(quote) = rest.chars().next() {
+        let (spec, remainder) = if quote == '\'' || quote == '"' {
+            let quoted = &rest[1..];
+            let end = quoted.find(quote).unwrap_or(quoted.len());
+            (&quoted[..end], quoted.get(end + 1..).unwrap_or_default())
+        } else {
+            rest.split_once(',').unwrap_or((rest, ""))
+        };
+        if !spec.trim().is_empty() {
+            result.push(spec.trim().to_owned());
+        }
+        rest = remainder.trim_start().trim_start_matches(',').trim_start();
+    }
+    result
+}
+
+/// Formats specs as a Python list of strings, the way conda writes them.
+fn format_specs(specs: &[String]) -> String {
+    let specs = specs
+        .iter()
+        .map(|spec| {
+            if spec.contains('\'') {
+                format!("\"{spec}\"")
+            } else {
+                format!("'{spec}'")
+            }
+        })
+        .collect::<Vec<_>>();
+    format!("[{}]", specs.join(", "))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{History, HistoryChange, HistoryRevision, ParseHistoryError};
+    use assert_matches::assert_matches;
+    use chrono::NaiveDate;
+    use std::str::FromStr;
+
+    const HISTORY: &str = r#"==> 2019-01-10 13:04:41 <==
+# cmd: /opt/conda/bin/conda create -n env python=3.7
+# conda version: 4.5.12
++defaults/linux-64::ca-certificates-2018.12.5-0
++defaults/linux-64::python-3.7.2-h0371630_0
+# create specs: ['python=3.7']
+
+==> 2019-01-11 09:15:02 <==
+# cmd: /opt/conda/bin/conda install -n env "numpy[version='>=1.15']" requests
+-defaults/linux-64::ca-certificates-2018.12.5-0
++defaults/linux-64::ca-certificates-2019.1.23-0
++defaults/linux-64::numpy-1.15.4-py37h7e9f1db_0
+# update specs: ["numpy[version='>=1.15']", 'requests']
+# neutered specs: ['python=3.7']
+
+==> 2019-01-12 10:00:00 <==
+# cmd: /opt/conda/bin/conda remove -n env numpy
+-defaults/linux-64::numpy-1.15.4-py37h7e9f1db_0
+# remove specs: numpy
+"#;
+
+    #[test]
+    fn test_parse() {
+        let history = History::from_str(HISTORY).unwrap();
+        assert_eq!(history.revisions.len(), 3);
+
+        let first = &history.revisions[0];
+        assert_eq!(
+            first.timestamp,
+            NaiveDate::from_ymd_opt(2019, 1, 10)
+                .unwrap()
+                .and_hms_opt(13, 4, 41)
+                .unwrap()
+        );
+        assert_eq!(
+            first.command.as_deref(),
+            Some("/opt/conda/bin/conda create -n env python=3.7")
+        );
+        assert_eq!(first.comments, ["conda version: 4.5.12"]);
+        assert_eq!(first.update_specs, ["python=3.7"]);
+        assert_eq!(
+            first.changes[1],
+            HistoryChange::Linked(String::from("defaults/linux-64::python-3.7.2-h0371630_0"))
+        );
+
+        let second = &history.revisions[1];
+        assert_eq!(second.update_specs, ["numpy[version='>=1.15']", "requests"]);
+        assert_eq!(second.neutered_specs, ["python=3.7"]);
+        assert_eq!(history.revisions[2].remove_specs, ["numpy"]);
+    }
+
+    #[test]
+    fn test_packages_at_revision() {
+        let history = History::from_str(HISTORY).unwrap();
+        assert_eq!(
+            history.packages_at_revision(1).unwrap(),
+            [
+                "defaults/linux-64::ca-certificates-2019.1.23-0",
+                "defaults/linux-64::numpy-1.15.4-py37h7e9f1db_0",
+                "defaults/linux-64::python-3.7.2-h0371630_0",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect()
+        );
+        assert_eq!(history.packages_at_revision(2).unwrap().len(), 2);
+        assert!(history.packages_at_revision(3).is_none());
+
+        // Revisions of old versions of conda list all packages.
+        let history = History::from_str(
+            "==> 2015-01-01 00:00:00 <==\npython-2.7.9-1\nzlib-1.2.8-0\n\n==> 2015-01-02 00:00:00 <==\n-zlib-1.2.8-0\n",
+        )
+        .unwrap();
+        assert_eq!(
+            history.packages_at_revision(1).unwrap(),
+            [String::from("python-2.7.9-1")].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let history = History::from_str(HISTORY).unwrap();
+        let reparsed = History::from_str(&history.to_string()).unwrap();
+
+        // The `create` specs are written as `update` specs.
+        assert_eq!(history, reparsed);
+        assert!(history
+            .to_string()
+            .contains("# update specs: ['python=3.7']"));
+    }
+
+    #[test]
+    fn test_append() {
+        let prefix = tempfile::tempdir().unwrap();
+        std::fs::create_dir(prefix.path().join("conda-meta")).unwrap();
+        assert!(History::from_prefix(prefix.path())
+            .unwrap()
+            .revisions
+            .is_empty());
+
+        let mut revision = HistoryRevision::new(
+            NaiveDate::from_ymd_opt(2023, 10, 18)
+                .unwrap()
+                .and_hms_opt(12, 0, 0)
+                .unwrap(),
+        );
+        revision.changes.push(HistoryChange::Linked(String::from(
+            "conda-forge/noarch::foo-1.0-0",
+        )));
+        revision.update_specs.push(String::from("foo"));
+        revision
+            .append_to_path(&History::path(prefix.path()))
+            .unwrap();
+        revision
+            .append_to_path(&History::path(prefix.path()))
+            .unwrap();
+
+        let history = History::from_prefix(prefix.path()).unwrap();
+        assert_eq!(history.revisions, [revision.clone(), revision]);
+    }
+
+    #[test]
+    fn test_missing_header() {
+        assert_matches!(
+            History::from_str("+conda-forge/noarch::foo-1.0-0\n"),
+            Err(ParseHistoryError::MissingHeader(_))
+        );
+        assert_matches!(
+            History::from_str("==> yesterday <==\n"),
+            Err(ParseHistoryError::InvalidTimestamp(..))
+        );
+    }
+}
@@ -6,6 +6,7 @@ mod build_spec;
 mod channel;
 mod channel_data;
 mod explicit_environment_spec;
+mod flock;
 mod match_spec;
 mod no_arch_type;
 mod platform;
@@ -17,8 +18,10 @@ mod version;
 pub mod version_spec;
 
 mod generic_virtual_package;
+pub mod history;
 pub mod package;
 mod package_name;
+mod prefix_lock;
 pub mod prefix_record;
 
 pub use build_spec::{BuildNumber, BuildNumberSpec, ParseBuildNumberSpecError};
@@ -28,17 +31,21 @@ pub use explicit_environment_spec::{
     ExplicitEnvironmentEntry, ExplicitEnvironmentSpec, PackageArchiveHash,
     ParseExplicitEnvironmentSpecError, ParsePackageArchiveHashError,
 };
+pub use flock::{FileLockMode, LockedFile};
 pub use generic_virtual_package::GenericVirtualPackage;
+pub use history::{History, HistoryChange, HistoryRevision, ParseHistoryError};
 pub use match_spec::matcher::{StringMatcher, StringMatcherParseError};
 pub use match_spec::parse::ParseMatchSpecError;
 pub use match_spec::{MatchSpec, NamelessMatchSpec};
 pub use no_arch_type::{NoArchKind, NoArchType};
 pub use package_name::{InvalidPackageNameError, PackageName};
 pub use platform::{Arch, ParseArchError, ParsePlatformError, Platform};
+pub use prefix_lock::{PrefixLock, PrefixLockError, PrefixLockMode};
 pub use prefix_record::PrefixRecord;
 pub use repo_data::patches::{PackageRecordPatch, PatchInstructions, RepoDataPatch};
 pub use repo_data::{
-    compute_package_url, ChannelInfo, ConvertSubdirError, PackageRecord, RepoData,
+    compute_package_url, ChannelInfo, ConvertSubdirError, DependencyStep, Dependent, PackageRecord,
+    RepoData,
 };
 pub use repo_data_record::RepoDataRecord;
 pub use run_export::RunExportKind;
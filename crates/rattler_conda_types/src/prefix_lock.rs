@@ -0,0 +1,265 @@
+//! Defines [`PrefixLock`], an advisory file lock that guards a prefix against concurrent
+//! modification.
+//!
+//! Processes that modify a prefix take an exclusive lock, processes that only read from it take a
+//! shared lock. The lock is stored in the `conda-meta/.rattler.lock` file of the prefix and is
+//! released when the [`PrefixLock`] is dropped.
+
+use crate::flock::{FileLockMode, LockedFile};
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// The location of the lock file relative to the prefix.
+const LOCK_FILE: &str = "conda-meta/.rattler.lock";
+
+/// The interval at which the lock is polled while another process holds it.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The kind of access a [`PrefixLock`] grants.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum PrefixLockMode {
+    /// Multiple processes can hold a shared lock at the same time. Used for reading the prefix.
+    Shared,
+
+    /// Only a single process can hold an exclusive lock. Used for modifying the prefix.
+    Exclusive,
+}
+
+/// An error that can occur while acquiring a [`PrefixLock`].
+#[derive(Debug, thiserror::Error)]
+pub enum PrefixLockError {
+    /// The lock file could not be opened or created.
+    #[error("failed to open the lock file '{0}'")]
+    FailedToOpen(PathBuf, #[source] io::Error),
+
+    /// The lock file could not be locked.
+    #[error("failed to lock '{0}'")]
+    FailedToLock(PathBuf, #[source] io::Error),
+
+    /// Another process held the lock for longer than the timeout.
+    #[error("timed out after {1:?} waiting for another process to release the lock '{0}'")]
+    Timeout(PathBuf, Duration),
+}
+
+/// An advisory lock on a prefix. The lock is released when this instance is dropped.
+///
+/// Locks are only respected by processes that also use [`PrefixLock`]. File locks are not
+/// reliable on network file systems, on NFS mounts and on file systems that do not support locking
+/// no lock is taken at all.
+///
+/// A process must not acquire a second lock on a prefix that it already holds an exclusive lock
+/// on, the second lock would wait for the first one to be released. Use
+/// [`crate::PrefixRecord::collect_from_locked_prefix`] to read the prefix while holding a lock.
+#[derive(Debug)]
+pub struct PrefixLock {
+    // Only held to release the lock when this instance is dropped
+    _lock: Option<LockedFile>,
+    prefix: PathBuf,
+    mode: PrefixLockMode,
+}
+
+impl PrefixLock {
+    /// Acquires an exclusive lock on the prefix, waiting for as long as another process holds a
+    /// lock on it. The `conda-meta` directory of the prefix is created if it does not exist yet.
+    pub fn exclusive(prefix: &Path) -> Result<Self, PrefixLockError> {
+        Self::acquire(prefix, PrefixLockMode::Exclusive, None, |_| {})
+    }
+
+    /// Acquires a shared lock on the prefix, waiting for as long as another process holds an
+    /// exclusive lock on it.
+    pub fn shared(prefix: &Path) -> Result<Self, PrefixLockError> {
+        Self::acquire(prefix, PrefixLockMode::Shared, None, |_| {})
+    }
+
+    /// Acquires a lock on the prefix.
+    ///
+    /// If another process holds a conflicting lock, the lock is polled until it is released or
+    /// until `timeout` has elapsed, in which case [`PrefixLockError::Timeout`] is returned. While
+    /// waiting, `on_wait` is periodically called with the time spent waiting so far, which can be
+    /// used to inform the user. Without a timeout this waits indefinitely.
+    ///
+    /// An exclusive lock creates the `conda-meta` directory of the prefix if it does not exist. A
+    /// shared lock never creates directories: if the prefix does not contain a `conda-meta`
+    /// directory, or if the lock file cannot be created because the prefix is read-only, the
+    /// returned lock does not lock anything.
+    pub fn acquire(
+        prefix: &Path,
+        mode: PrefixLockMode,
+        timeout: Option<Duration>,
+        mut on_wait: impl FnMut(Duration),
+    ) -> Result<Self, PrefixLockError> {
+        let path = prefix.join(LOCK_FILE);
+        let Some(file) = open_lock_file(&path, mode)? else {
+            return Ok(Self {
+                _lock: None,
+                prefix: prefix.to_path_buf(),
+                mode,
+            });
+        };
+
+        let file_lock_mode = match mode {
+            PrefixLockMode::Shared => FileLockMode::Shared,
+            PrefixLockMode::Exclusive => FileLockMode::Exclusive,
+        };
+
+        let start = Instant::now();
+        let mut waiting = false;
+        let mut file = file;
+        let lock = loop {
+            match LockedFile::try_lock(file, &path, file_lock_mode) {
+                Ok(Ok(lock)) => break lock,
+                Ok(Err((contended, _))) => file = contended,
+                Err(err) => return Err(PrefixLockError::FailedToLock(path, err)),
+            }
+
+            let waited = start.elapsed();
+            if timeout.map_or(false, |timeout| waited >= timeout) {
+                return Err(PrefixLockError::Timeout(path, waited));
+            }
+            if !waiting {
+                tracing::info!("waiting for the lock on {}", prefix.display());
+                waiting = true;
+            }
+            on_wait(waited);
+
+            let remaining = timeout.map_or(POLL_INTERVAL, |timeout| timeout.saturating_sub(waited));
+            std::thread::sleep(POLL_INTERVAL.min(remaining));
+        };
+
+        Ok(Self {
+            _lock: Some(lock),
+            prefix: prefix.to_path_buf(),
+            mode,
+        })
+    }
+
+    /// Returns the location of the lock file of the given prefix.
+    pub fn path(prefix: &Path) -> PathBuf {
+        prefix.join(LOCK_FILE)
+    }
+
+    /// Returns the prefix that is locked.
+    pub fn prefix(&self) -> &Path {
+        &self.prefix
+    }
+
+    /// Returns the kind of access this lock grants.
+    pub fn mode(&self) -> PrefixLockMode {
+        self.mode
+    }
+}
+
+/// Opens the lock file. Returns `None` if a shared lock is requested and the lock file does not
+/// exist and cannot be created.
+fn open_lock_file(path: &Path, mode: PrefixLockMode) -> Result<Option<File>, PrefixLockError> {
+    let mut options = OpenOptions::new();
+    options.read(true).write(true).create(true);
+
+    match mode {
+        PrefixLockMode::Exclusive => {
+            let parent = path.parent().expect("the lock file has a parent directory");
+            std::fs::create_dir_all(parent)
+                .and_then(|()| options.open(path))
+                .map(Some)
+                .map_err(|err| PrefixLockError::FailedToOpen(path.to_path_buf(), err))
+        }
+        PrefixLockMode::Shared => match options.open(path) {
+            Ok(file) => Ok(Some(file)),
+            Err(err) => match OpenOptions::new().read(true).open(path) {
+                Ok(file) => Ok(Some(file)),
+                Err(read_err) if read_err.kind() == io::ErrorKind::NotFound => {
+                    if err.kind() != io::ErrorKind::NotFound {
+                        tracing::debug!(
+                            "not locking {} because the lock file cannot be created: {err}",
+                            path.display()
+                        );
+                    }
+                    Ok(None)
+                }
+                Err(read_err) => Err(PrefixLockError::FailedToOpen(path.to_path_buf(), read_err)),
+            },
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PrefixLock, PrefixLockError, PrefixLockMode};
+    use assert_matches::assert_matches;
+    use std::time::Duration;
+
+    #[test]
+    fn test_exclusive_lock_excludes_others() {
+        let prefix = tempfile::tempdir().unwrap();
+        let lock = PrefixLock::exclusive(prefix.path()).unwrap();
+        assert!(PrefixLock::path(prefix.path()).is_file());
+
+        // Both an exclusive and a shared lock have to wait for the exclusive lock.
+        for mode in [PrefixLockMode::Exclusive, PrefixLockMode::Shared] {
+            let mut waits = Vec::new();
+            let err = PrefixLock::acquire(
+                prefix.path(),
+                mode,
+                Some(Duration::from_millis(250)),
+                |waited| waits.push(waited),
+            )
+            .unwrap_err();
+            assert_matches!(err, PrefixLockError::Timeout(_, waited) if waited >= Duration::from_millis(250));
+            assert!(!waits.is_empty());
+        }
+
+        // After releasing the lock it can be acquired again.
+        drop(lock);
+        PrefixLock::acquire(
+            prefix.path(),
+            PrefixLockMode::Exclusive,
+            Some(Duration::ZERO),
+            |_| panic!("there should be no need to wait"),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_shared_locks() {
+        let prefix = tempfile::tempdir().unwrap();
+
+        // A shared lock on a prefix without a `conda-meta` directory does not create anything.
+        let lock = PrefixLock::shared(prefix.path()).unwrap();
+        assert!(!prefix.path().join("conda-meta").exists());
+        drop(lock);
+
+        std::fs::create_dir(prefix.path().join("conda-meta")).unwrap();
+        let first = PrefixLock::shared(prefix.path()).unwrap();
+        let second = PrefixLock::acquire(
+            prefix.path(),
+            PrefixLockMode::Shared,
+            Some(Duration::ZERO),
+            |_| panic!("shared locks should not wait for each other"),
+        )
+        .unwrap();
+        assert_eq!(second.mode(), PrefixLockMode::Shared);
+
+        let err = PrefixLock::acquire(
+            prefix.path(),
+            PrefixLockMode::Exclusive,
+            Some(Duration::from_millis(100)),
+            |_| {},
+        )
+        .unwrap_err();
+        assert_matches!(err, PrefixLockError::Timeout(..));
+
+        drop(first);
+        drop(second);
+        PrefixLock::acquire(
+            prefix.path(),
+            PrefixLockMode::Exclusive,
+            Some(Duration::ZERO),
+            |_| {},
+        )
+        .unwrap();
+    }
+}
@@ -1,7 +1,8 @@
 //! Defines the `[PrefixRecord]` struct.
 
+use crate::package::FileMode;
 use crate::repo_data_record::RepoDataRecord;
-use crate::PackageRecord;
+use crate::{PackageRecord, PrefixLock, PrefixLockError};
 use rattler_digest::serde::SerializableHash;
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
@@ -85,6 +86,15 @@ pub struct PathsEntry {
     /// The size of the file in bytes
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub size_in_bytes: Option<u64>,
+
+    /// The prefix placeholder in the original file that was replaced with the target prefix when
+    /// the file was installed. `None` if the file did not contain a placeholder.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix_placeholder: Option<String>,
+
+    /// How the prefix placeholder was replaced, only set together with `prefix_placeholder`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_mode: Option<FileMode>,
 }
 
 /// Information about a single file installed for a package.
@@ -232,7 +242,26 @@ impl PrefixRecord {
 
     /// Collects all `PrefixRecord`s from the specified prefix. This function will read all files in
     /// the `$PREFIX/conda-meta` directory and parse them as `PrefixRecord`s.
+    ///
+    /// A shared [`PrefixLock`] is held while the records are read, this waits for any process that
+    /// is modifying the prefix. Use [`PrefixRecord::collect_from_locked_prefix`] if the current
+    /// process already holds a lock on the prefix.
     pub fn collect_from_prefix(prefix: &Path) -> Result<Vec<PrefixRecord>, std::io::Error> {
+        let lock = PrefixLock::shared(prefix).map_err(|err| match err {
+            PrefixLockError::FailedToOpen(_, err) | PrefixLockError::FailedToLock(_, err) => err,
+            err @ PrefixLockError::Timeout(..) => {
+                std::io::Error::new(std::io::ErrorKind::TimedOut, err)
+            }
+        })?;
+        Self::collect_from_locked_prefix(&lock)
+    }
+
+    /// Collects all `PrefixRecord`s from the prefix that is locked by `lock`. Unlike
+    /// [`PrefixRecord::collect_from_prefix`] this does not acquire a lock itself.
+    pub fn collect_from_locked_prefix(
+        lock: &PrefixLock,
+    ) -> Result<Vec<PrefixRecord>, std::io::Error> {
+        let prefix = lock.prefix();
         let mut records = Vec::new();
         let conda_meta_path = prefix.join("conda-meta");
 
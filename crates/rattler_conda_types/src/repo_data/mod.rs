@@ -4,6 +4,8 @@
 pub mod patches;
 mod topological_sort;
 
+pub use topological_sort::{DependencyStep, Dependent};
+
 use std::borrow::Cow;
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Display, Formatter};
@@ -21,7 +23,8 @@ use rattler_macros::sorted;
 
 use crate::{
     build_spec::BuildNumber, package::IndexJson, utils::serde::DeserializeFromStrUnchecked,
-    Channel, NoArchType, PackageName, PackageUrl, Platform, RepoDataRecord, VersionWithSource,
+    Channel, MatchSpec, NoArchType, PackageName, PackageUrl, Platform, RepoDataRecord,
+    VersionWithSource,
 };
 
 /// [`RepoData`] is an index of package binaries available on in a subdirectory of a Conda channel.
@@ -301,6 +304,28 @@ impl PackageRecord {
     pub fn sort_topologically<T: AsRef<PackageRecord> + Clone>(records: Vec<T>) -> Vec<T> {
         topological_sort::sort_topologically(records)
     }
+
+    /// Explains why the `target` package is part of an environment by returning the shortest
+    /// dependency path from each of the `root_specs` to the `target` package. Every step of a path
+    /// contains the spec that selected the package of that step.
+    ///
+    /// Note that this function only works for packages with unique names.
+    pub fn dependency_paths<'a, T: AsRef<PackageRecord>>(
+        records: &'a [T],
+        root_specs: &[MatchSpec],
+        target: &PackageName,
+    ) -> Vec<Vec<DependencyStep<'a, T>>> {
+        topological_sort::dependency_paths(records, root_specs, target)
+    }
+
+    /// Returns the records that directly depend on `package`, together with the spec of the
+    /// dependency.
+    pub fn reverse_dependencies<'a, T: AsRef<PackageRecord>>(
+        records: &'a [T],
+        package: &PackageName,
+    ) -> Vec<Dependent<'a, T>> {
+        topological_sort::reverse_dependencies(records, package)
+    }
 }
 
 /// An error that can occur when parsing a platform from a string.
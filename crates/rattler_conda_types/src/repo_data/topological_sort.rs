@@ -1,4 +1,4 @@
-use crate::PackageRecord;
+use crate::{MatchSpec, PackageName, PackageRecord};
 use fxhash::{FxHashMap, FxHashSet};
 
 /// Sorts the packages topologically
@@ -223,6 +223,186 @@ fn get_topological_order<T: AsRef<PackageRecord>>(
     output
 }
 
+/// A single step in a dependency path, see [`PackageRecord::dependency_paths`].
+#[derive(Debug)]
+pub struct DependencyStep<'a, T> {
+    /// The spec that selected the package. For the first step of a path this is one of the root
+    /// specs, for the other steps it is an entry of the `depends` of the previous package.
+    pub spec: String,
+
+    /// The record of the selected package
+    pub record: &'a T,
+}
+
+impl<'a, T> Clone for DependencyStep<'a, T> {
+    fn clone(&self) -> Self {
+        Self {
+            spec: self.spec.clone(),
+            record: self.record,
+        }
+    }
+}
+
+/// A package that directly depends on another package, see
+/// [`PackageRecord::reverse_dependencies`].
+#[derive(Debug)]
+pub struct Dependent<'a, T> {
+    /// The record of the package that has the dependency
+    pub record: &'a T,
+
+    /// The entry of the `depends` of the package that refers to the dependency
+    pub spec: &'a str,
+}
+
+/// Returns the shortest dependency path from each of the root specs to the `target` package,
+/// explaining why the package is part of the environment.
+///
+/// Each path starts with a package that is selected by one of the `root_specs` and ends with the
+/// `target` package. At most one path is returned per root spec, so the size of the result is
+/// bounded by the number of root specs times the number of packages, even for densely connected
+/// environments in which the number of distinct paths grows exponentially. The paths are ordered
+/// by the order of `root_specs`. If there are multiple shortest paths, the one that visits the
+/// dependencies in order of their names is returned, which makes the result deterministic. If a
+/// package depends on the same package multiple times, only the first spec is used.
+///
+/// Note that this function only works for packages with unique names.
+pub fn dependency_paths<'a, T: AsRef<PackageRecord>>(
+    records: &'a [T],
+    root_specs: &[MatchSpec],
+    target: &PackageName,
+) -> Vec<Vec<DependencyStep<'a, T>>> {
+    let packages: FxHashMap<_, _> = records
+        .iter()
+        .map(|r| (r.as_ref().name.as_normalized(), r))
+        .collect();
+
+    // Find all the packages from which the target can be reached, other packages don't have to
+    // be visited.
+    let mut dependents: FxHashMap<&str, Vec<&str>> = FxHashMap::default();
+    for record in records {
+        for dependency in &record.as_ref().depends {
+            dependents
+                .entry(package_name_from_match_spec(dependency))
+                .or_default()
+                .push(record.as_ref().name.as_normalized());
+        }
+    }
+    let mut reaches_target = FxHashSet::default();
+    let mut stack = vec![target.as_normalized()];
+    while let Some(name) = stack.pop() {
+        if reaches_target.insert(name) {
+            stack.extend(dependents.get(name).into_iter().flatten().copied());
+        }
+    }
+
+    let mut paths = Vec::new();
+    for spec in root_specs {
+        let Some(name) = &spec.name else {
+            continue;
+        };
+        if !reaches_target.contains(name.as_normalized()) {
+            continue;
+        }
+        let Some(&record) = packages.get(name.as_normalized()) else {
+            continue;
+        };
+
+        let root = DependencyStep {
+            spec: spec.to_string(),
+            record,
+        };
+        if let Some(path) =
+            shortest_dependency_path(&packages, &reaches_target, target.as_normalized(), root)
+        {
+            paths.push(path);
+        }
+    }
+
+    paths
+}
+
+/// Finds the shortest path from `root` to `target` with a breadth-first search over the packages
+/// that can reach the target.
+fn shortest_dependency_path<'a, T: AsRef<PackageRecord>>(
+    packages: &FxHashMap<&str, &'a T>,
+    reaches_target: &FxHashSet<&str>,
+    target: &str,
+    root: DependencyStep<'a, T>,
+) -> Option<Vec<DependencyStep<'a, T>>> {
+    // Every visited package is stored together with the index of the step through which it was
+    // reached, which allows reconstructing the path once the target is found.
+    let mut steps: Vec<(DependencyStep<'a, T>, Option<usize>)> = vec![(root, None)];
+    let mut visited: FxHashSet<&str> = FxHashSet::default();
+    visited.insert(steps[0].0.record.as_ref().name.as_normalized());
+
+    let mut next = 0;
+    while next < steps.len() {
+        let current = steps[next].0.record.as_ref();
+        if current.name.as_normalized() == target {
+            let mut path = Vec::new();
+            let mut index = Some(next);
+            while let Some(i) = index {
+                path.push(steps[i].0.clone());
+                index = steps[i].1;
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        // Sorting makes this step deterministic (i.e. the same output is returned, regardless of
+        // the original order of the input)
+        let mut dependencies: Vec<_> = current
+            .depends
+            .iter()
+            .map(|d| (package_name_from_match_spec(d), d))
+            .filter(|(name, _)| reaches_target.contains(name))
+            .collect();
+        dependencies.sort_by_key(|(name, _)| *name);
+        dependencies.dedup_by_key(|(name, _)| *name);
+
+        for (name, spec) in dependencies {
+            let Some(&record) = packages.get(name) else {
+                continue;
+            };
+            if visited.insert(name) {
+                steps.push((
+                    DependencyStep {
+                        spec: spec.clone(),
+                        record,
+                    },
+                    Some(next),
+                ));
+            }
+        }
+
+        next += 1;
+    }
+
+    None
+}
+
+/// Returns the packages that directly depend on `package`, ordered by name.
+///
+/// If a package depends on `package` multiple times, only the first spec is returned.
+pub fn reverse_dependencies<'a, T: AsRef<PackageRecord>>(
+    records: &'a [T],
+    package: &PackageName,
+) -> Vec<Dependent<'a, T>> {
+    let mut dependents: Vec<_> = records
+        .iter()
+        .filter_map(|record| {
+            let spec = record
+                .as_ref()
+                .depends
+                .iter()
+                .find(|d| package_name_from_match_spec(d) == package.as_normalized())?;
+            Some(Dependent { record, spec })
+        })
+        .collect();
+    dependents.sort_by_key(|dependent| dependent.record.as_ref().name.as_normalized());
+    dependents
+}
+
 /// Helper function to obtain the package name from a match spec
 fn package_name_from_match_spec(d: &str) -> &str {
     // Unwrap is safe because split always returns at least one value
@@ -232,8 +412,9 @@ fn package_name_from_match_spec(d: &str) -> &str {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::RepoDataRecord;
+    use crate::{RepoDataRecord, Version};
     use rstest::rstest;
+    use std::str::FromStr;
 
     /// Ensures that the packages are the same before and after the sort, and panics otherwise
     fn sanity_check_topological_sort(
@@ -359,6 +540,104 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_dependency_paths() {
+        let packages = get_resolved_packages_for_numpy();
+        let root_specs = [MatchSpec::from_str("numpy").unwrap()];
+        let target = PackageName::new_unchecked("libgfortran5");
+
+        let paths: Vec<Vec<_>> = dependency_paths(&packages, &root_specs, &target)
+            .into_iter()
+            .map(|path| path.into_iter().map(|step| step.spec).collect::<Vec<_>>())
+            .collect();
+
+        // Only the shortest path is returned, even though the target can also be reached through
+        // libblas and libopenblas
+        assert_eq!(
+            paths,
+            [["numpy", "liblapack >=3.9.0,<4.0a0", "libgfortran5 >=9.3.0"]]
+        );
+
+        // The target is not reachable from python
+        let root_specs = [MatchSpec::from_str("python").unwrap()];
+        assert!(dependency_paths(&packages, &root_specs, &target).is_empty());
+    }
+
+    #[test]
+    fn test_dependency_paths_dense_graph() {
+        // A chain of diamonds: every package of a layer depends on every package of the next
+        // layer, which results in `WIDTH ^ LAYERS` distinct paths from the root to the target.
+        const LAYERS: usize = 16;
+        const WIDTH: usize = 4;
+
+        let layer_names = |layer: usize| (0..WIDTH).map(move |i| format!("layer{layer:02}-{i}"));
+        let record = |name: String, depends: Vec<String>| {
+            let mut package_record = PackageRecord::new(
+                PackageName::new_unchecked(name.clone()),
+                Version::from_str("1.0").unwrap(),
+                String::from("0"),
+            );
+            package_record.depends = depends;
+            RepoDataRecord {
+                package_record,
+                file_name: format!("{name}-1.0-0.tar.bz2"),
+                url: format!("https://example.com/{name}-1.0-0.tar.bz2")
+                    .parse()
+                    .unwrap(),
+                channel: String::from("https://example.com/"),
+            }
+        };
+
+        let mut packages = vec![record(String::from("root"), layer_names(0).collect())];
+        for layer in 0..LAYERS {
+            let depends: Vec<_> = if layer + 1 == LAYERS {
+                vec![String::from("target")]
+            } else {
+                layer_names(layer + 1).collect()
+            };
+            packages.extend(layer_names(layer).map(|name| record(name, depends.clone())));
+        }
+        packages.push(record(String::from("target"), Vec::new()));
+
+        let root_specs = [MatchSpec::from_str("root").unwrap()];
+        let target = PackageName::new_unchecked("target");
+        let paths: Vec<Vec<_>> = dependency_paths(&packages, &root_specs, &target)
+            .into_iter()
+            .map(|path| path.into_iter().map(|step| step.spec).collect::<Vec<_>>())
+            .collect();
+
+        // Only the shortest path is returned, which visits the first package of every layer
+        let expected: Vec<_> = std::iter::once(String::from("root"))
+            .chain((0..LAYERS).map(|layer| format!("layer{layer:02}-0")))
+            .chain(std::iter::once(String::from("target")))
+            .collect();
+        assert_eq!(paths, [expected]);
+    }
+
+    #[test]
+    fn test_reverse_dependencies() {
+        let packages = get_resolved_packages_for_numpy();
+        let dependents: Vec<_> =
+            reverse_dependencies(&packages, &PackageName::new_unchecked("libgfortran5"))
+                .into_iter()
+                .map(|dependent| {
+                    (
+                        dependent.record.package_record.name.as_normalized(),
+                        dependent.spec,
+                    )
+                })
+                .collect();
+
+        assert_eq!(
+            dependents,
+            [
+                ("libgfortran-ng", "libgfortran5 12.2.0 h337968e_19"),
+                ("liblapack", "libgfortran5 >=9.3.0"),
+                ("libopenblas", "libgfortran5 >=10.3.0"),
+            ]
+        );
+    }
+
     fn get_resolved_packages_for_two_roots() -> Vec<RepoDataRecord> {
         let repodata_json = r#"[
             {
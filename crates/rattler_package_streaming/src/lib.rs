@@ -39,6 +39,14 @@ pub enum ExtractError {
     #[error(transparent)]
     ReqwestError(::reqwest_middleware::Error),
 
+    #[cfg(feature = "reqwest")]
+    #[error(transparent)]
+    AsyncHttpRangeReaderError(#[from] async_http_range_reader::AsyncHttpRangeReaderError),
+
+    #[cfg(feature = "reqwest")]
+    #[error("invalid zip archive")]
+    AsyncZipError(#[from] async_zip::error::ZipError),
+
     #[error("unsupported package archive format")]
     UnsupportedArchiveType,
 
@@ -47,6 +55,13 @@ pub enum ExtractError {
 
     #[error("could not parse archive member {0}: {1}")]
     ArchiveMemberParseError(PathBuf, #[source] std::io::Error),
+
+    #[error("the {algorithm} hash of the archive is {actual} but {expected} was expected")]
+    HashMismatch {
+        algorithm: &'static str,
+        expected: String,
+        actual: String,
+    },
 }
 
 #[cfg(feature = "reqwest")]
@@ -1,23 +1,40 @@
 //! Functionality to stream and extract packages directly from a [`reqwest::Url`] within a [`tokio`]
 //! async context.
 
-use crate::{ExtractError, ExtractResult};
-use futures_util::stream::TryStreamExt;
-use rattler_conda_types::package::ArchiveType;
+use crate::{
+    read::stream_tar_zst, seek::read_package_file_from_archive, ExtractError, ExtractResult,
+};
+use async_http_range_reader::{AsyncHttpRangeReader, CheckSupportMethod};
+use async_zip::{tokio::read::seek::ZipFileReader, Compression};
+use futures_util::{io::AsyncReadExt, stream::TryStreamExt};
+use rattler_conda_types::package::{ArchiveType, PackageFile};
 use reqwest::Response;
-use std::path::Path;
+use std::{
+    io::{Cursor, Read},
+    path::Path,
+};
 use tokio::io::BufReader;
+use tokio_util::compat::TokioAsyncReadCompatExt;
 use tokio_util::either::Either;
 use tokio_util::io::StreamReader;
 use url::Url;
 
+/// The number of bytes that are initially requested from the end of a remote `.conda` archive.
+/// This is more than enough to contain the central directory of the zip archive.
+const CONDA_TAIL_SIZE: u64 = 8192;
+
+/// The size of the buffer the zip reader uses to read from the remote archive.
+const ZIP_BUFFER_SIZE: u64 = 8192;
+
 fn error_for_status(response: reqwest::Response) -> reqwest_middleware::Result<Response> {
     response
         .error_for_status()
         .map_err(reqwest_middleware::Error::Reqwest)
 }
 
-async fn get_reader(
+/// Returns a reader that streams the contents of the file at the specified url. Urls with the
+/// `file` scheme are read from disk, other urls are downloaded with the `client`.
+pub async fn get_reader(
     url: Url,
     client: reqwest_middleware::ClientWithMiddleware,
 ) -> Result<impl tokio::io::AsyncRead, ExtractError> {
@@ -132,3 +149,98 @@ pub async fn extract(
         ArchiveType::Conda => extract_conda(client, url, destination).await,
     }
 }
+
+/// Streams the info section of a remote `.conda` package as a tar archive, without downloading the
+/// rest of the package.
+///
+/// The archive is read with range requests: the first request reads the central directory at the
+/// end of the zip archive, the second one only the `info-*.tar.zst` member. This usually
+/// transfers a few kilobytes, no matter how large the package is. The server must support range
+/// requests. Only `.conda` packages can be read this way, `.tar.bz2` packages result in
+/// [`ExtractError::UnsupportedArchiveType`].
+pub async fn stream_conda_info(
+    client: reqwest_middleware::ClientWithMiddleware,
+    url: Url,
+) -> Result<tar::Archive<impl Read + Sized>, ExtractError> {
+    if ArchiveType::try_from(Path::new(url.path())) != Some(ArchiveType::Conda) {
+        return Err(ExtractError::UnsupportedArchiveType);
+    }
+
+    let (range_reader, _) = AsyncHttpRangeReader::new(
+        client,
+        url,
+        CheckSupportMethod::NegativeRangeRequest(CONDA_TAIL_SIZE),
+    )
+    .await?;
+
+    // Opening the zip archive only reads its central directory.
+    let mut reader = ZipFileReader::new(range_reader.compat()).await?;
+    let (index, offset, size) = {
+        let (index, entry) = reader
+            .file()
+            .entries()
+            .iter()
+            .enumerate()
+            .find(|(_, entry)| {
+                entry.entry().filename().as_str().map_or(false, |name| {
+                    name.starts_with("info-") && name.ends_with(".tar.zst")
+                })
+            })
+            .ok_or(ExtractError::MissingComponent)?;
+
+        // Make sure the file is uncompressed.
+        if entry.entry().compression() != Compression::Stored {
+            return Err(ExtractError::UnsupportedCompressionMethod);
+        }
+
+        // The member is preceded by a local file header of 30 bytes, the file name and an extra
+        // field. The size of the extra field is not known, rounding up to the buffer size of the
+        // zip reader leaves room for it.
+        let size =
+            30 + entry.entry().filename().as_bytes().len() as u64 + entry.entry().compressed_size();
+        let size = ((size + ZIP_BUFFER_SIZE - 1) / ZIP_BUFFER_SIZE) * ZIP_BUFFER_SIZE;
+        (index, entry.header_offset(), size)
+    };
+
+    // Fetch the member with a single request instead of a request per buffer.
+    reader
+        .inner_mut()
+        .get_mut()
+        .prefetch(offset..offset + size)
+        .await;
+
+    let mut bytes = Vec::new();
+    reader
+        .reader_with_entry(index)
+        .await?
+        .read_to_end(&mut bytes)
+        .await?;
+
+    stream_tar_zst(Cursor::new(bytes))
+}
+
+/// Reads a file from the info section of a remote `.conda` package, without downloading the rest of
+/// the package. See [`stream_conda_info`] for the requirements on the server.
+///
+/// ```rust,no_run
+/// # #[tokio::main]
+/// # async fn main() {
+/// use rattler_conda_types::package::IndexJson;
+/// use rattler_package_streaming::reqwest::tokio::read_package_file;
+/// use reqwest::Client;
+/// use reqwest_middleware::ClientWithMiddleware;
+/// use url::Url;
+/// let index_json: IndexJson = read_package_file(
+///     ClientWithMiddleware::from(Client::new()),
+///     Url::parse("https://conda.anaconda.org/conda-forge/linux-64/python-3.10.8-h4a9ceb5_0_cpython.conda").unwrap())
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+pub async fn read_package_file<P: PackageFile>(
+    client: reqwest_middleware::ClientWithMiddleware,
+    url: Url,
+) -> Result<P, ExtractError> {
+    let mut info_archive = stream_conda_info(client, url).await?;
+    read_package_file_from_archive(&mut info_archive)
+}
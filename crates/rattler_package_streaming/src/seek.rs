@@ -104,17 +104,21 @@ pub fn read_package_file<P: PackageFile>(path: impl AsRef<Path>) -> Result<P, Ex
     match ArchiveType::try_from(&path).ok_or(ExtractError::UnsupportedArchiveType)? {
         ArchiveType::TarBz2 => {
             let mut archive = stream_tar_bz2(file);
-            let buf = get_file_from_archive(&mut archive, P::package_path())?;
-            return P::from_str(&String::from_utf8_lossy(&buf)).map_err(|e| {
-                ExtractError::ArchiveMemberParseError(P::package_path().to_owned(), e)
-            });
+            read_package_file_from_archive(&mut archive)
         }
         ArchiveType::Conda => {
             let mut info_archive = stream_conda_info(file).unwrap();
-            let buf = get_file_from_archive(&mut info_archive, P::package_path())?;
-            return P::from_str(&String::from_utf8_lossy(&buf)).map_err(|e| {
-                ExtractError::ArchiveMemberParseError(P::package_path().to_owned(), e)
-            });
+            read_package_file_from_archive(&mut info_archive)
         }
-    };
+    }
+}
+
+/// Reads and parses a package file from a tar archive that contains the `info` section of a
+/// package.
+pub(crate) fn read_package_file_from_archive<P: PackageFile>(
+    archive: &mut Archive<impl Read>,
+) -> Result<P, ExtractError> {
+    let buf = get_file_from_archive(archive, P::package_path())?;
+    P::from_str(&String::from_utf8_lossy(&buf))
+        .map_err(|e| ExtractError::ArchiveMemberParseError(P::package_path().to_owned(), e))
 }
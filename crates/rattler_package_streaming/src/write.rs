@@ -1,12 +1,17 @@
 //! Functionality for writing conda packages
+use std::borrow::Cow;
 use std::fs::{self, File};
-use std::io::{self, Seek, Write};
+use std::io::{self, Read, Seek, Write};
 use std::path::{Path, PathBuf};
 
 use itertools::sorted;
 
 use rattler_conda_types::package::PackageMetadata;
 
+use crate::read::stream_tar_bz2;
+use crate::seek::{stream_conda_content, stream_conda_info};
+use crate::ExtractError;
+
 /// a function that sorts paths into two iterators, one that starts with `info/` and one that does not
 /// both iterators are sorted alphabetically for reproducibility
 fn sort_paths<'a>(
@@ -157,9 +162,7 @@ fn write_zst_archive<W: Write>(
 
     // Compress it as tar.zst
     let mut tar_file = File::open(&tar_path)?;
-    let compression_level = compression_level.to_zstd_level()?;
-    let mut zst_encoder = zstd::Encoder::new(writer, compression_level)?;
-    zst_encoder.multithread(num_threads.unwrap_or_else(|| num_cpus::get() as u32))?;
+    let mut zst_encoder = create_zst_encoder(writer, compression_level, num_threads)?;
     zst_encoder.set_pledged_src_size(tar_file.metadata().map(|v| v.len()).ok())?;
     zst_encoder.include_contentsize(true)?;
 
@@ -170,6 +173,17 @@ fn write_zst_archive<W: Write>(
     Ok(())
 }
 
+/// Creates a zstd encoder for the inner archives of a `.conda` package.
+fn create_zst_encoder<W: Write>(
+    writer: W,
+    compression_level: CompressionLevel,
+    num_threads: Option<u32>,
+) -> Result<zstd::Encoder<'static, W>, std::io::Error> {
+    let mut zst_encoder = zstd::Encoder::new(writer, compression_level.to_zstd_level()?)?;
+    zst_encoder.multithread(num_threads.unwrap_or_else(|| num_cpus::get() as u32))?;
+    Ok(zst_encoder)
+}
+
 /// Write a `.conda` package to a writer
 /// A `.conda` package is an outer uncompressed zip archive that contains a `metadata.json` file, a
 /// `pkg-archive.tar.zst` file, and a `info-archive.tar.zst` file.
@@ -297,3 +311,138 @@ fn append_path_to_archive(
 
     Ok(())
 }
+
+/// Converts a `.tar.bz2` package read from `reader` to a `.conda` package written to `writer`,
+/// without extracting it to disk.
+///
+/// The files of the package are copied with their headers, which keeps file modes, timestamps and
+/// symlinks. Files in the `info/` directory end up in the `info-{out_name}.tar.zst` archive, all
+/// other files in the `pkg-{out_name}.tar.zst` archive, both in the order in which they appear in
+/// the original package.
+///
+/// # Arguments
+///
+/// * `reader` - the `.tar.bz2` package to convert
+/// * `writer` - the writer to write the `.conda` package to
+/// * `out_name` - the name of the package, usually the file name without the extension
+/// * `compression_level` - the compression level to use for the inner zstd encoded files
+/// * `compression_num_threads` - the number of threads to use for zstd compression (defaults to
+/// the number of CPU cores if `None`)
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use rattler_package_streaming::write::{convert_tar_bz2_to_conda, CompressionLevel};
+///
+/// let reader = File::open("mamba-1.0.0-py38hecfeebb_2.tar.bz2").unwrap();
+/// let writer = File::create("mamba-1.0.0-py38hecfeebb_2.conda").unwrap();
+/// convert_tar_bz2_to_conda(reader, writer, "mamba-1.0.0-py38hecfeebb_2", CompressionLevel::Default, None).unwrap();
+/// ```
+pub fn convert_tar_bz2_to_conda<W: Write + Seek>(
+    reader: impl Read,
+    writer: W,
+    out_name: &str,
+    compression_level: CompressionLevel,
+    compression_num_threads: Option<u32>,
+) -> Result<(), ExtractError> {
+    let mut outer_archive = zip::ZipWriter::new(writer);
+    let options =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+
+    // write the metadata as first file in the zip archive
+    let package_metadata = PackageMetadata::default();
+    let package_metadata = serde_json::to_string(&package_metadata).unwrap();
+    outer_archive.start_file("metadata.json", options)?;
+    outer_archive.write_all(package_metadata.as_bytes())?;
+
+    // The info archive comes last in the outer zip archive, so while the package is read it is
+    // kept in memory. It is small compared to the rest of the package.
+    let mut info_archive = tar::Builder::new(Vec::new());
+
+    outer_archive.start_file(format!("pkg-{out_name}.tar.zst"), options)?;
+    let mut pkg_archive = tar::Builder::new(create_zst_encoder(
+        &mut outer_archive,
+        compression_level,
+        compression_num_threads,
+    )?);
+    let info = Path::new("info/");
+    for entry in stream_tar_bz2(reader).entries()? {
+        let entry = entry?;
+        if entry.path()?.starts_with(info) {
+            copy_archive_entry(&mut info_archive, entry)?;
+        } else {
+            copy_archive_entry(&mut pkg_archive, entry)?;
+        }
+    }
+    pkg_archive.into_inner()?.finish()?;
+
+    outer_archive.start_file(format!("info-{out_name}.tar.zst"), options)?;
+    let mut zst_encoder = create_zst_encoder(
+        &mut outer_archive,
+        compression_level,
+        compression_num_threads,
+    )?;
+    zst_encoder.write_all(&info_archive.into_inner()?)?;
+    zst_encoder.finish()?;
+
+    outer_archive.finish()?;
+
+    Ok(())
+}
+
+/// Converts a `.conda` package read from `reader` to a `.tar.bz2` package written to `writer`,
+/// without extracting it to disk.
+///
+/// The files of the package are copied with their headers, which keeps file modes, timestamps and
+/// symlinks. The files of the info section come first, like in [`write_tar_bz2_package`], followed
+/// by the other files. Both are kept in the order in which they appear in the original package.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use rattler_package_streaming::write::{convert_conda_to_tar_bz2, CompressionLevel};
+///
+/// let reader = File::open("mamba-1.1.0-py39hb3d9227_2.conda").unwrap();
+/// let writer = File::create("mamba-1.1.0-py39hb3d9227_2.tar.bz2").unwrap();
+/// convert_conda_to_tar_bz2(reader, writer, CompressionLevel::Default).unwrap();
+/// ```
+pub fn convert_conda_to_tar_bz2<W: Write>(
+    mut reader: impl Read + Seek,
+    writer: W,
+    compression_level: CompressionLevel,
+) -> Result<(), ExtractError> {
+    let mut archive = tar::Builder::new(bzip2::write::BzEncoder::new(
+        writer,
+        compression_level.to_bzip2_level()?,
+    ));
+
+    for entry in stream_conda_info(&mut reader)?.entries()? {
+        copy_archive_entry(&mut archive, entry?)?;
+    }
+    for entry in stream_conda_content(&mut reader)?.entries()? {
+        copy_archive_entry(&mut archive, entry?)?;
+    }
+
+    archive.into_inner()?.finish()?;
+
+    Ok(())
+}
+
+/// Appends an entry of another archive to `archive`, keeping its header.
+fn copy_archive_entry(
+    archive: &mut tar::Builder<impl Write>,
+    mut entry: tar::Entry<'_, impl Read>,
+) -> Result<(), std::io::Error> {
+    let mut header = entry.header().clone();
+    let path = entry.path()?.into_owned();
+    let link_name = entry.link_name()?.map(Cow::into_owned);
+
+    match link_name {
+        Some(target) if header.entry_type().is_symlink() || header.entry_type().is_hard_link() => {
+            archive.append_link(&mut header, path, target)
+        }
+        _ => archive.append_data(&mut header, path, &mut entry),
+    }
+}
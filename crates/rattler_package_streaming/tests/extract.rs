@@ -5,6 +5,10 @@ use rstest_reuse::{self, apply, template};
 use std::fs::File;
 use std::path::{Path, PathBuf};
 
+#[cfg(feature = "reqwest")]
+#[path = "../../async_http_range_reader/src/static_directory_server.rs"]
+mod static_directory_server;
+
 fn test_data_dir() -> PathBuf {
     Path::new(env!("CARGO_MANIFEST_DIR")).join("../../test-data")
 }
@@ -147,6 +151,59 @@ fn read_package_file(#[case] input: &str, #[case] _sha256: &str, #[case] _md5: &
     assert!(input.starts_with(&name));
 }
 
+#[cfg(feature = "reqwest")]
+#[apply(conda_archives)]
+#[tokio::test]
+async fn read_remote_package_file(#[case] input: &str, #[case] _sha256: &str, #[case] _md5: &str) {
+    use rattler_conda_types::package::{AboutJson, PathsJson};
+    use rattler_package_streaming::{reqwest::tokio::read_package_file, seek};
+    use reqwest::Client;
+    use reqwest_middleware::ClientWithMiddleware;
+    use static_directory_server::StaticDirectoryServer;
+
+    let server = StaticDirectoryServer::new(test_data_dir());
+    let url = server.url().join(input).unwrap();
+    let client = ClientWithMiddleware::from(Client::new());
+    let local_path = test_data_dir().join(input);
+
+    let archive_size = std::fs::metadata(&local_path).unwrap().len();
+
+    // Reading a file from the archive only downloads the `info-` member and the tail of the
+    // archive, which is a small part of the whole package.
+    let mut bytes_served = server.bytes_served();
+    let mut assert_partial_download = || {
+        let bytes = server.bytes_served() - bytes_served;
+        bytes_served = server.bytes_served();
+        assert!(
+            bytes < archive_size / 2,
+            "downloaded {bytes} bytes of an archive of {archive_size} bytes"
+        );
+    };
+
+    let index_json: IndexJson = read_package_file(client.clone(), url.clone())
+        .await
+        .unwrap();
+    assert_partial_download();
+    assert_eq!(
+        index_json,
+        seek::read_package_file::<IndexJson>(&local_path).unwrap()
+    );
+    let paths_json: PathsJson = read_package_file(client.clone(), url.clone())
+        .await
+        .unwrap();
+    assert_partial_download();
+    assert_eq!(
+        paths_json,
+        seek::read_package_file::<PathsJson>(&local_path).unwrap()
+    );
+    let about_json: AboutJson = read_package_file(client, url).await.unwrap();
+    assert_partial_download();
+    assert_eq!(
+        about_json,
+        seek::read_package_file::<AboutJson>(&local_path).unwrap()
+    );
+}
+
 #[apply(tar_bz2_archives)]
 fn test_extract_tar_bz2(#[case] input: &str, #[case] sha256: &str, #[case] md5: &str) {
     let temp_dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
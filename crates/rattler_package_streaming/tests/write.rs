@@ -1,7 +1,9 @@
 use rattler_conda_types::package::ArchiveType;
-use rattler_package_streaming::read::{extract_conda, extract_tar_bz2};
+use rattler_package_streaming::read::{extract_conda, extract_tar_bz2, stream_tar_bz2};
+use rattler_package_streaming::seek::{stream_conda_content, stream_conda_info};
 use rattler_package_streaming::write::{
-    write_conda_package, write_tar_bz2_package, CompressionLevel,
+    convert_conda_to_tar_bz2, convert_tar_bz2_to_conda, write_conda_package, write_tar_bz2_package,
+    CompressionLevel,
 };
 use std::collections::HashMap;
 use std::fs::File;
@@ -227,3 +229,95 @@ fn test_rewrite_conda() {
         compare_two_conda_archives(&file_path, &new_archive);
     }
 }
+
+/// An entry of a tar archive: its path, mode, type, link name and contents.
+type ArchiveEntry = (PathBuf, u32, tar::EntryType, Option<PathBuf>, Vec<u8>);
+
+/// Reads all entries of a tar archive in the order in which they are stored.
+fn read_archive_entries(archive: &mut tar::Archive<impl Read>) -> Vec<ArchiveEntry> {
+    archive
+        .entries()
+        .unwrap()
+        .map(|entry| {
+            let mut entry = entry.unwrap();
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents).unwrap();
+            (
+                entry.path().unwrap().into_owned(),
+                entry.header().mode().unwrap(),
+                entry.header().entry_type(),
+                entry.link_name().unwrap().map(std::borrow::Cow::into_owned),
+                contents,
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_convert_tar_bz2_to_conda() {
+    let temp_dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+
+    for file_path in
+        find_all_archives().filter(|path| ArchiveType::try_from(path) == Some(ArchiveType::TarBz2))
+    {
+        println!("Name: {}", file_path.display());
+
+        let name = file_path.file_stem().unwrap().to_string_lossy();
+        let name = name.trim_end_matches(".tar");
+        let new_archive = temp_dir.join(format!("{name}-converted.conda"));
+        convert_tar_bz2_to_conda(
+            File::open(&file_path).unwrap(),
+            File::create(&new_archive).unwrap(),
+            name,
+            CompressionLevel::Numeric(3),
+            None,
+        )
+        .unwrap();
+
+        let (info_entries, pkg_entries): (Vec<_>, Vec<_>) =
+            read_archive_entries(&mut stream_tar_bz2(File::open(&file_path).unwrap()))
+                .into_iter()
+                .partition(|entry| entry.0.starts_with("info/"));
+
+        let mut converted = File::open(&new_archive).unwrap();
+        assert_eq!(
+            read_archive_entries(&mut stream_conda_info(&mut converted).unwrap()),
+            info_entries
+        );
+        assert_eq!(
+            read_archive_entries(&mut stream_conda_content(&mut converted).unwrap()),
+            pkg_entries
+        );
+    }
+}
+
+#[test]
+fn test_convert_conda_to_tar_bz2() {
+    let temp_dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+
+    for file_path in
+        find_all_archives().filter(|path| ArchiveType::try_from(path) == Some(ArchiveType::Conda))
+    {
+        println!("Name: {}", file_path.display());
+
+        let name = file_path.file_stem().unwrap().to_string_lossy();
+        let new_archive = temp_dir.join(format!("{name}-converted.tar.bz2"));
+        convert_conda_to_tar_bz2(
+            File::open(&file_path).unwrap(),
+            File::create(&new_archive).unwrap(),
+            CompressionLevel::Lowest,
+        )
+        .unwrap();
+
+        let mut original = File::open(&file_path).unwrap();
+        let mut expected = read_archive_entries(&mut stream_conda_info(&mut original).unwrap());
+        expected.extend(read_archive_entries(
+            &mut stream_conda_content(&mut original).unwrap(),
+        ));
+
+        assert_eq!(
+            read_archive_entries(&mut stream_tar_bz2(File::open(&new_archive).unwrap())),
+            expected
+        );
+    }
+}
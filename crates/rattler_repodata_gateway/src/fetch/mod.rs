@@ -1,10 +1,12 @@
 //! This module provides functionality to download and cache `repodata.json` from a remote location.
 
-use crate::utils::{AsyncEncoding, Encoding, LockedFile};
+use crate::utils::{AsyncEncoding, Encoding};
+use anyhow::Context;
 use cache::{CacheHeaders, Expiring, RepoDataState};
 use cache_control::{Cachability, CacheControl};
 use futures::{future::ready, FutureExt, TryStreamExt};
 use humansize::{SizeFormatter, DECIMAL};
+use rattler_conda_types::LockedFile;
 use rattler_digest::{compute_file_digest, Blake2b256, HashingWriter};
 use rattler_networking::{
     redact_known_secrets_from_error, redact_known_secrets_from_url, DEFAULT_REDACTION_STR,
@@ -326,10 +328,12 @@ pub async fn fetch_repo_data(
 
     // Lock all files that have to do with that cache key
     let lock_file_path = cache_path.join(format!("{}.lock", &cache_key));
-    let lock_file =
-        tokio::task::spawn_blocking(move || LockedFile::open_rw(lock_file_path, "repodata cache"))
-            .await?
-            .map_err(FetchRepoDataError::FailedToAcquireLock)?;
+    let lock_file = tokio::task::spawn_blocking(move || {
+        LockedFile::open_rw(&lock_file_path, "repodata cache")
+            .with_context(|| format!("failed to lock file: {}", lock_file_path.display()))
+    })
+    .await?
+    .map_err(FetchRepoDataError::FailedToAcquireLock)?;
 
     let cache_action = if subdir_url.scheme() == "file" {
         // If we are dealing with a local file, we can skip the cache entirely.
@@ -190,6 +190,11 @@ impl SparseRepoData {
     pub fn subdir(&self) -> &str {
         &self.subdir
     }
+
+    /// Returns the channel from which this repodata was loaded
+    pub fn channel(&self) -> &Channel {
+        &self.channel
+    }
 }
 
 /// A serde compatible struct that only sparsely parses a repodata.json file.
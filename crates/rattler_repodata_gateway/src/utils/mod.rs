@@ -1,5 +1,4 @@
 pub use encoding::{AsyncEncoding, Encoding};
-pub use flock::LockedFile;
 use std::fmt::Write;
 use url::Url;
 
@@ -8,8 +7,6 @@ mod encoding;
 #[cfg(test)]
 pub(crate) mod simple_channel_server;
 
-mod flock;
-
 /// Convert a URL to a cache filename
 pub(crate) fn url_to_cache_filename(url: &Url) -> String {
     // Start Rant:
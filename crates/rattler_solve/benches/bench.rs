@@ -59,11 +59,8 @@ fn bench_solve_environment(c: &mut Criterion, specs: Vec<&str>) {
         b.iter(|| {
             rattler_solve::libsolv_c::Solver
                 .solve(black_box(SolverTask {
-                    available_packages: &available_packages,
-                    locked_packages: vec![],
-                    pinned_packages: vec![],
-                    virtual_packages: vec![],
                     specs: specs.clone(),
+                    ..SolverTask::from_iter(&available_packages)
                 }))
                 .unwrap()
         });
@@ -74,11 +71,20 @@ fn bench_solve_environment(c: &mut Criterion, specs: Vec<&str>) {
         b.iter(|| {
             rattler_solve::resolvo::Solver
                 .solve(black_box(SolverTask {
-                    available_packages: &available_packages,
-                    locked_packages: vec![],
-                    pinned_packages: vec![],
-                    virtual_packages: vec![],
                     specs: specs.clone(),
+                    ..SolverTask::from_iter(&available_packages)
+                }))
+                .unwrap()
+        });
+    });
+
+    #[cfg(feature = "pubgrub")]
+    group.bench_function("pubgrub", |b| {
+        b.iter(|| {
+            rattler_solve::pubgrub::Solver
+                .solve(black_box(SolverTask {
+                    specs: specs.clone(),
+                    ..SolverTask::from_iter(&available_packages)
                 }))
                 .unwrap()
         });
@@ -0,0 +1,70 @@
+//! Cooperative cancellation of a running solve.
+
+use crate::SolveError;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::{Duration, Instant};
+
+/// A token that can be used to cancel a running solve, for instance from another thread.
+///
+/// Cloning the token results in a token that shares the cancellation state of the original, so
+/// cancelling any of the clones cancels all solves that use one of them.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Constructs a new token that has not been cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests cancellation of all solves that use this token.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns true if cancellation was requested.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Combines the deadline and the cancellation token of a [`crate::SolverTask`] so that backends
+/// can check whether they should stop.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StopCondition {
+    deadline: Option<Instant>,
+    cancellation_token: Option<CancellationToken>,
+}
+
+impl StopCondition {
+    /// Constructs a new instance. The deadline is computed relative to the current time.
+    pub fn new(timeout: Option<Duration>, cancellation_token: Option<CancellationToken>) -> Self {
+        Self {
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+            cancellation_token,
+        }
+    }
+
+    /// Returns an error if the solve was cancelled or if the deadline has passed.
+    pub fn check(&self) -> Result<(), SolveError> {
+        if self
+            .cancellation_token
+            .as_ref()
+            .map_or(false, CancellationToken::is_cancelled)
+        {
+            return Err(SolveError::Cancelled);
+        }
+
+        if self
+            .deadline
+            .map_or(false, |deadline| Instant::now() >= deadline)
+        {
+            return Err(SolveError::Timeout);
+        }
+
+        Ok(())
+    }
+}
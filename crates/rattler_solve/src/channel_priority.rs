@@ -0,0 +1,144 @@
+//! Determines how the order of the channels influences which candidates the solver may select.
+
+use rattler_conda_types::{MatchSpec, PackageName, RepoDataRecord};
+use std::collections::HashMap;
+
+/// Determines how the order of the channels in [`crate::SolverTask::available_packages`] affects
+/// the solution.
+///
+/// Channels are ranked by the order in which they are first encountered in the available
+/// packages, the first channel having the highest priority.
+///
+/// The default is [`ChannelPriority::Strict`], which is what the resolvo backend always did. Note
+/// that this differs from conda, which defaults to `flexible`. Before the channel priority could
+/// be configured the `libsolv_c` backend ignored the order of the channels, use
+/// [`ChannelPriority::Disabled`] to keep that behavior.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum ChannelPriority {
+    /// A package that is available in a channel is never taken from a channel with a lower
+    /// priority, even if that means the environment cannot be solved. This is the same as conda's
+    /// `channel_priority: strict`.
+    #[default]
+    Strict,
+
+    /// Packages from channels with a higher priority are preferred over packages with a higher
+    /// version from channels with a lower priority, but packages from lower priority channels are
+    /// used if that is required to solve the environment. This is the same as conda's
+    /// `channel_priority: flexible`.
+    Flexible,
+
+    /// The order of the channels is ignored. This is the same as conda's
+    /// `channel_priority: disabled`.
+    Disabled,
+}
+
+/// Ranks the channels of a set of records and determines which records are excluded because of
+/// strict channel priority. This is shared by the solver backends to make sure they behave the
+/// same.
+#[derive(Default)]
+pub(crate) struct ChannelPriorities<'a> {
+    mode: ChannelPriority,
+
+    /// The rank of each channel, lower is better
+    channel_ranks: HashMap<&'a str, usize>,
+
+    /// The rank of the best channel that provides a package with a certain name
+    best_rank_by_name: HashMap<&'a str, usize>,
+}
+
+impl<'a> ChannelPriorities<'a> {
+    /// Ranks the channels of the given records, in the order in which the records are presented.
+    ///
+    /// Records that cannot be selected because a spec requests the package from another channel
+    /// don't count towards the channels that provide a package.
+    pub fn new(
+        mode: ChannelPriority,
+        records: impl IntoIterator<Item = &'a RepoDataRecord>,
+        specs: &[MatchSpec],
+    ) -> Self {
+        let mut priorities = Self {
+            mode,
+            ..Self::default()
+        };
+        priorities.add_records(records, specs);
+        priorities
+    }
+
+    /// Ranks the given channels in order, before any of their records are known. Channels that
+    /// already have a rank keep it. This makes the ranks independent of the order in which the
+    /// records are added later, for instance when records are loaded on demand.
+    #[cfg_attr(not(feature = "resolvo"), allow(dead_code))]
+    pub fn rank_channels(&mut self, channels: impl IntoIterator<Item = &'a str>) {
+        for channel in channels {
+            let next_rank = self.channel_ranks.len();
+            self.channel_ranks.entry(channel).or_insert(next_rank);
+        }
+    }
+
+    /// Adds records to the channels that provide a package. Channels that have not been ranked
+    /// yet are ranked after all known channels.
+    pub fn add_records(
+        &mut self,
+        records: impl IntoIterator<Item = &'a RepoDataRecord>,
+        specs: &[MatchSpec],
+    ) {
+        for record in records {
+            let next_rank = self.channel_ranks.len();
+            let rank = *self
+                .channel_ranks
+                .entry(record.channel.as_str())
+                .or_insert(next_rank);
+
+            if requested_from_other_channel(record, specs) {
+                continue;
+            }
+
+            self.best_rank_by_name
+                .entry(record.package_record.name.as_normalized())
+                .and_modify(|best: &mut usize| *best = (*best).min(rank))
+                .or_insert(rank);
+        }
+    }
+
+    /// Returns the rank of a channel, lower is better. Channels that are unknown have the lowest
+    /// priority.
+    pub fn rank(&self, channel: &str) -> usize {
+        self.channel_ranks
+            .get(channel)
+            .copied()
+            .unwrap_or(usize::MAX)
+    }
+
+    /// Returns true if the order of the channels should be used to sort the candidates of a
+    /// package.
+    pub fn prefers_higher_priority(&self) -> bool {
+        self.mode != ChannelPriority::Disabled
+    }
+
+    /// Returns true if the record cannot be selected because the package is available from a
+    /// channel with a higher priority.
+    pub fn is_excluded(&self, record: &RepoDataRecord) -> bool {
+        if self.mode != ChannelPriority::Strict {
+            return false;
+        }
+
+        self.best_rank_by_name
+            .get(record.package_record.name.as_normalized())
+            .map_or(false, |&best| self.rank(&record.channel) > best)
+    }
+}
+
+/// Returns true if one of the specs requests the package of the record from another channel.
+pub(crate) fn requested_from_other_channel(record: &RepoDataRecord, specs: &[MatchSpec]) -> bool {
+    // TODO: Normalize these channel names to urls so we can compare them correctly.
+    specs
+        .iter()
+        .filter(|spec| {
+            spec.name.as_ref().map(PackageName::as_normalized)
+                == Some(record.package_record.name.as_normalized())
+        })
+        .find_map(|spec| spec.channel.as_ref())
+        .map_or(false, |channel| {
+            record.channel != channel.base_url.to_string()
+        })
+}
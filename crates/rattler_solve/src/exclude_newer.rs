@@ -0,0 +1,109 @@
+//! Allows excluding packages that were published after a certain point in time.
+
+use chrono::{DateTime, Utc};
+use rattler_conda_types::RepoDataRecord;
+
+/// Excludes the records that were published after a certain point in time. This makes it
+/// possible to reproduce the solution the solver would have found at that time.
+///
+/// Only records from [`crate::SolverTask::available_packages`] are excluded, locked and pinned
+/// packages are always available.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct ExcludeNewer {
+    /// Records with a timestamp after this point in time are excluded.
+    pub timestamp: DateTime<Utc>,
+
+    /// Determines what happens with records that do not have a timestamp.
+    pub missing_timestamp: MissingTimestamp,
+}
+
+/// Determines how [`ExcludeNewer`] treats records that do not have a timestamp.
+///
+/// Older packages are often published without a timestamp, so by default these are included.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum MissingTimestamp {
+    /// Records without a timestamp are available to the solver.
+    #[default]
+    Include,
+
+    /// Records without a timestamp are excluded.
+    Exclude,
+}
+
+impl ExcludeNewer {
+    /// Constructs a new instance that excludes all records published after `timestamp`. Records
+    /// without a timestamp are included.
+    pub fn new(timestamp: DateTime<Utc>) -> Self {
+        Self {
+            timestamp,
+            missing_timestamp: MissingTimestamp::default(),
+        }
+    }
+
+    /// Sets how records without a timestamp are treated.
+    #[must_use]
+    pub fn with_missing_timestamp(self, missing_timestamp: MissingTimestamp) -> Self {
+        Self {
+            missing_timestamp,
+            ..self
+        }
+    }
+
+    /// Returns true if the record should not be available to the solver.
+    pub fn excludes(&self, record: &RepoDataRecord) -> bool {
+        match record.package_record.timestamp {
+            Some(timestamp) => timestamp > self.timestamp,
+            None => self.missing_timestamp == MissingTimestamp::Exclude,
+        }
+    }
+
+    /// Returns the reason why a record is excluded.
+    pub(crate) fn reason(&self, record: &RepoDataRecord) -> String {
+        match record.package_record.timestamp {
+            Some(_) => format!("the package was published after {}", self.timestamp),
+            None => String::from("the package does not have a timestamp"),
+        }
+    }
+}
+
+impl From<DateTime<Utc>> for ExcludeNewer {
+    fn from(timestamp: DateTime<Utc>) -> Self {
+        Self::new(timestamp)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ExcludeNewer, MissingTimestamp};
+    use chrono::{TimeZone, Utc};
+    use rattler_conda_types::{PackageName, PackageRecord, RepoDataRecord, Version};
+    use std::str::FromStr;
+
+    fn record(timestamp: Option<i64>) -> RepoDataRecord {
+        let mut package_record = PackageRecord::new(
+            PackageName::new_unchecked("foo"),
+            Version::from_str("1.0").unwrap(),
+            String::from("0"),
+        );
+        package_record.timestamp = timestamp.map(|t| Utc.timestamp_opt(t, 0).unwrap());
+        RepoDataRecord {
+            package_record,
+            file_name: String::from("foo-1.0-0.tar.bz2"),
+            url: url::Url::parse("https://example.com/foo-1.0-0.tar.bz2").unwrap(),
+            channel: String::from("https://example.com/"),
+        }
+    }
+
+    #[test]
+    fn test_excludes() {
+        let exclude_newer = ExcludeNewer::new(Utc.timestamp_opt(1_000, 0).unwrap());
+        assert!(!exclude_newer.excludes(&record(Some(999))));
+        assert!(!exclude_newer.excludes(&record(Some(1_000))));
+        assert!(exclude_newer.excludes(&record(Some(1_001))));
+
+        assert!(!exclude_newer.excludes(&record(None)));
+        assert!(exclude_newer
+            .with_missing_timestamp(MissingTimestamp::Exclude)
+            .excludes(&record(None)));
+    }
+}
@@ -3,13 +3,35 @@
 
 #![deny(missing_docs)]
 
+mod cancellation;
+mod channel_priority;
+mod exclude_newer;
 #[cfg(feature = "libsolv_c")]
 pub mod libsolv_c;
+mod match_spec_cache;
+mod multi_platform;
+pub mod problem;
+#[cfg(feature = "pubgrub")]
+pub mod pubgrub;
+mod record_provider;
 #[cfg(feature = "resolvo")]
 pub mod resolvo;
-
+mod update;
+
+pub use cancellation::CancellationToken;
+pub(crate) use cancellation::StopCondition;
+pub use channel_priority::ChannelPriority;
+pub use exclude_newer::{ExcludeNewer, MissingTimestamp};
+pub use match_spec_cache::MatchSpecCache;
+pub use multi_platform::{
+    solve_multi_platform, MultiPlatformSolverTask, PlatformPackages, PlatformSolveError,
+};
+pub use problem::ConflictReport;
 use rattler_conda_types::{GenericVirtualPackage, MatchSpec, RepoDataRecord};
+pub use record_provider::RecordProvider;
 use std::fmt;
+use std::time::Duration;
+pub use update::{MovedPackage, UpdateSolution};
 
 /// Represents a solver implementation, capable of solving [`SolverTask`]s
 pub trait SolverImpl {
@@ -31,8 +53,9 @@ pub trait SolverImpl {
 /// Represents an error when solving the dependencies for a given environment
 #[derive(thiserror::Error, Debug)]
 pub enum SolveError {
-    /// There is no set of dependencies that satisfies the requirements
-    Unsolvable(Vec<String>),
+    /// There is no set of dependencies that satisfies the requirements. The [`ConflictReport`]
+    /// describes which requirements are in conflict.
+    Unsolvable(ConflictReport),
 
     /// The solver backend returned operations that we dont know how to install.
     /// Each string is a somewhat user-friendly representation of which operation was not recognized
@@ -42,17 +65,22 @@ pub enum SolveError {
     /// Error when converting matchspec
     #[error(transparent)]
     ParseMatchSpecError(#[from] rattler_conda_types::ParseMatchSpecError),
+
+    /// Error when loading the records of a package from a [`RecordProvider`]
+    LoadRecordsError(std::io::Error),
+
+    /// The solve was cancelled through the [`SolverTask::cancellation_token`]
+    Cancelled,
+
+    /// The solve did not finish within the [`SolverTask::timeout`]
+    Timeout,
 }
 
 impl fmt::Display for SolveError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            SolveError::Unsolvable(operations) => {
-                write!(
-                    f,
-                    "Cannot solve the request because of: {}",
-                    operations.join(", ")
-                )
+            SolveError::Unsolvable(report) => {
+                write!(f, "Cannot solve the request because of: {report}")
             }
             SolveError::UnsupportedOperations(operations) => {
                 write!(f, "Unsupported operations: {}", operations.join(", "))
@@ -60,12 +88,26 @@ impl fmt::Display for SolveError {
             SolveError::ParseMatchSpecError(e) => {
                 write!(f, "Error parsing match spec: {e}")
             }
+            SolveError::LoadRecordsError(e) => {
+                write!(f, "Error loading records: {e}")
+            }
+            SolveError::Cancelled => {
+                write!(f, "the solve was cancelled")
+            }
+            SolveError::Timeout => {
+                write!(f, "the solve did not finish within the configured timeout")
+            }
         }
     }
 }
 
 /// Represents a dependency resolution task, to be solved by one of the backends (currently only
 /// libsolv is supported)
+///
+/// A task is usually constructed with [`SolverTask::from_iter`], which only takes the available
+/// packages and leaves all other fields at their defaults. The fields that should differ can be
+/// set with the struct update syntax.
+#[derive(Clone)]
 pub struct SolverTask<TAvailablePackagesIterator> {
     /// An iterator over all available packages
     pub available_packages: TAvailablePackagesIterator,
@@ -93,6 +135,67 @@ pub struct SolverTask<TAvailablePackagesIterator> {
 
     /// The specs we want to solve
     pub specs: Vec<MatchSpec>,
+
+    /// Determines how the order of the channels in `available_packages` affects the solution.
+    pub channel_priority: ChannelPriority,
+
+    /// Determines whether the solver selects the highest or the lowest versions of packages.
+    pub strategy: SolveStrategy,
+
+    /// If set, the records in `available_packages` that were published after a certain point in
+    /// time are ignored.
+    pub exclude_newer: Option<ExcludeNewer>,
+
+    /// The maximum amount of time the solver is allowed to take. If the solve takes longer,
+    /// [`SolveError::Timeout`] is returned.
+    ///
+    /// Backends check the timeout cooperatively, so a solve may run slightly longer than the
+    /// specified duration.
+    pub timeout: Option<Duration>,
+
+    /// A token that can be used to cancel the solve from another thread. If the token is
+    /// cancelled, [`SolveError::Cancelled`] is returned.
+    pub cancellation_token: Option<CancellationToken>,
+
+    /// A cache of parsed match specs that can be shared with other solves that use the same
+    /// records. If `None`, the match specs are only cached for the duration of this solve.
+    pub match_spec_cache: Option<MatchSpecCache>,
+}
+
+impl<R> FromIterator<R> for SolverTask<Vec<R>> {
+    /// Constructs a task that solves against the given available packages. All other fields are
+    /// empty or set to their defaults.
+    fn from_iter<T: IntoIterator<Item = R>>(iter: T) -> Self {
+        Self {
+            available_packages: iter.into_iter().collect(),
+            locked_packages: Vec::new(),
+            pinned_packages: Vec::new(),
+            virtual_packages: Vec::new(),
+            specs: Vec::new(),
+            channel_priority: ChannelPriority::default(),
+            strategy: SolveStrategy::default(),
+            exclude_newer: None,
+            timeout: None,
+            cancellation_token: None,
+            match_spec_cache: None,
+        }
+    }
+}
+
+/// Determines which versions of packages the solver prefers.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum SolveStrategy {
+    /// Select the highest compatible version of all packages.
+    #[default]
+    Highest,
+
+    /// Select the lowest compatible version of all packages. This is useful to verify that the
+    /// lower bounds of the dependencies of a package are correct.
+    LowestVersion,
+
+    /// Select the lowest compatible version of the packages that are directly requested by the
+    /// specs, and the highest compatible version of all other packages.
+    LowestVersionDirect,
 }
 
 /// A representation of a collection of [`RepoDataRecord`] usable by a [`SolverImpl`]
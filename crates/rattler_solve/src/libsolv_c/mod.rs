@@ -1,19 +1,21 @@
 //! Provides an solver implementation based on the [`rattler_libsolv_c`] crate.
 
+use crate::channel_priority::ChannelPriorities;
 use crate::{IntoRepoData, SolverRepoData};
-use crate::{SolveError, SolverTask};
+use crate::{SolveError, SolveStrategy, SolverTask, StopCondition};
 pub use input::cache_repodata;
 use input::{add_repodata_records, add_solv_file, add_virtual_packages};
 pub use libc_byte_slice::LibcByteSlice;
-use output::get_required_packages;
-use rattler_conda_types::RepoDataRecord;
-use std::collections::HashMap;
+use output::{get_conflict_report, get_repodata_record, get_required_packages};
+use rattler_conda_types::{MatchSpec, RepoDataRecord};
+use std::collections::{HashMap, HashSet};
 use std::ffi::CString;
 use std::mem::ManuallyDrop;
 use wrapper::{
     flags::SolverFlag,
     pool::{Pool, Verbosity},
-    repo::Repo,
+    repo::{Repo, RepoId},
+    solvable::SolvableId,
     solve_goal::SolveGoal,
 };
 
@@ -77,6 +79,10 @@ fn c_string<T: AsRef<str>>(str: T) -> CString {
 }
 
 /// A [`Solver`] implemented using the `libsolv` library
+///
+/// libsolv itself cannot be interrupted, so [`SolverTask::timeout`] and
+/// [`SolverTask::cancellation_token`] are only checked while the problem is being set up and
+/// right before the actual solve starts.
 #[derive(Default)]
 pub struct Solver;
 
@@ -91,6 +97,9 @@ impl super::SolverImpl for Solver {
         &mut self,
         task: SolverTask<TAvailablePackagesIterator>,
     ) -> Result<Vec<RepoDataRecord>, SolveError> {
+        let stop_condition = StopCondition::new(task.timeout, task.cancellation_token.clone());
+        stop_condition.check()?;
+
         // Construct a default libsolv pool
         let pool = Pool::default();
 
@@ -107,10 +116,34 @@ impl super::SolverImpl for Solver {
         // Mark the virtual packages as installed.
         pool.set_installed(&repo);
 
+        // Rank the channels in the order in which the records are presented to the solver.
+        let available_packages = task
+            .available_packages
+            .into_iter()
+            .map(IntoRepoData::into)
+            .collect::<Vec<_>>();
+        // Records that are too new are ignored entirely.
+        let exclude_newer = task.exclude_newer;
+        let is_too_new = |record: &RepoDataRecord| {
+            exclude_newer.map_or(false, |exclude_newer| exclude_newer.excludes(record))
+        };
+
+        let channel_priorities = ChannelPriorities::new(
+            task.channel_priority,
+            available_packages
+                .iter()
+                .flat_map(|repodata| repodata.records.iter().copied())
+                .filter(|record| !is_too_new(record)),
+            &task.specs,
+        );
+        let is_excluded =
+            |record: &RepoDataRecord| is_too_new(record) || channel_priorities.is_excluded(record);
+
         // Create repos for all channel + platform combinations
         let mut repo_mapping = HashMap::new();
         let mut all_repodata_records = Vec::new();
-        for repodata in task.available_packages.into_iter().map(IntoRepoData::into) {
+        let mut available_solvables = Vec::new();
+        for repodata in available_packages {
             if repodata.records.is_empty() {
                 continue;
             }
@@ -120,15 +153,48 @@ impl super::SolverImpl for Solver {
             // We dont want to drop the Repo, its stored in the pool anyway.
             let repo = ManuallyDrop::new(Repo::new(&pool, channel_name));
 
-            if let Some(solv_file) = repodata.solv_file {
-                add_solv_file(&pool, &repo, solv_file);
+            // Repos from channels with a higher priority get a higher libsolv priority so that
+            // their packages are preferred.
+            if channel_priorities.prefers_higher_priority() {
+                let rank = channel_priorities.rank(channel_name);
+                repo.set_priority(-i32::try_from(rank).unwrap_or(i32::MAX));
+            }
+
+            // Records that are too new or that are excluded because of strict channel priority are
+            // never added to the pool. The cached .solv file contains all records, so it can only
+            // be used if none of the records are excluded.
+            let has_excluded_records = repodata.records.iter().any(|record| is_excluded(record));
+            let records = if has_excluded_records {
+                repodata
+                    .records
+                    .into_iter()
+                    .filter(|record| !is_excluded(record))
+                    .collect()
             } else {
-                add_repodata_records(&pool, &repo, repodata.records.iter().copied());
+                repodata.records
+            };
+
+            // The lowest version strategies need to know which solvables were created, which is
+            // not the case when the records are loaded from a .solv file.
+            let use_solv_file = !has_excluded_records && task.strategy == SolveStrategy::Highest;
+            match repodata.solv_file {
+                Some(solv_file) if use_solv_file => {
+                    add_solv_file(&pool, &repo, solv_file);
+                }
+                _ => {
+                    available_solvables.extend(add_repodata_records(
+                        &pool,
+                        &repo,
+                        records.iter().copied(),
+                    ));
+                }
             }
 
             // Keep our own info about repodata_records
             repo_mapping.insert(repo.id(), repo_mapping.len());
-            all_repodata_records.push(repodata.records);
+            all_repodata_records.push(records);
+
+            stop_condition.check()?;
         }
 
         // Create a special pool for records that are already installed or locked.
@@ -153,6 +219,19 @@ impl super::SolverImpl for Solver {
         // Add matchspec to the queue
         let mut goal = SolveGoal::default();
 
+        // Favor the lowest versions if the strategy asks for it. This happens before favoring the
+        // installed packages so that those still take precedence.
+        for favor_solvable in lowest_version_favors(
+            &pool,
+            &repo_mapping,
+            &all_repodata_records,
+            available_solvables,
+            task.strategy,
+            &task.specs,
+        ) {
+            goal.favor(favor_solvable);
+        }
+
         // Favor the currently installed packages
         for favor_solvable in installed_solvables {
             goal.favor(favor_solvable);
@@ -169,12 +248,23 @@ impl super::SolverImpl for Solver {
             goal.install(id, false);
         }
 
+        // This is the last chance to stop, once libsolv starts solving it cannot be interrupted
+        stop_condition.check()?;
+
         // Construct a solver and solve the problems in the queue
         let mut solver = pool.create_solver();
         solver.set_flag(SolverFlag::allow_uninstall(), true);
         solver.set_flag(SolverFlag::allow_downgrade(), true);
 
-        let transaction = solver.solve(&mut goal).map_err(SolveError::Unsolvable)?;
+        let transaction = solver.solve(&mut goal).map_err(|(messages, problems)| {
+            SolveError::Unsolvable(get_conflict_report(
+                &pool,
+                &repo_mapping,
+                problems,
+                all_repodata_records.as_slice(),
+                messages,
+            ))
+        })?;
 
         let required_records = get_required_packages(
             &pool,
@@ -195,6 +285,58 @@ impl super::SolverImpl for Solver {
     }
 }
 
+/// Returns the solvables that have to be favored to select the lowest versions of packages
+/// according to the given strategy.
+///
+/// libsolv gives precedence to the solvables that are favored last, so the solvables are ordered
+/// from the highest to the lowest version.
+fn lowest_version_favors(
+    pool: &Pool,
+    repo_mapping: &HashMap<RepoId, usize>,
+    repodata_records: &[Vec<&RepoDataRecord>],
+    solvables: Vec<SolvableId>,
+    strategy: SolveStrategy,
+    specs: &[MatchSpec],
+) -> Vec<SolvableId> {
+    if strategy == SolveStrategy::Highest {
+        return Vec::new();
+    }
+
+    let direct_dependencies = specs
+        .iter()
+        .filter_map(|spec| spec.name.as_ref())
+        .map(|name| name.as_normalized())
+        .collect::<HashSet<_>>();
+
+    let mut favors = solvables
+        .into_iter()
+        .filter_map(|id| {
+            get_repodata_record(pool, repo_mapping, repodata_records, id).map(|record| (id, record))
+        })
+        .filter(|(_, record)| match strategy {
+            SolveStrategy::Highest => false,
+            SolveStrategy::LowestVersion => true,
+            SolveStrategy::LowestVersionDirect => {
+                direct_dependencies.contains(record.package_record.name.as_normalized())
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // Among variants with the same version the highest build number is still preferred.
+    favors.sort_by(|(_, a), (_, b)| {
+        b.package_record
+            .version
+            .cmp(&a.package_record.version)
+            .then(
+                a.package_record
+                    .build_number
+                    .cmp(&b.package_record.build_number),
+            )
+    });
+
+    favors.into_iter().map(|(id, _)| id).collect()
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -5,11 +5,15 @@ use super::{
     wrapper::pool::{Pool, StringId},
     wrapper::repo::RepoId,
     wrapper::solvable::SolvableId,
+    wrapper::solve_problem::SolveProblem,
     wrapper::transaction::Transaction,
     wrapper::{ffi, solvable},
 };
-use rattler_conda_types::RepoDataRecord;
+use crate::problem::{ConflictCandidate, ConflictReason, ConflictReportBuilder};
+use crate::ConflictReport;
+use rattler_conda_types::{MatchSpec, PackageName, RepoDataRecord, Version};
 use std::collections::HashMap;
+use std::str::FromStr;
 
 /// Returns which packages should be installed in the environment
 ///
@@ -36,7 +40,8 @@ pub fn get_required_packages(
 
         // Retrieve the repodata record corresponding to this solvable
         let (repo_index, solvable_index) =
-            get_solvable_indexes(pool, repo_mapping, solvable_index_id, id);
+            get_solvable_indexes(pool, repo_mapping, solvable_index_id, id)
+                .expect("solvable does not originate from a known repo");
         let repodata_record = repodata_records[repo_index][solvable_index];
 
         match transaction_type as u32 {
@@ -56,20 +61,143 @@ pub fn get_required_packages(
     Ok(required_packages)
 }
 
+/// Builds a [`ConflictReport`] from the problem rules reported by libsolv
+pub fn get_conflict_report(
+    pool: &Pool,
+    repo_mapping: &HashMap<RepoId, usize>,
+    problems: Vec<SolveProblem>,
+    repodata_records: &[Vec<&RepoDataRecord>],
+    messages: Vec<String>,
+) -> ConflictReport {
+    let solvable_index_id = pool
+        .find_interned_str("solvable:repodata_record_index")
+        .unwrap();
+
+    // Solvables that are not part of a known repo are virtual packages, for which we only know the
+    // name and version.
+    let candidate = |id: SolvableId| -> ConflictCandidate {
+        match get_solvable_indexes(pool, repo_mapping, solvable_index_id, id) {
+            Some((repo_index, solvable_index)) => {
+                repodata_records[repo_index][solvable_index].into()
+            }
+            None => {
+                let (name, version) = id.name_and_version(pool);
+                ConflictCandidate {
+                    name,
+                    version,
+                    build: String::new(),
+                    channel: None,
+                }
+            }
+        }
+    };
+
+    let mut builder = ConflictReportBuilder::default();
+    let mut requirements = Vec::new();
+    for problem in problems {
+        match problem {
+            SolveProblem::Job { dep } | SolveProblem::Pkg { dep } => {
+                let node = builder.requested(dep.clone());
+                requirements.push((node, dep));
+            }
+            SolveProblem::JobNothingProvidesDep { dep }
+            | SolveProblem::JobUnknownPackage { dep } => {
+                let node = builder.requested(dep.clone());
+                let missing = builder.missing(dep.clone());
+                builder.requires(node, missing, dep);
+            }
+            SolveProblem::PkgNothingProvidesDep { source, dep } => {
+                let node = builder.candidate(candidate(source));
+                let missing = builder.missing(dep.clone());
+                builder.requires(node, missing, dep);
+            }
+            SolveProblem::PkgRequires { source, dep } => {
+                let node = builder.candidate(candidate(source));
+                requirements.push((node, dep));
+            }
+            SolveProblem::PkgConflicts { source, target }
+            | SolveProblem::PkgSameName { source, target } => {
+                let source = builder.candidate(candidate(source));
+                let target = builder.candidate(candidate(target));
+                builder.conflicts(source, target, ConflictReason::SameName);
+            }
+            SolveProblem::PkgConstrains {
+                source,
+                target,
+                dep,
+            } => {
+                let source = builder.candidate(candidate(source));
+                let target = builder.candidate(candidate(target));
+                builder.conflicts(source, target, ConflictReason::Constrains { spec: dep });
+            }
+            SolveProblem::Update => {}
+        }
+    }
+
+    // libsolv only reports which dependencies are involved in a problem, not which solvables
+    // provide them. Link each dependency to the candidates in the report that match it.
+    for (node, dep) in requirements {
+        let Ok(spec) = MatchSpec::from_str(&dep) else {
+            continue;
+        };
+        let matching_candidates = builder
+            .candidates()
+            .filter(|(_, candidate)| candidate_matches(&spec, candidate))
+            .map(|(idx, _)| idx)
+            .collect::<Vec<_>>();
+        for candidate in matching_candidates {
+            builder.requires(node, candidate, dep.clone());
+        }
+    }
+
+    builder.finish(messages)
+}
+
+/// Returns true if the candidate matches the name, version and build of the spec
+fn candidate_matches(spec: &MatchSpec, candidate: &ConflictCandidate) -> bool {
+    if spec.name.as_ref().map(PackageName::as_normalized) != Some(candidate.name.as_str()) {
+        return false;
+    }
+
+    if let Some(version_spec) = spec.version.as_ref() {
+        match Version::from_str(&candidate.version) {
+            Ok(version) if version_spec.matches(&version) => {}
+            _ => return false,
+        }
+    }
+
+    spec.build
+        .as_ref()
+        .map_or(true, |build| build.matches(&candidate.build))
+}
+
+/// Returns the record from which a solvable was created, or `None` if the solvable does not
+/// originate from a known repo.
+pub fn get_repodata_record<'a>(
+    pool: &Pool,
+    repo_mapping: &HashMap<RepoId, usize>,
+    repodata_records: &[Vec<&'a RepoDataRecord>],
+    id: SolvableId,
+) -> Option<&'a RepoDataRecord> {
+    let solvable_index_id = pool.find_interned_str("solvable:repodata_record_index")?;
+    let (repo_index, solvable_index) =
+        get_solvable_indexes(pool, repo_mapping, solvable_index_id, id)?;
+    Some(repodata_records[repo_index][solvable_index])
+}
+
 fn get_solvable_indexes(
     pool: &Pool,
     repo_mapping: &HashMap<RepoId, usize>,
     solvable_index_id: StringId,
     id: SolvableId,
-) -> (usize, usize) {
+) -> Option<(usize, usize)> {
     let solvable = id.resolve_raw(pool);
-    let solvable_index =
-        solvable::lookup_num(solvable.as_ptr(), solvable_index_id).unwrap() as usize;
+    let solvable_index = solvable::lookup_num(solvable.as_ptr(), solvable_index_id)? as usize;
 
     // Safe because there are no active mutable borrows of any solvable at this stage
     let repo_id = RepoId::from_ffi_solvable(unsafe { solvable.as_ref() });
 
-    let repo_index = repo_mapping[&repo_id];
+    let repo_index = *repo_mapping.get(&repo_id)?;
 
-    (repo_index, solvable_index)
+    Some((repo_index, solvable_index))
 }
@@ -59,6 +59,12 @@ impl<'pool> Repo<'pool> {
         self.0.as_ptr()
     }
 
+    /// Sets the priority of the repo. When multiple repos provide a package, libsolv prefers the
+    /// package from the repo with the highest priority.
+    pub fn set_priority(&self, priority: i32) {
+        unsafe { (*self.raw_ptr()).priority = priority }
+    }
+
     /// Adds a new repodata to this repo (repodata is a libsolv datastructure, see [`Repodata`] for
     /// details)
     pub fn add_repodata(&self) -> Repodata<'_> {
@@ -30,6 +30,16 @@ impl SolvableId {
             panic!("invalid solvable id!")
         }
     }
+
+    /// Returns the name and version of the solvable, as interned in the pool
+    ///
+    /// Panics if the solvable is not found in the pool
+    pub fn name_and_version(self, pool: &Pool) -> (String, String) {
+        // Safe because there are no active mutable borrows of any solvable at this stage
+        let solvable = unsafe { self.resolve_raw(pool).as_ref() };
+        let resolve = |id| StringId(id).resolve(pool).unwrap_or_default().to_string();
+        (resolve(solvable.name), resolve(solvable.evr))
+    }
 }
 
 /// Gets a number associated to this solvable
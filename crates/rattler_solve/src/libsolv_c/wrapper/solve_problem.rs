@@ -67,42 +67,47 @@ pub enum SolveProblem {
 }
 
 impl SolveProblem {
+    /// Constructs a problem from the information returned by `solver_ruleinfo`. Returns `None` if
+    /// the rule is of a type we don't know how to interpret, or if it lacks the information we
+    /// expect for its type.
     pub fn from_raw(
         problem_type: ffi::SolverRuleinfo,
         dep: Option<String>,
         source: Option<SolvableId>,
         target: Option<SolvableId>,
-    ) -> Self {
-        match problem_type {
-            SOLVER_RULE_JOB => Self::Job { dep: dep.unwrap() },
-            SOLVER_RULE_JOB_NOTHING_PROVIDES_DEP => {
-                Self::JobNothingProvidesDep { dep: dep.unwrap() }
-            }
-            SOLVER_RULE_JOB_UNKNOWN_PACKAGE => Self::JobUnknownPackage { dep: dep.unwrap() },
-            SOLVER_RULE_PKG => Self::Pkg { dep: dep.unwrap() },
+    ) -> Option<Self> {
+        let problem = match problem_type {
+            SOLVER_RULE_JOB => Self::Job { dep: dep? },
+            SOLVER_RULE_JOB_NOTHING_PROVIDES_DEP => Self::JobNothingProvidesDep { dep: dep? },
+            SOLVER_RULE_JOB_UNKNOWN_PACKAGE => Self::JobUnknownPackage { dep: dep? },
+            SOLVER_RULE_PKG => Self::Pkg { dep: dep? },
             SOLVER_RULE_SOLVER_RULE_PKG_CONFLICTS => Self::PkgConflicts {
-                source: source.unwrap(),
-                target: target.unwrap(),
+                source: source?,
+                target: target?,
             },
             SOLVER_RULE_PKG_CONSTRAINS => Self::PkgConstrains {
-                source: source.unwrap(),
-                target: target.unwrap(),
-                dep: dep.unwrap(),
+                source: source?,
+                target: target?,
+                dep: dep?,
             },
             SOLVER_RULE_SOLVER_RULE_PKG_NOTHING_PROVIDES_DEP => Self::PkgNothingProvidesDep {
-                source: source.unwrap(),
-                dep: dep.unwrap(),
+                source: source?,
+                dep: dep?,
             },
             SOLVER_RULE_PKG_REQUIRES => Self::PkgRequires {
-                source: source.unwrap(),
-                dep: dep.unwrap(),
+                source: source?,
+                dep: dep?,
             },
             SOLVER_RULE_SOLVER_RULE_PKG_SAME_NAME => Self::PkgSameName {
-                source: source.unwrap(),
-                target: target.unwrap(),
+                source: source?,
+                target: target?,
             },
             SOLVER_RULE_SOLVER_RULE_UPDATE => Self::Update,
-            _ => panic!("Unknown problem type: {problem_type}"),
-        }
+            _ => {
+                tracing::debug!("ignoring unknown problem type: {problem_type}");
+                return None;
+            }
+        };
+        Some(problem)
     }
 }
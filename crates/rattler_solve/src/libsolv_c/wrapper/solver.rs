@@ -63,6 +63,8 @@ impl<'pool> Solver<'pool> {
         output
     }
 
+    /// Returns the rules that are involved in each of the problems the solver encountered while
+    /// solving the matchspecs. Rules that cannot be interpreted are skipped.
     pub fn all_solver_problems(&self) -> Vec<SolveProblem> {
         let mut problems = Vec::new();
         let mut problem_rules = Queue::<ffi::Id>::default();
@@ -105,7 +107,7 @@ impl<'pool> Solver<'pool> {
                     let source = if source_id < 0 || source_id >= nsolvables {
                         None
                     } else {
-                        Some(SolvableId(target_id))
+                        Some(SolvableId(source_id))
                     };
 
                     let dep = if dep_id == 0 {
@@ -117,7 +119,7 @@ impl<'pool> Solver<'pool> {
                         Some(dep)
                     };
 
-                    problems.push(SolveProblem::from_raw(problem_type, dep, source, target));
+                    problems.extend(SolveProblem::from_raw(problem_type, dep, source, target));
                 }
             }
         }
@@ -130,8 +132,12 @@ impl<'pool> Solver<'pool> {
     }
 
     /// Solves all the problems in the `queue` and returns a transaction from the found solution.
-    /// Returns an error if problems remain unsolved.
-    pub fn solve(&mut self, queue: &mut SolveGoal) -> Result<Transaction<'_>, Vec<String>> {
+    /// Returns an error if problems remain unsolved. The error contains a user-friendly
+    /// representation of each problem, together with the rules involved in the problems.
+    pub fn solve(
+        &mut self,
+        queue: &mut SolveGoal,
+    ) -> Result<Transaction<'_>, (Vec<String>, Vec<SolveProblem>)> {
         let result = unsafe {
             // Run the solve method
             ffi::solver_solve(self.raw_ptr(), queue.raw_ptr());
@@ -146,7 +152,7 @@ impl<'pool> Solver<'pool> {
             // Safe because we know the `transaction` ptr is valid
             Ok(unsafe { Transaction::new(self, transaction) })
         } else {
-            Err(self.solver_problems())
+            Err((self.solver_problems(), self.all_solver_problems()))
         }
     }
 }
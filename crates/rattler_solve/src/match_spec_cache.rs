@@ -0,0 +1,56 @@
+//! A cache of parsed match specs that can be shared between solves.
+
+use rattler_conda_types::{MatchSpec, ParseMatchSpecError};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+/// A cache of the match specs that were parsed from the `depends` and `constrains` fields of
+/// records.
+///
+/// Solves that operate on the same records parse the same match specs over and over again. By
+/// passing the same cache to these solves (see [`crate::SolverTask::match_spec_cache`]) every
+/// match spec is only parsed once. Cloning the cache results in a cache that shares its entries
+/// with the original, the cache can be used from multiple threads at the same time.
+#[derive(Debug, Clone, Default)]
+pub struct MatchSpecCache(Arc<RwLock<HashMap<String, MatchSpec>>>);
+
+impl MatchSpecCache {
+    /// Constructs a new empty cache
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses a match spec, or returns the previously parsed match spec if the same string was
+    /// parsed before.
+    pub fn parse(&self, spec: &str) -> Result<MatchSpec, ParseMatchSpecError> {
+        if let Some(match_spec) = self
+            .0
+            .read()
+            .expect("match spec cache lock is poisoned")
+            .get(spec)
+        {
+            return Ok(match_spec.clone());
+        }
+
+        let match_spec = MatchSpec::from_str(spec)?;
+        self.0
+            .write()
+            .expect("match spec cache lock is poisoned")
+            .insert(spec.to_owned(), match_spec.clone());
+        Ok(match_spec)
+    }
+
+    /// Returns the number of match specs in the cache
+    pub fn len(&self) -> usize {
+        self.0
+            .read()
+            .expect("match spec cache lock is poisoned")
+            .len()
+    }
+
+    /// Returns true if the cache does not contain any match specs
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,138 @@
+//! Solves the same specs for multiple platforms at once.
+
+use crate::{
+    CancellationToken, ChannelPriority, ExcludeNewer, MatchSpecCache, SolveError, SolveStrategy,
+    SolverImpl, SolverTask,
+};
+use rattler_conda_types::{GenericVirtualPackage, MatchSpec, Platform, RepoDataRecord};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// The packages that are available on a single platform of a [`MultiPlatformSolverTask`].
+pub struct PlatformPackages<'a> {
+    /// The platform to solve for
+    pub platform: Platform,
+
+    /// The records of the platform specific subdirectories of the channels, ordered by the
+    /// priority of their channels.
+    pub available_packages: Vec<&'a [RepoDataRecord]>,
+
+    /// Records of packages that are previously selected on this platform, see
+    /// [`SolverTask::locked_packages`].
+    pub locked_packages: Vec<RepoDataRecord>,
+
+    /// Records of packages that are previously selected on this platform and that cannot be
+    /// changed, see [`SolverTask::pinned_packages`].
+    pub pinned_packages: Vec<RepoDataRecord>,
+
+    /// Virtual packages considered active on this platform
+    pub virtual_packages: Vec<GenericVirtualPackage>,
+}
+
+/// Represents a dependency resolution task that solves the same specs for multiple platforms.
+///
+/// The records that are available on all platforms (usually the `noarch` subdirectories of the
+/// channels) only have to be provided once, every solve refers to the same records. The match
+/// specs of the records are also only parsed once and shared between the solves.
+pub struct MultiPlatformSolverTask<'a> {
+    /// The platforms to solve for
+    pub platforms: Vec<PlatformPackages<'a>>,
+
+    /// The records that are available on all platforms, ordered by the priority of their
+    /// channels. These are added after the [`PlatformPackages::available_packages`] of each
+    /// platform.
+    pub noarch_packages: Vec<&'a [RepoDataRecord]>,
+
+    /// The specs we want to solve
+    pub specs: Vec<MatchSpec>,
+
+    /// Determines how the order of the channels affects the solutions, see
+    /// [`SolverTask::channel_priority`].
+    pub channel_priority: ChannelPriority,
+
+    /// Determines whether the solver selects the highest or the lowest versions of packages.
+    pub strategy: SolveStrategy,
+
+    /// If set, the records that were published after a certain point in time are ignored.
+    pub exclude_newer: Option<ExcludeNewer>,
+
+    /// The maximum amount of time each of the solves is allowed to take.
+    pub timeout: Option<Duration>,
+
+    /// A token that can be used to cancel all the solves from another thread.
+    pub cancellation_token: Option<CancellationToken>,
+}
+
+/// An error that occurred while solving for one of the platforms of a
+/// [`MultiPlatformSolverTask`].
+#[derive(Debug, thiserror::Error)]
+#[error("failed to solve the environment for {platform}")]
+pub struct PlatformSolveError {
+    /// The platform for which the solve failed
+    pub platform: Platform,
+
+    /// The reason why the solve failed
+    #[source]
+    pub error: SolveError,
+}
+
+/// Solves the specs of the task for every platform, returning the records that should be present
+/// in the environment of each platform.
+///
+/// The solves run in parallel, each on its own thread with its own instance of the solver `S`. If
+/// the solves fail for multiple platforms, the error of the first failing platform in
+/// [`MultiPlatformSolverTask::platforms`] is returned.
+pub fn solve_multi_platform<S: SolverImpl + Default>(
+    task: MultiPlatformSolverTask<'_>,
+) -> Result<HashMap<Platform, Vec<RepoDataRecord>>, PlatformSolveError> {
+    let match_spec_cache = MatchSpecCache::new();
+    let noarch_packages = &task.noarch_packages;
+    let specs = &task.specs;
+
+    let results = std::thread::scope(|scope| {
+        let handles = task
+            .platforms
+            .into_iter()
+            .map(|platform_packages| {
+                let match_spec_cache = match_spec_cache.clone();
+                let cancellation_token = task.cancellation_token.clone();
+                let platform = platform_packages.platform;
+                let handle = scope.spawn(move || {
+                    S::default().solve(SolverTask {
+                        available_packages: platform_packages
+                            .available_packages
+                            .into_iter()
+                            .chain(noarch_packages.iter().copied()),
+                        locked_packages: platform_packages.locked_packages,
+                        pinned_packages: platform_packages.pinned_packages,
+                        virtual_packages: platform_packages.virtual_packages,
+                        specs: specs.clone(),
+                        channel_priority: task.channel_priority,
+                        strategy: task.strategy,
+                        exclude_newer: task.exclude_newer,
+                        timeout: task.timeout,
+                        cancellation_token,
+                        match_spec_cache: Some(match_spec_cache),
+                    })
+                });
+                (platform, handle)
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .map(|(platform, handle)| match handle.join() {
+                Ok(result) => (platform, result),
+                Err(panic) => std::panic::resume_unwind(panic),
+            })
+            .collect::<Vec<_>>()
+    });
+
+    results
+        .into_iter()
+        .map(|(platform, result)| match result {
+            Ok(records) => Ok((platform, records)),
+            Err(error) => Err(PlatformSolveError { platform, error }),
+        })
+        .collect()
+}
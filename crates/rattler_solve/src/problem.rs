@@ -0,0 +1,493 @@
+//! Structured descriptions of why a [`crate::SolverTask`] could not be solved.
+//!
+//! When a solve fails, the backends produce a [`ConflictReport`]. The report describes the
+//! conflict as a graph: the specs that were requested, the candidate records that were considered,
+//! the dependencies between them and the constraints that cannot be satisfied together. The report
+//! can be serialized (e.g. to JSON) for machine consumption or rendered as a compact tree through
+//! its [`std::fmt::Display`] implementation.
+
+use rattler_conda_types::{GenericVirtualPackage, RepoDataRecord};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// A structured report describing why a set of specs cannot be solved.
+///
+/// Nodes are stored in [`ConflictReport::nodes`] and referred to by their index from the
+/// [`ConflictReport::edges`].
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConflictReport {
+    /// Human readable messages as produced by the solver backend.
+    pub messages: Vec<String>,
+
+    /// The nodes in the conflict graph.
+    pub nodes: Vec<ConflictNode>,
+
+    /// The edges between the nodes in the conflict graph.
+    pub edges: Vec<ConflictEdge>,
+}
+
+/// A node in a [`ConflictReport`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ConflictNode {
+    /// A spec that was requested by the user.
+    Requested {
+        /// The requested spec
+        spec: String,
+    },
+
+    /// A package that was considered by the solver.
+    Candidate(ConflictCandidate),
+
+    /// A spec for which no candidates are available.
+    Missing {
+        /// The spec that cannot be satisfied
+        spec: String,
+    },
+}
+
+/// Describes a package that was considered by the solver.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ConflictCandidate {
+    /// The normalized name of the package
+    pub name: String,
+
+    /// The version of the package
+    pub version: String,
+
+    /// The build string of the package
+    pub build: String,
+
+    /// The channel the package originates from. This is `None` for virtual packages.
+    pub channel: Option<String>,
+}
+
+/// A directed edge between two nodes in a [`ConflictReport`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ConflictEdge {
+    /// The index of the node this edge starts at
+    pub from: usize,
+
+    /// The index of the node this edge points to
+    pub to: usize,
+
+    /// What this edge represents
+    pub kind: ConflictEdgeKind,
+}
+
+/// The relation between two nodes in a [`ConflictReport`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ConflictEdgeKind {
+    /// The `spec` of the source node can be satisfied by the target node.
+    Requires {
+        /// The spec that is satisfied by the target
+        spec: String,
+    },
+
+    /// The source and the target node cannot be part of the same solution.
+    Conflicts {
+        /// The reason why the nodes are in conflict
+        reason: ConflictReason,
+    },
+}
+
+/// The reason why two nodes in a [`ConflictReport`] cannot be part of the same solution.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ConflictReason {
+    /// Only a single variant of a package can be installed at the same time.
+    SameName,
+
+    /// The source constrains the target through its `constrains` field.
+    Constrains {
+        /// The constraint that excludes the target
+        spec: String,
+    },
+
+    /// The target is locked, but the source requires another variant.
+    Locked,
+
+    /// The target was excluded from the solve before solving started.
+    Excluded {
+        /// A human readable reason why the target was excluded
+        reason: String,
+    },
+}
+
+impl ConflictReport {
+    /// Constructs a report that only contains the messages of a backend.
+    pub fn from_messages(messages: Vec<String>) -> Self {
+        Self {
+            messages,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the indices of the nodes that represent the specs that were requested.
+    pub fn requested(&self) -> impl Iterator<Item = usize> + '_ {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| matches!(node, ConflictNode::Requested { .. }))
+            .map(|(idx, _)| idx)
+    }
+
+    /// Returns the specs that can not be satisfied because there are no candidates for them.
+    pub fn missing(&self) -> impl Iterator<Item = &str> + '_ {
+        self.nodes.iter().filter_map(|node| match node {
+            ConflictNode::Missing { spec } => Some(spec.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Returns the edges that start at the node with the given index.
+    pub fn edges_from(&self, node: usize) -> impl Iterator<Item = &ConflictEdge> + '_ {
+        self.edges.iter().filter(move |edge| edge.from == node)
+    }
+
+    /// Returns the nodes that are in conflict with the node with the given index, together with
+    /// the reason of the conflict.
+    pub fn conflicts_of(&self, node: usize) -> impl Iterator<Item = (usize, &ConflictReason)> + '_ {
+        self.edges.iter().filter_map(move |edge| match &edge.kind {
+            ConflictEdgeKind::Conflicts { reason } if edge.from == node => Some((edge.to, reason)),
+            ConflictEdgeKind::Conflicts { reason } if edge.to == node => Some((edge.from, reason)),
+            _ => None,
+        })
+    }
+
+    /// Returns true if the report contains no structured information.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Removes all the nodes that do not lead to a conflict or to a spec without candidates.
+    #[cfg_attr(not(any(feature = "resolvo", feature = "pubgrub")), allow(dead_code))]
+    pub(crate) fn retain_conflicting(self) -> Self {
+        let mut relevant = vec![false; self.nodes.len()];
+
+        // Start at the nodes that are directly involved in a problem and walk the requirements
+        // backwards to find all the nodes that lead to them.
+        let mut stack = self
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| matches!(node, ConflictNode::Missing { .. }))
+            .map(|(idx, _)| idx)
+            .collect::<Vec<_>>();
+        for edge in &self.edges {
+            if let ConflictEdgeKind::Conflicts { .. } = edge.kind {
+                stack.push(edge.from);
+                stack.push(edge.to);
+            }
+        }
+        while let Some(idx) = stack.pop() {
+            if std::mem::replace(&mut relevant[idx], true) {
+                continue;
+            }
+            stack.extend(
+                self.edges
+                    .iter()
+                    .filter(|edge| {
+                        edge.to == idx && matches!(edge.kind, ConflictEdgeKind::Requires { .. })
+                    })
+                    .map(|edge| edge.from),
+            );
+        }
+
+        let mut new_indices = vec![None; self.nodes.len()];
+        let mut nodes = Vec::new();
+        for (idx, node) in self.nodes.into_iter().enumerate() {
+            if relevant[idx] {
+                new_indices[idx] = Some(nodes.len());
+                nodes.push(node);
+            }
+        }
+
+        let edges = self
+            .edges
+            .into_iter()
+            .filter_map(|edge| {
+                Some(ConflictEdge {
+                    from: new_indices[edge.from]?,
+                    to: new_indices[edge.to]?,
+                    kind: edge.kind,
+                })
+            })
+            .collect();
+
+        Self {
+            messages: self.messages,
+            nodes,
+            edges,
+        }
+    }
+}
+
+impl From<&RepoDataRecord> for ConflictCandidate {
+    fn from(record: &RepoDataRecord) -> Self {
+        Self {
+            name: record.package_record.name.as_normalized().to_string(),
+            version: record.package_record.version.to_string(),
+            build: record.package_record.build.clone(),
+            channel: Some(record.channel.clone()),
+        }
+    }
+}
+
+impl From<&GenericVirtualPackage> for ConflictCandidate {
+    fn from(package: &GenericVirtualPackage) -> Self {
+        Self {
+            name: package.name.as_normalized().to_string(),
+            version: package.version.to_string(),
+            build: package.build_string.clone(),
+            channel: None,
+        }
+    }
+}
+
+impl fmt::Display for ConflictCandidate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.name, self.version)?;
+        if !self.build.is_empty() {
+            write!(f, " {}", self.build)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ConflictNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConflictNode::Requested { spec } => write!(f, "{spec}"),
+            ConflictNode::Candidate(candidate) => write!(f, "{candidate}"),
+            ConflictNode::Missing { spec } => write!(f, "nothing provides {spec}"),
+        }
+    }
+}
+
+impl fmt::Display for ConflictReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConflictReason::SameName => write!(f, "only one variant can be installed"),
+            ConflictReason::Constrains { spec } => write!(f, "constrained by {spec}"),
+            ConflictReason::Locked => write!(f, "locked"),
+            ConflictReason::Excluded { reason } => write!(f, "excluded: {reason}"),
+        }
+    }
+}
+
+/// Renders the report as a compact tree, starting at each of the requested specs.
+///
+/// If the report does not contain any structured information the messages of the backend are
+/// printed instead.
+impl fmt::Display for ConflictReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "{}", self.messages.join("\n"));
+        }
+
+        writeln!(f, "The following specs cannot be satisfied together:")?;
+        let mut visited = HashSet::new();
+        for root in self.requested() {
+            self.fmt_node(f, root, 0, &mut visited)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ConflictReport {
+    fn fmt_node(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        node: usize,
+        depth: usize,
+        visited: &mut HashSet<usize>,
+    ) -> fmt::Result {
+        let indent = "    ".repeat(depth);
+        write!(f, "{indent}|-- {}", self.nodes[node])?;
+
+        let conflicts = self
+            .conflicts_of(node)
+            .map(|(other, reason)| format!("{} ({reason})", self.nodes[other]))
+            .collect::<Vec<_>>();
+        if !conflicts.is_empty() {
+            write!(f, ", which conflicts with {}", conflicts.join(", "))?;
+        }
+
+        if !visited.insert(node) {
+            return writeln!(f, " (see above)");
+        }
+        writeln!(f)?;
+
+        // Group the requirements of this node by their spec to keep the output compact.
+        let mut requirements: Vec<(&str, Vec<usize>)> = Vec::new();
+        for edge in self.edges_from(node) {
+            if let ConflictEdgeKind::Requires { spec } = &edge.kind {
+                match requirements.iter_mut().find(|(s, _)| s == spec) {
+                    Some((_, targets)) => targets.push(edge.to),
+                    None => requirements.push((spec, vec![edge.to])),
+                }
+            }
+        }
+
+        let requested_spec = match &self.nodes[node] {
+            ConflictNode::Requested { spec } => Some(spec.as_str()),
+            _ => None,
+        };
+        for (spec, targets) in requirements {
+            // The requirements of a requested spec are the spec itself, so there is no need to
+            // repeat it.
+            let child_depth = if requested_spec == Some(spec) {
+                depth + 1
+            } else {
+                writeln!(f, "{indent}    |-- requires {spec}")?;
+                depth + 2
+            };
+            for target in targets {
+                self.fmt_node(f, target, child_depth, visited)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Incrementally constructs a [`ConflictReport`], deduplicating nodes and edges.
+#[derive(Default)]
+pub(crate) struct ConflictReportBuilder {
+    report: ConflictReport,
+    node_indices: HashMap<ConflictNode, usize>,
+    edges: HashSet<ConflictEdge>,
+}
+
+impl ConflictReportBuilder {
+    /// Adds a node for a requested spec and returns its index
+    pub fn requested(&mut self, spec: impl Into<String>) -> usize {
+        self.node(ConflictNode::Requested { spec: spec.into() })
+    }
+
+    /// Adds a node for a candidate and returns its index
+    pub fn candidate(&mut self, candidate: impl Into<ConflictCandidate>) -> usize {
+        self.node(ConflictNode::Candidate(candidate.into()))
+    }
+
+    /// Adds a node for a spec that has no candidates and returns its index
+    pub fn missing(&mut self, spec: impl Into<String>) -> usize {
+        self.node(ConflictNode::Missing { spec: spec.into() })
+    }
+
+    /// Records that the `spec` of `from` can be satisfied by `to`
+    pub fn requires(&mut self, from: usize, to: usize, spec: impl Into<String>) {
+        self.edge(ConflictEdge {
+            from,
+            to,
+            kind: ConflictEdgeKind::Requires { spec: spec.into() },
+        });
+    }
+
+    /// Records that `from` and `to` cannot be part of the same solution
+    pub fn conflicts(&mut self, from: usize, to: usize, reason: ConflictReason) {
+        if from == to {
+            return;
+        }
+        self.edge(ConflictEdge {
+            from,
+            to,
+            kind: ConflictEdgeKind::Conflicts { reason },
+        });
+    }
+
+    /// Returns the indices of all candidate nodes in the report so far
+    #[cfg_attr(not(feature = "libsolv_c"), allow(dead_code))]
+    pub fn candidates(&self) -> impl Iterator<Item = (usize, &ConflictCandidate)> + '_ {
+        self.report
+            .nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, node)| match node {
+                ConflictNode::Candidate(candidate) => Some((idx, candidate)),
+                _ => None,
+            })
+    }
+
+    /// Finishes the report
+    pub fn finish(self, messages: Vec<String>) -> ConflictReport {
+        ConflictReport {
+            messages,
+            ..self.report
+        }
+    }
+
+    fn node(&mut self, node: ConflictNode) -> usize {
+        if let Some(&idx) = self.node_indices.get(&node) {
+            return idx;
+        }
+        let idx = self.report.nodes.len();
+        self.report.nodes.push(node.clone());
+        self.node_indices.insert(node, idx);
+        idx
+    }
+
+    fn edge(&mut self, edge: ConflictEdge) {
+        if self.edges.insert(edge.clone()) {
+            self.report.edges.push(edge);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn candidate(name: &str, version: &str) -> ConflictCandidate {
+        ConflictCandidate {
+            name: name.to_string(),
+            version: version.to_string(),
+            build: "bla_1".to_string(),
+            channel: Some("conda-forge".to_string()),
+        }
+    }
+
+    fn example_report() -> ConflictReport {
+        let mut builder = ConflictReportBuilder::default();
+        let foobar = builder.requested("foobar >=2");
+        let bors = builder.requested("bors >=2");
+        let foobar_2 = builder.candidate(candidate("foobar", "2.0"));
+        let bors_1 = builder.candidate(candidate("bors", "1.2.1"));
+        let bors_2 = builder.candidate(candidate("bors", "2.0"));
+        builder.requires(foobar, foobar_2, "foobar >=2");
+        builder.requires(foobar_2, bors_1, "bors <2.0");
+        builder.requires(bors, bors_2, "bors >=2");
+        builder.conflicts(bors_2, bors_1, ConflictReason::SameName);
+
+        // Duplicates are ignored
+        builder.requested("foobar >=2");
+        builder.requires(foobar, foobar_2, "foobar >=2");
+
+        builder.finish(vec!["message".to_string()])
+    }
+
+    #[test]
+    fn test_builder_deduplicates() {
+        let report = example_report();
+        assert_eq!(report.nodes.len(), 5);
+        assert_eq!(report.edges.len(), 4);
+        assert_eq!(report.requested().count(), 2);
+    }
+
+    #[test]
+    fn test_render_tree() {
+        insta::assert_display_snapshot!(example_report());
+    }
+
+    #[test]
+    fn test_serialize_roundtrip() {
+        let report = example_report();
+        let json = serde_json::to_string(&report).unwrap();
+        let parsed: ConflictReport = serde_json::from_str(&json).unwrap();
+        assert_eq!(report, parsed);
+    }
+}
@@ -0,0 +1,202 @@
+//! Derives a [`ConflictReport`] from the derivation tree produced by pubgrub.
+//!
+//! The leaves of the derivation tree are the incompatibilities that were derived directly from
+//! the dependencies of the packages. Each dependency on a package is turned into requirement
+//! edges to the candidates that satisfy it, dependencies of different packages on the same package
+//! that have no candidate in common are in conflict with each other.
+
+use super::{CondaDependencyProvider, PubGrubPackage, SolverPackageRecord, VersionRange};
+use crate::problem::{ConflictCandidate, ConflictReason, ConflictReportBuilder};
+use crate::ConflictReport;
+use pubgrub::{DerivationTree, External};
+use rattler_conda_types::MatchSpec;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::str::FromStr;
+
+/// Builds a [`ConflictReport`] for the given derivation tree.
+pub(super) fn conflict_report(
+    provider: &CondaDependencyProvider<'_>,
+    specs: &[MatchSpec],
+    derivation_tree: &DerivationTree<PubGrubPackage, VersionRange, String>,
+    messages: Vec<String>,
+) -> ConflictReport {
+    let mut analysis = ConflictAnalysis {
+        provider,
+        specs,
+        builder: ConflictReportBuilder::default(),
+        requirements: BTreeMap::default(),
+    };
+
+    let mut visited = HashSet::new();
+    let mut stack = vec![derivation_tree];
+    while let Some(tree) = stack.pop() {
+        match tree {
+            DerivationTree::External(External::FromDependencyOf(
+                package,
+                versions,
+                dependency,
+                dependency_versions,
+            )) => analysis.dependency(package, versions, dependency, dependency_versions),
+            DerivationTree::External(_) => {}
+            DerivationTree::Derived(derived) => {
+                if derived
+                    .shared_id
+                    .map_or(true, |shared_id| visited.insert(shared_id))
+                {
+                    stack.push(derived.cause1.as_ref());
+                    stack.push(derived.cause2.as_ref());
+                }
+            }
+        }
+    }
+
+    analysis.finish(messages)
+}
+
+struct ConflictAnalysis<'p, 'a> {
+    provider: &'p CondaDependencyProvider<'a>,
+    specs: &'p [MatchSpec],
+    builder: ConflictReportBuilder,
+
+    /// For every required package, the nodes that are allowed by each of the requirements on it.
+    /// Ordered collections keep the edges of the report deterministic.
+    requirements: BTreeMap<String, Vec<BTreeSet<usize>>>,
+}
+
+impl<'p, 'a> ConflictAnalysis<'p, 'a> {
+    /// Records that `versions` of `package` depend on `dependency_versions` of `dependency`.
+    fn dependency(
+        &mut self,
+        package: &PubGrubPackage,
+        versions: &VersionRange,
+        dependency: &PubGrubPackage,
+        dependency_versions: &VersionRange,
+    ) {
+        let PubGrubPackage::Package(dependency_name) = dependency else {
+            return;
+        };
+
+        let provider = self.provider;
+
+        // The sources of the dependency together with the spec they use
+        let sources = match provider.package_candidates(package) {
+            None => self
+                .specs
+                .iter()
+                .filter(|spec| {
+                    spec.name
+                        .as_ref()
+                        .map_or(false, |name| name.as_normalized() == dependency_name)
+                })
+                .map(|spec| {
+                    let spec = spec.to_string();
+                    (self.builder.requested(spec.clone()), spec)
+                })
+                .collect::<Vec<_>>(),
+            Some(candidates) => candidates
+                .versions
+                .iter()
+                .filter(|version| versions.contains(version))
+                .filter_map(|version| candidates.record(version.index))
+                .filter_map(|record| {
+                    let spec = dependency_spec(record, dependency_name)?;
+                    Some((self.builder.candidate(candidate(record)), spec))
+                })
+                .collect(),
+        };
+
+        let dependency_candidates = provider.candidates(dependency_name);
+        let (allowed, excluded): (Vec<_>, Vec<_>) = dependency_candidates
+            .records
+            .iter()
+            .zip(&dependency_candidates.versions[1..])
+            .partition(|(_, version)| dependency_versions.contains(version));
+
+        // A constraint allows the package to not be installed, it only excludes candidates.
+        let is_constraint = dependency_versions.contains(&dependency_candidates.versions[0]);
+        if is_constraint {
+            for (record, _) in excluded {
+                let target = self.builder.candidate(candidate(record));
+                for (source, spec) in &sources {
+                    self.builder.conflicts(
+                        *source,
+                        target,
+                        ConflictReason::Constrains { spec: spec.clone() },
+                    );
+                }
+            }
+            return;
+        }
+
+        let mut targets = BTreeSet::new();
+        for (source, spec) in &sources {
+            if allowed.is_empty() {
+                let missing = self.builder.missing(spec.clone());
+                self.builder.requires(*source, missing, spec.clone());
+                continue;
+            }
+            for (record, _) in &allowed {
+                let target = self.builder.candidate(candidate(record));
+                self.builder.requires(*source, target, spec.clone());
+                targets.insert(target);
+            }
+        }
+
+        if !targets.is_empty() {
+            self.requirements
+                .entry(dependency_name.clone())
+                .or_default()
+                .push(targets);
+        }
+    }
+
+    /// Adds the conflicts between requirements on the same package that have no candidate in
+    /// common and finishes the report.
+    fn finish(mut self, messages: Vec<String>) -> ConflictReport {
+        for requirements in self.requirements.values() {
+            for (idx, a) in requirements.iter().enumerate() {
+                for b in &requirements[idx + 1..] {
+                    if !a.is_disjoint(b) {
+                        continue;
+                    }
+                    for &from in a {
+                        for &to in b {
+                            self.builder.conflicts(from, to, ConflictReason::SameName);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.builder.finish(messages).retain_conflicting()
+    }
+}
+
+/// Returns the spec with which the record depends on or constrains the package with the given
+/// name.
+fn dependency_spec(record: &SolverPackageRecord<'_>, name: &str) -> Option<String> {
+    let SolverPackageRecord::Record(rec) = record else {
+        return None;
+    };
+    rec.package_record
+        .depends
+        .iter()
+        .chain(rec.package_record.constrains.iter())
+        .find(|spec| {
+            MatchSpec::from_str(spec).map_or(false, |spec| {
+                spec.name
+                    .as_ref()
+                    .map_or(false, |spec_name| spec_name.as_normalized() == name)
+            })
+        })
+        .cloned()
+}
+
+fn candidate(record: &SolverPackageRecord<'_>) -> ConflictCandidate {
+    match record {
+        SolverPackageRecord::Record(rec) => ConflictCandidate::from(*rec),
+        SolverPackageRecord::VirtualPackage(virtual_package) => {
+            ConflictCandidate::from(*virtual_package)
+        }
+    }
+}
@@ -0,0 +1,783 @@
+//! Provides a solver implementation based on the [`pubgrub`] crate.
+//!
+//! Pubgrub requires the versions of a package to be totally ordered and match specs to be ranges of
+//! versions. Conda match specs can also select packages by their build string, build number, etc.
+//! so instead of using the conda version directly, all the candidates of a package are sorted by
+//! their version and a [`rattler::range::Range`] over the indices of the candidates is used as
+//! version set.
+//!
+//! Pubgrub has no notion of optional dependencies, which are required to support the `constrains`
+//! field of a package. Every package therefore has an additional version that represents the
+//! package not being installed. Requirements never include this version while constraints always
+//! do.
+
+use crate::channel_priority::{requested_from_other_channel, ChannelPriorities};
+use crate::{
+    ChannelPriority, ExcludeNewer, IntoRepoData, MatchSpecCache, SolveError, SolveStrategy,
+    SolverRepoData, SolverTask, StopCondition,
+};
+use pubgrub::{
+    DefaultStringReporter, Dependencies, DependencyConstraints, DependencyProvider,
+    PackageResolutionStatistics, PubGrubError, Reporter,
+};
+use rattler::range::Range;
+use rattler_conda_types::package::ArchiveType;
+use rattler_conda_types::{GenericVirtualPackage, MatchSpec, PackageName, RepoDataRecord};
+use std::{
+    cell::RefCell,
+    cmp::{Ordering, Reverse},
+    collections::{HashMap, HashSet},
+    fmt::{Display, Formatter},
+    hash::{Hash, Hasher},
+    str::FromStr,
+    sync::Arc,
+};
+
+mod conflict;
+
+/// Represents the information required to load available packages into pubgrub for a single
+/// channel and platform combination
+#[derive(Clone)]
+pub struct RepoData<'a> {
+    /// The actual records after parsing `repodata.json`
+    pub records: Vec<&'a RepoDataRecord>,
+}
+
+impl<'a> FromIterator<&'a RepoDataRecord> for RepoData<'a> {
+    fn from_iter<T: IntoIterator<Item = &'a RepoDataRecord>>(iter: T) -> Self {
+        Self {
+            records: Vec::from_iter(iter),
+        }
+    }
+}
+
+impl<'a> SolverRepoData<'a> for RepoData<'a> {}
+
+/// A package as seen by pubgrub. The root package depends on the specs of the [`SolverTask`].
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+enum PubGrubPackage {
+    Root,
+    Package(String),
+}
+
+impl Display for PubGrubPackage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PubGrubPackage::Root => write!(f, "root"),
+            PubGrubPackage::Package(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+/// Identifies a candidate of a package by its index in the candidates sorted by version. The
+/// labels of all the versions of a package are shared, so that every version can be displayed in
+/// error messages.
+#[derive(Debug, Clone)]
+struct CandidateVersion {
+    index: u32,
+    labels: Option<Arc<[String]>>,
+}
+
+/// The index of the version that represents a package that is not installed.
+const NOT_INSTALLED: u32 = 0;
+
+impl CandidateVersion {
+    fn new(index: u32, labels: Arc<[String]>) -> Self {
+        Self {
+            index,
+            labels: Some(labels),
+        }
+    }
+
+    /// The version of the root package, which is also the lowest version of every package. It is
+    /// displayed as `*`.
+    fn root() -> Self {
+        Self {
+            index: NOT_INSTALLED,
+            labels: None,
+        }
+    }
+}
+
+impl PartialEq for CandidateVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+
+impl Eq for CandidateVersion {}
+
+impl Hash for CandidateVersion {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+    }
+}
+
+impl PartialOrd for CandidateVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for CandidateVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.index.cmp(&other.index)
+    }
+}
+
+impl Display for CandidateVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let label = self
+            .labels
+            .as_ref()
+            .and_then(|labels| labels.get(self.index as usize));
+        match label {
+            Some(label) => write!(f, "{label}"),
+            None => write!(f, "*"),
+        }
+    }
+}
+
+type VersionRange = Range<CandidateVersion>;
+
+/// A dependency of a record on a package and the set of versions it allows.
+type ParsedDependency = (PubGrubPackage, VersionRange);
+
+/// A candidate for a package, either a record from a channel or a virtual package.
+#[derive(Clone, Copy)]
+enum SolverPackageRecord<'a> {
+    Record(&'a RepoDataRecord),
+    VirtualPackage(&'a GenericVirtualPackage),
+}
+
+impl<'a> SolverPackageRecord<'a> {
+    fn version(&self) -> &rattler_conda_types::Version {
+        match self {
+            SolverPackageRecord::Record(rec) => rec.package_record.version.version(),
+            SolverPackageRecord::VirtualPackage(rec) => &rec.version,
+        }
+    }
+
+    fn build(&self) -> &str {
+        match self {
+            SolverPackageRecord::Record(rec) => &rec.package_record.build,
+            SolverPackageRecord::VirtualPackage(rec) => &rec.build_string,
+        }
+    }
+
+    fn build_number(&self) -> u64 {
+        match self {
+            SolverPackageRecord::Record(rec) => rec.package_record.build_number,
+            SolverPackageRecord::VirtualPackage(_rec) => 0,
+        }
+    }
+
+    fn has_track_features(&self) -> bool {
+        match self {
+            SolverPackageRecord::Record(rec) => !rec.package_record.track_features.is_empty(),
+            SolverPackageRecord::VirtualPackage(_rec) => false,
+        }
+    }
+
+    fn channel(&self) -> Option<&str> {
+        match self {
+            SolverPackageRecord::Record(rec) => Some(rec.channel.as_str()),
+            SolverPackageRecord::VirtualPackage(_rec) => None,
+        }
+    }
+
+    fn timestamp(&self) -> Option<&chrono::DateTime<chrono::Utc>> {
+        match self {
+            SolverPackageRecord::Record(rec) => rec.package_record.timestamp.as_ref(),
+            SolverPackageRecord::VirtualPackage(_rec) => None,
+        }
+    }
+
+    fn matches(&self, spec: &MatchSpec) -> bool {
+        match self {
+            SolverPackageRecord::Record(rec) => spec.matches(&rec.package_record),
+            SolverPackageRecord::VirtualPackage(GenericVirtualPackage {
+                version,
+                build_string,
+                ..
+            }) => {
+                spec.version
+                    .as_ref()
+                    .map_or(true, |spec| spec.matches(version))
+                    && spec
+                        .build
+                        .as_ref()
+                        .map_or(true, |build| build.matches(build_string))
+            }
+        }
+    }
+}
+
+/// All the candidates of a single package.
+struct PackageCandidates<'a> {
+    /// The candidates sorted by version. The candidate at index `i` has version index `i + 1`.
+    records: Vec<SolverPackageRecord<'a>>,
+
+    /// The versions of the package. The first version represents the package not being installed
+    /// and the last version is never available, it is used for requirements that do not match
+    /// any candidate.
+    versions: Vec<CandidateVersion>,
+
+    /// The indices of the versions of the candidates, ordered from most to least preferred.
+    preference: Vec<u32>,
+
+    /// The version of the locked candidate, which is preferred over all other candidates.
+    favored: Option<u32>,
+}
+
+impl<'a> PackageCandidates<'a> {
+    fn new(
+        mut records: Vec<SolverPackageRecord<'a>>,
+        favored: Option<&'a RepoDataRecord>,
+        prefers_lowest_version: bool,
+        channel_priorities: &ChannelPriorities<'_>,
+    ) -> Self {
+        records.sort_by(|a, b| {
+            a.version()
+                .cmp(b.version())
+                .then_with(|| a.build_number().cmp(&b.build_number()))
+                .then_with(|| a.timestamp().cmp(&b.timestamp()))
+        });
+
+        let labels = std::iter::once(String::from("<not installed>"))
+            .chain(
+                records
+                    .iter()
+                    .map(|record| format!("{} {}", record.version(), record.build())),
+            )
+            .chain(std::iter::once(String::from("<unavailable>")))
+            .collect::<Arc<[String]>>();
+        let versions = (0..labels.len() as u32)
+            .map(|index| CandidateVersion::new(index, labels.clone()))
+            .collect::<Vec<_>>();
+
+        let mut preference = (1..=records.len() as u32).collect::<Vec<_>>();
+        preference.sort_by(|&a, &b| {
+            compare_candidates(
+                &records[a as usize - 1],
+                &records[b as usize - 1],
+                prefers_lowest_version,
+                channel_priorities,
+            )
+        });
+
+        let favored = favored.and_then(|favored| {
+            (1..)
+                .zip(&records)
+                .find_map(|(index, record)| match record {
+                    SolverPackageRecord::Record(rec) if std::ptr::eq(*rec, favored) => Some(index),
+                    _ => None,
+                })
+        });
+
+        Self {
+            records,
+            versions,
+            preference,
+            favored,
+        }
+    }
+
+    /// Returns the candidate with the given version index, or `None` if the version does not
+    /// refer to a candidate.
+    fn record(&self, version: u32) -> Option<&SolverPackageRecord<'a>> {
+        self.records.get((version as usize).checked_sub(1)?)
+    }
+
+    /// Returns the version that is never available.
+    fn unavailable(&self) -> &CandidateVersion {
+        self.versions
+            .last()
+            .expect("there is always an unavailable version")
+    }
+
+    /// Returns the set of versions that match the spec. If `optional` is true the set also
+    /// includes the version that represents the package not being installed.
+    fn matching(&self, spec: &MatchSpec, optional: bool) -> VersionRange {
+        let mut intervals: Vec<(u32, u32)> = Vec::new();
+        let matching = (1..)
+            .zip(&self.records)
+            .filter(|(_, record)| record.matches(spec))
+            .map(|(index, _)| index);
+        for index in std::iter::once(NOT_INSTALLED)
+            .filter(|_| optional)
+            .chain(matching)
+        {
+            match intervals.last_mut() {
+                Some((_, end)) if *end + 1 == index => *end = index,
+                _ => intervals.push((index, index)),
+            }
+        }
+
+        if intervals.is_empty() {
+            return Range::equal(self.unavailable().clone());
+        }
+
+        // The version after the last candidate is the unavailable version, so the exclusive end
+        // of an interval always exists.
+        intervals
+            .into_iter()
+            .map(|(start, end)| {
+                Range::between(
+                    self.versions[start as usize].clone(),
+                    self.versions[end as usize + 1].clone(),
+                )
+            })
+            .fold(Range::none(), |range, interval| range.union(&interval))
+    }
+
+    /// Returns the number of candidates that are contained in the range.
+    fn count(&self, range: &VersionRange) -> usize {
+        self.versions[1..self.versions.len() - 1]
+            .iter()
+            .filter(|version| range.contains(version))
+            .count()
+    }
+
+    /// Selects the most preferred version that is contained in the range.
+    fn choose(&self, range: &VersionRange) -> Option<CandidateVersion> {
+        // If the package is only constrained, it does not have to be installed.
+        if range.contains(&self.versions[NOT_INSTALLED as usize]) {
+            return Some(self.versions[NOT_INSTALLED as usize].clone());
+        }
+
+        self.favored
+            .into_iter()
+            .chain(self.preference.iter().copied())
+            .map(|index| &self.versions[index as usize])
+            .find(|version| range.contains(version))
+            .cloned()
+    }
+}
+
+/// Returns the order in which candidates of the same package are preferred. Candidates that
+/// compare as [`Ordering::Less`] are tried first.
+fn compare_candidates(
+    a: &SolverPackageRecord<'_>,
+    b: &SolverPackageRecord<'_>,
+    prefers_lowest_version: bool,
+    channel_priorities: &ChannelPriorities<'_>,
+) -> Ordering {
+    // Packages with tracked features are only used if nothing else is possible.
+    let tracked_features = a.has_track_features().cmp(&b.has_track_features());
+    if tracked_features != Ordering::Equal {
+        return tracked_features;
+    }
+
+    if channel_priorities.prefers_higher_priority() {
+        let a_rank = a
+            .channel()
+            .map_or(usize::MAX, |channel| channel_priorities.rank(channel));
+        let b_rank = b
+            .channel()
+            .map_or(usize::MAX, |channel| channel_priorities.rank(channel));
+        if a_rank != b_rank {
+            return a_rank.cmp(&b_rank);
+        }
+    }
+
+    let version = if prefers_lowest_version {
+        a.version().cmp(b.version())
+    } else {
+        b.version().cmp(a.version())
+    };
+
+    version
+        .then_with(|| b.build_number().cmp(&a.build_number()))
+        .then_with(|| b.timestamp().cmp(&a.timestamp()))
+}
+
+/// Dependency provider for conda
+struct CondaDependencyProvider<'a> {
+    packages: HashMap<String, PackageCandidates<'a>>,
+
+    /// The candidates of packages that are not available at all
+    unknown_package: PackageCandidates<'a>,
+
+    /// The dependencies of the root package
+    root_dependencies: DependencyConstraints<PubGrubPackage, VersionRange>,
+
+    /// The parsed match specs of the `depends` (`false`) and `constrains` (`true`) of records
+    parse_match_spec_cache: RefCell<HashMap<(&'a str, bool), ParsedDependency>>,
+
+    /// A cache of parsed match specs that is shared with other solves
+    match_spec_cache: Option<MatchSpecCache>,
+
+    stop_condition: StopCondition,
+}
+
+impl<'a> CondaDependencyProvider<'a> {
+    #[allow(clippy::too_many_arguments)]
+    fn from_solver_task(
+        repodata: impl IntoIterator<Item = RepoData<'a>>,
+        favored_records: &'a [RepoDataRecord],
+        locked_records: &'a [RepoDataRecord],
+        virtual_packages: &'a [GenericVirtualPackage],
+        match_specs: &[MatchSpec],
+        channel_priority: ChannelPriority,
+        strategy: SolveStrategy,
+        exclude_newer: Option<ExcludeNewer>,
+        stop_condition: StopCondition,
+    ) -> Self {
+        // Records that are too new are ignored entirely.
+        let is_too_new = |record: &RepoDataRecord| {
+            exclude_newer.map_or(false, |exclude_newer| exclude_newer.excludes(record))
+        };
+
+        // Rank the channels in the order in which the records are presented to this function.
+        let repodata = repodata.into_iter().collect::<Vec<_>>();
+        let channel_priorities = ChannelPriorities::new(
+            channel_priority,
+            repodata
+                .iter()
+                .flat_map(|r| r.records.iter().copied())
+                .filter(|record| !is_too_new(record)),
+            match_specs,
+        );
+
+        let mut records_by_name: HashMap<&str, Vec<SolverPackageRecord<'a>>> = HashMap::new();
+
+        // Add virtual packages to the records
+        for virtual_package in virtual_packages {
+            records_by_name
+                .entry(virtual_package.name.as_normalized())
+                .or_default()
+                .push(SolverPackageRecord::VirtualPackage(virtual_package));
+        }
+
+        // Add the records that the solver is allowed to select
+        for repo_data in repodata {
+            for record in prefer_conda_archives(repo_data.records) {
+                if is_too_new(record)
+                    || requested_from_other_channel(record, match_specs)
+                    || channel_priorities.is_excluded(record)
+                {
+                    tracing::debug!(
+                        "Ignoring '{}' from '{}'",
+                        record.package_record.name.as_normalized(),
+                        &record.channel
+                    );
+                    continue;
+                }
+
+                records_by_name
+                    .entry(record.package_record.name.as_normalized())
+                    .or_default()
+                    .push(SolverPackageRecord::Record(record));
+            }
+        }
+
+        // Locked packages are the only candidates for their package.
+        let mut locked_names = HashSet::new();
+        for locked_record in locked_records {
+            let name = locked_record.package_record.name.as_normalized();
+            if locked_names.insert(name) {
+                records_by_name.remove(name);
+            }
+            records_by_name
+                .entry(name)
+                .or_default()
+                .push(SolverPackageRecord::Record(locked_record));
+        }
+
+        // Favored packages are preferred over other candidates.
+        let mut favored_by_name = HashMap::new();
+        for favored_record in favored_records {
+            let name = favored_record.package_record.name.as_normalized();
+            if locked_names.contains(name) {
+                continue;
+            }
+            records_by_name
+                .entry(name)
+                .or_default()
+                .push(SolverPackageRecord::Record(favored_record));
+            favored_by_name.insert(name, favored_record);
+        }
+
+        let direct_dependencies = match_specs
+            .iter()
+            .filter_map(|spec| spec.name.as_ref())
+            .map(PackageName::as_normalized)
+            .collect::<HashSet<_>>();
+
+        let packages = records_by_name
+            .into_iter()
+            .map(|(name, records)| {
+                let prefers_lowest_version = match strategy {
+                    SolveStrategy::Highest => false,
+                    SolveStrategy::LowestVersion => true,
+                    SolveStrategy::LowestVersionDirect => direct_dependencies.contains(name),
+                };
+                let candidates = PackageCandidates::new(
+                    records,
+                    favored_by_name.get(name).copied(),
+                    prefers_lowest_version,
+                    &channel_priorities,
+                );
+                (name.to_string(), candidates)
+            })
+            .collect();
+
+        let mut provider = Self {
+            packages,
+            unknown_package: PackageCandidates::new(
+                Vec::new(),
+                None,
+                false,
+                &ChannelPriorities::default(),
+            ),
+            root_dependencies: DependencyConstraints::default(),
+            parse_match_spec_cache: RefCell::default(),
+            match_spec_cache: None,
+            stop_condition,
+        };
+
+        let mut root_dependencies = DependencyConstraints::default();
+        for spec in match_specs {
+            let name = spec
+                .name
+                .as_ref()
+                .expect("cannot use matchspec without a name")
+                .as_normalized();
+            let range = provider.candidates(name).matching(spec, false);
+            add_dependency(
+                &mut root_dependencies,
+                PubGrubPackage::Package(name.to_string()),
+                range,
+            );
+        }
+        provider.root_dependencies = root_dependencies;
+
+        provider
+    }
+
+    /// Returns the candidates of the package with the given name.
+    fn candidates(&self, name: &str) -> &PackageCandidates<'a> {
+        self.packages.get(name).unwrap_or(&self.unknown_package)
+    }
+
+    /// Returns the candidates of a package, or `None` for the root package.
+    fn package_candidates(&self, package: &PubGrubPackage) -> Option<&PackageCandidates<'a>> {
+        match package {
+            PubGrubPackage::Root => None,
+            PubGrubPackage::Package(name) => Some(self.candidates(name)),
+        }
+    }
+
+    /// Parses a dependency of a record into a package and the set of allowed versions.
+    fn parse_dependency(
+        &self,
+        spec_str: &'a str,
+        optional: bool,
+    ) -> Result<ParsedDependency, SolveError> {
+        if let Some(dependency) = self
+            .parse_match_spec_cache
+            .borrow()
+            .get(&(spec_str, optional))
+        {
+            return Ok(dependency.clone());
+        }
+
+        let spec = match &self.match_spec_cache {
+            Some(cache) => cache.parse(spec_str)?,
+            None => MatchSpec::from_str(spec_str)?,
+        };
+        let name = spec
+            .name
+            .as_ref()
+            .expect("match specs without names are not supported")
+            .as_normalized();
+        let range = self.candidates(name).matching(&spec, optional);
+        let dependency = (PubGrubPackage::Package(name.to_string()), range);
+
+        self.parse_match_spec_cache
+            .borrow_mut()
+            .insert((spec_str, optional), dependency.clone());
+        Ok(dependency)
+    }
+}
+
+impl<'a> DependencyProvider for CondaDependencyProvider<'a> {
+    type P = PubGrubPackage;
+    type V = CandidateVersion;
+    type VS = VersionRange;
+    type Priority = Reverse<usize>;
+    type M = String;
+    type Err = SolveError;
+
+    fn prioritize(
+        &self,
+        package: &PubGrubPackage,
+        range: &VersionRange,
+        _package_conflicts_counts: &PackageResolutionStatistics,
+    ) -> Self::Priority {
+        // Decide on the package with the fewest candidates first, this leads to conflicts as
+        // early as possible.
+        Reverse(
+            self.package_candidates(package)
+                .map_or(0, |candidates| candidates.count(range)),
+        )
+    }
+
+    fn choose_version(
+        &self,
+        package: &PubGrubPackage,
+        range: &VersionRange,
+    ) -> Result<Option<CandidateVersion>, SolveError> {
+        Ok(match self.package_candidates(package) {
+            None => Some(CandidateVersion::root()),
+            Some(candidates) => candidates.choose(range),
+        })
+    }
+
+    fn get_dependencies(
+        &self,
+        package: &PubGrubPackage,
+        version: &CandidateVersion,
+    ) -> Result<Dependencies<PubGrubPackage, VersionRange, String>, SolveError> {
+        let PubGrubPackage::Package(name) = package else {
+            return Ok(Dependencies::Available(self.root_dependencies.clone()));
+        };
+
+        let Some(SolverPackageRecord::Record(rec)) = self.candidates(name).record(version.index)
+        else {
+            return Ok(Dependencies::Available(DependencyConstraints::default()));
+        };
+
+        let mut dependencies = DependencyConstraints::default();
+        let depends = rec.package_record.depends.iter().map(|spec| (spec, false));
+        let constrains = rec
+            .package_record
+            .constrains
+            .iter()
+            .map(|spec| (spec, true));
+        for (spec_str, optional) in depends.chain(constrains) {
+            let (dependency, range) = self.parse_dependency(spec_str, optional)?;
+
+            // Pubgrub does not allow packages to depend on themselves.
+            if &dependency == package {
+                continue;
+            }
+
+            add_dependency(&mut dependencies, dependency, range);
+        }
+
+        Ok(Dependencies::Available(dependencies))
+    }
+
+    fn should_cancel(&self) -> Result<(), SolveError> {
+        self.stop_condition.check()
+    }
+}
+
+/// Adds a dependency, if there already is a dependency on the same package only the versions that
+/// are allowed by both are allowed.
+fn add_dependency(
+    dependencies: &mut DependencyConstraints<PubGrubPackage, VersionRange>,
+    package: PubGrubPackage,
+    range: VersionRange,
+) {
+    match dependencies.get_mut(&package) {
+        Some(existing) => *existing = existing.intersection(&range),
+        None => {
+            dependencies.insert(package, range);
+        }
+    }
+}
+
+/// Deduplicates records that refer to the same package data but with different archive types,
+/// `.conda` packages are preferred over `.tar.bz2` packages. The order of the records is
+/// preserved to keep the solve deterministic.
+fn prefer_conda_archives(records: Vec<&RepoDataRecord>) -> Vec<&RepoDataRecord> {
+    let mut ordered_records: Vec<&RepoDataRecord> = Vec::with_capacity(records.len());
+    let mut package_to_type: HashMap<&str, (ArchiveType, usize)> = HashMap::new();
+    for record in records {
+        let (file_name, archive_type) = ArchiveType::split_str(&record.file_name)
+            .unwrap_or((&record.file_name, ArchiveType::TarBz2));
+        match package_to_type.get_mut(file_name) {
+            None => {
+                package_to_type.insert(file_name, (archive_type, ordered_records.len()));
+                ordered_records.push(record);
+            }
+            Some((prev_archive_type, idx)) => {
+                if archive_type > *prev_archive_type {
+                    *prev_archive_type = archive_type;
+                    ordered_records[*idx] = record;
+                }
+            }
+        }
+    }
+    ordered_records
+}
+
+/// A [`Solver`] implemented using the `pubgrub` library
+#[derive(Default)]
+pub struct Solver;
+
+impl super::SolverImpl for Solver {
+    type RepoData<'a> = RepoData<'a>;
+
+    fn solve<
+        'a,
+        R: IntoRepoData<'a, Self::RepoData<'a>>,
+        TAvailablePackagesIterator: IntoIterator<Item = R>,
+    >(
+        &mut self,
+        task: SolverTask<TAvailablePackagesIterator>,
+    ) -> Result<Vec<RepoDataRecord>, SolveError> {
+        let stop_condition = StopCondition::new(task.timeout, task.cancellation_token.clone());
+        stop_condition.check()?;
+
+        // Construct a provider that can serve the data.
+        let mut provider = CondaDependencyProvider::from_solver_task(
+            task.available_packages.into_iter().map(|r| r.into()),
+            &task.locked_packages,
+            &task.pinned_packages,
+            &task.virtual_packages,
+            &task.specs,
+            task.channel_priority,
+            task.strategy,
+            task.exclude_newer,
+            stop_condition,
+        );
+        provider.match_spec_cache = task.match_spec_cache;
+
+        let solution = pubgrub::resolve(&provider, PubGrubPackage::Root, CandidateVersion::root())
+            .map_err(|err| match err {
+                PubGrubError::NoSolution(mut derivation_tree) => {
+                    derivation_tree.collapse_no_versions();
+                    let message = DefaultStringReporter::report(&derivation_tree);
+                    SolveError::Unsolvable(conflict::conflict_report(
+                        &provider,
+                        &task.specs,
+                        &derivation_tree,
+                        vec![message],
+                    ))
+                }
+                PubGrubError::ErrorRetrievingDependencies { source, .. }
+                | PubGrubError::ErrorChoosingVersion { source, .. }
+                | PubGrubError::ErrorInShouldCancel(source) => source,
+            })?;
+
+        // Get the resulting packages from the solution.
+        let required_records = solution
+            .into_iter()
+            .filter_map(|(package, version)| {
+                match provider
+                    .package_candidates(&package)?
+                    .record(version.index)?
+                {
+                    SolverPackageRecord::Record(rec) => Some((*rec).clone()),
+                    SolverPackageRecord::VirtualPackage(_) => None,
+                }
+            })
+            .collect();
+
+        Ok(required_records)
+    }
+}
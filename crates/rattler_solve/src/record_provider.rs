@@ -0,0 +1,41 @@
+//! Allows solvers to load the records of packages on demand.
+
+use rattler_conda_types::{PackageName, RepoDataRecord};
+use std::io;
+
+/// A source of [`RepoDataRecord`]s that can be queried by package name, usually the records of a
+/// single channel and platform combination.
+///
+/// Instead of loading all the records that might be required up front, a solver can use this
+/// trait to only load the records of the packages it actually encounters while solving. This is
+/// currently supported by the `resolvo` backend.
+pub trait RecordProvider {
+    /// Returns the channel of the records of this provider, this must be the same as the
+    /// [`RepoDataRecord::channel`] of the records. Solvers use it to rank the channels before any
+    /// records are loaded.
+    fn channel(&self) -> String;
+
+    /// Returns all the records for the package with the specified name.
+    fn load_records(&self, package_name: &PackageName) -> io::Result<Vec<RepoDataRecord>>;
+}
+
+impl<T: RecordProvider + ?Sized> RecordProvider for &T {
+    fn channel(&self) -> String {
+        (**self).channel()
+    }
+
+    fn load_records(&self, package_name: &PackageName) -> io::Result<Vec<RepoDataRecord>> {
+        (**self).load_records(package_name)
+    }
+}
+
+#[cfg(feature = "sparse")]
+impl RecordProvider for rattler_repodata_gateway::sparse::SparseRepoData {
+    fn channel(&self) -> String {
+        rattler_repodata_gateway::sparse::SparseRepoData::channel(self).canonical_name()
+    }
+
+    fn load_records(&self, package_name: &PackageName) -> io::Result<Vec<RepoDataRecord>> {
+        rattler_repodata_gateway::sparse::SparseRepoData::load_records(self, package_name)
+    }
+}
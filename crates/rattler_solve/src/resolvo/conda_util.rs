@@ -1,6 +1,6 @@
 use crate::resolvo::{CondaDependencyProvider, SolverMatchSpec};
 use rattler_conda_types::Version;
-use resolvo::{SolvableId, SolverCache, VersionSetId};
+use resolvo::{Dependencies, SolvableId, SolverCache, VersionSetId};
 use std::cmp::Ordering;
 use std::collections::HashMap;
 
@@ -9,6 +9,7 @@ use std::collections::HashMap;
 pub(super) fn compare_candidates<'a>(
     a: SolvableId,
     b: SolvableId,
+    provider: &CondaDependencyProvider<'a>,
     solver: &SolverCache<SolverMatchSpec<'a>, String, CondaDependencyProvider<'a>>,
     match_spec_highest_version: &mut HashMap<
         VersionSetId,
@@ -33,12 +34,35 @@ pub(super) fn compare_candidates<'a>(
         Ordering::Equal => {}
     };
 
-    // Otherwise, select the variant with the highest version
-    match a_record.version().cmp(b_record.version()) {
-        Ordering::Less => return Ordering::Greater,
-        Ordering::Greater => return Ordering::Less,
-        Ordering::Equal => {}
+    // Then prefer the variant from the channel with the highest priority, unless channel priority
+    // is disabled.
+    let channel_priorities = provider.channel_priorities.borrow();
+    if channel_priorities.prefers_higher_priority() {
+        let a_rank = a_record
+            .channel()
+            .map_or(usize::MAX, |channel| channel_priorities.rank(channel));
+        let b_rank = b_record
+            .channel()
+            .map_or(usize::MAX, |channel| channel_priorities.rank(channel));
+        match a_rank.cmp(&b_rank) {
+            Ordering::Less => return Ordering::Less,
+            Ordering::Greater => return Ordering::Greater,
+            Ordering::Equal => {}
+        };
+    }
+    drop(channel_priorities);
+
+    // Otherwise, select the variant with the highest version, or the lowest version if that is
+    // what the solve strategy asks for.
+    let version_order = a_record.version().cmp(b_record.version());
+    let version_order = if provider.prefers_lowest_version(a_record.name()) {
+        version_order
+    } else {
+        version_order.reverse()
     };
+    if version_order != Ordering::Equal {
+        return version_order;
+    }
 
     // Otherwise, select the variant with the highest build number
     match a_record.build_number().cmp(&b_record.build_number()) {
@@ -49,13 +73,19 @@ pub(super) fn compare_candidates<'a>(
 
     // Otherwise, compare the dependencies of the variants. If there are similar
     // dependencies select the variant that selects the highest version of the dependency.
-    let a_match_specs = solver
-        .get_or_cache_dependencies(a)
+    // The dependencies can only be unavailable if the solve was cancelled, in which case the
+    // order does not matter anymore.
+    let (Ok(Dependencies::Known(a_dependencies)), Ok(Dependencies::Known(b_dependencies))) = (
+        solver.get_or_cache_dependencies(a),
+        solver.get_or_cache_dependencies(b),
+    ) else {
+        return Ordering::Equal;
+    };
+    let a_match_specs = a_dependencies
         .requirements
         .iter()
         .map(|id| (*id, pool.resolve_version_set(*id)));
-    let b_match_specs = solver
-        .get_or_cache_dependencies(b)
+    let b_match_specs = b_dependencies
         .requirements
         .iter()
         .map(|id| (*id, pool.resolve_version_set(*id)));
@@ -131,7 +161,10 @@ pub(super) fn find_highest_version<'a>(
     match_spec_highest_version
         .entry(match_spec_id)
         .or_insert_with(|| {
-            let candidates = solver.get_or_cache_matching_candidates(match_spec_id);
+            // The candidates can only be unavailable if the solve was cancelled.
+            let candidates = solver
+                .get_or_cache_matching_candidates(match_spec_id)
+                .ok()?;
             candidates
                 .iter()
                 .map(|id| solver.pool().resolve_solvable(*id).inner())
@@ -0,0 +1,271 @@
+//! Derives a [`ConflictReport`] from the candidates that were available to the resolvo solver.
+//!
+//! `resolvo` does not expose its problem graph in a structured form, so instead we reconstruct the
+//! conflict by propagating the requirements that are forced by the root requirements. A package is
+//! forced if all the remaining candidates of an already constrained package depend on it. Whenever
+//! a forced requirement has no candidates left, either because nothing matches it or because it is
+//! incompatible with another forced requirement on the same package, the requirements that lead
+//! to it are recorded in the report.
+
+use super::{parse_match_spec, SolverMatchSpec, SolverPackageRecord};
+use crate::problem::{ConflictCandidate, ConflictReason, ConflictReportBuilder};
+use crate::ConflictReport;
+use resolvo::{Candidates, NameId, Pool, SolvableId, VersionSet, VersionSetId};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// The maximum number of packages that are visited while propagating requirements. Limits the
+/// amount of work done for very large problems.
+const MAX_PROPAGATIONS: usize = 10_000;
+
+/// Builds a [`ConflictReport`] for the given root requirements.
+pub(super) fn conflict_report<'a>(
+    pool: &Pool<SolverMatchSpec<'a>, String>,
+    records: &HashMap<NameId, Candidates>,
+    root_requirements: &[VersionSetId],
+    messages: Vec<String>,
+) -> ConflictReport {
+    let mut analysis = ConflictAnalysis {
+        pool,
+        records,
+        parse_match_spec_cache: HashMap::default(),
+        constraints: HashMap::default(),
+        builder: ConflictReportBuilder::default(),
+        queue: VecDeque::default(),
+    };
+
+    for &version_set in root_requirements {
+        let requested = analysis.builder.requested(analysis.display(version_set));
+        analysis.require(&[requested], version_set);
+    }
+
+    let mut propagations = 0;
+    while let Some(name) = analysis.queue.pop_front() {
+        if propagations == MAX_PROPAGATIONS {
+            tracing::debug!("stopped analyzing the conflict after {MAX_PROPAGATIONS} steps");
+            break;
+        }
+        analysis.propagate(name);
+        propagations += 1;
+    }
+
+    analysis.builder.finish(messages).retain_conflicting()
+}
+
+struct ConflictAnalysis<'p, 'a> {
+    pool: &'p Pool<SolverMatchSpec<'a>, String>,
+    records: &'p HashMap<NameId, Candidates>,
+    parse_match_spec_cache: HashMap<&'a str, VersionSetId>,
+
+    /// The candidates of each package that are still allowed by the forced requirements
+    constraints: HashMap<NameId, Vec<SolvableId>>,
+
+    builder: ConflictReportBuilder,
+
+    /// Packages whose constraints changed and whose requirements have to be propagated
+    queue: VecDeque<NameId>,
+}
+
+impl<'p, 'a> ConflictAnalysis<'p, 'a> {
+    /// Records that all the `sources` require `version_set` and narrows down the constraints of
+    /// the required package.
+    fn require(&mut self, sources: &[usize], version_set: VersionSetId) {
+        let name = self.pool.resolve_version_set_package_name(version_set);
+        let spec = self.display(version_set);
+        let allowed = self.allowed_candidates(version_set);
+
+        if allowed.is_empty() {
+            let missing = self.builder.missing(spec.clone());
+            for &source in sources {
+                self.builder.requires(source, missing, spec.clone());
+            }
+            self.explain_unavailable(sources, version_set);
+            return;
+        }
+
+        let (remaining, previous) = match self.constraints.get(&name) {
+            Some(previous) => (
+                allowed
+                    .iter()
+                    .copied()
+                    .filter(|candidate| previous.contains(candidate))
+                    .collect::<Vec<_>>(),
+                previous.clone(),
+            ),
+            None => (allowed.clone(), Vec::new()),
+        };
+
+        // If no candidates remain, the requirement conflicts with a previous requirement on the
+        // same package.
+        if remaining.is_empty() {
+            for &candidate in &allowed {
+                let node = self.candidate_node(candidate);
+                for &source in sources {
+                    self.builder.requires(source, node, spec.clone());
+                }
+                for &other in &previous {
+                    let other = self.candidate_node(other);
+                    self.builder
+                        .conflicts(node, other, ConflictReason::SameName);
+                }
+            }
+            return;
+        }
+
+        for &candidate in &remaining {
+            let node = self.candidate_node(candidate);
+            for &source in sources {
+                self.builder.requires(source, node, spec.clone());
+            }
+        }
+
+        if previous.is_empty() || remaining.len() < previous.len() {
+            self.constraints.insert(name, remaining);
+            self.queue.push_back(name);
+        }
+    }
+
+    /// Propagates the requirements that all the allowed candidates of a package share.
+    fn propagate(&mut self, name: NameId) {
+        let Some(allowed) = self.constraints.get(&name).cloned() else {
+            return;
+        };
+
+        let dependencies = allowed
+            .iter()
+            .map(|&candidate| self.requirements(candidate))
+            .collect::<Vec<_>>();
+
+        // Only the packages that are required by every allowed candidate are forced.
+        let Some((first, rest)) = dependencies.split_first() else {
+            return;
+        };
+        let forced = first
+            .iter()
+            .map(|(dependency, _)| *dependency)
+            .filter(|dependency| {
+                rest.iter()
+                    .all(|deps| deps.iter().any(|(name, _)| name == dependency))
+            })
+            .collect::<Vec<_>>();
+
+        for dependency in forced {
+            // Group the candidates by the requirement they have on the dependency.
+            let mut by_version_set: Vec<(VersionSetId, Vec<usize>)> = Vec::new();
+            for (&candidate, deps) in allowed.iter().zip(dependencies.iter()) {
+                let node = self.candidate_node(candidate);
+                let version_sets = deps
+                    .iter()
+                    .filter(|(name, _)| *name == dependency)
+                    .map(|(_, version_set)| *version_set);
+                for version_set in version_sets {
+                    match by_version_set.iter_mut().find(|(id, _)| *id == version_set) {
+                        Some((_, nodes)) => nodes.push(node),
+                        None => by_version_set.push((version_set, vec![node])),
+                    }
+                }
+            }
+
+            for (version_set, sources) in by_version_set {
+                self.require(&sources, version_set);
+            }
+        }
+    }
+
+    /// Adds the candidates that match `version_set` but that were excluded before solving.
+    fn explain_unavailable(&mut self, sources: &[usize], version_set: VersionSetId) {
+        let (pool, records) = (self.pool, self.records);
+        let name = pool.resolve_version_set_package_name(version_set);
+        let Some(candidates) = records.get(&name) else {
+            return;
+        };
+
+        for &(candidate, reason) in &candidates.excluded {
+            if self.matches(version_set, candidate) {
+                let node = self.candidate_node(candidate);
+                let reason = ConflictReason::Excluded {
+                    reason: pool.resolve_string(reason).to_string(),
+                };
+                for &source in sources {
+                    self.builder.conflicts(source, node, reason.clone());
+                }
+            }
+        }
+
+        if let Some(locked) = candidates.locked {
+            let locked_node = self.candidate_node(locked);
+            for &source in sources {
+                self.builder
+                    .conflicts(source, locked_node, ConflictReason::Locked);
+            }
+        }
+    }
+
+    /// Returns the candidates that match `version_set` and that have not been excluded.
+    fn allowed_candidates(&self, version_set: VersionSetId) -> Vec<SolvableId> {
+        let name = self.pool.resolve_version_set_package_name(version_set);
+        let Some(candidates) = self.records.get(&name) else {
+            return Vec::new();
+        };
+
+        let excluded = candidates
+            .excluded
+            .iter()
+            .map(|&(candidate, _)| candidate)
+            .collect::<HashSet<_>>();
+
+        candidates
+            .candidates
+            .iter()
+            .copied()
+            .filter(|candidate| !excluded.contains(candidate))
+            .filter(|&candidate| candidates.locked.map_or(true, |locked| locked == candidate))
+            .filter(|&candidate| self.matches(version_set, candidate))
+            .collect()
+    }
+
+    /// Returns the requirements of a candidate together with the name of the required package,
+    /// in the order in which they are specified.
+    fn requirements(&mut self, candidate: SolvableId) -> Vec<(NameId, VersionSetId)> {
+        let pool = self.pool;
+        let SolverPackageRecord::Record(rec) = pool.resolve_solvable(candidate).inner() else {
+            return Vec::new();
+        };
+
+        rec.package_record
+            .depends
+            .iter()
+            .filter_map(|depends| {
+                parse_match_spec(pool, depends, &mut self.parse_match_spec_cache, None).ok()
+            })
+            .map(|version_set| {
+                (
+                    pool.resolve_version_set_package_name(version_set),
+                    version_set,
+                )
+            })
+            .collect()
+    }
+
+    fn matches(&self, version_set: VersionSetId, candidate: SolvableId) -> bool {
+        self.pool
+            .resolve_version_set(version_set)
+            .contains(self.pool.resolve_solvable(candidate).inner())
+    }
+
+    fn candidate_node(&mut self, candidate: SolvableId) -> usize {
+        let candidate = match self.pool.resolve_solvable(candidate).inner() {
+            SolverPackageRecord::Record(rec) => ConflictCandidate::from(*rec),
+            SolverPackageRecord::VirtualPackage(package) => ConflictCandidate::from(*package),
+        };
+        self.builder.candidate(candidate)
+    }
+
+    fn display(&self, version_set: VersionSetId) -> String {
+        let name = self.pool.resolve_version_set_package_name(version_set);
+        format!(
+            "{} {}",
+            self.pool.resolve_package_name(name),
+            self.pool.resolve_version_set(version_set)
+        )
+    }
+}
@@ -1,28 +1,38 @@
 //! Provides an solver implementation based on the [`resolvo`] crate.
 
-use crate::{IntoRepoData, SolveError, SolverRepoData, SolverTask};
+use crate::channel_priority::ChannelPriorities;
+use crate::{
+    ChannelPriority, ExcludeNewer, IntoRepoData, MatchSpecCache, RecordProvider, SolveError,
+    SolveStrategy, SolverRepoData, SolverTask, StopCondition,
+};
+use elsa::FrozenVec;
 use rattler_conda_types::package::ArchiveType;
 use rattler_conda_types::{
-    GenericVirtualPackage, MatchSpec, NamelessMatchSpec, PackageRecord, ParseMatchSpecError,
-    RepoDataRecord,
+    GenericVirtualPackage, MatchSpec, NamelessMatchSpec, PackageName, PackageRecord,
+    ParseMatchSpecError, RepoDataRecord,
 };
 use resolvo::{
-    Candidates, Dependencies, DependencyProvider, NameId, Pool, SolvableDisplay, SolvableId,
-    Solver as LibSolvRsSolver, SolverCache, VersionSet, VersionSetId,
+    Candidates, Dependencies, DependencyProvider, KnownDependencies, NameId, Pool, SolvableDisplay,
+    SolvableId, Solver as LibSolvRsSolver, SolverCache, UnsolvableOrCancelled, VersionSet,
+    VersionSetId,
 };
 use std::{
+    any::Any,
     cell::RefCell,
     cmp::Ordering,
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fmt::{Display, Formatter},
+    io,
     marker::PhantomData,
     ops::Deref,
+    rc::Rc,
     str::FromStr,
 };
 
 use itertools::Itertools;
 
 mod conda_util;
+mod conflict;
 
 /// Represents the information required to load available packages into libsolv for a single channel
 /// and platform combination
@@ -110,6 +120,13 @@ enum SolverPackageRecord<'a> {
 }
 
 impl<'a> SolverPackageRecord<'a> {
+    fn name(&self) -> &str {
+        match self {
+            SolverPackageRecord::Record(rec) => rec.package_record.name.as_normalized(),
+            SolverPackageRecord::VirtualPackage(rec) => rec.name.as_normalized(),
+        }
+    }
+
     fn version(&self) -> &rattler_conda_types::Version {
         match self {
             SolverPackageRecord::Record(rec) => rec.package_record.version.version(),
@@ -132,6 +149,13 @@ impl<'a> SolverPackageRecord<'a> {
         }
     }
 
+    fn channel(&self) -> Option<&str> {
+        match self {
+            SolverPackageRecord::Record(rec) => Some(rec.channel.as_str()),
+            SolverPackageRecord::VirtualPackage(_rec) => None,
+        }
+    }
+
     fn timestamp(&self) -> Option<&chrono::DateTime<chrono::Utc>> {
         match self {
             SolverPackageRecord::Record(rec) => rec.package_record.timestamp.as_ref(),
@@ -153,26 +177,73 @@ impl<'a> Display for SolverPackageRecord<'a> {
     }
 }
 
+/// Owns the records that are loaded on demand while solving. The solver refers to these records,
+/// so they have to outlive the [`CondaDependencyProvider`].
+#[derive(Default)]
+struct LoadedRecords {
+    records: FrozenVec<Vec<RepoDataRecord>>,
+
+    /// The first error that occurred while loading records
+    error: RefCell<Option<io::Error>>,
+}
+
+/// The sources from which the records of packages are loaded when the solver first asks for them.
+struct LazyRecords<'a> {
+    sources: Vec<&'a dyn RecordProvider>,
+
+    loaded: &'a LoadedRecords,
+
+    /// The packages for which the records have already been loaded
+    loaded_names: RefCell<HashSet<NameId>>,
+}
+
 /// Dependency provider for conda
 #[derive(Default)]
 pub(crate) struct CondaDependencyProvider<'a> {
     pool: Pool<SolverMatchSpec<'a>, String>,
 
-    records: HashMap<NameId, Candidates>,
+    records: Rc<RefCell<HashMap<NameId, Candidates>>>,
+
+    /// If set, the records of packages are loaded on demand
+    lazy: Option<LazyRecords<'a>>,
 
     matchspec_to_highest_version:
         RefCell<HashMap<VersionSetId, Option<(rattler_conda_types::Version, bool)>>>,
 
     parse_match_spec_cache: RefCell<HashMap<&'a str, VersionSetId>>,
+
+    /// A cache of parsed match specs that is shared with other solves
+    match_spec_cache: Option<MatchSpecCache>,
+
+    channel_priorities: RefCell<ChannelPriorities<'a>>,
+
+    /// The specs that are requested from a specific channel
+    channel_specific_specs: Vec<MatchSpec>,
+
+    match_specs: Vec<MatchSpec>,
+
+    exclude_newer: Option<ExcludeNewer>,
+
+    strategy: SolveStrategy,
+
+    /// The names of the packages that are directly requested by the specs
+    direct_dependencies: HashSet<String>,
+
+    stop_condition: StopCondition,
 }
 
 impl<'a> CondaDependencyProvider<'a> {
+    #[allow(clippy::too_many_arguments)]
     pub fn from_solver_task(
         repodata: impl IntoIterator<Item = RepoData<'a>>,
         favored_records: &'a [RepoDataRecord],
         locked_records: &'a [RepoDataRecord],
         virtual_packages: &'a [GenericVirtualPackage],
         match_specs: &[MatchSpec],
+        channel_priority: ChannelPriority,
+        strategy: SolveStrategy,
+        exclude_newer: Option<ExcludeNewer>,
+        stop_condition: StopCondition,
     ) -> Self {
         let pool = Pool::default();
         let mut records: HashMap<NameId, Candidates> = HashMap::default();
@@ -189,125 +260,35 @@ impl<'a> CondaDependencyProvider<'a> {
         let channel_specific_specs = match_specs
             .iter()
             .filter(|spec| spec.channel.is_some())
+            .cloned()
             .collect::<Vec<_>>();
 
-        // Hashmap that maps the package name to the channel it was first found in.
-        let mut package_name_found_in_channel = HashMap::<String, &String>::new();
+        // Records that are too new are ignored entirely.
+        let is_too_new = |record: &RepoDataRecord| {
+            exclude_newer.map_or(false, |exclude_newer| exclude_newer.excludes(record))
+        };
+
+        // Rank the channels in the order in which the records are presented to this function.
+        let repodata = repodata.into_iter().collect::<Vec<_>>();
+        let channel_priorities = ChannelPriorities::new(
+            channel_priority,
+            repodata
+                .iter()
+                .flat_map(|r| r.records.iter().copied())
+                .filter(|record| !is_too_new(record)),
+            match_specs,
+        );
 
         // Add additional records
         for repo_datas in repodata {
-            // Iterate over all records and dedup records that refer to the same package data but with
-            // different archive types. This can happen if you have two variants of the same package but
-            // with different extensions. We prefer `.conda` packages over `.tar.bz`.
-            //
-            // Its important to insert the records in the same same order as how they were presented to this
-            // function to ensure that each solve is deterministic. Iterating over HashMaps is not
-            // deterministic at runtime so instead we store the values in a Vec as we iterate over the
-            // records. This guarentees that the order of records remains the same over runs.
-            let mut ordered_repodata = Vec::with_capacity(repo_datas.records.len());
-            let mut package_to_type: HashMap<&str, (ArchiveType, usize)> =
-                HashMap::with_capacity(repo_datas.records.len());
-
-            for record in repo_datas.records {
-                let (file_name, archive_type) = ArchiveType::split_str(&record.file_name)
-                    .unwrap_or((&record.file_name, ArchiveType::TarBz2));
-                match package_to_type.get_mut(file_name) {
-                    None => {
-                        let idx = ordered_repodata.len();
-                        ordered_repodata.push(record);
-                        package_to_type.insert(file_name, (archive_type, idx));
-                    }
-                    Some((prev_archive_type, idx)) => match archive_type.cmp(prev_archive_type) {
-                        Ordering::Greater => {
-                            // A previous package has a worse package "type", we'll use the current record
-                            // instead.
-                            *prev_archive_type = archive_type;
-                            ordered_repodata[*idx] = record;
-                        }
-                        Ordering::Less => {
-                            // A previous package that we already stored is actually a package of a better
-                            // "type" so we'll just use that instead (.conda > .tar.bz)
-                        }
-                        Ordering::Equal => {
-                            if record != ordered_repodata[*idx] {
-                                unreachable!(
-                                    "found duplicate record with different values for {}",
-                                    &record.file_name
-                                );
-                            }
-                        }
-                    },
-                }
-            }
-
-            for record in ordered_repodata {
-                let package_name =
-                    pool.intern_package_name(record.package_record.name.as_normalized());
-                let solvable_id =
-                    pool.intern_solvable(package_name, SolverPackageRecord::Record(record));
-                let candidates = records.entry(package_name).or_default();
-                candidates.candidates.push(solvable_id);
-
-                // Add to excluded when package is not in the specified channel.
-                if !channel_specific_specs.is_empty() {
-                    if let Some(spec) = channel_specific_specs.iter().find(|&&spec| {
-                        spec.name
-                            .as_ref()
-                            .expect("expecting a name")
-                            .as_normalized()
-                            == record.package_record.name.as_normalized()
-                    }) {
-                        // Check if the spec has a channel, and compare it to the repodata channel
-                        if let Some(spec_channel) = &spec.channel {
-                            if record.channel != spec_channel.base_url.to_string() {
-                                tracing::debug!("Ignoring {} from {} because it was not requested from that channel.", &record.package_record.name.as_normalized(), &record.channel);
-                                // Add record to the excluded with reason of being in the non requested channel.
-                                let message = format!(
-                                    "candidate not in requested channel: '{}'",
-                                    spec_channel
-                                        .name
-                                        .clone()
-                                        .unwrap_or(spec_channel.base_url.to_string())
-                                );
-                                candidates
-                                    .excluded
-                                    .push((solvable_id, pool.intern_string(message)));
-                                continue;
-                            }
-                        }
-                    }
-                }
-
-                // Enforce channel priority
-                // This functions makes the assumtion that the records are given in order of the channels.
-                if let Some(first_channel) = package_name_found_in_channel
-                    .get(&record.package_record.name.as_normalized().to_string())
-                {
-                    // Add the record to the excluded list when it is from a different channel.
-                    if first_channel != &&record.channel {
-                        tracing::debug!(
-                            "Ignoring '{}' from '{}' because of strict channel priority.",
-                            &record.package_record.name.as_normalized(),
-                            &record.channel
-                        );
-                        candidates.excluded.push((
-                            solvable_id,
-                            pool.intern_string(format!(
-                                "due to strict channel priority not using this option from: '{}'",
-                                &record.channel
-                            )),
-                        ));
-                        continue;
-                    }
-                } else {
-                    package_name_found_in_channel.insert(
-                        record.package_record.name.as_normalized().to_string(),
-                        &record.channel,
-                    );
-                }
-
-                candidates.hint_dependencies_available.push(solvable_id);
-            }
+            add_repodata_records(
+                &pool,
+                &mut records,
+                repo_datas.records,
+                &channel_specific_specs,
+                exclude_newer,
+                &channel_priorities,
+            );
         }
 
         // Add favored packages to the records
@@ -329,9 +310,82 @@ impl<'a> CondaDependencyProvider<'a> {
 
         Self {
             pool,
-            records,
+            records: Rc::new(RefCell::new(records)),
+            lazy: None,
             matchspec_to_highest_version: RefCell::default(),
             parse_match_spec_cache: RefCell::default(),
+            match_spec_cache: None,
+            channel_priorities: RefCell::new(channel_priorities),
+            channel_specific_specs,
+            match_specs: match_specs.to_vec(),
+            exclude_newer,
+            strategy,
+            direct_dependencies: match_specs
+                .iter()
+                .filter_map(|spec| spec.name.as_ref())
+                .map(|name| name.as_normalized().to_string())
+                .collect(),
+            stop_condition,
+        }
+    }
+
+    /// Loads the records of a package from the lazy sources, if that did not happen before.
+    fn load_lazy_records(&self, lazy: &LazyRecords<'a>, name: NameId) {
+        if !lazy.loaded_names.borrow_mut().insert(name) {
+            return;
+        }
+
+        let loaded: &'a LoadedRecords = lazy.loaded;
+        let package_name =
+            PackageName::new_unchecked(self.pool.resolve_package_name(name).as_str());
+        let mut source_records = Vec::with_capacity(lazy.sources.len());
+        for source in &lazy.sources {
+            match source.load_records(&package_name) {
+                Ok(records) => source_records.push(loaded.records.push_get(records)),
+                Err(err) => {
+                    tracing::debug!(
+                        "failed to load the records of '{}': {err}",
+                        package_name.as_normalized()
+                    );
+                    loaded.error.borrow_mut().get_or_insert(err);
+                    return;
+                }
+            }
+        }
+
+        // All the records of the package have to be known before channel priority can determine
+        // which of them are excluded.
+        let mut channel_priorities = self.channel_priorities.borrow_mut();
+        for &records in &source_records {
+            channel_priorities.add_records(
+                records.iter().filter(|record| {
+                    self.exclude_newer
+                        .map_or(true, |exclude_newer| !exclude_newer.excludes(record))
+                }),
+                &self.match_specs,
+            );
+        }
+
+        let mut candidates = self.records.borrow_mut();
+        for records in source_records {
+            add_repodata_records(
+                &self.pool,
+                &mut candidates,
+                records.iter().collect(),
+                &self.channel_specific_specs,
+                self.exclude_newer,
+                &channel_priorities,
+            );
+        }
+    }
+
+    /// Returns true if the lowest version of the package should be selected instead of the
+    /// highest.
+    fn prefers_lowest_version(&self, name: &str) -> bool {
+        match self.strategy {
+            SolveStrategy::Highest => false,
+            SolveStrategy::LowestVersion => true,
+            SolveStrategy::LowestVersionDirect => self.direct_dependencies.contains(name),
         }
     }
 }
@@ -348,34 +402,54 @@ impl<'a> DependencyProvider<SolverMatchSpec<'a>> for CondaDependencyProvider<'a>
     ) {
         let mut highest_version_spec = self.matchspec_to_highest_version.borrow_mut();
         solvables.sort_by(|&p1, &p2| {
-            conda_util::compare_candidates(p1, p2, solver, &mut highest_version_spec)
+            conda_util::compare_candidates(p1, p2, self, solver, &mut highest_version_spec)
         });
     }
 
     fn get_candidates(&self, name: NameId) -> Option<Candidates> {
-        self.records.get(&name).cloned()
+        if let Some(lazy) = &self.lazy {
+            self.load_lazy_records(lazy, name);
+        }
+        self.records.borrow().get(&name).cloned()
     }
 
     fn get_dependencies(&self, solvable: SolvableId) -> Dependencies {
         let SolverPackageRecord::Record(rec) = self.pool.resolve_solvable(solvable).inner() else {
-            return Dependencies::default();
+            return Dependencies::Known(KnownDependencies::default());
         };
 
         let mut parse_match_spec_cache = self.parse_match_spec_cache.borrow_mut();
-        let mut dependencies = Dependencies::default();
+        let mut dependencies = KnownDependencies::default();
         for depends in rec.package_record.depends.iter() {
-            let version_set_id =
-                parse_match_spec(&self.pool, depends, &mut parse_match_spec_cache).unwrap();
+            let version_set_id = parse_match_spec(
+                &self.pool,
+                depends,
+                &mut parse_match_spec_cache,
+                self.match_spec_cache.as_ref(),
+            )
+            .unwrap();
             dependencies.requirements.push(version_set_id);
         }
 
         for constrains in rec.package_record.constrains.iter() {
-            let version_set_id =
-                parse_match_spec(&self.pool, constrains, &mut parse_match_spec_cache).unwrap();
+            let version_set_id = parse_match_spec(
+                &self.pool,
+                constrains,
+                &mut parse_match_spec_cache,
+                self.match_spec_cache.as_ref(),
+            )
+            .unwrap();
             dependencies.constrains.push(version_set_id);
         }
 
-        dependencies
+        Dependencies::Known(dependencies)
+    }
+
+    fn should_cancel_with_value(&self) -> Option<Box<dyn Any>> {
+        self.stop_condition
+            .check()
+            .err()
+            .map(|err| Box::new(err) as Box<dyn Any>)
     }
 }
 
@@ -401,6 +475,72 @@ impl SolvableDisplay<SolverMatchSpec<'_>> for CondaSolvableDisplay {
 #[derive(Default)]
 pub struct Solver;
 
+impl Solver {
+    /// Solves the task, loading the records of a package from the [`RecordProvider`]s in
+    /// [`SolverTask::available_packages`] only when the solver first encounters the package.
+    ///
+    /// Compared to loading all the records that might be required up front, this avoids parsing
+    /// the records of packages that are never considered by the solver. The order of the providers
+    /// determines the priority of their channels.
+    pub fn solve_lazy<'p, P: RecordProvider + 'p, TAvailablePackagesIterator>(
+        &mut self,
+        task: SolverTask<TAvailablePackagesIterator>,
+    ) -> Result<Vec<RepoDataRecord>, SolveError>
+    where
+        TAvailablePackagesIterator: IntoIterator<Item = &'p P>,
+    {
+        let stop_condition = StopCondition::new(task.timeout, task.cancellation_token.clone());
+        stop_condition.check()?;
+
+        let loaded = LoadedRecords::default();
+        let sources: Vec<&dyn RecordProvider> = task
+            .available_packages
+            .into_iter()
+            .map(|source| source as &dyn RecordProvider)
+            .collect();
+
+        // The channels are ranked by the order of the sources, not by the order in which the
+        // records of their packages happen to be loaded.
+        let channels = sources
+            .iter()
+            .map(RecordProvider::channel)
+            .collect::<Vec<_>>();
+
+        // Construct a provider that loads the records of packages when they are requested.
+        let mut provider = CondaDependencyProvider::from_solver_task(
+            std::iter::empty(),
+            &task.locked_packages,
+            &task.pinned_packages,
+            &task.virtual_packages,
+            &task.specs,
+            task.channel_priority,
+            task.strategy,
+            task.exclude_newer,
+            stop_condition,
+        );
+        provider.match_spec_cache = task.match_spec_cache;
+        provider
+            .channel_priorities
+            .get_mut()
+            .rank_channels(channels.iter().map(String::as_str));
+        provider.lazy = Some(LazyRecords {
+            sources,
+            loaded: &loaded,
+            loaded_names: RefCell::default(),
+        });
+
+        let result = solve_with_provider(provider, &task.specs);
+
+        // If loading records failed the solver might have been working with incomplete
+        // information, so that error takes precedence over the result of the solve.
+        if let Some(err) = loaded.error.into_inner() {
+            return Err(SolveError::LoadRecordsError(err));
+        }
+
+        result
+    }
+}
+
 impl super::SolverImpl for Solver {
     type RepoData<'a> = RepoData<'a>;
 
@@ -413,45 +553,207 @@ impl super::SolverImpl for Solver {
         &mut self,
         task: SolverTask<TAvailablePackagesIterator>,
     ) -> Result<Vec<RepoDataRecord>, SolveError> {
+        let stop_condition = StopCondition::new(task.timeout, task.cancellation_token.clone());
+        stop_condition.check()?;
+
         // Construct a provider that can serve the data.
-        let provider = CondaDependencyProvider::from_solver_task(
+        let mut provider = CondaDependencyProvider::from_solver_task(
             task.available_packages.into_iter().map(|r| r.into()),
             &task.locked_packages,
             &task.pinned_packages,
             &task.virtual_packages,
             task.specs.clone().as_ref(),
+            task.channel_priority,
+            task.strategy,
+            task.exclude_newer,
+            stop_condition,
         );
+        provider.match_spec_cache = task.match_spec_cache;
 
-        // Construct the requirements that the solver needs to satisfy.
-        let root_requirements = task
-            .specs
-            .iter()
-            .map(|spec| {
-                let (name, spec) = spec.clone().into_nameless();
-                let name = name.expect("cannot use matchspec without a name");
-                let name_id = provider.pool.intern_package_name(name.as_normalized());
-                provider.pool.intern_version_set(name_id, spec.into())
-            })
-            .collect();
+        solve_with_provider(provider, &task.specs)
+    }
+}
 
-        // Construct a solver and solve the problems in the queue
-        let mut solver = LibSolvRsSolver::new(provider);
-        let solvables = solver.solve(root_requirements).map_err(|problem| {
-            SolveError::Unsolvable(vec![problem
-                .display_user_friendly(&solver, &CondaSolvableDisplay)
-                .to_string()])
+/// Solves the specs with the records served by the provider.
+fn solve_with_provider(
+    provider: CondaDependencyProvider<'_>,
+    specs: &[MatchSpec],
+) -> Result<Vec<RepoDataRecord>, SolveError> {
+    // Construct the requirements that the solver needs to satisfy.
+    let root_requirements: Vec<_> = specs
+        .iter()
+        .map(|spec| {
+            let (name, spec) = spec.clone().into_nameless();
+            let name = name.expect("cannot use matchspec without a name");
+            let name_id = provider.pool.intern_package_name(name.as_normalized());
+            provider.pool.intern_version_set(name_id, spec.into())
+        })
+        .collect();
+
+    // Keep the candidates around so we can explain a conflict if there is one.
+    let candidates = Rc::clone(&provider.records);
+
+    // Construct a solver and solve the problems in the queue
+    let mut solver = LibSolvRsSolver::new(provider);
+    let solvables = solver
+        .solve(root_requirements.clone())
+        .map_err(|err| match err {
+            UnsolvableOrCancelled::Unsolvable(problem) => {
+                let message = problem
+                    .display_user_friendly(&solver, &CondaSolvableDisplay)
+                    .to_string();
+                SolveError::Unsolvable(conflict::conflict_report(
+                    solver.pool(),
+                    &candidates.borrow(),
+                    &root_requirements,
+                    vec![message],
+                ))
+            }
+            UnsolvableOrCancelled::Cancelled(reason) => *reason
+                .downcast::<SolveError>()
+                .expect("the solve can only be cancelled with a SolveError"),
         })?;
 
-        // Get the resulting packages from the solver.
-        let required_records = solvables
-            .into_iter()
-            .filter_map(|id| match solver.pool().resolve_solvable(id).inner() {
-                SolverPackageRecord::Record(rec) => Some(rec.deref().clone()),
-                SolverPackageRecord::VirtualPackage(_) => None,
-            })
-            .collect();
+    // Get the resulting packages from the solver.
+    let required_records = solvables
+        .into_iter()
+        .filter_map(|id| match solver.pool().resolve_solvable(id).inner() {
+            SolverPackageRecord::Record(rec) => Some(rec.deref().clone()),
+            SolverPackageRecord::VirtualPackage(_) => None,
+        })
+        .collect();
+
+    Ok(required_records)
+}
+
+/// Adds the records of a single channel and platform combination to the candidates of their
+/// packages. Records that cannot be selected are added to the excluded candidates together with
+/// the reason why.
+fn add_repodata_records<'a>(
+    pool: &Pool<SolverMatchSpec<'a>, String>,
+    records: &mut HashMap<NameId, Candidates>,
+    repo_data_records: Vec<&'a RepoDataRecord>,
+    channel_specific_specs: &[MatchSpec],
+    exclude_newer: Option<ExcludeNewer>,
+    channel_priorities: &ChannelPriorities<'a>,
+) {
+    // Iterate over all records and dedup records that refer to the same package data but with
+    // different archive types. This can happen if you have two variants of the same package but
+    // with different extensions. We prefer `.conda` packages over `.tar.bz`.
+    //
+    // Its important to insert the records in the same same order as how they were presented to this
+    // function to ensure that each solve is deterministic. Iterating over HashMaps is not
+    // deterministic at runtime so instead we store the values in a Vec as we iterate over the
+    // records. This guarentees that the order of records remains the same over runs.
+    let mut ordered_repodata = Vec::with_capacity(repo_data_records.len());
+    let mut package_to_type: HashMap<&str, (ArchiveType, usize)> =
+        HashMap::with_capacity(repo_data_records.len());
+
+    for record in repo_data_records {
+        let (file_name, archive_type) = ArchiveType::split_str(&record.file_name)
+            .unwrap_or((&record.file_name, ArchiveType::TarBz2));
+        match package_to_type.get_mut(file_name) {
+            None => {
+                let idx = ordered_repodata.len();
+                ordered_repodata.push(record);
+                package_to_type.insert(file_name, (archive_type, idx));
+            }
+            Some((prev_archive_type, idx)) => match archive_type.cmp(prev_archive_type) {
+                Ordering::Greater => {
+                    // A previous package has a worse package "type", we'll use the current record
+                    // instead.
+                    *prev_archive_type = archive_type;
+                    ordered_repodata[*idx] = record;
+                }
+                Ordering::Less => {
+                    // A previous package that we already stored is actually a package of a better
+                    // "type" so we'll just use that instead (.conda > .tar.bz)
+                }
+                Ordering::Equal => {
+                    if record != ordered_repodata[*idx] {
+                        unreachable!(
+                            "found duplicate record with different values for {}",
+                            &record.file_name
+                        );
+                    }
+                }
+            },
+        }
+    }
+
+    for record in ordered_repodata {
+        let package_name = pool.intern_package_name(record.package_record.name.as_normalized());
+        let solvable_id = pool.intern_solvable(package_name, SolverPackageRecord::Record(record));
+        let candidates = records.entry(package_name).or_default();
+        candidates.candidates.push(solvable_id);
+
+        // Add to excluded when the package was published after the cutoff.
+        if let Some(exclude_newer) = exclude_newer {
+            if exclude_newer.excludes(record) {
+                tracing::debug!(
+                    "Ignoring '{}' from '{}' because it is newer than {}.",
+                    &record.package_record.name.as_normalized(),
+                    &record.channel,
+                    exclude_newer.timestamp
+                );
+                candidates.excluded.push((
+                    solvable_id,
+                    pool.intern_string(exclude_newer.reason(record)),
+                ));
+                continue;
+            }
+        }
+
+        // Add to excluded when package is not in the specified channel.
+        if let Some(spec) = channel_specific_specs.iter().find(|spec| {
+            spec.name
+                .as_ref()
+                .expect("expecting a name")
+                .as_normalized()
+                == record.package_record.name.as_normalized()
+        }) {
+            // Check if the spec has a channel, and compare it to the repodata channel
+            if let Some(spec_channel) = &spec.channel {
+                if record.channel != spec_channel.base_url.to_string() {
+                    tracing::debug!(
+                        "Ignoring {} from {} because it was not requested from that channel.",
+                        &record.package_record.name.as_normalized(),
+                        &record.channel
+                    );
+                    // Add record to the excluded with reason of being in the non requested channel.
+                    let message = format!(
+                        "candidate not in requested channel: '{}'",
+                        spec_channel
+                            .name
+                            .clone()
+                            .unwrap_or(spec_channel.base_url.to_string())
+                    );
+                    candidates
+                        .excluded
+                        .push((solvable_id, pool.intern_string(message)));
+                    continue;
+                }
+            }
+        }
 
-        Ok(required_records)
+        // Enforce strict channel priority
+        if channel_priorities.is_excluded(record) {
+            tracing::debug!(
+                "Ignoring '{}' from '{}' because of strict channel priority.",
+                &record.package_record.name.as_normalized(),
+                &record.channel
+            );
+            candidates.excluded.push((
+                solvable_id,
+                pool.intern_string(format!(
+                    "due to strict channel priority not using this option from: '{}'",
+                    &record.channel
+                )),
+            ));
+            continue;
+        }
+
+        candidates.hint_dependencies_available.push(solvable_id);
     }
 }
 
@@ -459,11 +761,15 @@ fn parse_match_spec<'a>(
     pool: &Pool<SolverMatchSpec<'a>>,
     spec_str: &'a str,
     parse_match_spec_cache: &mut HashMap<&'a str, VersionSetId>,
+    shared_match_spec_cache: Option<&MatchSpecCache>,
 ) -> Result<VersionSetId, ParseMatchSpecError> {
     if let Some(spec_id) = parse_match_spec_cache.get(spec_str) {
         Ok(*spec_id)
     } else {
-        let match_spec = MatchSpec::from_str(spec_str)?;
+        let match_spec = match shared_match_spec_cache {
+            Some(cache) => cache.parse(spec_str)?,
+            None => MatchSpec::from_str(spec_str)?,
+        };
         let (name, spec) = match_spec.into_nameless();
         let dependency_name = pool.intern_package_name(
             name.as_ref()
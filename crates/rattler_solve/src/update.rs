@@ -0,0 +1,142 @@
+//! Updates a selected set of packages while keeping the rest of an environment as it is.
+
+use crate::{IntoRepoData, SolveError, SolverImpl, SolverTask};
+use rattler_conda_types::version_spec::EqualityOperator;
+use rattler_conda_types::{MatchSpec, PackageName, RepoDataRecord, StringMatcher, VersionSpec};
+use std::collections::HashMap;
+
+/// The result of [`SolverTask::solve_update`].
+#[derive(Debug, Clone)]
+pub struct UpdateSolution {
+    /// The records that should be present in the environment
+    pub records: Vec<RepoDataRecord>,
+
+    /// The locked packages outside of the update set that had to change to be able to update the
+    /// packages in the update set.
+    pub moved_packages: Vec<MovedPackage>,
+}
+
+/// A locked package that is not part of the update set but that had to change anyway.
+#[derive(Debug, Clone)]
+pub struct MovedPackage {
+    /// The record that was locked
+    pub locked: RepoDataRecord,
+
+    /// The record that replaces the locked record, or `None` if the package is removed.
+    pub solved: Option<RepoDataRecord>,
+}
+
+impl<TAvailablePackagesIterator: IntoIterator + Clone> SolverTask<TAvailablePackagesIterator> {
+    /// Updates the packages in `update` to the best version that satisfies the specs, while all
+    /// the other [`SolverTask::locked_packages`] are kept at their current version unless
+    /// changing them is required to update the packages in `update`. This mirrors the behavior of
+    /// `conda update <package>`.
+    ///
+    /// First the best versions of the packages in `update` are determined by solving without any
+    /// locked packages. Then the other locked packages are pinned, and if the update cannot be
+    /// solved that way they are only preferred and the ones that change are reported in
+    /// [`UpdateSolution::moved_packages`].
+    ///
+    /// This solves the task up to three times, [`SolverTask::timeout`] applies to each of the
+    /// solves.
+    pub fn solve_update<'a, S: SolverImpl>(
+        self,
+        solver: &mut S,
+        update: &[PackageName],
+    ) -> Result<UpdateSolution, SolveError>
+    where
+        TAvailablePackagesIterator::Item: IntoRepoData<'a, S::RepoData<'a>>,
+    {
+        let frozen = self
+            .locked_packages
+            .iter()
+            .filter(|record| !update.contains(&record.package_record.name))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        // Determine the best versions of the packages that should be updated.
+        let unlocked = solver.solve(SolverTask {
+            locked_packages: Vec::new(),
+            ..self.clone()
+        })?;
+
+        // Require exactly those versions in the following solves.
+        let mut specs = self.specs.clone();
+        for record in unlocked
+            .iter()
+            .filter(|record| update.contains(&record.package_record.name))
+        {
+            specs.push(MatchSpec {
+                name: Some(record.package_record.name.clone()),
+                version: Some(VersionSpec::Exact(
+                    EqualityOperator::Equals,
+                    record.package_record.version.version().clone(),
+                )),
+                build: Some(StringMatcher::Exact(record.package_record.build.clone())),
+                ..MatchSpec::default()
+            });
+        }
+        tracing::debug!(
+            "updating {} package(s), keeping {} package(s)",
+            update.len(),
+            frozen.len()
+        );
+
+        // Try to keep all the other packages exactly as they are.
+        let pinned_packages = self
+            .pinned_packages
+            .iter()
+            .cloned()
+            .chain(frozen.iter().cloned())
+            .collect();
+        let pinned = solver.solve(SolverTask {
+            locked_packages: Vec::new(),
+            pinned_packages,
+            specs: specs.clone(),
+            ..self.clone()
+        });
+
+        let records = match pinned {
+            Ok(records) => records,
+            Err(SolveError::Unsolvable(_)) => {
+                // Some of the other packages have to change, prefer keeping them as they are.
+                solver.solve(SolverTask {
+                    locked_packages: frozen.clone(),
+                    specs,
+                    ..self
+                })?
+            }
+            Err(err) => return Err(err),
+        };
+
+        let solved_by_name = records
+            .iter()
+            .map(|record| (&record.package_record.name, record))
+            .collect::<HashMap<_, _>>();
+        let moved_packages = frozen
+            .into_iter()
+            .filter_map(|locked| {
+                let solved = solved_by_name.get(&locked.package_record.name).copied();
+                match solved {
+                    Some(solved) if is_same_package(&locked, solved) => None,
+                    _ => Some(MovedPackage {
+                        solved: solved.cloned(),
+                        locked,
+                    }),
+                }
+            })
+            .collect();
+
+        Ok(UpdateSolution {
+            records,
+            moved_packages,
+        })
+    }
+}
+
+/// Returns true if both records refer to the same package.
+fn is_same_package(a: &RepoDataRecord, b: &RepoDataRecord) -> bool {
+    a.package_record.version == b.package_record.version
+        && a.package_record.build == b.package_record.build
+        && a.package_record.build_number == b.package_record.build_number
+}
@@ -1,12 +1,20 @@
+use chrono::{TimeZone, Utc};
+use itertools::Itertools;
 use once_cell::sync::Lazy;
 use rattler_conda_types::{
-    Channel, ChannelConfig, GenericVirtualPackage, MatchSpec, NoArchType, PackageRecord, RepoData,
-    RepoDataRecord, Version,
+    Channel, ChannelConfig, GenericVirtualPackage, MatchSpec, NoArchType, PackageRecord, Platform,
+    RepoData, RepoDataRecord, Version,
 };
 use rattler_repodata_gateway::sparse::SparseRepoData;
-use rattler_solve::{SolveError, SolverImpl, SolverTask};
+use rattler_solve::{
+    problem::{ConflictEdgeKind, ConflictNode},
+    solve_multi_platform, CancellationToken, ChannelPriority, ExcludeNewer, MissingTimestamp,
+    MultiPlatformSolverTask, PlatformPackages, PlatformSolveError, SolveError, SolveStrategy,
+    SolverImpl, SolverTask, UpdateSolution,
+};
+use std::collections::HashMap;
 use std::str::FromStr;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use url::Url;
 
 fn conda_json_path() -> String {
@@ -110,23 +118,24 @@ fn installed_package(
 }
 
 fn solve_real_world<T: SolverImpl + Default>(specs: Vec<&str>) -> Vec<String> {
+    solve_real_world_with_strategy::<T>(specs, SolveStrategy::default())
+}
+
+fn solve_real_world_with_strategy<T: SolverImpl + Default>(
+    specs: Vec<&str>,
+    strategy: SolveStrategy,
+) -> Vec<String> {
     let specs = specs
         .iter()
         .map(|s| MatchSpec::from_str(s).unwrap())
         .collect::<Vec<_>>();
 
-    let sparse_repo_datas = read_real_world_repo_data();
-
-    let names = specs.iter().filter_map(|s| s.name.as_ref().cloned());
-    let available_packages =
-        SparseRepoData::load_records_recursive(sparse_repo_datas, names, None).unwrap();
+    let available_packages = solve_real_world_records(&specs);
 
     let solver_task = SolverTask {
-        available_packages: &available_packages,
         specs: specs.clone(),
-        locked_packages: Vec::default(),
-        pinned_packages: Vec::default(),
-        virtual_packages: Vec::default(),
+        strategy,
+        ..SolverTask::from_iter(&available_packages)
     };
 
     let pkgs1 = match T::default().solve(solver_task) {
@@ -156,6 +165,12 @@ fn solve_real_world<T: SolverImpl + Default>(specs: Vec<&str>) -> Vec<String> {
     extract_pkgs(pkgs1)
 }
 
+/// Loads the records from the real world repodata that might be required to solve the specs.
+fn solve_real_world_records(specs: &[MatchSpec]) -> Vec<Vec<RepoDataRecord>> {
+    let names = specs.iter().filter_map(|s| s.name.as_ref().cloned());
+    SparseRepoData::load_records_recursive(read_real_world_repo_data(), names, None).unwrap()
+}
+
 fn read_real_world_repo_data() -> &'static Vec<SparseRepoData> {
     static REPO_DATA: Lazy<Vec<SparseRepoData>> = Lazy::new(|| {
         let json_file = conda_json_path();
@@ -276,10 +291,86 @@ macro_rules! solver_backend_tests {
                 &["asdfasdf", "foo<4"],
             );
 
-            assert!(result.is_err());
+            let report = match result {
+                Err(SolveError::Unsolvable(report)) => report,
+                _ => panic!("expected the solve to fail"),
+            };
+            insta::assert_debug_snapshot!(report.messages);
+            assert_eq!(
+                report.missing().count(),
+                1,
+                "expected a single missing spec"
+            );
+        }
 
-            let err = result.err().unwrap();
-            insta::assert_debug_snapshot!(err);
+        #[test]
+        fn test_solve_with_error_report() {
+            let result = solve::<$T>(
+                dummy_channel_json_path(),
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                &["foobar >=2", "bors >= 2"],
+            );
+
+            let report = match result {
+                Err(SolveError::Unsolvable(report)) => report,
+                _ => panic!("expected the solve to fail"),
+            };
+
+            // Both requested specs are part of the conflict
+            let requested = report
+                .requested()
+                .map(|idx| report.nodes[idx].to_string())
+                .collect::<Vec<_>>();
+            assert_eq!(requested.len(), 2, "requested specs: {requested:?}");
+            assert!(requested.iter().any(|spec| spec.starts_with("foobar")));
+            assert!(requested.iter().any(|spec| spec.starts_with("bors")));
+
+            // The conflict is between the different variants of bors
+            let conflicting_names = report
+                .edges
+                .iter()
+                .filter(|edge| matches!(edge.kind, ConflictEdgeKind::Conflicts { .. }))
+                .flat_map(|edge| [edge.from, edge.to])
+                .filter_map(|idx| match &report.nodes[idx] {
+                    ConflictNode::Candidate(candidate) => Some(candidate.name.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            assert!(!conflicting_names.is_empty());
+            assert!(conflicting_names.iter().all(|&name| name == "bors"));
+
+            // The report can be serialized for machine consumption
+            let json = serde_json::to_string(&report).unwrap();
+            let parsed: rattler_solve::ConflictReport = serde_json::from_str(&json).unwrap();
+            assert_eq!(parsed, report);
+        }
+
+        #[test]
+        fn test_solve_cancelled() {
+            let cancellation_token = CancellationToken::new();
+            cancellation_token.cancel();
+
+            let result =
+                solve_with_stop_condition::<$T>(&["foo<4"], None, Some(cancellation_token));
+            assert!(matches!(result, Err(SolveError::Cancelled)));
+        }
+
+        #[test]
+        fn test_solve_timeout() {
+            let result = solve_with_stop_condition::<$T>(&["foo<4"], Some(Duration::ZERO), None);
+            assert!(matches!(result, Err(SolveError::Timeout)));
+        }
+
+        #[test]
+        fn test_solve_within_timeout() {
+            let result = solve_with_stop_condition::<$T>(
+                &["foo<4"],
+                Some(Duration::from_secs(60)),
+                Some(CancellationToken::new()),
+            );
+            assert_eq!(result.unwrap().len(), 1);
         }
 
         #[test]
@@ -484,14 +575,248 @@ macro_rules! solver_backend_tests {
             // Should be no packages!
             assert_eq!(0, pkgs.len());
         }
+
+        #[test]
+        fn test_channel_priority_strict() {
+            let conda_forge = read_conda_forge_sparse_repo_data();
+            let pytorch = read_pytorch_sparse_repo_data();
+
+            let record = solve_to_get_record_of_spec::<$T>(
+                "pytorch-cpu",
+                vec![conda_forge, pytorch],
+                ChannelPriority::Strict,
+            );
+            assert_eq!(record.channel, "https://conda.anaconda.org/conda-forge/");
+
+            let record = solve_to_get_record_of_spec::<$T>(
+                "pytorch-cpu",
+                vec![pytorch, conda_forge],
+                ChannelPriority::Strict,
+            );
+            assert_eq!(record.channel, "https://conda.anaconda.org/pytorch/");
+
+            // `foo` is available from the higher priority channel, so the newer version from the
+            // lower priority channel is never used.
+            let result =
+                solve_channel_priority_foo::<$T>(&["foo"], ChannelPriority::Strict).unwrap();
+            assert_eq!(
+                channel_and_version(&result, "foo"),
+                (CHANNEL_A.to_string(), "1.0".to_string())
+            );
+
+            let result = solve_channel_priority_foo::<$T>(&["foo >=2"], ChannelPriority::Strict);
+            assert!(matches!(result, Err(SolveError::Unsolvable(_))));
+
+            // `bar` is only available from the lower priority channel.
+            let result =
+                solve_channel_priority_foo::<$T>(&["bar"], ChannelPriority::Strict).unwrap();
+            assert_eq!(
+                channel_and_version(&result, "bar"),
+                (CHANNEL_B.to_string(), "1.0".to_string())
+            );
+        }
+
+        #[test]
+        fn test_channel_priority_flexible() {
+            // The higher priority channel is preferred over a higher version.
+            let result =
+                solve_channel_priority_foo::<$T>(&["foo"], ChannelPriority::Flexible).unwrap();
+            assert_eq!(
+                channel_and_version(&result, "foo"),
+                (CHANNEL_A.to_string(), "1.0".to_string())
+            );
+
+            // The lower priority channel is used when the higher priority channel cannot satisfy
+            // the spec.
+            let result =
+                solve_channel_priority_foo::<$T>(&["foo >=2"], ChannelPriority::Flexible).unwrap();
+            assert_eq!(
+                channel_and_version(&result, "foo"),
+                (CHANNEL_B.to_string(), "2.0".to_string())
+            );
+
+            let result =
+                solve_channel_priority_foo::<$T>(&["bar"], ChannelPriority::Flexible).unwrap();
+            assert_eq!(
+                channel_and_version(&result, "bar"),
+                (CHANNEL_B.to_string(), "1.0".to_string())
+            );
+        }
+
+        #[test]
+        fn test_channel_priority_disabled() {
+            let conda_forge = read_conda_forge_sparse_repo_data();
+            let pytorch = read_pytorch_sparse_repo_data();
+
+            // The order of the channels should not influence the selected version
+            let first = solve_to_get_record_of_spec::<$T>(
+                "pytorch-cpu",
+                vec![conda_forge, pytorch],
+                ChannelPriority::Disabled,
+            );
+            let second = solve_to_get_record_of_spec::<$T>(
+                "pytorch-cpu",
+                vec![pytorch, conda_forge],
+                ChannelPriority::Disabled,
+            );
+            assert_eq!(first.package_record.version, second.package_record.version);
+
+            // The highest version is selected, even though it comes from the lower priority
+            // channel.
+            let result =
+                solve_channel_priority_foo::<$T>(&["foo"], ChannelPriority::Disabled).unwrap();
+            assert_eq!(
+                channel_and_version(&result, "foo"),
+                (CHANNEL_B.to_string(), "2.0".to_string())
+            );
+
+            let result =
+                solve_channel_priority_foo::<$T>(&["foo <2"], ChannelPriority::Disabled).unwrap();
+            assert_eq!(
+                channel_and_version(&result, "foo"),
+                (CHANNEL_A.to_string(), "1.0".to_string())
+            );
+
+            let result =
+                solve_channel_priority_foo::<$T>(&["bar"], ChannelPriority::Disabled).unwrap();
+            assert_eq!(
+                channel_and_version(&result, "bar"),
+                (CHANNEL_B.to_string(), "1.0".to_string())
+            );
+        }
+
+        #[test]
+        fn test_solve_lowest_version() {
+            let lowest_foo = read_repodata(&dummy_channel_json_path())
+                .into_iter()
+                .filter(|record| record.package_record.name.as_normalized() == "foo")
+                .map(|record| record.package_record.version)
+                .min()
+                .unwrap();
+
+            let pkgs = solve_with_strategy::<$T>(&["foo"], SolveStrategy::LowestVersion).unwrap();
+            let foo = pkgs
+                .iter()
+                .find(|record| record.package_record.name.as_normalized() == "foo")
+                .unwrap();
+            assert_eq!(foo.package_record.version, lowest_foo);
+
+            let pkgs = solve_with_strategy::<$T>(&["foo"], SolveStrategy::Highest).unwrap();
+            let foo = pkgs
+                .iter()
+                .find(|record| record.package_record.name.as_normalized() == "foo")
+                .unwrap();
+            assert_eq!("4.0.2", foo.package_record.version.to_string());
+        }
+
+        #[test]
+        fn test_solve_lowest_version_direct() {
+            let pkgs = solve_real_world_with_strategy::<$T>(
+                vec!["xtensor"],
+                SolveStrategy::LowestVersionDirect,
+            );
+            let xtensor = pkgs.iter().find(|pkg| pkg.starts_with("xtensor ")).unwrap();
+
+            // The dependencies of the direct specs should be solved as if the lowest version of
+            // the direct spec was requested explicitly.
+            let (_, version, build) = xtensor.split(' ').collect_tuple().unwrap();
+            let expected = solve_real_world::<$T>(vec![&format!("xtensor =={version} {build}")]);
+            assert_eq!(pkgs, expected);
+        }
+
+        #[test]
+        fn test_solve_exclude_newer() {
+            let cutoff = Utc.timestamp_opt(1_500, 0).unwrap();
+
+            // Records without a timestamp are included by default
+            let pkgs = solve_with_exclude_newer::<$T>(ExcludeNewer::new(cutoff)).unwrap();
+            assert_eq!(pkgs.len(), 1);
+            assert_eq!("3.0", pkgs[0].package_record.version.to_string());
+
+            let pkgs = solve_with_exclude_newer::<$T>(
+                ExcludeNewer::new(cutoff).with_missing_timestamp(MissingTimestamp::Exclude),
+            )
+            .unwrap();
+            assert_eq!(pkgs.len(), 1);
+            assert_eq!("1.0", pkgs[0].package_record.version.to_string());
+        }
+
+        #[test]
+        fn test_solve_multi_platform() {
+            let solutions = solve_multi_platform_foo::<$T>(true).unwrap();
+            let packages = |platform| {
+                solutions[&platform]
+                    .iter()
+                    .map(|record| {
+                        format!(
+                            "{}/{}",
+                            record.package_record.subdir,
+                            record.package_record.name.as_normalized()
+                        )
+                    })
+                    .sorted()
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(packages(Platform::Linux64), ["linux-64/foo", "noarch/bar"]);
+            assert_eq!(packages(Platform::Win64), ["noarch/bar", "win-64/foo"]);
+
+            // Without the `__win` virtual package there is no solution for windows
+            let err = solve_multi_platform_foo::<$T>(false).unwrap_err();
+            assert_eq!(err.platform, Platform::Win64);
+            assert!(matches!(err.error, SolveError::Unsolvable(_)));
+        }
+
+        #[test]
+        fn test_solve_update() {
+            let packages = |solution: &UpdateSolution| {
+                solution
+                    .records
+                    .iter()
+                    .map(|record| {
+                        format!(
+                            "{} {}",
+                            record.package_record.name.as_normalized(),
+                            record.package_record.version
+                        )
+                    })
+                    .sorted()
+                    .collect::<Vec<_>>()
+            };
+
+            // `foo` can be updated without changing `bar`
+            let solution = solve_update_foo::<$T>(&[]).unwrap();
+            assert_eq!(packages(&solution), ["bar 1.0", "foo 2.0"]);
+            assert!(solution.moved_packages.is_empty());
+
+            // The new version of `foo` requires a newer version of `bar`
+            let solution = solve_update_foo::<$T>(&["bar >=2"]).unwrap();
+            assert_eq!(packages(&solution), ["bar 2.0", "foo 2.0"]);
+            assert_eq!(solution.moved_packages.len(), 1);
+            let moved = &solution.moved_packages[0];
+            assert_eq!(moved.locked.package_record.name.as_normalized(), "bar");
+            assert_eq!(moved.locked.package_record.version.to_string(), "1.0");
+            assert_eq!(
+                moved
+                    .solved
+                    .as_ref()
+                    .map(|record| record.package_record.version.to_string()),
+                Some(String::from("2.0"))
+            );
+        }
     };
 }
 
 #[cfg(feature = "libsolv_c")]
 mod libsolv_c {
     use super::{
-        dummy_channel_json_path, installed_package, solve, solve_real_world, FromStr,
-        GenericVirtualPackage, SolveError, Version,
+        channel_and_version, dummy_channel_json_path, installed_package,
+        read_conda_forge_sparse_repo_data, read_pytorch_sparse_repo_data, read_repodata, solve,
+        solve_channel_priority_foo, solve_multi_platform_foo, solve_real_world,
+        solve_real_world_with_strategy, solve_to_get_record_of_spec, solve_update_foo,
+        solve_with_exclude_newer, solve_with_stop_condition, solve_with_strategy,
+        CancellationToken, ChannelPriority, ConflictEdgeKind, ConflictNode, Duration, ExcludeNewer,
+        FromStr, GenericVirtualPackage, Itertools, MissingTimestamp, Platform, SolveError,
+        SolveStrategy, TimeZone, UpdateSolution, Utc, Version, CHANNEL_A, CHANNEL_B,
     };
 
     solver_backend_tests!(rattler_solve::libsolv_c::Solver);
@@ -499,7 +824,6 @@ mod libsolv_c {
     #[test]
     #[cfg(target_family = "unix")]
     fn test_solve_with_cached_solv_file_install_new() {
-        use super::read_repodata;
         use rattler_conda_types::{Channel, ChannelConfig, MatchSpec};
         use rattler_solve::{SolverImpl, SolverTask};
 
@@ -522,11 +846,8 @@ mod libsolv_c {
 
         let pkgs = rattler_solve::libsolv_c::Solver
             .solve(SolverTask {
-                locked_packages: Vec::new(),
-                virtual_packages: Vec::new(),
-                available_packages: [libsolv_repodata],
                 specs,
-                pinned_packages: Vec::new(),
+                ..SolverTask::from_iter([libsolv_repodata])
             })
             .unwrap();
 
@@ -570,9 +891,19 @@ mod libsolv_c {
 #[cfg(feature = "resolvo")]
 mod resolvo {
     use super::{
-        dummy_channel_json_path, installed_package, solve, solve_real_world, FromStr,
-        GenericVirtualPackage, SolveError, Version,
+        channel_and_version, dummy_channel_json_path, installed_package,
+        read_conda_forge_sparse_repo_data, read_pytorch_sparse_repo_data, read_repodata, solve,
+        solve_channel_priority_foo, solve_multi_platform_foo, solve_real_world,
+        solve_real_world_with_strategy, solve_to_get_record_of_spec, solve_update_foo,
+        solve_with_exclude_newer, solve_with_stop_condition, solve_with_strategy,
+        CancellationToken, ChannelPriority, ConflictEdgeKind, ConflictNode, Duration, ExcludeNewer,
+        FromStr, GenericVirtualPackage, Itertools, MatchSpec, MissingTimestamp, Platform,
+        RepoDataRecord, SolveError, SolveStrategy, SolverTask, TimeZone, UpdateSolution, Utc,
+        Version, CHANNEL_A, CHANNEL_B,
     };
+    use rattler_conda_types::PackageName;
+    use rattler_solve::RecordProvider;
+    use std::io;
 
     solver_backend_tests!(rattler_solve::resolvo::Solver);
 
@@ -596,6 +927,148 @@ mod resolvo {
         // We expect an error here. `bors` is pinnend to 1, but we try to install `>=2`.
         insta::assert_display_snapshot!(result.unwrap_err());
     }
+
+    #[cfg(feature = "sparse")]
+    #[test]
+    fn test_solve_lazy() {
+        use super::{read_real_world_repo_data, solve_real_world_records, SolverImpl};
+
+        fn task<T: IntoIterator>(
+            available_packages: T,
+            specs: &[MatchSpec],
+        ) -> SolverTask<Vec<T::Item>> {
+            SolverTask {
+                specs: specs.to_vec(),
+                ..SolverTask::from_iter(available_packages)
+            }
+        }
+
+        let specs = [MatchSpec::from_str("tensorflow").unwrap()];
+        let lazy = rattler_solve::resolvo::Solver
+            .solve_lazy(task(read_real_world_repo_data(), &specs))
+            .unwrap();
+        let eager = rattler_solve::resolvo::Solver
+            .solve(task(&solve_real_world_records(&specs), &specs))
+            .unwrap();
+
+        let file_names = |records: Vec<RepoDataRecord>| {
+            records
+                .into_iter()
+                .map(|record| record.file_name)
+                .sorted()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(file_names(lazy), file_names(eager));
+    }
+
+    #[test]
+    fn test_solve_lazy_load_error() {
+        struct FailingProvider;
+
+        impl RecordProvider for FailingProvider {
+            fn channel(&self) -> String {
+                CHANNEL_A.to_string()
+            }
+
+            fn load_records(&self, _package_name: &PackageName) -> io::Result<Vec<RepoDataRecord>> {
+                Err(io::Error::new(io::ErrorKind::Other, "failed to load"))
+            }
+        }
+
+        let result = rattler_solve::resolvo::Solver.solve_lazy(SolverTask {
+            specs: vec![MatchSpec::from_str("foo").unwrap()],
+            ..SolverTask::from_iter([&FailingProvider])
+        });
+
+        assert!(matches!(result, Err(SolveError::LoadRecordsError(_))));
+    }
+
+    #[test]
+    fn test_solve_lazy_interleaved_channels() {
+        use super::SolverImpl;
+
+        struct InMemoryProvider {
+            channel: &'static str,
+            records: Vec<RepoDataRecord>,
+        }
+
+        impl RecordProvider for InMemoryProvider {
+            fn channel(&self) -> String {
+                self.channel.to_string()
+            }
+
+            fn load_records(&self, package_name: &PackageName) -> io::Result<Vec<RepoDataRecord>> {
+                Ok(self
+                    .records
+                    .iter()
+                    .filter(|record| &record.package_record.name == package_name)
+                    .cloned()
+                    .collect())
+            }
+        }
+
+        let record = |channel: &str, subdir: &str, name: &str, version: &str| {
+            let mut record = installed_package(channel, subdir, name, version, "0", 0);
+            record.file_name = format!("{name}-{version}-0.tar.bz2");
+            record
+        };
+
+        // `bar` is only available from the second source and from the noarch subdir of the
+        // channel of the first source, so that channel is not known yet when `bar` is loaded.
+        let sources = [
+            InMemoryProvider {
+                channel: CHANNEL_A,
+                records: vec![record(CHANNEL_A, "linux-64", "foo", "1.0")],
+            },
+            InMemoryProvider {
+                channel: CHANNEL_B,
+                records: vec![record(CHANNEL_B, "linux-64", "bar", "2.0")],
+            },
+            InMemoryProvider {
+                channel: CHANNEL_A,
+                records: vec![record(CHANNEL_A, "noarch", "bar", "1.0")],
+            },
+        ];
+
+        let specs = vec![MatchSpec::from_str("bar").unwrap()];
+        let lazy = rattler_solve::resolvo::Solver
+            .solve_lazy(SolverTask {
+                specs: specs.clone(),
+                ..SolverTask::from_iter(&sources)
+            })
+            .unwrap();
+        let eager = rattler_solve::resolvo::Solver
+            .solve(SolverTask {
+                specs,
+                ..sources.iter().map(|source| &source.records).collect()
+            })
+            .unwrap();
+
+        assert_eq!(
+            channel_and_version(&lazy, "bar"),
+            (CHANNEL_A.to_string(), "1.0".to_string())
+        );
+        assert_eq!(
+            channel_and_version(&lazy, "bar"),
+            channel_and_version(&eager, "bar")
+        );
+    }
+}
+
+#[cfg(feature = "pubgrub")]
+mod pubgrub {
+    use super::{
+        channel_and_version, dummy_channel_json_path, installed_package,
+        read_conda_forge_sparse_repo_data, read_pytorch_sparse_repo_data, read_repodata, solve,
+        solve_channel_priority_foo, solve_multi_platform_foo, solve_real_world,
+        solve_real_world_with_strategy, solve_to_get_record_of_spec, solve_update_foo,
+        solve_with_exclude_newer, solve_with_stop_condition, solve_with_strategy,
+        CancellationToken, ChannelPriority, ConflictEdgeKind, ConflictNode, Duration, ExcludeNewer,
+        FromStr, GenericVirtualPackage, Itertools, MissingTimestamp, Platform, SolveError,
+        SolveStrategy, TimeZone, UpdateSolution, Utc, Version, CHANNEL_A, CHANNEL_B,
+    };
+
+    solver_backend_tests!(rattler_solve::pubgrub::Solver);
 }
 
 fn solve<T: SolverImpl + Default>(
@@ -615,9 +1088,9 @@ fn solve<T: SolverImpl + Default>(
     let task = SolverTask {
         locked_packages: installed_packages,
         virtual_packages,
-        available_packages: [&repo_data],
         specs,
         pinned_packages,
+        ..SolverTask::from_iter([&repo_data])
     };
 
     let pkgs = T::default().solve(task)?;
@@ -629,6 +1102,213 @@ fn solve<T: SolverImpl + Default>(
     Ok(pkgs)
 }
 
+fn solve_with_stop_condition<T: SolverImpl + Default>(
+    match_specs: &[&str],
+    timeout: Option<Duration>,
+    cancellation_token: Option<CancellationToken>,
+) -> Result<Vec<RepoDataRecord>, SolveError> {
+    let repo_data = read_repodata(&dummy_channel_json_path());
+
+    let specs: Vec<_> = match_specs
+        .iter()
+        .map(|m| MatchSpec::from_str(m).unwrap())
+        .collect();
+
+    T::default().solve(SolverTask {
+        specs,
+        timeout,
+        cancellation_token,
+        ..SolverTask::from_iter([&repo_data])
+    })
+}
+
+fn solve_with_strategy<T: SolverImpl + Default>(
+    match_specs: &[&str],
+    strategy: SolveStrategy,
+) -> Result<Vec<RepoDataRecord>, SolveError> {
+    let repo_data = read_repodata(&dummy_channel_json_path());
+
+    let specs: Vec<_> = match_specs
+        .iter()
+        .map(|m| MatchSpec::from_str(m).unwrap())
+        .collect();
+
+    T::default().solve(SolverTask {
+        specs,
+        strategy,
+        ..SolverTask::from_iter([&repo_data])
+    })
+}
+
+fn solve_with_exclude_newer<T: SolverImpl + Default>(
+    exclude_newer: ExcludeNewer,
+) -> Result<Vec<RepoDataRecord>, SolveError> {
+    let foo = |version: &str, timestamp: Option<i64>| {
+        let mut record = installed_package(
+            "https://conda.anaconda.org/conda-forge/",
+            "linux-64",
+            "foo",
+            version,
+            "0",
+            0,
+        );
+        record.file_name = format!("foo-{version}-0.tar.bz2");
+        record.package_record.timestamp =
+            timestamp.map(|timestamp| Utc.timestamp_opt(timestamp, 0).unwrap());
+        record
+    };
+    let repo_data = vec![
+        foo("1.0", Some(1_000)),
+        foo("2.0", Some(2_000)),
+        foo("3.0", None),
+    ];
+
+    T::default().solve(SolverTask {
+        specs: vec![MatchSpec::from_str("foo").unwrap()],
+        exclude_newer: Some(exclude_newer),
+        ..SolverTask::from_iter([&repo_data])
+    })
+}
+
+/// Solves `foo` for linux-64 and win-64. `foo` depends on a platform specific virtual package and
+/// on the noarch package `bar`.
+fn solve_multi_platform_foo<T: SolverImpl + Default>(
+    with_win_virtual_package: bool,
+) -> Result<HashMap<Platform, Vec<RepoDataRecord>>, PlatformSolveError> {
+    let record = |subdir: &str, name: &str, depends: &[&str]| {
+        let mut record = installed_package(
+            "https://conda.anaconda.org/conda-forge/",
+            subdir,
+            name,
+            "1.0",
+            "0",
+            0,
+        );
+        record.file_name = format!("{name}-1.0-0.tar.bz2");
+        record.package_record.depends = depends.iter().map(ToString::to_string).collect();
+        record
+    };
+    let virtual_package = |name: &str| GenericVirtualPackage {
+        name: name.parse().unwrap(),
+        version: Version::from_str("1").unwrap(),
+        build_string: String::from("0"),
+    };
+
+    let linux_64 = vec![record("linux-64", "foo", &["bar", "__glibc"])];
+    let win_64 = vec![record("win-64", "foo", &["bar", "__win"])];
+    let noarch = vec![record("noarch", "bar", &[])];
+
+    solve_multi_platform::<T>(MultiPlatformSolverTask {
+        platforms: vec![
+            PlatformPackages {
+                platform: Platform::Linux64,
+                available_packages: vec![linux_64.as_slice()],
+                locked_packages: Vec::new(),
+                pinned_packages: Vec::new(),
+                virtual_packages: vec![virtual_package("__glibc")],
+            },
+            PlatformPackages {
+                platform: Platform::Win64,
+                available_packages: vec![win_64.as_slice()],
+                locked_packages: Vec::new(),
+                pinned_packages: Vec::new(),
+                virtual_packages: if with_win_virtual_package {
+                    vec![virtual_package("__win")]
+                } else {
+                    Vec::new()
+                },
+            },
+        ],
+        noarch_packages: vec![noarch.as_slice()],
+        specs: vec![MatchSpec::from_str("foo").unwrap()],
+        channel_priority: ChannelPriority::default(),
+        strategy: SolveStrategy::default(),
+        exclude_newer: None,
+        timeout: None,
+        cancellation_token: None,
+    })
+}
+
+/// Updates `foo` in an environment in which `foo 1.0` and `bar 1.0` are installed. `foo 2.0`
+/// depends on `foo2_depends`.
+fn solve_update_foo<T: SolverImpl + Default>(
+    foo2_depends: &[&str],
+) -> Result<UpdateSolution, SolveError> {
+    let record = |name: &str, version: &str, depends: &[&str]| {
+        let mut record = installed_package(
+            "https://conda.anaconda.org/conda-forge/",
+            "linux-64",
+            name,
+            version,
+            "0",
+            0,
+        );
+        record.file_name = format!("{name}-{version}-0.tar.bz2");
+        record.package_record.depends = depends.iter().map(ToString::to_string).collect();
+        record
+    };
+
+    let available = vec![
+        record("foo", "1.0", &[]),
+        record("foo", "2.0", foo2_depends),
+        record("bar", "1.0", &[]),
+        record("bar", "2.0", &[]),
+    ];
+
+    SolverTask {
+        locked_packages: vec![record("foo", "1.0", &[]), record("bar", "1.0", &[])],
+        specs: vec![
+            MatchSpec::from_str("foo").unwrap(),
+            MatchSpec::from_str("bar").unwrap(),
+        ],
+        ..SolverTask::from_iter([&available])
+    }
+    .solve_update(&mut T::default(), &["foo".parse().unwrap()])
+}
+
+const CHANNEL_A: &str = "https://conda.anaconda.org/channel-a/";
+const CHANNEL_B: &str = "https://conda.anaconda.org/channel-b/";
+
+/// Solves `specs` with two channels. The higher priority channel [`CHANNEL_A`] provides `foo 1.0`,
+/// the lower priority channel [`CHANNEL_B`] provides `foo 2.0` and `bar 1.0`.
+fn solve_channel_priority_foo<T: SolverImpl + Default>(
+    specs: &[&str],
+    channel_priority: ChannelPriority,
+) -> Result<Vec<RepoDataRecord>, SolveError> {
+    let record = |channel: &str, name: &str, version: &str| {
+        let mut record = installed_package(channel, "linux-64", name, version, "0", 0);
+        record.file_name = format!("{name}-{version}-0.tar.bz2");
+        record
+    };
+
+    let channel_a = vec![record(CHANNEL_A, "foo", "1.0")];
+    let channel_b = vec![
+        record(CHANNEL_B, "foo", "2.0"),
+        record(CHANNEL_B, "bar", "1.0"),
+    ];
+
+    T::default().solve(SolverTask {
+        specs: specs
+            .iter()
+            .map(|spec| MatchSpec::from_str(spec).unwrap())
+            .collect(),
+        channel_priority,
+        ..SolverTask::from_iter([&channel_a, &channel_b])
+    })
+}
+
+/// Returns the channel and version of the package with the given name in a solution.
+fn channel_and_version(records: &[RepoDataRecord], name: &str) -> (String, String) {
+    let record = records
+        .iter()
+        .find(|record| record.package_record.name.as_normalized() == name)
+        .unwrap();
+    (
+        record.channel.clone(),
+        record.package_record.version.to_string(),
+    )
+}
+
 fn compare_solve(specs: Vec<&str>) {
     let specs = specs
         .iter()
@@ -670,11 +1350,8 @@ fn compare_solve(specs: Vec<&str>) {
             extract_pkgs(
                 rattler_solve::libsolv_c::Solver
                     .solve(SolverTask {
-                        available_packages: &available_packages,
                         specs: specs.clone(),
-                        locked_packages: Vec::default(),
-                        pinned_packages: Vec::default(),
-                        virtual_packages: Vec::default(),
+                        ..SolverTask::from_iter(&available_packages)
                     })
                     .unwrap(),
             ),
@@ -691,11 +1368,8 @@ fn compare_solve(specs: Vec<&str>) {
             extract_pkgs(
                 rattler_solve::resolvo::Solver
                     .solve(SolverTask {
-                        available_packages: &available_packages,
                         specs: specs.clone(),
-                        locked_packages: Vec::default(),
-                        pinned_packages: Vec::default(),
-                        virtual_packages: Vec::default(),
+                        ..SolverTask::from_iter(&available_packages)
                     })
                     .unwrap(),
             ),
@@ -704,6 +1378,24 @@ fn compare_solve(specs: Vec<&str>) {
         println!("resolvo took {}ms", (end_solve - start_solve).as_millis());
     }
 
+    #[cfg(feature = "pubgrub")]
+    {
+        let start_solve = Instant::now();
+        results.push((
+            "pubgrub",
+            extract_pkgs(
+                rattler_solve::pubgrub::Solver
+                    .solve(SolverTask {
+                        specs: specs.clone(),
+                        ..SolverTask::from_iter(&available_packages)
+                    })
+                    .unwrap(),
+            ),
+        ));
+        let end_solve = Instant::now();
+        println!("pubgrub took {}ms", (end_solve - start_solve).as_millis());
+    }
+
     results.into_iter().fold(None, |previous, current| {
         let previous = match previous {
             Some(previous) => previous,
@@ -752,6 +1444,19 @@ fn solve_to_get_channel_of_spec(
     expected_channel: &str,
     repo_data: Vec<&SparseRepoData>,
 ) {
+    let record = solve_to_get_record_of_spec::<rattler_solve::resolvo::Solver>(
+        spec_str,
+        repo_data,
+        ChannelPriority::default(),
+    );
+    assert_eq!(record.channel, expected_channel.to_string());
+}
+
+fn solve_to_get_record_of_spec<T: SolverImpl + Default>(
+    spec_str: &str,
+    repo_data: Vec<&SparseRepoData>,
+    channel_priority: ChannelPriority,
+) -> RepoDataRecord {
     let spec = MatchSpec::from_str(spec_str).unwrap();
     let specs = vec![spec.clone()];
     let names = specs.iter().filter_map(|s| s.name.as_ref().cloned());
@@ -759,20 +1464,21 @@ fn solve_to_get_channel_of_spec(
     let available_packages =
         SparseRepoData::load_records_recursive(repo_data, names, None).unwrap();
 
-    let result = rattler_solve::resolvo::Solver
+    let result = T::default()
         .solve(SolverTask {
-            available_packages: &available_packages,
             specs: specs.clone(),
-            locked_packages: Vec::default(),
-            pinned_packages: Vec::default(),
-            virtual_packages: Vec::default(),
+            channel_priority,
+            ..SolverTask::from_iter(&available_packages)
         })
         .unwrap();
 
-    let record = result.iter().find(|record| {
-        record.package_record.name.as_normalized() == spec.name.as_ref().unwrap().as_normalized()
-    });
-    assert_eq!(record.unwrap().channel, expected_channel.to_string());
+    result
+        .into_iter()
+        .find(|record| {
+            record.package_record.name.as_normalized()
+                == spec.name.as_ref().unwrap().as_normalized()
+        })
+        .unwrap()
 }
 
 #[test]
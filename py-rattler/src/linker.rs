@@ -147,7 +147,7 @@ pub async fn execute_operation(
                     client.clone(),
                     default_retry_policy(),
                 )
-                .map_ok(|cache_dir| Some((install_record.clone(), cache_dir)))
+                .map_ok(|package| Some((install_record.clone(), package)))
                 .map_err(|e| PyRattlerError::LinkError(e.to_string()))
                 .await
         }
@@ -158,10 +158,11 @@ pub async fn execute_operation(
 
     let (_, install_package) = tokio::try_join!(remove_future, cached_package_dir_fut)?;
 
-    if let Some((record, package_dir)) = install_package {
+    // The package is kept locked in the cache until it has been linked.
+    if let Some((record, package)) = install_package {
         install_package_to_environment(
             target_prefix,
-            package_dir,
+            package.path().to_path_buf(),
             record.clone(),
             install_driver,
             install_options,
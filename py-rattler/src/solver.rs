@@ -28,7 +28,6 @@ pub fn py_solve(
         )?;
 
         let task = SolverTask {
-            available_packages: &available_packages,
             locked_packages: locked_packages
                 .into_iter()
                 .map(TryInto::try_into)
@@ -39,6 +38,7 @@ pub fn py_solve(
                 .collect::<PyResult<Vec<_>>>()?,
             virtual_packages: virtual_packages.into_iter().map(Into::into).collect(),
             specs: specs.into_iter().map(Into::into).collect(),
+            ..SolverTask::from_iter(&available_packages)
         };
 
         Ok(Solver